@@ -0,0 +1,104 @@
+//! A bot with a few commands. Quits on ctrl-c, after sending the queued replies.
+
+use libtiny_bot::{Bot, Context, Handler, Privmsg};
+use libtiny_client::{IpPref, ServerAddr, ServerInfo};
+
+use std::process::exit;
+
+fn main() {
+    // bot <nick> <server> <port> [<chan_1> ... <chan_N>]
+    let mut args_vec: Vec<String> = std::env::args().collect();
+    if args_vec.len() < 4 {
+        show_usage();
+        exit(1);
+    }
+
+    let nick = args_vec.remove(1);
+    let server = args_vec.remove(1);
+    let port_str = args_vec.remove(1);
+    let port = match port_str.parse::<u16>() {
+        Ok(port) => port,
+        Err(err) => {
+            println!("Can't parse port: {:?}", port_str);
+            println!("{}", err);
+            exit(1);
+        }
+    };
+
+    let chans = &args_vec[1..];
+
+    let server_info = ServerInfo {
+        name: server.clone(),
+        addrs: vec![ServerAddr {
+            host: server,
+            port,
+            ws_path: None,
+        }],
+        tls: false,
+        sts_file: None,
+        bind_addr: None,
+        ip_pref: IpPref::Any,
+        pass: None,
+        realname: "tiny bot".to_owned(),
+        nicks: vec![nick],
+        auto_join: chans.to_owned(),
+        join_invites_from: vec![],
+        nickserv_ident: None,
+        sasl_auth: None,
+        ctcp_replies: true,
+        dcc_download_dir: None,
+        record_file: None,
+    };
+
+    let mut runtime = tokio::runtime::Builder::new()
+        .threaded_scheduler()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    runtime.block_on(async move {
+        let mut bot = Bot::new(server_info);
+
+        bot.on_command("!echo", |ctx, msg, args| ctx.reply(msg, args));
+        bot.on_command("!nicks", |ctx, msg, _| match msg.chan {
+            None => ctx.reply(msg, "!nicks only works in channels"),
+            Some(ref chan) => {
+                let nicks = ctx.chan_nicks(chan).join(" ");
+                ctx.reply(msg, &nicks)
+            }
+        });
+        bot.on_join(|ctx, chan, nick| {
+            if nick != ctx.nick() {
+                ctx.say(chan, &format!("Welcome, {}!", nick));
+            }
+        });
+        bot.add_handler(Counter::default());
+
+        let mut shutdown = bot.shutdown_handle();
+        tokio::spawn(async move {
+            let _ = tokio::signal::ctrl_c().await;
+            shutdown.shutdown(Some("Bye"));
+        });
+
+        bot.run().await;
+    });
+}
+
+fn show_usage() {
+    println!("bot <nick> <server> <port> [<chan_1> .. <chan_N>]");
+}
+
+/// Counts messages, reports the count on `!count`.
+#[derive(Default)]
+struct Counter {
+    n_msgs: u64,
+}
+
+impl Handler for Counter {
+    fn privmsg(&mut self, ctx: &mut Context, msg: &Privmsg) {
+        self.n_msgs += 1;
+        if msg.msg == "!count" {
+            ctx.reply(msg, &format!("{} messages so far", self.n_msgs));
+        }
+    }
+}
@@ -0,0 +1,557 @@
+//! A small framework for writing IRC bots on top of `libtiny_client`.
+//!
+//! A `Bot` connects to a server and calls its handlers on messages. Handlers are implementations
+//! of the `Handler` trait, or closures registered with `Bot::on_privmsg`, `Bot::on_join` and
+//! `Bot::on_command`. Handlers reply using the `Context` passed to them. Replies are split into
+//! messages that fit into IRC messages, and sent with a per-target rate limit (see `RateLimit`).
+//!
+//! ```no_run
+//! # async fn run(server_info: libtiny_client::ServerInfo) {
+//! let mut bot = libtiny_bot::Bot::new(server_info);
+//! bot.on_command("!echo", |ctx, msg, args| ctx.reply(msg, args));
+//! bot.run().await;
+//! # }
+//! ```
+
+#[macro_use]
+extern crate log;
+
+mod rate_limit;
+
+pub use rate_limit::RateLimit;
+
+use rate_limit::RateLimiter;
+
+use libtiny_client::{Client, Event, ServerInfo};
+use libtiny_wire as wire;
+
+use futures::future::FutureExt;
+use futures::stream::StreamExt;
+use futures::{pin_mut, select};
+use std::time::Instant;
+use tokio::sync::mpsc;
+
+/// A PRIVMSG sent to the bot or to a channel the bot is in. NOTICEs and CTCPs other than ACTION
+/// are not passed to handlers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Privmsg {
+    /// Sender of the message.
+    pub nick: String,
+    /// Channel the message is sent to. `None` for messages sent to the bot.
+    pub chan: Option<String>,
+    pub msg: String,
+    /// Is this a CTCP ACTION (`/me`)?
+    pub is_action: bool,
+}
+
+impl Privmsg {
+    /// Where to send replies: the channel for channel messages, the sender otherwise.
+    pub fn reply_target(&self) -> &str {
+        self.chan.as_ref().unwrap_or(&self.nick)
+    }
+}
+
+/// Bot event handlers. All methods do nothing by default.
+pub trait Handler: Send {
+    /// Called when the bot is registered with the server, after each (re)connect.
+    fn connected(&mut self, _ctx: &mut Context) {}
+
+    /// Called on PRIVMSGs from other users.
+    fn privmsg(&mut self, _ctx: &mut Context, _msg: &Privmsg) {}
+
+    /// Called when a user (including the bot itself) joins a channel.
+    fn join(&mut self, _ctx: &mut Context, _chan: &str, _nick: &str) {}
+
+    /// Called on all client events, before the other methods.
+    fn event(&mut self, _ctx: &mut Context, _ev: &Event) {}
+}
+
+enum Action {
+    Say { target: String, msg: String },
+    Join(String),
+    Part(String),
+    Raw(String),
+    Quit(Option<String>),
+}
+
+/// Passed to handlers to query the client state and to send messages.
+pub struct Context<'a> {
+    client: &'a Client,
+    /// Applied after the handler returns.
+    actions: &'a mut Vec<Action>,
+}
+
+impl<'a> Context<'a> {
+    /// Current nick of the bot.
+    pub fn nick(&self) -> String {
+        self.client.get_nick()
+    }
+
+    /// Nicks in a channel the bot is in.
+    pub fn chan_nicks(&self, chan: &str) -> Vec<String> {
+        self.client.get_chan_nicks(chan)
+    }
+
+    pub fn client(&self) -> &Client {
+        self.client
+    }
+
+    /// Reply to a message, in the channel for channel messages, to the sender otherwise.
+    pub fn reply(&mut self, msg: &Privmsg, text: &str) {
+        self.say(msg.reply_target(), text);
+    }
+
+    /// Send a message to a channel or a user. Each line of `text` is sent in one or more
+    /// messages, empty lines are skipped. Messages are sent with the bot's rate limit.
+    pub fn say(&mut self, target: &str, text: &str) {
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            for msg in self.client.split_privmsg(target.len(), line) {
+                self.actions.push(Action::Say {
+                    target: target.to_owned(),
+                    msg: msg.to_owned(),
+                });
+            }
+        }
+    }
+
+    pub fn join(&mut self, chan: &str) {
+        self.actions.push(Action::Join(chan.to_owned()));
+    }
+
+    pub fn part(&mut self, chan: &str) {
+        self.actions.push(Action::Part(chan.to_owned()));
+    }
+
+    /// Send a message directly to the server, without rate limiting. "\r\n" suffix is added.
+    pub fn raw_msg(&mut self, msg: &str) {
+        self.actions.push(Action::Raw(msg.to_owned()));
+    }
+
+    /// Stop the bot, after sending the queued messages.
+    pub fn quit(&mut self, reason: Option<&str>) {
+        self.actions.push(Action::Quit(reason.map(str::to_owned)));
+    }
+}
+
+struct PrivmsgFn<F>(F);
+
+impl<F> Handler for PrivmsgFn<F>
+where
+    F: FnMut(&mut Context, &Privmsg) + Send,
+{
+    fn privmsg(&mut self, ctx: &mut Context, msg: &Privmsg) {
+        (self.0)(ctx, msg)
+    }
+}
+
+struct JoinFn<F>(F);
+
+impl<F> Handler for JoinFn<F>
+where
+    F: FnMut(&mut Context, &str, &str) + Send,
+{
+    fn join(&mut self, ctx: &mut Context, chan: &str, nick: &str) {
+        (self.0)(ctx, chan, nick)
+    }
+}
+
+struct CommandFn<F> {
+    prefix: String,
+    f: F,
+}
+
+impl<F> Handler for CommandFn<F>
+where
+    F: FnMut(&mut Context, &Privmsg, &str) + Send,
+{
+    fn privmsg(&mut self, ctx: &mut Context, msg: &Privmsg) {
+        if msg.is_action {
+            return;
+        }
+        if let Some(args) = parse_command(&self.prefix, &msg.msg) {
+            (self.f)(ctx, msg, args)
+        }
+    }
+}
+
+/// If `msg` is the command `prefix`, followed by whitespace or the end of the message, returns
+/// arguments of the command.
+fn parse_command<'a>(prefix: &str, msg: &'a str) -> Option<&'a str> {
+    if !msg.starts_with(prefix) {
+        return None;
+    }
+    let args = &msg[prefix.len()..];
+    if args.is_empty() || args.starts_with(char::is_whitespace) {
+        Some(args.trim())
+    } else {
+        None
+    }
+}
+
+/// Stops a bot from other tasks or threads. See `Bot::shutdown_handle`.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    snd_shutdown: mpsc::Sender<Option<String>>,
+}
+
+impl ShutdownHandle {
+    /// Stop the bot: queued messages are sent, then the bot quits with the given reason.
+    pub fn shutdown(&mut self, reason: Option<&str>) {
+        // Channel is full when a shutdown is already requested
+        let _ = self.snd_shutdown.try_send(reason.map(str::to_owned));
+    }
+}
+
+pub struct Bot {
+    client: Client,
+    rcv_ev: mpsc::Receiver<Event>,
+    handlers: Vec<Box<dyn Handler>>,
+    rate_limiter: RateLimiter,
+    snd_shutdown: mpsc::Sender<Option<String>>,
+    rcv_shutdown: mpsc::Receiver<Option<String>>,
+}
+
+impl Bot {
+    /// Create a bot and connect to the server. Like `Client::new`, this needs to be called in
+    /// the context of a tokio runtime. Messages are handled when `run` is called.
+    pub fn new(server_info: ServerInfo) -> Bot {
+        let (client, rcv_ev) = Client::new(server_info);
+        let (snd_shutdown, rcv_shutdown) = mpsc::channel(1);
+        Bot {
+            client,
+            rcv_ev,
+            handlers: vec![],
+            rate_limiter: RateLimiter::new(RateLimit::default()),
+            snd_shutdown,
+            rcv_shutdown,
+        }
+    }
+
+    pub fn set_rate_limit(&mut self, limit: RateLimit) {
+        self.rate_limiter.set_limit(limit);
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Add a handler. Handlers are called in the order they're added.
+    pub fn add_handler<H: Handler + 'static>(&mut self, handler: H) {
+        self.handlers.push(Box::new(handler));
+    }
+
+    /// Call `f` on PRIVMSGs. See `Handler::privmsg`.
+    pub fn on_privmsg<F>(&mut self, f: F)
+    where
+        F: FnMut(&mut Context, &Privmsg) + Send + 'static,
+    {
+        self.add_handler(PrivmsgFn(f));
+    }
+
+    /// Call `f` with the channel and nick on JOINs. See `Handler::join`.
+    pub fn on_join<F>(&mut self, f: F)
+    where
+        F: FnMut(&mut Context, &str, &str) + Send + 'static,
+    {
+        self.add_handler(JoinFn(f));
+    }
+
+    /// Call `f` on PRIVMSGs starting with `prefix` (e.g. `"!echo"`), followed by whitespace or
+    /// the end of the message. `f` is passed rest of the message, with whitespace trimmed.
+    pub fn on_command<F>(&mut self, prefix: &str, f: F)
+    where
+        F: FnMut(&mut Context, &Privmsg, &str) + Send + 'static,
+    {
+        self.add_handler(CommandFn {
+            prefix: prefix.to_owned(),
+            f,
+        });
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            snd_shutdown: self.snd_shutdown.clone(),
+        }
+    }
+
+    /// Handle messages until the bot is stopped with `Context::quit` or a `ShutdownHandle`, or
+    /// the client stops (e.g. because it can't resolve the server address).
+    pub async fn run(self) {
+        let Bot {
+            mut client,
+            rcv_ev,
+            mut handlers,
+            mut rate_limiter,
+            snd_shutdown: _,
+            rcv_shutdown,
+        } = self;
+
+        let mut rcv_ev = rcv_ev.fuse();
+        let mut rcv_shutdown = rcv_shutdown.fuse();
+
+        // Set when stopping. Quit message is sent when the queued messages are sent.
+        let mut quit: Option<Option<String>> = None;
+        let mut quit_sent = false;
+
+        loop {
+            for (target, msg) in rate_limiter.pop_ready(Instant::now()) {
+                client.privmsg(&target, &msg, false);
+            }
+
+            if !quit_sent && rate_limiter.is_empty() {
+                if let Some(reason) = quit.take() {
+                    debug!("Queued messages sent, quitting");
+                    client.quit(reason);
+                    quit_sent = true;
+                }
+            }
+
+            let next_ready = rate_limiter.next_ready();
+            let delay = async move {
+                match next_ready {
+                    None => futures::future::pending().await,
+                    Some(at) => tokio::time::delay_until(at.into()).await,
+                }
+            }
+            .fuse();
+            pin_mut!(delay);
+
+            select! {
+                () = delay => {}
+                ev = rcv_ev.next() => {
+                    let ev = match ev {
+                        None | Some(Event::Closed) => {
+                            return;
+                        }
+                        Some(ev) => ev,
+                    };
+                    if quit.is_some() || quit_sent {
+                        continue;
+                    }
+                    let actions = handle_event(&client, &mut handlers, ev);
+                    for action in actions {
+                        match action {
+                            Action::Say { target, msg } => {
+                                rate_limiter.push(&target, msg, Instant::now());
+                            }
+                            Action::Join(chan) => {
+                                client.join(&[&chan]);
+                            }
+                            Action::Part(chan) => {
+                                client.part(&chan, None);
+                            }
+                            Action::Raw(msg) => {
+                                client.raw_msg(&msg);
+                            }
+                            Action::Quit(reason) => {
+                                quit = Some(reason);
+                            }
+                        }
+                    }
+                }
+                reason = rcv_shutdown.next() => {
+                    if let Some(reason) = reason {
+                        if !quit_sent {
+                            quit = Some(reason);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Call handlers on an event. Returns the actions requested by handlers.
+fn handle_event(client: &Client, handlers: &mut [Box<dyn Handler>], ev: Event) -> Vec<Action> {
+    let mut actions = vec![];
+    let mut ctx = Context {
+        client,
+        actions: &mut actions,
+    };
+
+    for handler in handlers.iter_mut() {
+        handler.event(&mut ctx, &ev);
+    }
+
+    let wire::Msg { pfx, cmd } = match ev {
+        Event::Msg(msg) => msg,
+        _ => {
+            return actions;
+        }
+    };
+
+    match (pfx, cmd) {
+        (_, wire::Cmd::Reply(wire::Reply::Welcome { .. })) => {
+            for handler in handlers.iter_mut() {
+                handler.connected(&mut ctx);
+            }
+        }
+        (
+            Some(wire::Pfx::User { nick, .. }),
+            wire::Cmd::PRIVMSG {
+                targets,
+                msg,
+                is_notice: false,
+                ctcp,
+            },
+        ) => {
+            let is_action = match ctcp {
+                None => false,
+                Some(wire::CTCP::Action) => true,
+                // Other CTCPs are handled by the client
+                Some(_) => {
+                    return actions;
+                }
+            };
+            if nick == client.get_nick() {
+                return actions;
+            }
+            for target in targets {
+                let chan = match target {
+                    wire::MsgTarget::Chan(chan) => Some(chan),
+                    wire::MsgTarget::User(_) => None,
+                };
+                let privmsg = Privmsg {
+                    nick: nick.clone(),
+                    chan,
+                    msg: msg.clone(),
+                    is_action,
+                };
+                for handler in handlers.iter_mut() {
+                    handler.privmsg(&mut ctx, &privmsg);
+                }
+            }
+        }
+        (Some(wire::Pfx::User { nick, .. }), wire::Cmd::JOIN { chans, .. }) => {
+            for chan in chans {
+                for handler in handlers.iter_mut() {
+                    handler.join(&mut ctx, &chan, &nick);
+                }
+            }
+        }
+        _ => {}
+    }
+
+    actions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libtiny_client::{IpPref, ServerAddr};
+    use std::io::{BufRead, BufReader, Write};
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("!echo", "!echo  hi there "), Some("hi there"));
+        assert_eq!(parse_command("!echo", "!echo"), Some(""));
+        assert_eq!(parse_command("!echo", "!echoes hi"), None);
+        assert_eq!(parse_command("!echo", "hi !echo"), None);
+    }
+
+    fn stub_read_until(reader: &mut impl BufRead, prefix: &str) -> String {
+        loop {
+            let mut line = String::new();
+            assert!(reader.read_line(&mut line).unwrap() != 0);
+            if line.starts_with(prefix) {
+                return line;
+            }
+        }
+    }
+
+    /// Run an echo bot against a stub server on a local port. The bot quits after a `!quit`
+    /// command, after sending the queued replies.
+    #[test]
+    fn test_bot() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+
+            stub_read_until(&mut reader, "USER ");
+            writer
+                .write_all(
+                    b":irc.example.org 001 bot :Welcome\r\n\
+                      :bot!bot@host JOIN #chan\r\n\
+                      :alice!alice@host JOIN #chan\r\n\
+                      :alice!alice@host PRIVMSG #chan :!echo hi\r\n\
+                      :alice!alice@host PRIVMSG bot :!echo line 1\\nline 2\r\n\
+                      :alice!alice@host NOTICE bot :!echo notice\r\n\
+                      :alice!alice@host PRIVMSG bot :!echoes\r\n\
+                      :alice!alice@host PRIVMSG #chan :!quit\r\n",
+                )
+                .unwrap();
+
+            let mut lines = vec![];
+            loop {
+                let mut line = String::new();
+                assert!(reader.read_line(&mut line).unwrap() != 0);
+                if line.starts_with("QUIT") {
+                    lines.push(line);
+                    break;
+                }
+                if line.starts_with("PRIVMSG") {
+                    lines.push(line);
+                }
+            }
+            lines
+        });
+
+        let server_info = ServerInfo {
+            name: "test".to_owned(),
+            addrs: vec![ServerAddr {
+                host: "127.0.0.1".to_owned(),
+                port: addr.port(),
+                ws_path: None,
+            }],
+            tls: false,
+            sts_file: None,
+            bind_addr: None,
+            ip_pref: IpPref::Any,
+            pass: None,
+            realname: "bot".to_owned(),
+            nicks: vec!["bot".to_owned()],
+            auto_join: vec![],
+            join_invites_from: vec![],
+            nickserv_ident: None,
+            sasl_auth: None,
+            ctcp_replies: false,
+            dcc_download_dir: None,
+            record_file: None,
+        };
+
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async move {
+            let mut bot = Bot::new(server_info);
+            bot.on_join(|ctx, chan, nick| {
+                if nick != ctx.nick() {
+                    ctx.say(chan, &format!("hi {}", nick));
+                }
+            });
+            bot.on_command("!echo", |ctx, msg, args| {
+                ctx.reply(msg, &args.replace("\\n", "\n"))
+            });
+            bot.on_command("!quit", |ctx, _, _| ctx.quit(Some("bye")));
+            bot.run().await;
+        });
+
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                "PRIVMSG #chan :hi alice\r\n",
+                "PRIVMSG #chan :hi\r\n",
+                "PRIVMSG alice :line 1\r\n",
+                "PRIVMSG alice :line 2\r\n",
+                "QUIT :bye\r\n",
+            ]
+        );
+    }
+}
@@ -0,0 +1,214 @@
+//! Per-target rate limiting of outgoing messages, to avoid flooding channels (and getting kicked
+//! or killed by the server for it).
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// How many messages the bot sends to a target (a channel or a nick): `burst` messages can be
+/// sent right away, after that one message per `interval`. `burst` of 0 is the same as 1, and
+/// `interval` is at least `RateLimit::MIN_INTERVAL`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub interval: Duration,
+}
+
+impl RateLimit {
+    /// Smallest `interval`, smaller intervals are rounded up to this.
+    pub const MIN_INTERVAL: Duration = Duration::from_millis(10);
+
+    /// With `burst` 0 no messages would be sent, and with `interval` 0 the limiter would be a
+    /// busy loop.
+    fn clamp(self) -> RateLimit {
+        RateLimit {
+            burst: std::cmp::max(self.burst, 1),
+            interval: std::cmp::max(self.interval, RateLimit::MIN_INTERVAL),
+        }
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> RateLimit {
+        RateLimit {
+            burst: 4,
+            interval: Duration::from_secs(2),
+        }
+    }
+}
+
+/// A token bucket and a message queue.
+#[derive(Debug)]
+struct Target {
+    queue: VecDeque<String>,
+    /// Number of messages that can be sent right away.
+    tokens: u32,
+    /// When the last token was added. The next token is added `interval` after this.
+    last_refill: Instant,
+}
+
+impl Target {
+    fn refill(&mut self, limit: RateLimit, now: Instant) {
+        while self.tokens < limit.burst && now >= self.last_refill + limit.interval {
+            self.tokens += 1;
+            self.last_refill += limit.interval;
+        }
+        if self.tokens >= limit.burst {
+            self.last_refill = now;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    /// Keys are lowercased targets.
+    targets: HashMap<String, (String, Target)>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> RateLimiter {
+        RateLimiter {
+            limit: limit.clamp(),
+            targets: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn set_limit(&mut self, limit: RateLimit) {
+        self.limit = limit.clamp();
+    }
+
+    /// Queue a message to `target`. Use `pop_ready` to get messages that can be sent.
+    pub(crate) fn push(&mut self, target: &str, msg: String, now: Instant) {
+        let burst = self.limit.burst;
+        let (_, target) = self
+            .targets
+            .entry(target.to_lowercase())
+            .or_insert_with(|| {
+                (
+                    target.to_owned(),
+                    Target {
+                        queue: VecDeque::new(),
+                        tokens: burst,
+                        last_refill: now,
+                    },
+                )
+            });
+        target.queue.push_back(msg);
+    }
+
+    /// Remove messages that can be sent at `now` from the queues. Returns (target, message)
+    /// pairs, messages to a target are in the order they're pushed.
+    pub(crate) fn pop_ready(&mut self, now: Instant) -> Vec<(String, String)> {
+        let limit = self.limit;
+        let mut ret = vec![];
+        for (name, target) in self.targets.values_mut() {
+            target.refill(limit, now);
+            while target.tokens > 0 {
+                match target.queue.pop_front() {
+                    None => {
+                        break;
+                    }
+                    Some(msg) => {
+                        target.tokens -= 1;
+                        ret.push((name.clone(), msg));
+                    }
+                }
+            }
+        }
+        // Targets with no queued messages and full buckets are the same as new targets
+        self.targets
+            .retain(|_, (_, target)| !target.queue.is_empty() || target.tokens < limit.burst);
+        ret
+    }
+
+    /// When the next queued message can be sent. `None` when there are no queued messages.
+    pub(crate) fn next_ready(&self) -> Option<Instant> {
+        self.targets
+            .values()
+            .filter(|(_, target)| !target.queue.is_empty())
+            .map(|(_, target)| target.last_refill + self.limit.interval)
+            .min()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.targets
+            .values()
+            .all(|(_, target)| target.queue.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limit = RateLimit {
+            burst: 2,
+            interval: Duration::from_secs(1),
+        };
+        let mut limiter = RateLimiter::new(limit);
+        let now = Instant::now();
+
+        for i in 0..4 {
+            limiter.push("#chan", format!("msg {}", i), now);
+        }
+        limiter.push("Nick", "hi".to_owned(), now);
+        limiter.push("nick", "hi again".to_owned(), now);
+
+        let mut ready = limiter.pop_ready(now);
+        ready.sort();
+        assert_eq!(
+            ready,
+            vec![
+                ("#chan".to_owned(), "msg 0".to_owned()),
+                ("#chan".to_owned(), "msg 1".to_owned()),
+                ("Nick".to_owned(), "hi".to_owned()),
+                ("Nick".to_owned(), "hi again".to_owned()),
+            ]
+        );
+        assert_eq!(limiter.next_ready(), Some(now + Duration::from_secs(1)));
+
+        // Not enough time passed for a new token
+        assert!(limiter
+            .pop_ready(now + Duration::from_millis(500))
+            .is_empty());
+
+        assert_eq!(
+            limiter.pop_ready(now + Duration::from_millis(1500)),
+            vec![("#chan".to_owned(), "msg 2".to_owned())]
+        );
+        assert_eq!(limiter.next_ready(), Some(now + Duration::from_secs(2)));
+        assert_eq!(
+            limiter.pop_ready(now + Duration::from_secs(10)),
+            vec![("#chan".to_owned(), "msg 3".to_owned())]
+        );
+        assert!(limiter.is_empty());
+        assert_eq!(limiter.next_ready(), None);
+
+        // Buckets are full again, targets are removed
+        assert!(limiter.pop_ready(now + Duration::from_secs(20)).is_empty());
+        assert!(limiter.targets.is_empty());
+    }
+
+    #[test]
+    fn test_zero_rate_limit() {
+        let mut limiter = RateLimiter::new(RateLimit {
+            burst: 0,
+            interval: Duration::from_secs(0),
+        });
+        let now = Instant::now();
+
+        limiter.push("#chan", "msg 0".to_owned(), now);
+        limiter.push("#chan", "msg 1".to_owned(), now);
+        assert_eq!(
+            limiter.pop_ready(now),
+            vec![("#chan".to_owned(), "msg 0".to_owned())]
+        );
+        assert_eq!(limiter.next_ready(), Some(now + RateLimit::MIN_INTERVAL));
+        assert_eq!(
+            limiter.pop_ready(now + RateLimit::MIN_INTERVAL),
+            vec![("#chan".to_owned(), "msg 1".to_owned())]
+        );
+    }
+}
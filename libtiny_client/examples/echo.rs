@@ -1,9 +1,9 @@
 //! An echo bot that just repeats stuff sent to it (either in a channel or as PRIVMSG).
 
-use libtiny_client::{Client, Event, ServerInfo};
+use libtiny_client::{Client, Event, IpPref, ServerAddr, ServerInfo};
 use libtiny_wire::{Cmd, Msg, MsgTarget, Pfx};
 
-use futures_util::stream::StreamExt;
+use futures::stream::StreamExt;
 use std::process::exit;
 
 fn main() {
@@ -29,24 +29,38 @@ fn main() {
     let chans = &args_vec[1..];
 
     let server_info = ServerInfo {
-        addr: server,
-        port,
+        name: server.clone(),
+        addrs: vec![ServerAddr {
+            host: server,
+            port,
+            ws_path: None,
+        }],
         tls: false,
+        sts_file: None,
+        bind_addr: None,
+        ip_pref: IpPref::Any,
         pass: None,
         realname: "tiny echo bot".to_owned(),
         nicks: vec![nick],
         auto_join: chans.to_owned(),
+        join_invites_from: vec![],
         nickserv_ident: None,
         sasl_auth: None,
+        ctcp_replies: true,
+        dcc_download_dir: None,
+        record_file: None,
     };
 
     println!("{:?}", server_info);
 
-    let mut executor = tokio::runtime::current_thread::Runtime::new().unwrap();
+    // Client is `Send`, so the bot can run on the threaded scheduler
+    let mut runtime = tokio::runtime::Builder::new()
+        .threaded_scheduler()
+        .enable_all()
+        .build()
+        .unwrap();
 
-    executor.spawn(echo_bot_task(server_info));
-
-    executor.run().unwrap();
+    runtime.block_on(echo_bot_task(server_info));
 }
 
 fn show_usage() {
@@ -56,40 +70,42 @@ fn show_usage() {
 static NICK_SEP: [&str; 4] = [": ", ", ", ":", ","];
 
 async fn echo_bot_task(server_info: ServerInfo) {
-    let (mut client, mut rcv_ev) = Client::new(server_info, None);
+    let (mut client, mut rcv_ev) = Client::new(server_info);
 
     while let Some(ev) = rcv_ev.next().await {
         println!("Client event: {:?}", ev);
         if let Event::Msg(Msg {
             pfx: Some(Pfx::User { nick, .. }),
-            cmd: Cmd::PRIVMSG { target, msg, .. },
+            cmd: Cmd::PRIVMSG { targets, msg, .. },
         }) = ev
         {
-            let echo_msg = match target {
-                MsgTarget::User(_) => {
-                    // Message is a PRIVMSG to us, just echo the whole message to the sender
-                    Some((nick, msg))
-                }
-                MsgTarget::Chan(chan) => {
-                    // Message was sent to a channel. Only echo if it's directed at us
-                    let our_nick = client.get_nick();
-                    if msg.starts_with(&our_nick) {
-                        let mut msg = &msg[our_nick.len()..];
-                        for nick_sep in NICK_SEP.iter() {
-                            if msg.starts_with(nick_sep) {
-                                msg = &msg[nick_sep.len()..];
-                                break;
+            for target in targets {
+                let echo_msg = match target {
+                    MsgTarget::User(_) => {
+                        // Message is a PRIVMSG to us, just echo the whole message to the sender
+                        Some((nick.clone(), msg.clone()))
+                    }
+                    MsgTarget::Chan(chan) => {
+                        // Message was sent to a channel. Only echo if it's directed at us
+                        let our_nick = client.get_nick();
+                        if msg.starts_with(&our_nick) {
+                            let mut msg = &msg[our_nick.len()..];
+                            for nick_sep in NICK_SEP.iter() {
+                                if msg.starts_with(nick_sep) {
+                                    msg = &msg[nick_sep.len()..];
+                                    break;
+                                }
                             }
+                            Some((chan, msg.to_owned()))
+                        } else {
+                            None
                         }
-                        Some((chan, msg.to_owned()))
-                    } else {
-                        None
                     }
-                }
-            };
+                };
 
-            if let Some((target, msg)) = echo_msg {
-                client.privmsg(&target, &msg, false);
+                if let Some((target, msg)) = echo_msg {
+                    client.privmsg(&target, &msg, false);
+                }
             }
         }
     }
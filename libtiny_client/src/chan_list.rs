@@ -0,0 +1,179 @@
+//! Collecting LIST replies into a channel list.
+
+use libtiny_ui::ChanListEntry;
+use libtiny_wire as wire;
+use libtiny_wire::Reply;
+
+/// Filters of a LIST request. Sent to the server when it advertises the ELIST extensions for the
+/// filters, and also applied to the replies, as servers without ELIST ignore (or reject) them.
+#[derive(Debug, Default)]
+struct Filter {
+    /// A glob pattern with `*` and `?`, matched case-insensitively against channel names.
+    pattern: Option<String>,
+    min_users: Option<u32>,
+}
+
+impl Filter {
+    fn matches(&self, entry: &ChanListEntry) -> bool {
+        if let Some(min_users) = self.min_users {
+            if entry.n_users < min_users {
+                return false;
+            }
+        }
+        match self.pattern {
+            None => true,
+            Some(ref pattern) => glob_match(
+                pattern.to_lowercase().as_bytes(),
+                entry.chan.to_lowercase().as_bytes(),
+            ),
+        }
+    }
+}
+
+/// Collects RPL_LIST replies until RPL_LISTEND.
+#[derive(Debug, Default)]
+pub(crate) struct ChanList {
+    /// Filters of the LIST request in progress. `None` when we're not waiting for a LIST result.
+    filter: Option<Filter>,
+    chans: Vec<ChanListEntry>,
+}
+
+impl ChanList {
+    /// Start a new LIST request. `elist` is the value of the ELIST token in RPL_ISUPPORT. Returns
+    /// the message to send. A request in progress is abandoned.
+    pub(crate) fn request(
+        &mut self,
+        pattern: Option<&str>,
+        min_users: Option<u32>,
+        elist: Option<&str>,
+    ) -> String {
+        // Search for the pattern anywhere in channel names unless it has wildcards
+        let pattern = pattern.map(|pattern| {
+            if pattern.contains('*') || pattern.contains('?') {
+                pattern.to_owned()
+            } else {
+                format!("*{}*", pattern)
+            }
+        });
+
+        let elist = elist.unwrap_or("").to_uppercase();
+        let mut conds: Vec<String> = vec![];
+        if let Some(ref pattern) = pattern {
+            if elist.contains('M') {
+                conds.push(pattern.clone());
+            }
+        }
+        if let Some(min_users) = min_users {
+            if min_users > 0 && elist.contains('U') {
+                // ">N" means more than N users
+                conds.push(format!(">{}", min_users - 1));
+            }
+        }
+
+        self.filter = Some(Filter { pattern, min_users });
+        self.chans.clear();
+
+        if conds.is_empty() {
+            wire::list(None)
+        } else {
+            wire::list(Some(&conds.join(",")))
+        }
+    }
+
+    /// Returns the result when `reply` ends the LIST request in progress.
+    pub(crate) fn update(&mut self, reply: &Reply) -> Option<Vec<ChanListEntry>> {
+        let filter = self.filter.as_ref()?;
+        match reply {
+            Reply::List {
+                chan,
+                n_users,
+                topic,
+            } => {
+                let entry = ChanListEntry {
+                    chan: chan.clone(),
+                    n_users: *n_users,
+                    topic: topic.clone(),
+                };
+                if filter.matches(&entry) {
+                    self.chans.push(entry);
+                }
+                None
+            }
+            Reply::ListEnd => {
+                self.filter = None;
+                let chans = self.chans.clone();
+                self.chans.clear();
+                Some(chans)
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.filter = None;
+        self.chans.clear();
+    }
+}
+
+/// Match `s` against a glob pattern with `*` (any number of characters) and `?` (any single
+/// character).
+pub(crate) fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => s.is_empty(),
+        Some((b'*', pattern_rest)) => {
+            (0..=s.len()).any(|skip| glob_match(pattern_rest, &s[skip..]))
+        }
+        Some((p, pattern_rest)) => match s.split_first() {
+            None => false,
+            Some((c, s_rest)) => (*p == b'?' || p == c) && glob_match(pattern_rest, s_rest),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*rust*", b"#rust-beginners"));
+        assert!(glob_match(b"#r?st", b"#rust"));
+        assert!(glob_match(b"*", b""));
+        assert!(!glob_match(b"#rust", b"#rust-beginners"));
+        assert!(!glob_match(b"*rust?", b"#rust"));
+    }
+
+    #[test]
+    fn test_chan_list() {
+        let mut chan_list = ChanList::default();
+
+        // Replies without a request in progress are ignored
+        assert_eq!(chan_list.update(&Reply::ListEnd), None);
+
+        assert_eq!(
+            chan_list.request(Some("rust"), Some(10), Some("CMNTU")),
+            "LIST *rust*,>9\r\n"
+        );
+        assert_eq!(chan_list.request(Some("rust"), Some(10), None), "LIST\r\n");
+
+        let list = |chan: &str, n_users: u32| Reply::List {
+            chan: chan.to_owned(),
+            n_users,
+            topic: "topic".to_owned(),
+        };
+        assert_eq!(chan_list.update(&Reply::ListStart), None);
+        assert_eq!(chan_list.update(&list("#rust", 100)), None);
+        assert_eq!(chan_list.update(&list("#RUST-offtopic", 5)), None);
+        assert_eq!(chan_list.update(&list("#haskell", 100)), None);
+        assert_eq!(
+            chan_list.update(&Reply::ListEnd),
+            Some(vec![ChanListEntry {
+                chan: "#rust".to_owned(),
+                n_users: 100,
+                topic: "topic".to_owned(),
+            }])
+        );
+        assert_eq!(chan_list.update(&list("#rust", 100)), None);
+        assert!(chan_list.chans.is_empty());
+    }
+}
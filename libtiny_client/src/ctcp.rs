@@ -0,0 +1,127 @@
+//! Automatic replies to CTCP requests. See https://defs.ircdocs.horse/defs/ctcp.html
+
+use libtiny_wire as wire;
+use libtiny_wire::CTCP;
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Sent in VERSION replies.
+const VERSION: &str = concat!("tiny (libtiny_client ", env!("CARGO_PKG_VERSION"), ")");
+
+/// Sent in SOURCE replies.
+const SOURCE: &str = "https://github.com/osa1/tiny";
+
+/// Sent in CLIENTINFO replies: CTCP messages that we understand.
+const CLIENTINFO: &str = "ACTION CLIENTINFO DCC PING SOURCE TIME USERINFO VERSION";
+
+/// Max. number of CTCP replies to send in `RATE_LIMIT_WINDOW`. Requests after this are ignored,
+/// to avoid being kicked from the server for flooding when someone (or a channel full of people)
+/// floods us with CTCP requests.
+const RATE_LIMIT_REPLIES: usize = 3;
+
+/// See `RATE_LIMIT_REPLIES`.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(10);
+
+pub(crate) struct CtcpReplier {
+    /// Times of the replies sent in the last `RATE_LIMIT_WINDOW`.
+    reply_times: VecDeque<Instant>,
+}
+
+impl CtcpReplier {
+    pub(crate) fn new() -> CtcpReplier {
+        CtcpReplier {
+            reply_times: VecDeque::with_capacity(RATE_LIMIT_REPLIES),
+        }
+    }
+
+    /// Generate a reply for a CTCP request from `origin`. Returns `None` if we don't reply to the
+    /// request, or we sent too many replies recently.
+    pub(crate) fn reply(
+        &mut self,
+        now: Instant,
+        origin: &str,
+        ctcp: &CTCP,
+        args: &str,
+        realname: &str,
+    ) -> Option<String> {
+        let reply = match ctcp {
+            CTCP::Version => VERSION.to_owned(),
+            CTCP::Ping => args.to_owned(),
+            CTCP::Time => time::now().ctime().to_string(),
+            CTCP::ClientInfo => CLIENTINFO.to_owned(),
+            CTCP::Source => SOURCE.to_owned(),
+            CTCP::UserInfo => realname.to_owned(),
+            CTCP::Action | CTCP::Dcc | CTCP::Other(_) => {
+                return None;
+            }
+        };
+
+        if !self.check_rate_limit(now) {
+            debug!("CTCP rate limit reached, not replying to {}", origin);
+            return None;
+        }
+
+        Some(wire::ctcp_reply(origin, ctcp.name(), &reply))
+    }
+
+    /// Returns whether we can send one more reply at `now`. Records the reply when we can.
+    fn check_rate_limit(&mut self, now: Instant) -> bool {
+        while let Some(time) = self.reply_times.front() {
+            if now.duration_since(*time) >= RATE_LIMIT_WINDOW {
+                self.reply_times.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        if self.reply_times.len() >= RATE_LIMIT_REPLIES {
+            false
+        } else {
+            self.reply_times.push_back(now);
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ctcp_replies() {
+        let mut replier = CtcpReplier::new();
+        let now = Instant::now();
+        assert_eq!(
+            replier.reply(now, "nick", &CTCP::Ping, "123", "real name"),
+            Some("NOTICE nick :\x01PING 123\x01\r\n".to_owned())
+        );
+        assert_eq!(
+            replier.reply(now, "nick", &CTCP::UserInfo, "", "real name"),
+            Some("NOTICE nick :\x01USERINFO real name\x01\r\n".to_owned())
+        );
+        assert_eq!(
+            replier.reply(now, "nick", &CTCP::Action, "", "real name"),
+            None
+        );
+        assert_eq!(
+            replier.reply(now, "nick", &CTCP::Other("FOO".to_owned()), "", "real name"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_ctcp_rate_limit() {
+        let mut replier = CtcpReplier::new();
+        let now = Instant::now();
+        for _ in 0..RATE_LIMIT_REPLIES {
+            assert!(replier.reply(now, "nick", &CTCP::Version, "", "").is_some());
+        }
+        assert!(replier.reply(now, "nick", &CTCP::Version, "", "").is_none());
+
+        let later = now + RATE_LIMIT_WINDOW;
+        assert!(replier
+            .reply(later, "nick", &CTCP::Version, "", "")
+            .is_some());
+    }
+}
@@ -0,0 +1,1286 @@
+//! DCC (Direct Client-to-Client) chats and file transfers. See https://modern.ircdocs.horse/dcc.html
+//! for the protocol.
+//!
+//! DCC offers are sent as CTCP messages over IRC, the actual chat or transfer happens over a
+//! direct TCP connection between the clients. In a normal offer the offering side listens on a
+//! port and sends its address in the offer. In a "passive" (or "reverse") offer the offering side
+//! sends port 0 and a token, and the accepting side listens and sends its address back in an offer
+//! with the same token. Passive offers are useful when the offering side is behind a NAT.
+//!
+//! Both sides of both kinds of offers are implemented here.
+
+use crate::utils;
+use crate::{Cmd, Event};
+use libtiny_wire as wire;
+
+use futures::future::FutureExt;
+use futures::stream::StreamExt;
+use futures::{pin_mut, select};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+/// Max. number of incoming offers waiting for the user to accept or reject. Further offers are
+/// ignored, to avoid using unbounded memory when someone floods us with offers.
+const MAX_PENDING_OFFERS: usize = 20;
+
+/// How long to wait for the other side to connect after sending an offer or accepting a passive
+/// offer.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Identifies a DCC chat or file transfer in a `Client`.
+pub type DccId = usize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DccKind {
+    Chat,
+    Send {
+        file_name: String,
+        /// File size in bytes. Optional in offers.
+        size: Option<u64>,
+    },
+}
+
+/// Direction of a chat or transfer: `Incoming` for offers we received, `Outgoing` for offers we
+/// sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DccDir {
+    Incoming,
+    Outgoing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DccStatus {
+    /// Offer sent or received, waiting for the other side to accept (outgoing) or user to accept
+    /// (incoming).
+    Offered,
+    /// Offer accepted, waiting for the connection to be established.
+    Connecting,
+    /// Connection established, chat or transfer in progress.
+    Active,
+    /// Transfer completed or chat ended.
+    Done,
+    Failed,
+    Rejected,
+    Cancelled,
+}
+
+/// Information about a DCC chat or transfer.
+#[derive(Debug, Clone)]
+pub struct DccInfo {
+    pub id: DccId,
+    /// The other side of the chat or transfer
+    pub nick: String,
+    pub kind: DccKind,
+    pub dir: DccDir,
+    pub status: DccStatus,
+    /// Number of bytes sent or received so far
+    pub bytes: u64,
+}
+
+#[derive(Debug)]
+pub enum DccError {
+    /// No chat or transfer with the given id.
+    NoSuchId(DccId),
+    /// Chat or transfer is not in a state that allows the operation (e.g. accepting an outgoing
+    /// offer, or sending a chat message on a file transfer).
+    InvalidState(DccId),
+    /// Can't accept a file because download directory is not configured.
+    NoDownloadDir,
+    /// We don't know our own IP address to send in offers (i.e. not connected to the server).
+    NoLocalAddr,
+    Io(io::Error),
+}
+
+impl std::fmt::Display for DccError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DccError::NoSuchId(id) => write!(f, "No DCC with id {}", id),
+            DccError::InvalidState(id) => write!(f, "Can't do that with DCC {} now", id),
+            DccError::NoDownloadDir => write!(f, "DCC download directory is not set"),
+            DccError::NoLocalAddr => write!(f, "Local address unknown, not connected?"),
+            DccError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<io::Error> for DccError {
+    fn from(err: io::Error) -> DccError {
+        DccError::Io(err)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// Offers
+
+/// Arguments of a `DCC SEND` or `DCC CHAT` CTCP message.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct DccOffer {
+    kind: DccKind,
+    ip: IpAddr,
+    /// 0 in passive offers.
+    port: u16,
+    /// Only used in passive offers, and replies to passive offers.
+    token: Option<String>,
+}
+
+impl DccOffer {
+    /// Parse arguments of a DCC CTCP message:
+    ///
+    /// - `SEND <filename> <ip> <port> [<size> [<token>]]`
+    /// - `CHAT chat <ip> <port> [<token>]`
+    ///
+    /// Filenames with spaces are quoted. IPv4 addresses are sent as integers, IPv6 addresses are
+    /// sent in the usual text form.
+    pub(crate) fn parse(args: &str) -> Option<DccOffer> {
+        let args = split_args(args);
+        let ty = args.first()?;
+        let (kind, rest) = if ty.eq_ignore_ascii_case("SEND") {
+            let file_name = args.get(1)?.clone();
+            let size = match args.get(4) {
+                None => None,
+                Some(size) => Some(size.parse::<u64>().ok()?),
+            };
+            (DccKind::Send { file_name, size }, args.get(2..)?)
+        } else if ty.eq_ignore_ascii_case("CHAT") {
+            (DccKind::Chat, args.get(2..)?)
+        } else {
+            return None;
+        };
+
+        if rest.len() < 2 {
+            return None;
+        }
+
+        let ip = match rest[0].parse::<u32>() {
+            Ok(ip) => IpAddr::V4(Ipv4Addr::from(ip)),
+            Err(_) => rest[0].parse::<IpAddr>().ok()?,
+        };
+        let port = rest[1].parse::<u16>().ok()?;
+        let token = match kind {
+            DccKind::Chat => rest.get(2),
+            DccKind::Send { .. } => rest.get(3),
+        }
+        .cloned();
+
+        Some(DccOffer {
+            kind,
+            ip,
+            port,
+            token,
+        })
+    }
+
+    fn to_ctcp_args(&self) -> String {
+        let ip = match self.ip {
+            IpAddr::V4(ip) => u32::from(ip).to_string(),
+            IpAddr::V6(ip) => ip.to_string(),
+        };
+        let mut args = match self.kind {
+            DccKind::Chat => format!("CHAT chat {} {}", ip, self.port),
+            DccKind::Send {
+                ref file_name,
+                size,
+            } => {
+                let file_name = if file_name.contains(' ') {
+                    format!("\"{}\"", file_name)
+                } else {
+                    file_name.clone()
+                };
+                let mut args = format!("SEND {} {} {}", file_name, ip, self.port);
+                if let Some(size) = size {
+                    args.push_str(&format!(" {}", size));
+                }
+                args
+            }
+        };
+        if let Some(ref token) = self.token {
+            args.push(' ');
+            args.push_str(token);
+        }
+        args
+    }
+}
+
+/// Split DCC arguments on whitespace, treating double-quoted strings as one argument.
+fn split_args(args: &str) -> Vec<String> {
+    let mut ret = vec![];
+    let mut current = String::new();
+    let mut in_quotes = false;
+    for c in args.chars() {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        } else if c.is_whitespace() && !in_quotes {
+            if !current.is_empty() {
+                ret.push(current.clone());
+                current.clear();
+            }
+        } else {
+            current.push(c);
+        }
+    }
+    if !current.is_empty() {
+        ret.push(current);
+    }
+    ret
+}
+
+/// Path to save a received file. The file name comes from the other side, so only the last
+/// component of it is used to avoid writing to outside of the download directory. The file may
+/// exist, see `create_download_file`.
+fn download_path(dir: &Path, file_name: &str) -> PathBuf {
+    let file_name = file_name.replace('\\', "/");
+    let file_name = Path::new(&file_name)
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .trim_start_matches('.');
+    let file_name = if file_name.is_empty() {
+        "dcc_file"
+    } else {
+        file_name
+    };
+
+    dir.join(file_name)
+}
+
+/// Create the file to save a received file. Existing files are not overwritten; a number is added
+/// to the file name instead. Returns the file and its path.
+async fn create_download_file(path: &Path) -> io::Result<(File, PathBuf)> {
+    let mut new_path = path.to_owned();
+    let mut i = 1;
+    loop {
+        // `create_new` fails if the file exists, checking `exists` first would be racy
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&new_path)
+            .await
+        {
+            Ok(file) => {
+                return Ok((file, new_path));
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                let mut file_name = path.file_name().unwrap_or_default().to_owned();
+                file_name.push(format!(".{}", i));
+                new_path = path.with_file_name(file_name);
+                i += 1;
+            }
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// State
+
+/// DCC chats and transfers of a client.
+#[derive(Clone)]
+pub(crate) struct Dcc {
+    inner: Arc<Mutex<DccInner>>,
+}
+
+/// A `Dcc` that doesn't keep the chats and transfers alive. Tasks hold this, so that when the
+/// client is dropped the `snd_cancel`s are dropped and the tasks stop.
+struct WeakDcc {
+    inner: Weak<Mutex<DccInner>>,
+}
+
+struct DccInner {
+    next_id: DccId,
+
+    transfers: Vec<Transfer>,
+
+    /// Where to save received files.
+    download_dir: Option<PathBuf>,
+
+    /// Our address, as seen in the local end of the connection to the IRC server. Sent in offers.
+    local_ip: Option<IpAddr>,
+
+    snd_ev: mpsc::Sender<Event>,
+}
+
+struct Transfer {
+    info: DccInfo,
+
+    /// Address of the other side, for incoming offers and replies to our passive offers. Port is
+    /// 0 in passive offers.
+    addr: Option<(IpAddr, u16)>,
+
+    /// Token of a passive offer.
+    token: Option<String>,
+
+    /// File to send (outgoing) or save to (incoming).
+    path: Option<PathBuf>,
+
+    /// Dropping this cancels the task for the chat or transfer.
+    snd_cancel: Option<mpsc::Sender<()>>,
+
+    /// Lines to send, for chats.
+    snd_chat: Option<mpsc::Sender<String>>,
+}
+
+/// How to establish the connection for a chat or transfer.
+enum Conn {
+    /// Connect to the other side.
+    Connect(SocketAddr),
+    /// Listen on a random port, send the offer with the port to `nick`, and wait for the other
+    /// side to connect.
+    Listen {
+        nick: String,
+        offer: DccOffer,
+        snd_cmd: mpsc::Sender<Cmd>,
+    },
+}
+
+impl Dcc {
+    pub(crate) fn new(download_dir: Option<PathBuf>, snd_ev: mpsc::Sender<Event>) -> Dcc {
+        Dcc {
+            inner: Arc::new(Mutex::new(DccInner {
+                next_id: 0,
+                transfers: vec![],
+                download_dir,
+                local_ip: None,
+                snd_ev,
+            })),
+        }
+    }
+
+    pub(crate) fn set_local_ip(&self, ip: IpAddr) {
+        utils::lock(&self.inner).local_ip = Some(ip);
+    }
+
+    pub(crate) fn list(&self) -> Vec<DccInfo> {
+        utils::lock(&self.inner)
+            .transfers
+            .iter()
+            .map(|t| t.info.clone())
+            .collect()
+    }
+
+    /// Find an active chat with the given nick.
+    pub(crate) fn find_chat(&self, nick: &str) -> Option<DccId> {
+        utils::lock(&self.inner)
+            .transfers
+            .iter()
+            .find(|t| {
+                t.info.kind == DccKind::Chat
+                    && t.info.status == DccStatus::Active
+                    && t.info.nick == nick
+            })
+            .map(|t| t.info.id)
+    }
+
+    /// Handle a `DCC` CTCP message from `nick`. This is either a new offer, a reply to one of our
+    /// passive offers, or a rejection of one of our offers.
+    pub(crate) fn handle_ctcp(&self, nick: &str, args: &str) {
+        let mut words = args.splitn(2, ' ');
+        if words.next() == Some("REJECT") {
+            self.handle_reject(nick, words.next().unwrap_or(""));
+            return;
+        }
+
+        let offer = match DccOffer::parse(args) {
+            None => {
+                debug!("Can't parse DCC message from {}: {:?}", nick, args);
+                return;
+            }
+            Some(offer) => offer,
+        };
+
+        // Is this a reply to our passive offer?
+        let reply_to = match offer.token {
+            Some(ref token) if offer.port != 0 => {
+                utils::lock(&self.inner).transfers.iter().position(|t| {
+                    t.info.dir == DccDir::Outgoing
+                        && t.info.status == DccStatus::Offered
+                        && t.info.nick == nick
+                        && t.token.as_ref() == Some(token)
+                })
+            }
+            _ => None,
+        };
+
+        match reply_to {
+            Some(idx) => {
+                let id = {
+                    let mut inner = utils::lock(&self.inner);
+                    let transfer = &mut inner.transfers[idx];
+                    transfer.addr = Some((offer.ip, offer.port));
+                    transfer.info.status = DccStatus::Connecting;
+                    transfer.info.id
+                };
+                self.start(id, Conn::Connect(SocketAddr::new(offer.ip, offer.port)));
+            }
+            None => {
+                let mut inner = utils::lock(&self.inner);
+                let n_pending = inner
+                    .transfers
+                    .iter()
+                    .filter(|t| {
+                        t.info.dir == DccDir::Incoming && t.info.status == DccStatus::Offered
+                    })
+                    .count();
+                if n_pending >= MAX_PENDING_OFFERS {
+                    debug!("Too many pending DCC offers, ignoring offer from {}", nick);
+                    return;
+                }
+                let id = inner.next_id;
+                inner.next_id += 1;
+                let info = DccInfo {
+                    id,
+                    nick: nick.to_owned(),
+                    kind: offer.kind,
+                    dir: DccDir::Incoming,
+                    status: DccStatus::Offered,
+                    bytes: 0,
+                };
+                inner.transfers.push(Transfer {
+                    info: info.clone(),
+                    addr: Some((offer.ip, offer.port)),
+                    token: offer.token,
+                    path: None,
+                    snd_cancel: None,
+                    snd_chat: None,
+                });
+                let _ = inner.snd_ev.try_send(Event::DccOffer(info));
+            }
+        }
+    }
+
+    /// Handle `DCC REJECT <type> <arg>` reply to one of our offers.
+    fn handle_reject(&self, nick: &str, args: &str) {
+        let mut inner = utils::lock(&self.inner);
+        let DccInner {
+            ref mut transfers,
+            ref mut snd_ev,
+            ..
+        } = *inner;
+        let rejected = transfers.iter_mut().find(|t| {
+            t.info.dir == DccDir::Outgoing
+                && t.info.status == DccStatus::Offered
+                && t.info.nick == nick
+                && match t.info.kind {
+                    DccKind::Chat => args.starts_with("CHAT"),
+                    DccKind::Send { ref file_name, .. } => {
+                        args.starts_with("SEND") && args[4..].trim().trim_matches('"') == file_name
+                    }
+                }
+        });
+        if let Some(transfer) = rejected {
+            transfer.info.status = DccStatus::Rejected;
+            transfer.snd_cancel = None;
+            let _ = snd_ev.try_send(Event::DccFailed {
+                id: transfer.info.id,
+                reason: "Rejected".to_owned(),
+            });
+        }
+    }
+
+    /// Accept an incoming offer. For passive offers our address is sent to the other side using
+    /// `snd_cmd`.
+    pub(crate) fn accept(&self, id: DccId, snd_cmd: mpsc::Sender<Cmd>) -> Result<(), DccError> {
+        let conn = {
+            let mut inner = utils::lock(&self.inner);
+            let download_dir = inner.download_dir.clone();
+            let local_ip = inner.local_ip;
+            let transfer = inner.find_mut(id)?;
+            if transfer.info.dir != DccDir::Incoming || transfer.info.status != DccStatus::Offered {
+                return Err(DccError::InvalidState(id));
+            }
+            if let DccKind::Send { ref file_name, .. } = transfer.info.kind {
+                match download_dir {
+                    None => {
+                        return Err(DccError::NoDownloadDir);
+                    }
+                    Some(dir) => {
+                        transfer.path = Some(download_path(&dir, file_name));
+                    }
+                }
+            }
+            let (ip, port) = transfer.addr.unwrap();
+            let conn = if port != 0 {
+                Conn::Connect(SocketAddr::new(ip, port))
+            } else {
+                // Passive offer: listen and send our address back
+                Conn::Listen {
+                    nick: transfer.info.nick.clone(),
+                    offer: DccOffer {
+                        kind: transfer.info.kind.clone(),
+                        ip: local_ip.ok_or(DccError::NoLocalAddr)?,
+                        port: 0,
+                        token: transfer.token.clone(),
+                    },
+                    snd_cmd,
+                }
+            };
+            transfer.info.status = DccStatus::Connecting;
+            conn
+        };
+        self.start(id, conn);
+        Ok(())
+    }
+
+    /// Reject an incoming offer. Returns a message to send to the server to let the other side
+    /// know.
+    pub(crate) fn reject(&self, id: DccId) -> Result<String, DccError> {
+        let mut inner = utils::lock(&self.inner);
+        let transfer = inner.find_mut(id)?;
+        if transfer.info.dir != DccDir::Incoming || transfer.info.status != DccStatus::Offered {
+            return Err(DccError::InvalidState(id));
+        }
+        transfer.info.status = DccStatus::Rejected;
+        let reject_args = match transfer.info.kind {
+            DccKind::Chat => "REJECT CHAT chat".to_owned(),
+            DccKind::Send { ref file_name, .. } => format!("REJECT SEND {}", file_name),
+        };
+        Ok(wire::ctcp_reply(&transfer.info.nick, "DCC", &reject_args))
+    }
+
+    /// Cancel an offer, chat, or transfer.
+    pub(crate) fn cancel(&self, id: DccId) -> Result<(), DccError> {
+        let mut inner = utils::lock(&self.inner);
+        let transfer = inner.find_mut(id)?;
+        match transfer.info.status {
+            DccStatus::Offered | DccStatus::Connecting | DccStatus::Active => {
+                transfer.info.status = DccStatus::Cancelled;
+                // Dropping the sender cancels the task
+                transfer.snd_cancel = None;
+                transfer.snd_chat = None;
+                Ok(())
+            }
+            _ => Err(DccError::InvalidState(id)),
+        }
+    }
+
+    /// Offer a file to `nick`. The offer is sent using `snd_cmd`.
+    pub(crate) fn send_file(
+        &self,
+        nick: &str,
+        path: &Path,
+        passive: bool,
+        snd_cmd: mpsc::Sender<Cmd>,
+    ) -> Result<DccId, DccError> {
+        let file_name = path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("dcc_file")
+            .to_owned();
+        // Size is added after reading the file metadata in a task, to avoid blocking
+        let kind = DccKind::Send {
+            file_name: file_name.clone(),
+            size: None,
+        };
+        let (id, mut offer) = self.new_offer(nick, kind, Some(path.to_owned()), passive)?;
+
+        let dcc = self.clone();
+        let nick = nick.to_owned();
+        let path = path.to_owned();
+        tokio::spawn(async move {
+            match tokio::fs::metadata(&path).await {
+                Err(err) => {
+                    dcc.set_status(id, DccStatus::Failed);
+                    let _ = dcc
+                        .snd_ev()
+                        .send(Event::DccFailed {
+                            id,
+                            reason: err.to_string(),
+                        })
+                        .await;
+                }
+                Ok(metadata) => {
+                    offer.kind = DccKind::Send {
+                        file_name,
+                        size: Some(metadata.len()),
+                    };
+                    // Offer may be cancelled in the meantime
+                    let offered = match utils::lock(&dcc.inner).find_mut(id) {
+                        Ok(transfer) if transfer.info.status == DccStatus::Offered => {
+                            transfer.info.kind = offer.kind.clone();
+                            true
+                        }
+                        _ => false,
+                    };
+                    if offered {
+                        dcc.send_offer(id, &nick, offer, passive, snd_cmd);
+                    }
+                }
+            }
+        });
+        Ok(id)
+    }
+
+    /// Offer a chat to `nick`. The offer is sent using `snd_cmd`.
+    pub(crate) fn chat(
+        &self,
+        nick: &str,
+        passive: bool,
+        snd_cmd: mpsc::Sender<Cmd>,
+    ) -> Result<DccId, DccError> {
+        self.offer(nick, DccKind::Chat, None, passive, snd_cmd)
+    }
+
+    /// Send a line in a chat.
+    pub(crate) fn chat_msg(&self, id: DccId, msg: &str) -> Result<(), DccError> {
+        let mut inner = utils::lock(&self.inner);
+        let transfer = inner.find_mut(id)?;
+        match transfer.snd_chat {
+            Some(ref mut snd_chat) if transfer.info.status == DccStatus::Active => {
+                let _ = snd_chat.try_send(msg.to_owned());
+                Ok(())
+            }
+            _ => Err(DccError::InvalidState(id)),
+        }
+    }
+
+    fn offer(
+        &self,
+        nick: &str,
+        kind: DccKind,
+        path: Option<PathBuf>,
+        passive: bool,
+        snd_cmd: mpsc::Sender<Cmd>,
+    ) -> Result<DccId, DccError> {
+        let (id, offer) = self.new_offer(nick, kind, path, passive)?;
+        self.send_offer(id, nick, offer, passive, snd_cmd);
+        Ok(id)
+    }
+
+    /// Add an outgoing offer. Returns the offer to send with `send_offer`.
+    fn new_offer(
+        &self,
+        nick: &str,
+        kind: DccKind,
+        path: Option<PathBuf>,
+        passive: bool,
+    ) -> Result<(DccId, DccOffer), DccError> {
+        let mut inner = utils::lock(&self.inner);
+        let local_ip = inner.local_ip.ok_or(DccError::NoLocalAddr)?;
+        let id = inner.next_id;
+        inner.next_id += 1;
+        // In passive offers the other side listens and sends its address with this token
+        let token = if passive { Some(id.to_string()) } else { None };
+        inner.transfers.push(Transfer {
+            info: DccInfo {
+                id,
+                nick: nick.to_owned(),
+                kind: kind.clone(),
+                dir: DccDir::Outgoing,
+                status: DccStatus::Offered,
+                bytes: 0,
+            },
+            addr: None,
+            token: token.clone(),
+            path,
+            snd_cancel: None,
+            snd_chat: None,
+        });
+        let ip = if passive {
+            IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0))
+        } else {
+            local_ip
+        };
+        Ok((
+            id,
+            DccOffer {
+                kind,
+                ip,
+                port: 0,
+                token,
+            },
+        ))
+    }
+
+    /// Send an offer created with `new_offer`. In passive offers the other side listens, otherwise
+    /// we listen and send the offer with our port.
+    fn send_offer(
+        &self,
+        id: DccId,
+        nick: &str,
+        offer: DccOffer,
+        passive: bool,
+        mut snd_cmd: mpsc::Sender<Cmd>,
+    ) {
+        if passive {
+            let _ = snd_cmd.try_send(Cmd::Msg(wire::ctcp_request(
+                nick,
+                "DCC",
+                Some(&offer.to_ctcp_args()),
+            )));
+        } else {
+            self.start(
+                id,
+                Conn::Listen {
+                    nick: nick.to_owned(),
+                    offer,
+                    snd_cmd,
+                },
+            );
+        }
+    }
+
+    /// Spawn the task for a chat or transfer.
+    fn start(&self, id: DccId, conn: Conn) {
+        let (snd_cancel, rcv_cancel) = mpsc::channel(1);
+        if let Ok(transfer) = utils::lock(&self.inner).find_mut(id) {
+            transfer.snd_cancel = Some(snd_cancel);
+        }
+        let dcc = WeakDcc {
+            inner: Arc::downgrade(&self.inner),
+        };
+        tokio::spawn(dcc_task(dcc, id, conn, rcv_cancel));
+    }
+
+    fn set_status(&self, id: DccId, status: DccStatus) {
+        if let Ok(transfer) = utils::lock(&self.inner).find_mut(id) {
+            transfer.info.status = status;
+            if status != DccStatus::Active {
+                transfer.snd_cancel = None;
+                transfer.snd_chat = None;
+            }
+        }
+    }
+
+    fn set_bytes(&self, id: DccId, bytes: u64) {
+        if let Ok(transfer) = utils::lock(&self.inner).find_mut(id) {
+            transfer.info.bytes = bytes;
+        }
+    }
+
+    fn snd_ev(&self) -> mpsc::Sender<Event> {
+        utils::lock(&self.inner).snd_ev.clone()
+    }
+}
+
+impl WeakDcc {
+    fn upgrade(&self) -> Option<Dcc> {
+        self.inner.upgrade().map(|inner| Dcc { inner })
+    }
+
+    fn set_status(&self, id: DccId, status: DccStatus) {
+        if let Some(dcc) = self.upgrade() {
+            dcc.set_status(id, status);
+        }
+    }
+
+    fn set_bytes(&self, id: DccId, bytes: u64) {
+        if let Some(dcc) = self.upgrade() {
+            dcc.set_bytes(id, bytes);
+        }
+    }
+}
+
+impl DccInner {
+    fn find_mut(&mut self, id: DccId) -> Result<&mut Transfer, DccError> {
+        self.transfers
+            .iter_mut()
+            .find(|t| t.info.id == id)
+            .ok_or(DccError::NoSuchId(id))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// Tasks
+
+async fn dcc_task(dcc: WeakDcc, id: DccId, conn: Conn, rcv_cancel: mpsc::Receiver<()>) {
+    let mut rcv_cancel = rcv_cancel.fuse();
+    let mut snd_ev = match dcc.upgrade() {
+        None => {
+            return;
+        }
+        Some(dcc) => dcc.snd_ev(),
+    };
+
+    let run = run_dcc(&dcc, id, conn).fuse();
+    pin_mut!(run);
+
+    select! {
+        ret = run => {
+            match ret {
+                Ok(path) => {
+                    dcc.set_status(id, DccStatus::Done);
+                    let _ = snd_ev.send(Event::DccDone { id, path }).await;
+                }
+                Err(err) => {
+                    dcc.set_status(id, DccStatus::Failed);
+                    let _ = snd_ev
+                        .send(Event::DccFailed {
+                            id,
+                            reason: err.to_string(),
+                        })
+                        .await;
+                }
+            }
+        }
+        _ = rcv_cancel.next() => {
+            // Either cancelled by the user (status is updated by `Dcc::cancel`), or the client is
+            // dropped and `snd_cancel` is dropped with it.
+        }
+    }
+}
+
+/// Establish connection and run the chat or transfer. Returns path of the received file for
+/// incoming transfers.
+async fn run_dcc(dcc: &WeakDcc, id: DccId, conn: Conn) -> io::Result<Option<PathBuf>> {
+    let stream = match conn {
+        Conn::Connect(addr) => TcpStream::connect(addr).await?,
+        Conn::Listen {
+            nick,
+            mut offer,
+            mut snd_cmd,
+        } => {
+            let mut listener = TcpListener::bind(SocketAddr::new(offer.ip, 0)).await?;
+            offer.port = listener.local_addr()?.port();
+            let _ = snd_cmd.try_send(Cmd::Msg(wire::ctcp_request(
+                &nick,
+                "DCC",
+                Some(&offer.to_ctcp_args()),
+            )));
+
+            let accept = listener.accept().fuse();
+            let timeout = async {
+                tokio::time::delay_for(CONNECT_TIMEOUT).await;
+            }
+            .fuse();
+            pin_mut!(accept);
+            pin_mut!(timeout);
+            select! {
+                ret = accept => ret?.0,
+                () = timeout => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "Timed out waiting for connection",
+                    ));
+                }
+            }
+        }
+    };
+
+    let (kind, dir, path, nick, mut snd_ev) = {
+        let dcc = dcc
+            .upgrade()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "Client closed"))?;
+        let mut inner = utils::lock(&dcc.inner);
+        let snd_ev = inner.snd_ev.clone();
+        let transfer = inner.find_mut(id).unwrap();
+        transfer.info.status = DccStatus::Active;
+        (
+            transfer.info.kind.clone(),
+            transfer.info.dir,
+            transfer.path.clone(),
+            transfer.info.nick.clone(),
+            snd_ev,
+        )
+    };
+
+    let _ = snd_ev.send(Event::DccConnected(id)).await;
+
+    let mut progress = Progress::new(match kind {
+        DccKind::Send { size, .. } => size,
+        DccKind::Chat => None,
+    });
+    let mut snd_ev_ = snd_ev.clone();
+    let on_progress = |bytes: u64| {
+        dcc.set_bytes(id, bytes);
+        if progress.update(bytes) {
+            let _ = snd_ev_.try_send(Event::DccProgress {
+                id,
+                bytes,
+                total: progress.total,
+            });
+        }
+    };
+
+    match (kind, dir) {
+        (DccKind::Send { size, .. }, DccDir::Incoming) => {
+            let (file, path) = create_download_file(&path.unwrap()).await?;
+            receive_file(stream, file, size, on_progress).await?;
+            Ok(Some(path))
+        }
+        (DccKind::Send { .. }, DccDir::Outgoing) => {
+            send_file(stream, &path.unwrap(), on_progress).await?;
+            Ok(None)
+        }
+        (DccKind::Chat, _) => {
+            let (snd_chat, rcv_chat) = mpsc::channel(100);
+            if let Some(dcc) = dcc.upgrade() {
+                if let Ok(transfer) = utils::lock(&dcc.inner).find_mut(id) {
+                    transfer.snd_chat = Some(snd_chat);
+                }
+            }
+            run_chat(stream, id, &nick, rcv_chat, &mut snd_ev).await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Decides when to report progress: every 10% when size is known, every MiB otherwise.
+struct Progress {
+    total: Option<u64>,
+    last_reported: u64,
+}
+
+impl Progress {
+    fn new(total: Option<u64>) -> Progress {
+        Progress {
+            total,
+            last_reported: 0,
+        }
+    }
+
+    fn update(&mut self, bytes: u64) -> bool {
+        let report = match self.total {
+            Some(total) if total > 0 => {
+                bytes * 10 / total > self.last_reported * 10 / total && bytes != total
+            }
+            _ => bytes / (1024 * 1024) > self.last_reported / (1024 * 1024),
+        };
+        if report {
+            self.last_reported = bytes;
+        }
+        report
+    }
+}
+
+async fn receive_file<S, W, F>(
+    mut stream: S,
+    mut file: W,
+    size: Option<u64>,
+    mut on_progress: F,
+) -> io::Result<u64>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    W: AsyncWrite + Unpin,
+    F: FnMut(u64),
+{
+    let mut buf = [0u8; 8192];
+    let mut bytes: u64 = 0;
+
+    // Only read up to the advertised size, otherwise the sender can fill the disk
+    loop {
+        let max = match size {
+            Some(size) if bytes >= size => break,
+            Some(size) => std::cmp::min(buf.len() as u64, size - bytes) as usize,
+            None => buf.len(),
+        };
+        let n = stream.read(&mut buf[..max]).await?;
+        if n == 0 {
+            break;
+        }
+        file.write_all(&buf[..n]).await?;
+        bytes += n as u64;
+        // Acknowledge received bytes as a 32-bit network byte order integer. Acks are not really
+        // needed with TCP but some old clients wait for them.
+        stream.write_all(&(bytes as u32).to_be_bytes()).await?;
+        on_progress(bytes);
+    }
+    // Wait for the last write to complete
+    file.flush().await?;
+
+    match size {
+        Some(size) if bytes < size => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Connection closed before the transfer is completed",
+        )),
+        // The sender should wait for the last ack and close the connection. Check data that
+        // arrived after the file, without waiting for more.
+        Some(_) => match stream.read(&mut buf).now_or_never() {
+            Some(Ok(n)) if n != 0 => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Received more data than the file size",
+            )),
+            _ => Ok(bytes),
+        },
+        None => Ok(bytes),
+    }
+}
+
+async fn send_file<S, F>(stream: S, path: &Path, mut on_progress: F) -> io::Result<u64>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    F: FnMut(u64),
+{
+    let mut file = File::open(path).await?;
+    let size = file.metadata().await?.len();
+    let (mut read_half, mut write_half) = tokio::io::split(stream);
+
+    let write = async {
+        let mut buf = [0u8; 8192];
+        let mut bytes: u64 = 0;
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            write_half.write_all(&buf[..n]).await?;
+            bytes += n as u64;
+            on_progress(bytes);
+        }
+        Ok::<u64, io::Error>(bytes)
+    };
+
+    // Read acks concurrently, otherwise the receiver may block when sending acks. Done when the
+    // whole file is acknowledged or the receiver closes the connection.
+    let read_acks = async {
+        let mut ack = [0u8; 4];
+        while read_half.read_exact(&mut ack).await.is_ok() {
+            if u32::from_be_bytes(ack) == size as u32 {
+                break;
+            }
+        }
+    };
+
+    let (ret, ()) = futures::future::join(write, read_acks).await;
+    ret
+}
+
+async fn run_chat<S>(
+    stream: S,
+    id: DccId,
+    nick: &str,
+    rcv_chat: mpsc::Receiver<String>,
+    snd_ev: &mut mpsc::Sender<Event>,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut read_half, mut write_half) = tokio::io::split(stream);
+    let mut rcv_chat = rcv_chat.fuse();
+    let mut line_buf: Vec<u8> = vec![];
+
+    loop {
+        let mut read_buf = [0u8; 1024];
+        select! {
+            bytes = read_half.read(&mut read_buf).fuse() => {
+                let bytes = bytes?;
+                if bytes == 0 {
+                    return Ok(());
+                }
+                line_buf.extend_from_slice(&read_buf[..bytes]);
+                while let Some(idx) = wire::find_byte(&line_buf, b'\n') {
+                    let line: Vec<u8> = line_buf.drain(..=idx).collect();
+                    let msg = String::from_utf8_lossy(&line);
+                    let msg = msg.trim_end_matches(&['\r', '\n'][..]);
+                    let _ = snd_ev
+                        .send(Event::DccChatMsg {
+                            id,
+                            nick: nick.to_owned(),
+                            msg: msg.to_owned(),
+                        })
+                        .await;
+                }
+            }
+            msg = rcv_chat.next() => {
+                match msg {
+                    None => {
+                        return Ok(());
+                    }
+                    Some(msg) => {
+                        write_half.write_all(msg.as_bytes()).await?;
+                        write_half.write_all(b"\n").await?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_offer() {
+        assert_eq!(
+            DccOffer::parse("SEND \"my file.txt\" 2130706433 5000 1234"),
+            Some(DccOffer {
+                kind: DccKind::Send {
+                    file_name: "my file.txt".to_owned(),
+                    size: Some(1234),
+                },
+                ip: "127.0.0.1".parse().unwrap(),
+                port: 5000,
+                token: None,
+            })
+        );
+        assert_eq!(
+            DccOffer::parse("SEND file.txt 2130706433 0 1234 42"),
+            Some(DccOffer {
+                kind: DccKind::Send {
+                    file_name: "file.txt".to_owned(),
+                    size: Some(1234),
+                },
+                ip: "127.0.0.1".parse().unwrap(),
+                port: 0,
+                token: Some("42".to_owned()),
+            })
+        );
+        assert_eq!(
+            DccOffer::parse("CHAT chat ::1 5000"),
+            Some(DccOffer {
+                kind: DccKind::Chat,
+                ip: "::1".parse().unwrap(),
+                port: 5000,
+                token: None,
+            })
+        );
+        assert_eq!(DccOffer::parse("SEND file.txt"), None);
+        // Truncated offers
+        assert_eq!(DccOffer::parse("CHAT"), None);
+        assert_eq!(DccOffer::parse("CHAT chat"), None);
+        assert_eq!(DccOffer::parse("SEND"), None);
+        assert_eq!(DccOffer::parse(""), None);
+        assert_eq!(DccOffer::parse("SEND file.txt 1.2.3 5000"), None);
+        assert_eq!(DccOffer::parse("RESUME file.txt 5000 0"), None);
+    }
+
+    #[test]
+    fn test_offer_roundtrip() {
+        let offers = vec![
+            DccOffer {
+                kind: DccKind::Send {
+                    file_name: "my file.txt".to_owned(),
+                    size: Some(1234),
+                },
+                ip: "10.0.0.1".parse().unwrap(),
+                port: 0,
+                token: Some("3".to_owned()),
+            },
+            DccOffer {
+                kind: DccKind::Chat,
+                ip: "::1".parse().unwrap(),
+                port: 1234,
+                token: None,
+            },
+        ];
+        for offer in offers {
+            assert_eq!(DccOffer::parse(&offer.to_ctcp_args()), Some(offer));
+        }
+    }
+
+    #[test]
+    fn test_max_pending_offers() {
+        let (snd_ev, _rcv_ev) = mpsc::channel(100);
+        let dcc = Dcc::new(None, snd_ev);
+        for _ in 0..MAX_PENDING_OFFERS + 5 {
+            dcc.handle_ctcp("bob", "CHAT chat 2130706433 5000");
+        }
+        assert_eq!(dcc.list().len(), MAX_PENDING_OFFERS);
+        // Rejecting an offer makes room for a new one
+        dcc.reject(0).unwrap();
+        dcc.handle_ctcp("bob", "CHAT chat 2130706433 5000");
+        assert_eq!(dcc.list().len(), MAX_PENDING_OFFERS + 1);
+    }
+
+    #[test]
+    fn test_download_path() {
+        let dir = Path::new("/nonexistent_dir");
+        assert_eq!(
+            download_path(dir, "file.txt"),
+            PathBuf::from("/nonexistent_dir/file.txt")
+        );
+        assert_eq!(
+            download_path(dir, "../../.bashrc"),
+            PathBuf::from("/nonexistent_dir/bashrc")
+        );
+        assert_eq!(
+            download_path(dir, "..\\..\\x.exe"),
+            PathBuf::from("/nonexistent_dir/x.exe")
+        );
+        assert_eq!(
+            download_path(dir, ".."),
+            PathBuf::from("/nonexistent_dir/dcc_file")
+        );
+    }
+
+    #[test]
+    fn test_progress() {
+        let mut progress = Progress::new(Some(100));
+        assert!(!progress.update(5));
+        assert!(progress.update(10));
+        assert!(!progress.update(15));
+        assert!(progress.update(35));
+        assert!(!progress.update(100));
+    }
+
+    #[test]
+    fn test_file_transfer() {
+        let dir = std::env::temp_dir().join(format!("tiny_dcc_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src_path = dir.join("src");
+        let dst_path = dir.join("dst");
+        let contents: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        std::fs::write(&src_path, &contents).unwrap();
+
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let (accepted, connected) =
+                futures::future::join(listener.accept(), TcpStream::connect(addr)).await;
+            let (sender, _) = accepted.unwrap();
+            let receiver = connected.unwrap();
+
+            let (dst_file, new_dst_path) = create_download_file(&dst_path).await.unwrap();
+            assert_eq!(new_dst_path, dst_path);
+            let (sent, received) = futures::future::join(
+                send_file(sender, &src_path, |_| {}),
+                receive_file(receiver, dst_file, Some(contents.len() as u64), |_| {}),
+            )
+            .await;
+            assert_eq!(sent.unwrap(), contents.len() as u64);
+            assert_eq!(received.unwrap(), contents.len() as u64);
+        });
+
+        assert_eq!(std::fs::read(&dst_path).unwrap(), contents);
+
+        // Existing files are not overwritten
+        runtime.block_on(async {
+            let (_, path) = create_download_file(&dst_path).await.unwrap();
+            assert_eq!(path, dir.join("dst.1"));
+            let (_, path) = create_download_file(&dst_path).await.unwrap();
+            assert_eq!(path, dir.join("dst.2"));
+        });
+        assert_eq!(std::fs::read(&dst_path).unwrap(), contents);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_receive_file_extra_data() {
+        let mut received = vec![];
+
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let (accepted, connected) =
+                futures::future::join(listener.accept(), TcpStream::connect(addr)).await;
+            let (mut sender, _) = accepted.unwrap();
+            let receiver = connected.unwrap();
+
+            // Advertise 100 bytes, send 110
+            sender.write_all(&[0u8; 110]).await.unwrap();
+            let ret = receive_file(receiver, &mut received, Some(100), |_| {}).await;
+            assert_eq!(ret.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        });
+
+        assert_eq!(received.len(), 100);
+    }
+}
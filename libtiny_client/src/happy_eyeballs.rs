@@ -0,0 +1,192 @@
+//! Connecting to a server with multiple IP addresses. Addresses of the two IP versions are
+//! interleaved and connection attempts are raced, as described in RFC 8305 ("Happy Eyeballs"),
+//! so that a broken IPv6 (or IPv4) route doesn't delay the connection until a timeout.
+
+use crate::Event;
+
+use futures::future::FutureExt;
+use futures::stream::{FuturesUnordered, StreamExt};
+use futures::{pin_mut, select};
+use net2::TcpBuilder;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+/// Which IP versions to use when connecting to a server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpPref {
+    /// Use both, start with the version of the first address returned by the resolver.
+    Any,
+    /// Use both, start with IPv4.
+    PreferV4,
+    /// Use both, start with IPv6.
+    PreferV6,
+    /// Only connect to IPv4 addresses.
+    OnlyV4,
+    /// Only connect to IPv6 addresses.
+    OnlyV6,
+}
+
+/// How long to wait for a connection attempt before starting the next one in parallel. RFC 8305
+/// recommends 250 ms.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Order resolved addresses for connection attempts: addresses of other IP versions than allowed
+/// by `pref` (or than the version of `bind_addr`, as we can't connect to those from that address)
+/// are removed, the rest are interleaved, starting with the preferred version.
+pub(crate) fn sort_addrs(
+    addrs: Vec<SocketAddr>,
+    pref: IpPref,
+    bind_addr: Option<IpAddr>,
+) -> Vec<SocketAddr> {
+    let addrs: Vec<SocketAddr> = addrs
+        .into_iter()
+        .filter(|addr| match pref {
+            IpPref::OnlyV4 => addr.is_ipv4(),
+            IpPref::OnlyV6 => addr.is_ipv6(),
+            IpPref::Any | IpPref::PreferV4 | IpPref::PreferV6 => true,
+        })
+        .filter(|addr| match bind_addr {
+            None => true,
+            Some(bind_addr) => bind_addr.is_ipv4() == addr.is_ipv4(),
+        })
+        .collect();
+
+    let v4_first = match pref {
+        IpPref::PreferV4 | IpPref::OnlyV4 => true,
+        IpPref::PreferV6 | IpPref::OnlyV6 => false,
+        IpPref::Any => addrs.first().map(SocketAddr::is_ipv4).unwrap_or(true),
+    };
+
+    let n_addrs = addrs.len();
+    let (v4, v6): (Vec<SocketAddr>, Vec<SocketAddr>) =
+        addrs.into_iter().partition(SocketAddr::is_ipv4);
+    let (mut first, mut second) = if v4_first {
+        (v4.into_iter(), v6.into_iter())
+    } else {
+        (v6.into_iter(), v4.into_iter())
+    };
+
+    let mut ret = Vec::with_capacity(n_addrs);
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => {
+                break;
+            }
+            (addr1, addr2) => {
+                ret.extend(addr1);
+                ret.extend(addr2);
+            }
+        }
+    }
+    ret
+}
+
+/// Connect to one of the addresses, trying them in order. A new attempt is started when the
+/// previous one fails or doesn't complete in `CONNECTION_ATTEMPT_DELAY`, without cancelling the
+/// attempts in progress. The first established connection is returned, others are dropped.
+/// Attempts are reported as `Event::Connecting` when started, and as `Event::IoErr` when failed.
+pub(crate) async fn connect(
+    addrs: Vec<SocketAddr>,
+    bind_addr: Option<IpAddr>,
+    snd_ev: &mut mpsc::Sender<Event>,
+) -> Option<TcpStream> {
+    let mut addrs = addrs.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut start_next = true;
+
+    loop {
+        if start_next {
+            match addrs.next() {
+                Some(addr) => {
+                    debug!("Trying {:?}", addr);
+                    snd_ev.send(Event::Connecting(addr)).await.unwrap();
+                    attempts.push(connect_addr(addr, bind_addr).map(move |ret| (addr, ret)));
+                }
+                None => {
+                    if attempts.is_empty() {
+                        return None;
+                    }
+                }
+            }
+        }
+
+        let delay = tokio::time::delay_for(CONNECTION_ATTEMPT_DELAY).fuse();
+        pin_mut!(delay);
+
+        select! {
+            () = delay => {
+                start_next = true;
+            }
+            attempt = attempts.select_next_some() => {
+                let (addr, ret) = attempt;
+                match ret {
+                    Ok(stream) => {
+                        return Some(stream);
+                    }
+                    Err(io_err) => {
+                        debug!("Can't connect to {:?}: {:?}", addr, io_err);
+                        snd_ev.send(Event::IoErr(io_err)).await.unwrap();
+                        start_next = true;
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn connect_addr(addr: SocketAddr, bind_addr: Option<IpAddr>) -> std::io::Result<TcpStream> {
+    match bind_addr {
+        None => TcpStream::connect(addr).await,
+        Some(bind_addr) => {
+            let builder = if addr.is_ipv4() {
+                TcpBuilder::new_v4()?
+            } else {
+                TcpBuilder::new_v6()?
+            };
+            builder.bind(SocketAddr::new(bind_addr, 0))?;
+            let std_stream = builder.to_tcp_stream()?;
+            TcpStream::connect_std(std_stream, &addr).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(addrs: &[&str]) -> Vec<SocketAddr> {
+        addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_sort_addrs() {
+        let resolved = addrs(&["[::1]:6667", "[::2]:6667", "[::3]:6667", "1.1.1.1:6667"]);
+
+        assert_eq!(
+            sort_addrs(resolved.clone(), IpPref::Any, None),
+            addrs(&["[::1]:6667", "1.1.1.1:6667", "[::2]:6667", "[::3]:6667"])
+        );
+        assert_eq!(
+            sort_addrs(resolved.clone(), IpPref::PreferV4, None),
+            addrs(&["1.1.1.1:6667", "[::1]:6667", "[::2]:6667", "[::3]:6667"])
+        );
+        assert_eq!(
+            sort_addrs(resolved.clone(), IpPref::OnlyV4, None),
+            addrs(&["1.1.1.1:6667"])
+        );
+        assert_eq!(
+            sort_addrs(
+                resolved.clone(),
+                IpPref::PreferV4,
+                Some("::5".parse().unwrap())
+            ),
+            addrs(&["[::1]:6667", "[::2]:6667", "[::3]:6667"])
+        );
+        assert_eq!(
+            sort_addrs(resolved, IpPref::OnlyV6, Some("1.2.3.4".parse().unwrap())),
+            vec![]
+        );
+    }
+}
@@ -1,27 +1,48 @@
-#![recursion_limit = "512"]
+#![recursion_limit = "1024"]
 #![feature(test)]
 #![allow(clippy::unneeded_field_pattern)]
 #![allow(clippy::cognitive_complexity)]
 
+mod chan_list;
+mod ctcp;
+mod dcc;
+mod happy_eyeballs;
+#[cfg(test)]
+mod main_loop_tests;
 mod pinger;
+mod record;
 mod state;
 mod stream;
+mod sts;
+mod topics;
+mod users;
 mod utils;
+mod whois;
+mod ws;
 
+pub use dcc::{DccDir, DccError, DccId, DccInfo, DccKind, DccStatus};
+pub use happy_eyeballs::IpPref;
+pub use libtiny_ui::ChanListEntry;
 pub use libtiny_wire as wire;
+pub use topics::Topic;
+pub use users::UserInfo;
+pub use whois::WhoisInfo;
 
+use dcc::Dcc;
 use pinger::Pinger;
+use record::Recorder;
 use state::State;
 use stream::{Stream, StreamError};
+use sts::StsPolicies;
 
 use futures::future::FutureExt;
+use futures::stream::Fuse;
 use futures::stream::StreamExt;
 use futures::{pin_mut, select};
-use futures_util::stream::Fuse;
-use std::net::{SocketAddr, ToSocketAddrs};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::runtime::current_thread::Runtime;
 use tokio::sync::mpsc;
 
 #[macro_use]
@@ -34,17 +55,62 @@ extern crate log;
 /// `Client` tries to reconnect on error after this many seconds.
 pub const RECONNECT_SECS: u64 = 30;
 
+/// `Client` disconnects when the server doesn't complete registration (doesn't send RPL_WELCOME)
+/// in this many seconds after connecting, and then tries to reconnect.
+pub const REGISTRATION_TIMEOUT_SECS: u64 = 60;
+
+/// `Client` moves to the next server address after failing to connect to an address this many
+/// times in a row.
+const MAX_CONNECT_FAILS: usize = 2;
+
+/// `Client` sends a PING when it doesn't receive anything from the server in this many seconds,
+/// and disconnects when it doesn't receive anything in this many seconds after the PING.
+const PING_INTERVAL_SECS: u64 = 60;
+
+/// Durations of the main loop's timers. Only the tests use durations other than the defaults.
+#[derive(Debug, Clone, Copy)]
+struct Timeouts {
+    reconnect: Duration,
+    registration: Duration,
+    ping_interval: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        Timeouts {
+            reconnect: Duration::from_secs(RECONNECT_SECS),
+            registration: Duration::from_secs(REGISTRATION_TIMEOUT_SECS),
+            ping_interval: Duration::from_secs(PING_INTERVAL_SECS),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ServerInfo {
-    /// Server address
-    pub addr: String,
+    /// Network name. This is the name returned by `Client::get_serv_name`, so it's used to
+    /// identify the connection (e.g. in tabs and log files) independently of the server address.
+    pub name: String,
 
-    /// Server port
-    pub port: u16,
+    /// Server addresses, tried in order. The client moves to the next address when it can't
+    /// resolve the current address, or can't connect to it `MAX_CONNECT_FAILS` times in a row.
+    /// When this is empty the client sends `Event::CantResolveAddr` and stops.
+    pub addrs: Vec<ServerAddr>,
 
-    /// Use TLS?
+    /// Use TLS? Even when this is `false` the client uses TLS for servers with a Strict
+    /// Transport Security (STS) policy.
     pub tls: bool,
 
+    /// File to persist STS policies in. Policies are only kept until the client stops when this
+    /// is `None`.
+    pub sts_file: Option<PathBuf>,
+
+    /// Local address to connect from, e.g. to use a vhost. Only server addresses of the same IP
+    /// version are used when set.
+    pub bind_addr: Option<IpAddr>,
+
+    /// Which IP versions to use, and which one to try first.
+    pub ip_pref: IpPref,
+
     /// Server password.
     pub pass: Option<String>,
 
@@ -56,12 +122,41 @@ pub struct ServerInfo {
     /// Channels to automatically join
     pub auto_join: Vec<String>,
 
+    /// Users to accept invites from: when one of these users invites us to a channel the channel
+    /// is joined automatically. Entries with a `!` are `nick!user@host` masks, with `*` and `?`
+    /// wildcards. Other entries are services account names, matched against the inviter's account
+    /// when known (from WHOX or `extended-join`). Nicks alone are not enough as anyone can take a
+    /// nick. Entries are compared case-insensitively.
+    pub join_invites_from: Vec<String>,
+
     /// Nickserv password. Sent to NickServ on connecting to the server and nick change, before
     /// join commands.
     pub nickserv_ident: Option<String>,
 
     /// SASL authentication credentials,
     pub sasl_auth: Option<SASLAuth>,
+
+    /// Reply to CTCP requests (VERSION, PING, TIME, CLIENTINFO, SOURCE, USERINFO)?
+    pub ctcp_replies: bool,
+
+    /// Where to save files received via DCC. Incoming DCC file offers can't be accepted when this
+    /// is `None`.
+    pub dcc_download_dir: Option<PathBuf>,
+
+    /// Append raw messages sent to and received from the server to this file, for debugging.
+    /// Passwords in sent messages are redacted. Recordings can be replayed with
+    /// `Client::replay`.
+    pub record_file: Option<PathBuf>,
+}
+
+/// Host name (or IP address) and port of a server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerAddr {
+    pub host: String,
+    pub port: u16,
+    /// WebSocket resource path, e.g. `/webirc`. When set the client connects via WebSocket
+    /// (over TLS when `ServerInfo::tls` is set), using the IRCv3 WebSocket subprotocols.
+    pub ws_path: Option<String>,
 }
 
 /// SASL authentication credentials
@@ -71,6 +166,33 @@ pub struct SASLAuth {
     pub password: String,
 }
 
+/// Errors during connection registration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistrationError {
+    /// 464 ERR_PASSWDMISMATCH: server password is wrong or missing.
+    PasswdMismatch(String),
+    /// 465 ERR_YOUREBANNEDCREEP: banned from the server, e.g. K-lined.
+    Banned(String),
+    /// 432 ERR_ERRONEUSNICKNAME for the last nick in `ServerInfo::nicks`. Other nicks in the list
+    /// are tried before this error.
+    ErroneousNickname { nick: String, msg: String },
+    /// 904 ERR_SASLFAIL, or the server doesn't advertise the `sasl` capability. Registration
+    /// continues without authentication.
+    SaslFail(String),
+}
+
+impl RegistrationError {
+    /// Reconnecting won't fix fatal errors, so the client stops after these.
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            RegistrationError::SaslFail(_) => false,
+            RegistrationError::PasswdMismatch(_)
+            | RegistrationError::Banned(_)
+            | RegistrationError::ErroneousNickname { .. } => true,
+        }
+    }
+}
+
 /// IRC client events. Returned by `Client` to the users via a channel.
 ///
 /// Note that Client only returns when it can't resolve the domain name. In all other cases (no
@@ -79,12 +201,23 @@ pub struct SASLAuth {
 /// The latter happens after sending a `Disconnected` event.
 #[derive(Debug)]
 pub enum Event {
-    /// Client resolving domain name
-    ResolvingHost,
-    /// Domain name resolved, client trying to connect to the given IP address
+    /// Client resolving the given domain name
+    ResolvingHost(String),
+    /// Domain name resolved, connecting to the given IP address. When the domain has multiple
+    /// addresses connection attempts are raced, this is sent for each attempt as it starts.
     Connecting(SocketAddr),
     /// TCP connection established *and* the introduction sequence with the IRC server started.
     Connected,
+    /// Server didn't complete registration in `REGISTRATION_TIMEOUT_SECS` seconds. Followed by a
+    /// `Disconnected`.
+    RegistrationTimeout,
+    /// An error during registration. After fatal errors (see `RegistrationError::is_fatal`) the
+    /// client disconnects (and sends `Disconnected`), and doesn't reconnect until
+    /// `Client::reconnect` is called.
+    RegistrationError(RegistrationError),
+    /// Server advertised a Strict Transport Security policy over plaintext. The client reconnects
+    /// with TLS to the given port.
+    StsUpgrade(u16),
     /// Disconnected from the server. Usually sent right after an `Event::IoErr`. Client tries to
     /// reconnect after `RECONNECT_SECS` seconds after sending this event.
     Disconnected,
@@ -94,15 +227,54 @@ pub enum Event {
     TlsErr(native_tls::Error),
     /// Remote end closed the connection
     ConnectionClosed,
-    /// Client couldn't resolve host address. The client stops after sending this event.
+    /// Client couldn't resolve any of the server addresses, or `ServerInfo::addrs` is empty. The
+    /// client stops after sending this event.
     CantResolveAddr,
     /// Nick changed.
     NickChange(String),
-    /// A message from the server
+    /// A message from the server. Replies to requests sent by the client itself (WHO requests
+    /// sent after joining channels) are not sent as events.
     Msg(wire::Msg),
 
-    /// This is to signal the task that listens for events to stop.
-    // TODO: Maybe try something like making Client non-Clone and sharing Weaks with tasks
+    /// A line read from the server, without the "\r\n" suffix. Sent before the `Event::Msg`
+    /// for the line, only when enabled with `Client::set_raw_events`.
+    RawIn(String),
+    /// A line sent to the server, without the "\r\n" suffix. Passwords are redacted. Only sent
+    /// when enabled with `Client::set_raw_events`.
+    RawOut(String),
+
+    /// Result of a WHOIS or WHOWAS query, collected from the replies.
+    Whois(WhoisInfo),
+
+    /// Result of a LIST request sent with `Client::list`.
+    ChanList(Vec<ChanListEntry>),
+
+    /// Received a DCC chat or file offer. Use `Client::dcc_accept` or `Client::dcc_reject` to
+    /// accept or reject it.
+    DccOffer(DccInfo),
+    /// Connection for a DCC chat or transfer established.
+    DccConnected(DccId),
+    /// Progress of a DCC transfer. Sent every 10% of the file when size of the file is known,
+    /// every 1 MiB otherwise.
+    DccProgress {
+        id: DccId,
+        bytes: u64,
+        total: Option<u64>,
+    },
+    /// A line in a DCC chat.
+    DccChatMsg {
+        id: DccId,
+        nick: String,
+        msg: String,
+    },
+    /// DCC transfer completed or chat ended. `path` is where the file is saved, for received
+    /// files.
+    DccDone { id: DccId, path: Option<PathBuf> },
+    /// DCC chat or transfer failed, or our offer is rejected.
+    DccFailed { id: DccId, reason: String },
+
+    /// The client stopped, after `Client::quit` or `Event::CantResolveAddr`. This is the last
+    /// event, the task that listens for events should stop.
     Closed,
 }
 
@@ -129,33 +301,66 @@ pub struct Client {
     /// Reference to the state, to be able to provide methods like `get_nick` and
     /// `is_nick_accepted`.
     state: State,
-    // We can't have a channel to the sender task directly here, because when the sender task
-    // returns we lose the receiving end of the channel and there's no way to avoid this except
-    // with annoying hacks like wrapping it with an `Arc<Mutex<..>>` or something.
-    snd_ev: mpsc::Sender<Event>,
+
+    /// DCC chats and transfers.
+    dcc: Dcc,
 }
 
 impl Client {
-    /// Create a new client. Spawns two `tokio` tasks on the given `runtime`. If not given, tasks
-    /// are created on the default executor using `tokio::spawn`.
-    pub fn new(
-        server_info: ServerInfo,
-        runtime: Option<&mut Runtime>,
-    ) -> (Client, mpsc::Receiver<Event>) {
-        connect(server_info, runtime)
+    /// Create a new client. Tasks of the client are spawned with `tokio::spawn`, so this needs
+    /// to be called in the context of a tokio runtime. Both the basic (single-threaded) and the
+    /// threaded scheduler can be used.
+    ///
+    /// When `server_info.addrs` is empty the client sends `Event::CantResolveAddr` and stops.
+    pub fn new(server_info: ServerInfo) -> (Client, mpsc::Receiver<Event>) {
+        connect(server_info, Timeouts::default())
     }
 
-    /// Reconnect to the server, possibly using a new port.
+    /// Create a client that replays a recording made with `ServerInfo::record_file` instead of
+    /// connecting to a server. Received messages in the recording are processed as if they're
+    /// read from the server and returned as `Event::Msg`s, messages sent with the client are
+    /// dropped. Like `new`, needs to be called in the context of a tokio runtime.
+    pub fn replay(server_info: ServerInfo, recording: String) -> (Client, mpsc::Receiver<Event>) {
+        let serv_name = server_info.name.clone();
+        let (mut snd_ev, rcv_ev) = mpsc::channel::<Event>(100);
+        let (snd_cmd, rcv_cmd) = mpsc::channel::<Cmd>(100);
+        let dcc = Dcc::new(server_info.dcc_download_dir.clone(), snd_ev.clone());
+        let irc_state = State::new(server_info, dcc.clone());
+
+        let task = record::replay_task(recording, irc_state.clone(), snd_ev.clone(), rcv_cmd);
+        tokio::spawn(async move {
+            task.await;
+            let _ = snd_ev.send(Event::Closed).await;
+        });
+
+        (
+            Client {
+                msg_chan: snd_cmd,
+                serv_name,
+                state: irc_state,
+                dcc,
+            },
+            rcv_ev,
+        )
+    }
+
+    /// Reconnect to the server, possibly using a new port for the current server address. Also
+    /// restarts a client stopped after a fatal `RegistrationError`.
     pub fn reconnect(&mut self, port: Option<u16>) {
         debug!("reconnect cmd received, port: {:?}", port);
         self.msg_chan.try_send(Cmd::Reconnect(port)).unwrap()
     }
 
-    /// Get host name of this connection.
+    /// Get network name of this connection. See `ServerInfo::name`.
     pub fn get_serv_name(&self) -> &str {
         &self.serv_name
     }
 
+    /// Get server addresses of this connection. See `ServerInfo::addrs`.
+    pub fn get_serv_addrs(&self) -> Vec<ServerAddr> {
+        self.state.get_serv_addrs()
+    }
+
     /// Get current nick. Not that this returns the nick we're currently trying when the nick is
     /// not yet accepted. See `is_nick_accepted`.
     // FIXME: This allocates a String
@@ -163,6 +368,12 @@ impl Client {
         self.state.get_nick()
     }
 
+    /// Max. number of modes with parameters the server accepts in a single MODE message. Taken
+    /// from the `MODES` ISUPPORT token, defaults to 3.
+    pub fn get_max_modes(&self) -> usize {
+        self.state.get_max_modes()
+    }
+
     /// Is current nick accepted by the server?
     // TODO: Do we really need this?
     pub fn is_nick_accepted(&self) -> bool {
@@ -209,6 +420,13 @@ impl Client {
         utils::split_iterator(msg, max)
     }
 
+    /// Send a notice. Like `privmsg`, long messages should be split with `split_privmsg`.
+    pub fn notice(&mut self, target: &str, msg: &str) {
+        self.msg_chan
+            .try_send(Cmd::Msg(wire::notice(target, msg)))
+            .unwrap();
+    }
+
     /// Send a privmsg. Note that this method does not split long messages into smaller messages;
     /// use `split_privmsg` for that.
     pub fn privmsg(&mut self, target: &str, msg: &str, is_action: bool) {
@@ -222,6 +440,13 @@ impl Client {
             .unwrap();
     }
 
+    /// Send a CTCP request, e.g. `client.ctcp("nick", "VERSION", None)`.
+    pub fn ctcp(&mut self, target: &str, ctcp: &str, args: Option<&str>) {
+        self.msg_chan
+            .try_send(Cmd::Msg(wire::ctcp_request(target, ctcp, args)))
+            .unwrap();
+    }
+
     /// Join the given list of channels.
     pub fn join(&mut self, chans: &[&str]) {
         self.msg_chan
@@ -229,9 +454,11 @@ impl Client {
             .unwrap()
     }
 
-    /// Leave a channel.
-    pub fn part(&mut self, chan: &str) {
-        self.msg_chan.try_send(Cmd::Msg(wire::part(chan))).unwrap();
+    /// Leave a channel, with an optional reason shown to the other users in the channel.
+    pub fn part(&mut self, chan: &str, reason: Option<&str>) {
+        self.msg_chan
+            .try_send(Cmd::Msg(wire::part(chan, reason)))
+            .unwrap();
     }
 
     /// Set away status. `None` means not away.
@@ -248,19 +475,150 @@ impl Client {
             .unwrap()
     }
 
-    /// Send a QUIT message to the server, with optional "reason". This stops the client; so the
-    /// sender end of the `Cmd` channel and the receiver end of the IRC message channel (for
-    /// outgoing messages) will be dropped.
+    /// Send a QUIT message to the server, with optional "reason". This stops the client, and
+    /// `Event::Closed` is sent after the QUIT message is sent.
     pub fn quit(&mut self, reason: Option<String>) {
         debug!("quit cmd received");
         self.msg_chan.try_send(Cmd::Quit(reason)).unwrap();
-        self.snd_ev.try_send(Event::Closed).unwrap();
+    }
+
+    /// Get host, account etc. of a user. Users in channels are populated using WHO (or WHOX)
+    /// after joining, unless the channel is large.
+    pub fn get_user_info(&self, nick: &str) -> Option<UserInfo> {
+        self.state.get_user_info(nick)
+    }
+
+    /// Ignore (or stop ignoring, when already ignored) messages from users with the given host.
+    /// Returns whether the host is now ignored. Ignore list is not used by the client, it's for
+    /// the UI to check with `is_host_ignored`.
+    pub fn toggle_ignore_host(&mut self, host: &str) -> bool {
+        self.state.toggle_ignore_host(host)
+    }
+
+    pub fn is_host_ignored(&self, host: &str) -> bool {
+        self.state.is_host_ignored(host)
+    }
+
+    pub fn get_ignored_hosts(&self) -> Vec<String> {
+        self.state.get_ignored_hosts()
+    }
+
+    /// Enable or disable `Event::RawIn` and `Event::RawOut` events, for debugging.
+    pub fn set_raw_events(&self, enabled: bool) {
+        self.state.set_raw_events(enabled)
+    }
+
+    /// Topic of a joined channel, if it has one.
+    pub fn get_topic(&self, chan: &str) -> Option<Topic> {
+        self.state.get_topic(chan)
+    }
+
+    /// Set the topic of a channel, or request the current topic when `topic` is `None`. The
+    /// server replies with a TOPIC message, or with RPL_TOPIC when requesting the topic.
+    pub fn topic(&mut self, chan: &str, topic: Option<&str>) {
+        self.msg_chan
+            .try_send(Cmd::Msg(wire::topic(chan, topic)))
+            .unwrap();
+    }
+
+    /// Invite a user to a channel.
+    pub fn invite(&mut self, nick: &str, chan: &str) {
+        self.msg_chan
+            .try_send(Cmd::Msg(wire::invite(nick, chan)))
+            .unwrap();
+    }
+
+    /// Kick a user from a channel.
+    pub fn kick(&mut self, chan: &str, nick: &str, reason: Option<&str>) {
+        self.msg_chan
+            .try_send(Cmd::Msg(wire::kick(chan, nick, reason)))
+            .unwrap();
+    }
+
+    /// Send a MODE message. `params` are the mode string and its arguments, e.g.
+    /// `["+b", "*!*@host"]`. Queries the current modes of the target when `params` is empty.
+    pub fn mode(&mut self, target: &str, params: &[&str]) {
+        self.msg_chan
+            .try_send(Cmd::Msg(wire::mode(target, params)))
+            .unwrap();
+    }
+
+    /// Are invites from the given user joined automatically? `user` is the `user@host` part of
+    /// the user's prefix. See `ServerInfo::join_invites_from`.
+    pub fn joins_invites_from(&self, nick: &str, user: &str) -> bool {
+        self.state.joins_invites_from(nick, user)
+    }
+
+    /// Send a WHOIS query. Result is returned as an `Event::Whois`.
+    pub fn whois(&mut self, nick: &str) {
+        self.msg_chan.try_send(Cmd::Msg(wire::whois(nick))).unwrap();
+    }
+
+    /// Send a WHOWAS query. Result is returned as an `Event::Whois`.
+    pub fn whowas(&mut self, nick: &str) {
+        self.msg_chan
+            .try_send(Cmd::Msg(wire::whowas(nick)))
+            .unwrap();
+    }
+
+    /// Request the channel list. Only channels with names matching `pattern` (a glob pattern, or
+    /// a part of the name when it doesn't have `*` or `?`) and with at least `min_users` users are
+    /// returned. Filters are sent to the server when it supports ELIST. Result is returned as an
+    /// `Event::ChanList`.
+    pub fn list(&mut self, pattern: Option<&str>, min_users: Option<u32>) {
+        let msg = self.state.list(pattern, min_users);
+        self.msg_chan.try_send(Cmd::Msg(msg)).unwrap();
     }
 
     /// Get all nicks in a channel.
     pub fn get_chan_nicks(&self, chan: &str) -> Vec<String> {
         self.state.get_chan_nicks(chan)
     }
+
+    /// Offer a file to `nick` via DCC. In a passive offer the other side listens for the
+    /// connection instead of us; useful when we're behind a NAT. The file is read in a task,
+    /// errors when reading the file are reported with `Event::DccFailed`.
+    pub fn dcc_send(&mut self, nick: &str, path: &Path, passive: bool) -> Result<DccId, DccError> {
+        self.dcc
+            .send_file(nick, path, passive, self.msg_chan.clone())
+    }
+
+    /// Offer a DCC chat to `nick`. See `dcc_send` for `passive`.
+    pub fn dcc_chat(&mut self, nick: &str, passive: bool) -> Result<DccId, DccError> {
+        self.dcc.chat(nick, passive, self.msg_chan.clone())
+    }
+
+    /// Accept a DCC offer. Received files are saved to `ServerInfo::dcc_download_dir`.
+    pub fn dcc_accept(&mut self, id: DccId) -> Result<(), DccError> {
+        self.dcc.accept(id, self.msg_chan.clone())
+    }
+
+    /// Reject a DCC offer.
+    pub fn dcc_reject(&mut self, id: DccId) -> Result<(), DccError> {
+        let msg = self.dcc.reject(id)?;
+        self.msg_chan.try_send(Cmd::Msg(msg)).unwrap();
+        Ok(())
+    }
+
+    /// Cancel a DCC offer, chat, or transfer.
+    pub fn dcc_cancel(&mut self, id: DccId) -> Result<(), DccError> {
+        self.dcc.cancel(id)
+    }
+
+    /// Send a line in a DCC chat.
+    pub fn dcc_chat_msg(&mut self, id: DccId, msg: &str) -> Result<(), DccError> {
+        self.dcc.chat_msg(id, msg)
+    }
+
+    /// Get all DCC offers, chats, and transfers.
+    pub fn dcc_list(&self) -> Vec<DccInfo> {
+        self.dcc.list()
+    }
+
+    /// Find the active DCC chat with `nick`.
+    pub fn dcc_find_chat(&self, nick: &str) -> Option<DccId> {
+        self.dcc.find_chat(nick)
+    }
 }
 
 //
@@ -272,18 +630,15 @@ enum Cmd {
     /// Send this IRC message to the server. Note that this needs to be a valid IRC message
     /// (including the trailing "\r\n").
     Msg(String),
-    /// Reconnect to the server, possibly using a new port.
+    /// Reconnect to the server, possibly using a new port for the current server address.
     Reconnect(Option<u16>),
     /// Close the connection. This sends a QUIT message to the server (with optional "reason") and
     /// then all tasks return.
     Quit(Option<String>),
 }
 
-fn connect(
-    server_info: ServerInfo,
-    runtime: Option<&mut Runtime>,
-) -> (Client, mpsc::Receiver<Event>) {
-    let serv_name = server_info.addr.clone();
+fn connect(server_info: ServerInfo, timeouts: Timeouts) -> (Client, mpsc::Receiver<Event>) {
+    let serv_name = server_info.name.clone();
 
     //
     // Create communication channels
@@ -291,7 +646,7 @@ fn connect(
 
     // Channel for returning IRC events to user.
     let (snd_ev, rcv_ev) = mpsc::channel::<Event>(100);
-    let snd_ev_clone = snd_ev.clone(); // for Client
+    let mut snd_ev_clone = snd_ev.clone(); // for `Event::Closed`
 
     // Channel for commands from user.
     let (snd_cmd, rcv_cmd) = mpsc::channel::<Cmd>(100);
@@ -300,26 +655,31 @@ fn connect(
     // Create the main loop task
     //
 
-    let irc_state = State::new(server_info.clone());
+    let dcc = Dcc::new(server_info.dcc_download_dir.clone(), snd_ev.clone());
+    let irc_state = State::new(server_info.clone(), dcc.clone());
     let irc_state_clone = irc_state.clone();
 
-    let task = main_loop(server_info, irc_state_clone, snd_ev, rcv_cmd);
-
-    match runtime {
-        Some(runtime) => {
-            runtime.spawn(task);
-        }
-        None => {
-            tokio::runtime::current_thread::spawn(task);
-        }
-    }
+    let task = main_loop(
+        server_info,
+        timeouts,
+        irc_state_clone,
+        dcc.clone(),
+        snd_ev,
+        rcv_cmd,
+    );
+
+    tokio::spawn(async move {
+        task.await;
+        // Receiver may be dropped already, ignore errors
+        let _ = snd_ev_clone.send(Event::Closed).await;
+    });
 
     (
         Client {
             msg_chan: snd_cmd,
             serv_name,
             state: irc_state,
-            snd_ev: snd_ev_clone,
+            dcc,
         },
         rcv_ev,
     )
@@ -329,30 +689,73 @@ use TaskResult::*;
 
 async fn main_loop(
     server_info: ServerInfo,
+    timeouts: Timeouts,
     irc_state: State,
+    dcc: Dcc,
     mut snd_ev: mpsc::Sender<Event>,
     rcv_cmd: mpsc::Receiver<Cmd>,
 ) {
     let mut rcv_cmd = rcv_cmd.fuse();
 
+    if server_info.addrs.is_empty() {
+        debug!("main loop: no server addresses");
+        snd_ev.send(Event::CantResolveAddr).await.unwrap();
+        return;
+    }
+
     // We allow changing ports when reconnecting, so `mut`
-    let mut port = server_info.port;
+    let mut addrs = server_info.addrs.clone();
+
+    // Index of the address we're currently using in `addrs`
+    let mut addr_idx = 0;
+
+    // Number of failed attempts to connect to the current address, in a row
+    let mut connect_fails = 0;
+
+    // Number of addresses we failed to resolve or connect to, in a row. We only wait before
+    // reconnecting when all addresses fail.
+    let mut failed_addrs = 0;
 
     // Whether to wait before trying to (re)connect
     let mut wait = false;
 
+    // Whether we stopped after a fatal registration error. We wait for a reconnect command in
+    // that case.
+    let mut stopped = false;
+
+    let mut sts_policies = StsPolicies::new(server_info.sts_file.clone()).await;
+
+    let recorder = match server_info.record_file {
+        None => None,
+        Some(ref path) => match Recorder::new(path).await {
+            Err(io_err) => {
+                debug!("Can't open recording file {:?}: {:?}", path, io_err);
+                snd_ev.send(Event::IoErr(io_err)).await.unwrap();
+                None
+            }
+            Ok(recorder) => Some(recorder),
+        },
+    };
+
     // Main loop just tries to (re)connect
     'connect: loop {
-        if wait {
-            match wait_(&mut rcv_cmd).await {
+        if wait || stopped {
+            let delay = if stopped {
+                None
+            } else {
+                Some(timeouts.reconnect)
+            };
+            match wait_(&mut rcv_cmd, delay).await {
                 Done(()) => {}
                 TryWithPort(new_port) => {
-                    port = new_port;
+                    addrs[addr_idx].port = new_port;
                     wait = false;
+                    stopped = false;
                     continue;
                 }
                 TryReconnect => {
                     wait = false;
+                    stopped = false;
                     continue;
                 }
                 TryAfterDelay => {
@@ -372,22 +775,38 @@ async fn main_loop(
         // Resolve IP address
         //
 
-        snd_ev.send(Event::ResolvingHost).await.unwrap();
+        let serv_name = addrs[addr_idx].host.clone();
+        let ws_path = addrs[addr_idx].ws_path.clone();
+
+        // Use the TLS port in the STS policy of the host, if we have one. STS doesn't apply to
+        // WebSocket connections.
+        let sts_port = if ws_path.is_none() {
+            sts_policies.get_port(&serv_name)
+        } else {
+            None
+        };
+        let (port, use_tls) = match sts_port {
+            Some(sts_port) if !server_info.tls => (sts_port, true),
+            _ => (addrs[addr_idx].port, server_info.tls),
+        };
 
-        let serv_name = server_info.addr.clone();
+        snd_ev
+            .send(Event::ResolvingHost(serv_name.clone()))
+            .await
+            .unwrap();
 
         debug!("Resolving address");
 
         let serv_name_clone = serv_name.clone();
 
-        let addr_iter = match resolve_addr(serv_name_clone, port, &mut rcv_cmd, &mut snd_ev).await {
-            Done(addr_iter) => {
+        let resolved = match resolve_addr(serv_name_clone, port, &mut rcv_cmd, &mut snd_ev).await {
+            Done(resolved) => {
                 debug!("resolve_addr: done");
-                addr_iter
+                resolved
             }
             TryWithPort(new_port) => {
                 debug!("resolve_addr: try new port");
-                port = new_port;
+                addrs[addr_idx].port = new_port;
                 wait = false;
                 continue;
             }
@@ -398,7 +817,9 @@ async fn main_loop(
             }
             TryAfterDelay => {
                 debug!("resolve_addr: try after delay");
-                wait = true;
+                // Try the next address right away, unless all addresses failed
+                wait = !next_addr(&mut addr_idx, &mut failed_addrs, addrs.len());
+                connect_fails = 0;
                 continue;
             }
             Return => {
@@ -407,30 +828,64 @@ async fn main_loop(
             }
         };
 
-        let addrs = addr_iter.collect::<Vec<_>>();
+        let sock_addrs =
+            happy_eyeballs::sort_addrs(resolved, server_info.ip_pref, server_info.bind_addr);
 
-        if addrs.is_empty() {
+        if sock_addrs.is_empty() {
+            connect_fails = 0;
+            if next_addr(&mut addr_idx, &mut failed_addrs, addrs.len()) {
+                wait = false;
+                continue;
+            }
             snd_ev.send(Event::CantResolveAddr).await.unwrap();
             break; // returns
         }
 
-        debug!("Address resolved: {:?}", addrs);
+        debug!("Address resolved: {:?}", sock_addrs);
 
         //
         // Establish TCP connection to the server
         //
 
-        let stream = match try_connect(addrs, &serv_name, server_info.tls, &mut snd_ev).await {
+        let stream = match try_connect(
+            sock_addrs,
+            &serv_name,
+            port,
+            use_tls,
+            ws_path.as_ref(),
+            server_info.bind_addr,
+            &mut snd_ev,
+        )
+        .await
+        {
             None => {
                 snd_ev.send(Event::Disconnected).await.unwrap();
+                connect_fails += 1;
                 wait = true;
+                if connect_fails >= MAX_CONNECT_FAILS {
+                    connect_fails = 0;
+                    wait = !next_addr(&mut addr_idx, &mut failed_addrs, addrs.len());
+                }
                 continue;
             }
-            Some(stream) => stream,
+            Some(stream) => {
+                connect_fails = 0;
+                failed_addrs = 0;
+                stream
+            }
         };
 
+        // Local end of the connection is used as our address in DCC offers
+        if let Ok(local_addr) = stream.local_addr() {
+            dcc.set_local_ip(local_addr.ip());
+        }
+
         let (mut read_half, mut write_half) = tokio::io::split(stream);
 
+        if let Some(ref recorder) = recorder {
+            recorder.connected(&serv_name, port);
+        }
+
         debug!("Done");
 
         //
@@ -439,21 +894,36 @@ async fn main_loop(
 
         // Reset the connection state
         irc_state.reset();
-        // Introduce self
-        if server_info.sasl_auth.is_some() {
-            // Will introduce self after getting a response to this LS command.
-            // This is to avoid getting stuck during nick registration. See the
-            // discussion in #91.
-            snd_msg.try_send(wire::cap_ls()).unwrap();
-        } else {
+        // Introduce self. Capabilities are requested even without SASL to learn about the STS
+        // policy of the server.
+        snd_msg.try_send(wire::cap_ls()).unwrap();
+        if server_info.sasl_auth.is_none() {
             irc_state.introduce(&mut snd_msg);
         }
+        // Otherwise will introduce self after getting a response to the LS command. This is to
+        // avoid getting stuck during nick registration. See the discussion in #91.
 
         // Spawn a task for outgoing messages.
         let mut snd_ev_clone = snd_ev.clone();
-        tokio::runtime::current_thread::spawn(async move {
+        let recorder_clone = recorder.clone();
+        let irc_state_clone = irc_state.clone();
+        let sender_task = tokio::spawn(async move {
             while let Some(msg) = rcv_msg.next().await {
-                if let Err(io_err) = write_half.write_all(msg.as_str().as_bytes()).await {
+                if let Some(ref recorder) = recorder_clone {
+                    recorder.outgoing(&msg);
+                }
+                if irc_state_clone.raw_events() {
+                    for line in msg.lines() {
+                        let line = record::redact(line).into_owned();
+                        snd_ev_clone.send(Event::RawOut(line)).await.unwrap();
+                    }
+                }
+                // Flush, as WebSocket streams may buffer the message
+                let ret = match write_half.write_all(msg.as_bytes()).await {
+                    Ok(()) => write_half.flush().await,
+                    Err(io_err) => Err(io_err),
+                };
+                if let Err(io_err) = ret {
                     debug!("IO error when writing: {:?}", io_err);
                     snd_ev_clone.send(Event::IoErr(io_err)).await.unwrap();
                     return;
@@ -462,15 +932,30 @@ async fn main_loop(
         });
 
         // Spawn pinger task
-        let (mut pinger, rcv_ping_evs) = Pinger::new();
+        let (mut pinger, rcv_ping_evs) = Pinger::new(timeouts.ping_interval);
         let mut rcv_ping_evs = rcv_ping_evs.fuse();
 
         let mut parse_buf: Vec<u8> = Vec::with_capacity(1024);
 
+        let registration_timeout = async {
+            tokio::time::delay_for(timeouts.registration).await;
+        }
+        .fuse();
+        pin_mut!(registration_timeout);
+
         loop {
             let mut read_buf: [u8; 1024] = [0; 1024];
 
             select! {
+                () = registration_timeout => {
+                    if !irc_state.is_nick_accepted() {
+                        debug!("main loop: registration timeout");
+                        snd_ev.send(Event::RegistrationTimeout).await.unwrap();
+                        snd_ev.send(Event::Disconnected).await.unwrap();
+                        wait = true;
+                        continue 'connect;
+                    }
+                }
                 cmd = rcv_cmd.next() => {
                     match cmd {
                         None => {
@@ -482,16 +967,18 @@ async fn main_loop(
                         }
                         Some(Cmd::Reconnect(mb_port)) => {
                             if let Some(new_port) = mb_port {
-                                port = new_port;
+                                addrs[addr_idx].port = new_port;
                             }
                             wait = false;
                             continue 'connect;
                         }
                         Some(Cmd::Quit(reason)) => {
                             snd_msg.try_send(wire::quit(reason)).unwrap();
-                            // This drops the sender end of the channel that the sender task
-                            // uses, which in turn causes the sender task to return. Somewhat
-                            // hacky?
+                            // Dropping the sender end of the channel that the sender task uses
+                            // causes the sender task to return after sending QUIT. Wait for it
+                            // so that `Event::Closed` is sent after the message.
+                            drop(snd_msg);
+                            let _ = sender_task.await;
                             return;
                         }
                     }
@@ -516,12 +1003,54 @@ async fn main_loop(
                             continue 'connect;
                         }
                         Ok(bytes) => {
+                            if let Some(ref recorder) = recorder {
+                                recorder.incoming(&read_buf[0..bytes]);
+                            }
                             parse_buf.extend_from_slice(&read_buf[0..bytes]);
-                            while let Some(mut msg) = wire::parse_irc_msg(&mut parse_buf) {
+                            loop {
+                                if irc_state.raw_events() {
+                                    if let Some(line) = first_line(&parse_buf) {
+                                        snd_ev.send(Event::RawIn(line)).await.unwrap();
+                                    }
+                                }
+                                let mut msg = match wire::parse_irc_msg(&mut parse_buf) {
+                                    None => break,
+                                    Some(msg) => msg,
+                                };
                                 debug!("parsed msg: {:?}", msg);
                                 pinger.reset();
                                 irc_state.update(&mut msg, &mut snd_ev, &mut snd_msg);
-                                snd_ev.send(Event::Msg(msg)).await.unwrap();
+                                if !irc_state.take_internal_reply() {
+                                    snd_ev.send(Event::Msg(msg)).await.unwrap();
+                                }
+
+                                if irc_state.take_fatal_error() {
+                                    debug!("main loop: fatal registration error, stopping");
+                                    snd_ev.send(Event::Disconnected).await.unwrap();
+                                    stopped = true;
+                                    continue 'connect;
+                                }
+
+                                let sts = irc_state.take_sts().filter(|_| ws_path.is_none());
+                                if let Some(sts) = sts {
+                                    let sts = sts::parse_sts_value(&sts);
+                                    if use_tls {
+                                        if let Some(duration) = sts.duration {
+                                            if let Err(err) = sts_policies
+                                                .update(&serv_name, port, duration)
+                                                .await
+                                            {
+                                                debug!("Can't persist STS policy: {:?}", err);
+                                            }
+                                        }
+                                    } else if let Some(sts_port) = sts.port {
+                                        // Reconnect with TLS right away, like `Cmd::Reconnect`
+                                        sts_policies.upgrade(&serv_name, sts_port);
+                                        snd_ev.send(Event::StsUpgrade(sts_port)).await.unwrap();
+                                        wait = false;
+                                        continue 'connect;
+                                    }
+                                }
                             }
                         }
                     }
@@ -548,6 +1077,27 @@ async fn main_loop(
     }
 }
 
+/// Move to the next server address after failing to resolve or connect to the current one.
+/// Returns `false` when all addresses failed, in which case the failure count is reset to try all
+/// addresses again later.
+fn next_addr(addr_idx: &mut usize, failed_addrs: &mut usize, n_addrs: usize) -> bool {
+    *addr_idx = (*addr_idx + 1) % n_addrs;
+    *failed_addrs += 1;
+    if *failed_addrs >= n_addrs {
+        *failed_addrs = 0;
+        false
+    } else {
+        true
+    }
+}
+
+/// The first complete line in a buffer of incoming bytes, without the "\r\n" suffix.
+fn first_line(buf: &[u8]) -> Option<String> {
+    buf.windows(2)
+        .position(|sub| sub == b"\r\n")
+        .map(|idx| String::from_utf8_lossy(&buf[0..idx]).into_owned())
+}
+
 enum TaskResult<A> {
     Done(A),
     TryWithPort(u16),
@@ -556,10 +1106,14 @@ enum TaskResult<A> {
     Return,
 }
 
-async fn wait_(rcv_cmd: &mut Fuse<mpsc::Receiver<Cmd>>) -> TaskResult<()> {
+/// Wait `delay` before reconnecting, or until a reconnect command when `delay` is `None`.
+async fn wait_(rcv_cmd: &mut Fuse<mpsc::Receiver<Cmd>>, delay: Option<Duration>) -> TaskResult<()> {
     // Weird code because of a bug in select!?
     let delay = async {
-        tokio::timer::delay_for(Duration::from_secs(60)).await;
+        match delay {
+            Some(delay) => tokio::time::delay_for(delay).await,
+            None => futures::future::pending::<()>().await,
+        }
     }
     .fuse();
     pin_mut!(delay);
@@ -604,9 +1158,14 @@ async fn resolve_addr(
     port: u16,
     rcv_cmd: &mut Fuse<mpsc::Receiver<Cmd>>,
     snd_ev: &mut mpsc::Sender<Event>,
-) -> TaskResult<std::vec::IntoIter<SocketAddr>> {
-    let mut addr_iter_task =
-        tokio_executor::blocking::run(move || (serv_name.as_str(), port).to_socket_addrs()).fuse();
+) -> TaskResult<Vec<SocketAddr>> {
+    let addr_iter_task = async move {
+        tokio::net::lookup_host((serv_name, port))
+            .await
+            .map(|addr_iter| addr_iter.collect())
+    }
+    .fuse();
+    pin_mut!(addr_iter_task);
 
     loop {
         select! {
@@ -616,8 +1175,8 @@ async fn resolve_addr(
                         snd_ev.send(Event::IoErr(io_err)).await.unwrap();
                         return TryAfterDelay;
                     }
-                    Ok(addr_iter) => {
-                        return Done(addr_iter);
+                    Ok(addrs) => {
+                        return Done(addrs);
                     }
                 }
             }
@@ -652,25 +1211,42 @@ async fn resolve_addr(
 async fn try_connect(
     addrs: Vec<SocketAddr>,
     serv_name: &str,
+    port: u16,
     use_tls: bool,
+    ws_path: Option<&String>,
+    bind_addr: Option<IpAddr>,
     snd_ev: &mut mpsc::Sender<Event>,
 ) -> Option<Stream> {
-    for addr in addrs {
-        snd_ev.send(Event::Connecting(addr)).await.unwrap();
-        let mb_stream = if use_tls {
-            Stream::new_tls(addr, &serv_name).await
-        } else {
-            Stream::new_tcp(addr).await
-        };
-        match mb_stream {
-            Err(err) => {
-                snd_ev.send(Event::from(err)).await.unwrap();
-            }
-            Ok(stream) => {
-                return Some(stream);
-            }
+    let tcp_stream = happy_eyeballs::connect(addrs, bind_addr, snd_ev).await?;
+
+    let mb_stream = if use_tls {
+        Stream::new_tls(tcp_stream, serv_name).await
+    } else {
+        Ok(Stream::new_tcp(tcp_stream))
+    };
+
+    let mb_stream = match (mb_stream, ws_path) {
+        (Ok(stream), Some(ws_path)) => Stream::new_ws(stream, serv_name, port, ws_path).await,
+        (mb_stream, _) => mb_stream,
+    };
+
+    match mb_stream {
+        Err(err) => {
+            snd_ev.send(Event::from(err)).await.unwrap();
+            None
         }
+        Ok(stream) => Some(stream),
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    None
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_client_send_sync() {
+        assert_send_sync::<Client>();
+    }
 }
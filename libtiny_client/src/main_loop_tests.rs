@@ -0,0 +1,433 @@
+//! Tests of the main loop against a mock server: registration, reconnecting, SASL, pinging.
+
+use super::*;
+use mock_irc_server::{MockServer, Script};
+
+fn server_info(port: u16) -> ServerInfo {
+    ServerInfo {
+        name: "mock".to_owned(),
+        addrs: vec![ServerAddr {
+            host: "127.0.0.1".to_owned(),
+            port,
+            ws_path: None,
+        }],
+        tls: false,
+        sts_file: None,
+        bind_addr: None,
+        ip_pref: IpPref::Any,
+        pass: None,
+        realname: "tiny test".to_owned(),
+        nicks: vec!["tiny".to_owned()],
+        auto_join: vec![],
+        join_invites_from: vec![],
+        nickserv_ident: None,
+        sasl_auth: None,
+        ctcp_replies: false,
+        dcc_download_dir: None,
+        record_file: None,
+    }
+}
+
+/// Default timeouts, but reconnect right away.
+fn timeouts() -> Timeouts {
+    Timeouts {
+        reconnect: Duration::from_millis(100),
+        ..Timeouts::default()
+    }
+}
+
+fn run<F: std::future::Future<Output = ()>>(test: F) {
+    let mut runtime = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(test);
+}
+
+/// Skip events until one satisfies `f`.
+async fn expect_ev<F: Fn(&Event) -> bool>(rcv_ev: &mut mpsc::Receiver<Event>, f: F) -> Event {
+    let find = async {
+        while let Some(ev) = rcv_ev.next().await {
+            if f(&ev) {
+                return ev;
+            }
+        }
+        panic!("Event channel closed");
+    };
+    tokio::time::timeout(Duration::from_secs(10), find)
+        .await
+        .expect("Timed out waiting for event")
+}
+
+fn is_end_of_motd(ev: &Event) -> bool {
+    matches!(
+        ev,
+        Event::Msg(wire::Msg {
+            cmd: wire::Cmd::Reply(wire::Reply::EndOfMotd { .. }),
+            ..
+        })
+    )
+}
+
+/// Quit and wait for the client to stop.
+async fn quit(client: &mut Client, rcv_ev: &mut mpsc::Receiver<Event>) {
+    client.quit(None);
+    expect_ev(rcv_ev, |ev| matches!(ev, Event::Closed)).await;
+}
+
+#[test]
+fn test_connect() {
+    let server = MockServer::start(
+        Script::new()
+            .accept()
+            .expect("CAP LS 302")
+            .expect("NICK tiny")
+            .expect("USER tiny 8 * :tiny test")
+            .send(":irc.example.org 001 tiny :Welcome")
+            .send(":irc.example.org 376 tiny :End of MOTD")
+            .expect("JOIN #tiny")
+            .expect("QUIT")
+            .expect_closed(),
+    );
+
+    let mut server_info = server_info(server.port());
+    server_info.auto_join = vec!["#tiny".to_owned()];
+
+    run(async move {
+        let (mut client, mut rcv_ev) = connect(server_info, timeouts());
+        expect_ev(&mut rcv_ev, |ev| matches!(ev, Event::Connected)).await;
+        assert!(client.is_nick_accepted());
+        expect_ev(&mut rcv_ev, is_end_of_motd).await;
+        quit(&mut client, &mut rcv_ev).await;
+    });
+
+    server.finish();
+}
+
+#[test]
+fn test_no_addrs() {
+    let mut server_info = server_info(0);
+    server_info.addrs = vec![];
+
+    run(async move {
+        let (_client, mut rcv_ev) = connect(server_info, timeouts());
+        expect_ev(&mut rcv_ev, |ev| matches!(ev, Event::CantResolveAddr)).await;
+        expect_ev(&mut rcv_ev, |ev| matches!(ev, Event::Closed)).await;
+    });
+}
+
+#[test]
+fn test_nick_in_use() {
+    let server = MockServer::start(
+        Script::new()
+            .accept()
+            .expect("NICK tiny")
+            .send(":irc.example.org 433 * tiny :Nickname is already in use")
+            .expect("NICK tiny_")
+            .send(":irc.example.org 001 tiny_ :Welcome")
+            .expect("QUIT")
+            .expect_closed(),
+    );
+
+    let server_info = server_info(server.port());
+
+    run(async move {
+        let (mut client, mut rcv_ev) = connect(server_info, timeouts());
+        expect_ev(&mut rcv_ev, |ev| match ev {
+            Event::NickChange(nick) => nick == "tiny_",
+            _ => false,
+        })
+        .await;
+        expect_ev(&mut rcv_ev, |ev| matches!(ev, Event::Connected)).await;
+        assert_eq!(client.get_nick(), "tiny_");
+        quit(&mut client, &mut rcv_ev).await;
+    });
+
+    server.finish();
+}
+
+#[test]
+fn test_sasl_plain() {
+    let server = MockServer::start(
+        Script::new()
+            .accept()
+            .expect("CAP LS 302")
+            .send(":irc.example.org CAP * LS :multi-prefix sasl")
+            .expect("NICK tiny")
+            .expect("CAP REQ :sasl")
+            .send(":irc.example.org CAP tiny ACK :sasl")
+            .expect("AUTHENTICATE PLAIN")
+            .send("AUTHENTICATE +")
+            .expect(&format!(
+                "AUTHENTICATE {}",
+                base64::encode("tiny\x00tiny\x00hunter2")
+            ))
+            .send(":irc.example.org 903 tiny :SASL authentication successful")
+            .expect("CAP END")
+            .send(":irc.example.org 001 tiny :Welcome")
+            .expect("QUIT")
+            .expect_closed(),
+    );
+
+    let mut server_info = server_info(server.port());
+    server_info.sasl_auth = Some(SASLAuth {
+        username: "tiny".to_owned(),
+        password: "hunter2".to_owned(),
+    });
+
+    run(async move {
+        let (mut client, mut rcv_ev) = connect(server_info, timeouts());
+        expect_ev(&mut rcv_ev, |ev| matches!(ev, Event::Connected)).await;
+        quit(&mut client, &mut rcv_ev).await;
+    });
+
+    server.finish();
+}
+
+#[test]
+fn test_sasl_not_advertised() {
+    let server = MockServer::start(
+        Script::new()
+            .accept()
+            .expect("CAP LS 302")
+            .send(":irc.example.org CAP * LS :multi-prefix")
+            .expect("NICK tiny")
+            .expect("CAP END")
+            .send(":irc.example.org 001 tiny :Welcome")
+            .expect("QUIT")
+            .expect_closed(),
+    );
+
+    let mut server_info = server_info(server.port());
+    server_info.sasl_auth = Some(SASLAuth {
+        username: "tiny".to_owned(),
+        password: "hunter2".to_owned(),
+    });
+
+    run(async move {
+        let (mut client, mut rcv_ev) = connect(server_info, timeouts());
+        expect_ev(&mut rcv_ev, |ev| {
+            matches!(ev, Event::RegistrationError(RegistrationError::SaslFail(_)))
+        })
+        .await;
+        expect_ev(&mut rcv_ev, |ev| matches!(ev, Event::Connected)).await;
+        quit(&mut client, &mut rcv_ev).await;
+    });
+
+    server.finish();
+}
+
+/// Channels in `auto_join` and channels joined later are joined after reconnecting.
+#[test]
+fn test_rejoin_after_reconnect() {
+    let server = MockServer::start(
+        Script::new()
+            .accept()
+            .expect("USER *")
+            .send(":irc.example.org 001 tiny :Welcome")
+            .send(":irc.example.org 376 tiny :End of MOTD")
+            .expect("JOIN #a")
+            .send(":tiny!tiny@127.0.0.1 JOIN #a")
+            .send(":tiny!tiny@127.0.0.1 JOIN #b")
+            .disconnect()
+            .accept()
+            .expect("USER *")
+            .send(":irc.example.org 001 tiny :Welcome")
+            .send(":irc.example.org 376 tiny :End of MOTD")
+            .expect("JOIN #a,#b")
+            .expect("QUIT")
+            .expect_closed(),
+    );
+
+    let mut server_info = server_info(server.port());
+    server_info.auto_join = vec!["#a".to_owned()];
+
+    run(async move {
+        let (mut client, mut rcv_ev) = connect(server_info, timeouts());
+        expect_ev(&mut rcv_ev, |ev| matches!(ev, Event::Disconnected)).await;
+        expect_ev(&mut rcv_ev, is_end_of_motd).await;
+        quit(&mut client, &mut rcv_ev).await;
+    });
+
+    server.finish();
+}
+
+/// Client sends a PING when the server is silent, and reconnects when the server doesn't reply.
+#[test]
+fn test_ping_timeout() {
+    let server = MockServer::start(
+        Script::new()
+            .accept()
+            .expect("USER *")
+            .send(":irc.example.org 001 tiny :Welcome")
+            .send(
+                ":irc.example.org 002 tiny :Your host is irc.example.org[127.0.0.1/6667], \
+                 running version test",
+            )
+            .expect("PING irc.example.org")
+            .expect_closed()
+            .accept()
+            .expect("USER *")
+            .send(":irc.example.org 001 tiny :Welcome")
+            .expect("QUIT")
+            .expect_closed(),
+    );
+
+    let server_info = server_info(server.port());
+    let timeouts = Timeouts {
+        ping_interval: Duration::from_millis(500),
+        ..timeouts()
+    };
+
+    run(async move {
+        let (mut client, mut rcv_ev) = connect(server_info, timeouts);
+        expect_ev(&mut rcv_ev, |ev| matches!(ev, Event::Disconnected)).await;
+        expect_ev(&mut rcv_ev, |ev| matches!(ev, Event::Connected)).await;
+        quit(&mut client, &mut rcv_ev).await;
+    });
+
+    server.finish();
+}
+
+/// Record a session with a server password, then replay it.
+#[test]
+fn test_record_replay() {
+    let server = MockServer::start(
+        Script::new()
+            .accept()
+            .expect("USER *")
+            .send(":irc.example.org 001 tiny_ :Welcome")
+            .send(":tiny_!tiny@127.0.0.1 JOIN #tiny")
+            .expect("QUIT")
+            .expect_closed(),
+    );
+
+    let record_file =
+        std::env::temp_dir().join(format!("tiny_test_recording_{}", std::process::id()));
+    let _ = std::fs::remove_file(&record_file);
+
+    let replay_server_info = server_info(0);
+    let mut server_info = server_info(server.port());
+    server_info.pass = Some("hunter2".to_owned());
+    server_info.record_file = Some(record_file.clone());
+
+    run(async move {
+        let (mut client, mut rcv_ev) = connect(server_info, timeouts());
+        expect_ev(&mut rcv_ev, |ev| {
+            matches!(
+                ev,
+                Event::Msg(wire::Msg {
+                    cmd: wire::Cmd::JOIN { .. },
+                    ..
+                })
+            )
+        })
+        .await;
+        quit(&mut client, &mut rcv_ev).await;
+    });
+
+    server.finish();
+
+    let recording = std::fs::read_to_string(&record_file).unwrap();
+    std::fs::remove_file(&record_file).unwrap();
+    assert!(!recording.contains("hunter2"));
+    assert!(recording.contains(" out PASS <redacted>\n"));
+    assert!(recording.contains(" in :irc.example.org 001 tiny_ :Welcome\n"));
+
+    run(async move {
+        let (mut client, mut rcv_ev) = Client::replay(replay_server_info, recording);
+        expect_ev(&mut rcv_ev, |ev| matches!(ev, Event::Connected)).await;
+        expect_ev(&mut rcv_ev, |ev| {
+            matches!(
+                ev,
+                Event::Msg(wire::Msg {
+                    cmd: wire::Cmd::JOIN { .. },
+                    ..
+                })
+            )
+        })
+        .await;
+        // Nick is the one in the recording
+        assert_eq!(client.get_nick(), "tiny_");
+        quit(&mut client, &mut rcv_ev).await;
+    });
+}
+
+/// Raw lines are sent as events when enabled, with passwords redacted.
+#[test]
+fn test_raw_events() {
+    let server = MockServer::start(
+        Script::new()
+            .accept()
+            .expect("USER *")
+            .send(":irc.example.org 001 tiny :Welcome")
+            .expect("QUIT")
+            .expect_closed(),
+    );
+
+    let mut server_info = server_info(server.port());
+    server_info.pass = Some("hunter2".to_owned());
+
+    run(async move {
+        let (mut client, mut rcv_ev) = connect(server_info, timeouts());
+        client.set_raw_events(true);
+        expect_ev(&mut rcv_ev, |ev| match ev {
+            Event::RawOut(line) => line == "PASS <redacted>",
+            _ => false,
+        })
+        .await;
+        expect_ev(&mut rcv_ev, |ev| match ev {
+            Event::RawIn(line) => line == ":irc.example.org 001 tiny :Welcome",
+            _ => false,
+        })
+        .await;
+        quit(&mut client, &mut rcv_ev).await;
+    });
+
+    server.finish();
+}
+
+/// `invite-notify` is requested when available, invites from `join_invites_from` are joined.
+#[test]
+fn test_invite() {
+    let server = MockServer::start(
+        Script::new()
+            .accept()
+            .expect("CAP LS 302")
+            .send(":irc.example.org CAP * LS :invite-notify")
+            .expect("CAP REQ :invite-notify")
+            .send(":irc.example.org CAP tiny ACK :invite-notify")
+            .expect("CAP END")
+            .send(":irc.example.org 001 tiny :Welcome")
+            .send(":bob!bob@127.0.0.1 INVITE tiny :#spam")
+            .send(":alice!alice@127.0.0.1 INVITE tiny :#tiny")
+            .expect("JOIN *")
+            .expect("QUIT")
+            .expect_closed(),
+    );
+
+    let mut server_info = server_info(server.port());
+    server_info.join_invites_from = vec!["Alice!*@127.0.0.1".to_owned()];
+
+    run(async move {
+        let (mut client, mut rcv_ev) = connect(server_info, timeouts());
+        // JOIN is sent before the event for the INVITE
+        expect_ev(&mut rcv_ev, |ev| match ev {
+            Event::Msg(wire::Msg {
+                cmd: wire::Cmd::INVITE { chan, .. },
+                ..
+            }) => chan == "#tiny",
+            _ => false,
+        })
+        .await;
+        assert!(client.joins_invites_from("alice", "alice@127.0.0.1"));
+        assert!(!client.joins_invites_from("alice", "alice@example.org"));
+        assert!(!client.joins_invites_from("bob", "bob@127.0.0.1"));
+        quit(&mut client, &mut rcv_ev).await;
+    });
+
+    let lines = server.finish();
+    assert!(lines.contains(&"JOIN #tiny".to_owned()));
+    assert!(!lines.contains(&"JOIN #spam".to_owned()));
+}
@@ -0,0 +1,271 @@
+//! Recording raw IRC traffic to a file, and replaying recordings. See `ServerInfo::record_file`
+//! and `Client::replay`.
+//!
+//! File format is one line per IRC message: `<Unix time in milliseconds> in|out <message>`.
+//! Lines starting with `#` are comments, a comment with the server address is added on each
+//! connection. Passwords in outgoing PASS, AUTHENTICATE, OPER and NickServ IDENTIFY messages are
+//! redacted.
+//!
+//! Lines are written to the file by a separate task, to avoid blocking the main loop on file
+//! I/O.
+
+use crate::state::State;
+use crate::utils;
+use crate::{Cmd, Event};
+use libtiny_wire as wire;
+
+use futures::stream::StreamExt;
+use std::borrow::Cow;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+#[derive(Clone)]
+pub(crate) struct Recorder {
+    inner: Arc<Mutex<RecorderInner>>,
+}
+
+struct RecorderInner {
+    /// Lines to write, sent to the writer task.
+    snd_line: mpsc::UnboundedSender<String>,
+    /// Incoming bytes after the last "\n".
+    in_buf: Vec<u8>,
+}
+
+impl Recorder {
+    /// Open the file for appending, creating it (and its directory) if it doesn't exist, and
+    /// spawn the writer task. The task stops when all clones of the recorder are dropped.
+    pub(crate) async fn new(path: &Path) -> std::io::Result<Recorder> {
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        let (snd_line, rcv_line) = mpsc::unbounded_channel();
+        tokio::spawn(writer_task(file, rcv_line));
+        Ok(Recorder {
+            inner: Arc::new(Mutex::new(RecorderInner {
+                snd_line,
+                in_buf: vec![],
+            })),
+        })
+    }
+
+    /// Record the start of a new connection.
+    pub(crate) fn connected(&self, host: &str, port: u16) {
+        let mut inner = utils::lock(&self.inner);
+        inner.in_buf.clear();
+        inner.write(&format!("# connected to {}:{}", host, port));
+    }
+
+    /// Record bytes read from the server. Lines are written when they're complete.
+    pub(crate) fn incoming(&self, bytes: &[u8]) {
+        let mut inner = utils::lock(&self.inner);
+        inner.in_buf.extend_from_slice(bytes);
+        while let Some(idx) = wire::find_byte(&inner.in_buf, b'\n') {
+            let line: Vec<u8> = inner.in_buf.drain(..=idx).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = format!(
+                "{} in {}",
+                now_millis(),
+                line.trim_end_matches(&['\r', '\n'][..])
+            );
+            inner.write(&line);
+        }
+    }
+
+    /// Record messages sent to the server.
+    pub(crate) fn outgoing(&self, msg: &str) {
+        let mut inner = utils::lock(&self.inner);
+        for line in msg.lines() {
+            let line = format!("{} out {}", now_millis(), redact(line));
+            inner.write(&line);
+        }
+    }
+}
+
+impl RecorderInner {
+    fn write(&mut self, line: &str) {
+        // Fails when the writer task stopped after an error, which is already logged
+        let _ = self.snd_line.send(format!("{}\n", line));
+    }
+}
+
+async fn writer_task(mut file: File, mut rcv_line: mpsc::UnboundedReceiver<String>) {
+    while let Some(line) = rcv_line.next().await {
+        // `tokio::fs::File` writes in the background, flush to write lines one at a time
+        let ret = match file.write_all(line.as_bytes()).await {
+            Ok(()) => file.flush().await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = ret {
+            debug!("Can't write to the recording: {:?}", err);
+            return;
+        }
+    }
+}
+
+fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or(0)
+}
+
+/// Remove passwords from an outgoing message. Commands are case-insensitive, so messages sent
+/// with `/quote` may have lowercase commands.
+pub(crate) fn redact(line: &str) -> Cow<'_, str> {
+    let words: Vec<&str> = line.splitn(3, ' ').collect();
+    let cmd = words[0].to_ascii_uppercase();
+    match (cmd.as_str(), &words[1..]) {
+        ("PASS", [_]) | ("PASS", [_, _]) => Cow::Borrowed("PASS <redacted>"),
+        // Mechanism names, "+" (empty response) and "*" (abort) are not secrets
+        ("AUTHENTICATE", [param])
+            if ["PLAIN", "EXTERNAL", "+", "*"]
+                .iter()
+                .any(|s| s.eq_ignore_ascii_case(param)) =>
+        {
+            Cow::Borrowed(line)
+        }
+        ("AUTHENTICATE", [_, ..]) => Cow::Borrowed("AUTHENTICATE <redacted>"),
+        ("OPER", [name, _]) => Cow::Owned(format!("OPER {} <redacted>", name)),
+        ("PRIVMSG", [target, msg])
+            if target.eq_ignore_ascii_case("nickserv")
+                && msg.len() >= 10
+                && msg.is_char_boundary(10)
+                && msg[..10].eq_ignore_ascii_case(":identify ") =>
+        {
+            Cow::Owned(format!("PRIVMSG {} :identify <redacted>", target))
+        }
+        _ => Cow::Borrowed(line),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Recorded<'a> {
+    /// Start of a new connection.
+    Connected,
+    /// An incoming message.
+    In(&'a str),
+}
+
+/// Connections and incoming messages in a recording.
+fn parse_recording(recording: &str) -> impl Iterator<Item = Recorded<'_>> {
+    recording.lines().filter_map(|line| {
+        if line.starts_with("# connected") {
+            return Some(Recorded::Connected);
+        }
+        let mut words = line.splitn(3, ' ');
+        match (words.next(), words.next(), words.next()) {
+            (Some(_time), Some("in"), Some(msg)) => Some(Recorded::In(msg)),
+            _ => None,
+        }
+    })
+}
+
+/// Feed incoming messages in the recording to the state and the user, as if they're read from
+/// the server. Messages to the server are dropped. Returns on `Cmd::Quit`.
+pub(crate) async fn replay_task(
+    recording: String,
+    irc_state: State,
+    mut snd_ev: mpsc::Sender<Event>,
+    rcv_cmd: mpsc::Receiver<Cmd>,
+) {
+    let (mut snd_msg, mut rcv_msg) = mpsc::channel::<String>(100);
+    let mut parse_buf: Vec<u8> = Vec::with_capacity(1024);
+
+    for recorded in parse_recording(&recording) {
+        let line = match recorded {
+            Recorded::Connected => {
+                irc_state.reset();
+                parse_buf.clear();
+                continue;
+            }
+            Recorded::In(line) => line,
+        };
+        parse_buf.extend_from_slice(line.as_bytes());
+        parse_buf.extend_from_slice(b"\r\n");
+        while let Some(mut msg) = wire::parse_irc_msg(&mut parse_buf) {
+            // Nick in RPL_WELCOME is the nick used in the recording
+            if let wire::Cmd::Reply(wire::Reply::Welcome { ref nick, .. }) = msg.cmd {
+                irc_state.set_nick(nick);
+            }
+            irc_state.update(&mut msg, &mut snd_ev, &mut snd_msg);
+            if !irc_state.take_internal_reply() {
+                snd_ev.send(Event::Msg(msg)).await.unwrap();
+            }
+            while let Ok(msg) = rcv_msg.try_recv() {
+                debug!("replay: dropping message to server: {:?}", msg);
+            }
+        }
+    }
+
+    let mut rcv_cmd = rcv_cmd;
+    while let Some(cmd) = rcv_cmd.next().await {
+        match cmd {
+            Cmd::Msg(msg) => {
+                debug!("replay: dropping message to server: {:?}", msg);
+            }
+            Cmd::Reconnect(_) => {}
+            Cmd::Quit(_) => {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(redact("PASS hunter2"), "PASS <redacted>");
+        assert_eq!(redact("AUTHENTICATE PLAIN"), "AUTHENTICATE PLAIN");
+        assert_eq!(redact("AUTHENTICATE +"), "AUTHENTICATE +");
+        assert_eq!(
+            redact("AUTHENTICATE dGlueQB0aW55AGh1bnRlcjI="),
+            "AUTHENTICATE <redacted>"
+        );
+        assert_eq!(redact("OPER admin hunter2"), "OPER admin <redacted>");
+        assert_eq!(
+            redact("PRIVMSG NickServ :IDENTIFY hunter2"),
+            "PRIVMSG NickServ :identify <redacted>"
+        );
+        assert_eq!(redact("PRIVMSG #tiny :PASS hi"), "PRIVMSG #tiny :PASS hi");
+
+        // Commands and the NickServ target are case-insensitive
+        assert_eq!(redact("pass hunter2"), "PASS <redacted>");
+        assert_eq!(redact("authenticate plain"), "authenticate plain");
+        assert_eq!(
+            redact("Authenticate dGlueQB0aW55AGh1bnRlcjI="),
+            "AUTHENTICATE <redacted>"
+        );
+        assert_eq!(redact("oper admin hunter2"), "OPER admin <redacted>");
+        assert_eq!(
+            redact("privmsg NICKSERV :identify hunter2"),
+            "PRIVMSG NICKSERV :identify <redacted>"
+        );
+    }
+
+    #[test]
+    fn test_parse_recording() {
+        let recording = "# connected to irc.example.org:6667\n\
+                         1600000000000 out NICK tiny\n\
+                         1600000000100 in :irc.example.org 001 tiny :Welcome to IRC\n\
+                         garbage\n";
+        assert_eq!(
+            parse_recording(recording).collect::<Vec<_>>(),
+            vec![
+                Recorded::Connected,
+                Recorded::In(":irc.example.org 001 tiny :Welcome to IRC")
+            ]
+        );
+    }
+}
@@ -1,33 +1,42 @@
 #![allow(clippy::zero_prefixed_literal)]
 
+use crate::chan_list::{glob_match, ChanList};
+use crate::ctcp::CtcpReplier;
+use crate::dcc::Dcc;
+use crate::topics::{Topic, Topics};
+use crate::users::{UserInfo, Users};
 use crate::utils;
-use crate::{Event, ServerInfo};
+use crate::whois::Whois;
+use crate::{Event, RegistrationError, ServerAddr, ServerInfo};
 use libtiny_wire as wire;
 use libtiny_wire::{find_byte, Msg, Pfx};
 
-use std::cell::RefCell;
-use std::collections::HashSet;
-use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use tokio::sync::mpsc::Sender;
 
+/// Default of the `MODES` ISUPPORT token, also the limit in RFC 2812.
+const DEFAULT_MAX_MODES: usize = 3;
+
 #[derive(Clone)]
 pub struct State {
-    inner: Rc<RefCell<StateInner>>,
+    inner: Arc<Mutex<StateInner>>,
 }
 
 impl State {
-    pub(crate) fn new(server_info: ServerInfo) -> State {
+    pub(crate) fn new(server_info: ServerInfo, dcc: Dcc) -> State {
         State {
-            inner: Rc::new(RefCell::new(StateInner::new(server_info))),
+            inner: Arc::new(Mutex::new(StateInner::new(server_info, dcc))),
         }
     }
 
     pub(crate) fn reset(&self) {
-        self.inner.borrow_mut().reset()
+        utils::lock(&self.inner).reset()
     }
 
     pub(crate) fn send_ping(&self, snd_irc_msg: &mut Sender<String>) {
-        self.inner.borrow_mut().send_ping(snd_irc_msg)
+        utils::lock(&self.inner).send_ping(snd_irc_msg)
     }
 
     pub(crate) fn update(
@@ -36,33 +45,117 @@ impl State {
         snd_ev: &mut Sender<Event>,
         snd_irc_msg: &mut Sender<String>,
     ) {
-        self.inner.borrow_mut().update(msg, snd_ev, snd_irc_msg);
+        utils::lock(&self.inner).update(msg, snd_ev, snd_irc_msg);
     }
 
     pub(crate) fn introduce(&self, snd_irc_msg: &mut Sender<String>) {
-        self.inner.borrow_mut().introduce(snd_irc_msg)
+        utils::lock(&self.inner).introduce(snd_irc_msg)
     }
 
     // FIXME: This allocates a new String
     pub(crate) fn get_nick(&self) -> String {
-        self.inner.borrow().current_nick.clone()
+        utils::lock(&self.inner).current_nick.clone()
+    }
+
+    /// Set current nick, without sending a NICK message. Used when replaying recordings.
+    pub(crate) fn set_nick(&self, nick: &str) {
+        utils::lock(&self.inner).current_nick = nick.to_owned();
     }
 
     // FIXME: Maybe use RwLock instead of Mutex
     pub(crate) fn is_nick_accepted(&self) -> bool {
-        self.inner.borrow().nick_accepted
+        utils::lock(&self.inner).nick_accepted
+    }
+
+    pub(crate) fn get_max_modes(&self) -> usize {
+        utils::lock(&self.inner).get_max_modes()
     }
 
     pub(crate) fn get_usermask(&self) -> Option<String> {
-        self.inner.borrow().usermask.clone()
+        utils::lock(&self.inner).usermask.clone()
     }
 
     pub(crate) fn set_away(&self, msg: Option<&str>) {
-        self.inner.borrow_mut().away_status = msg.map(str::to_owned);
+        utils::lock(&self.inner).away_status = msg.map(str::to_owned);
     }
 
     pub(crate) fn get_chan_nicks(&self, chan: &str) -> Vec<String> {
-        self.inner.borrow().get_chan_nicks(chan)
+        utils::lock(&self.inner).get_chan_nicks(chan)
+    }
+
+    pub(crate) fn joins_invites_from(&self, nick: &str, user: &str) -> bool {
+        utils::lock(&self.inner).joins_invites_from(nick, user)
+    }
+
+    pub(crate) fn get_topic(&self, chan: &str) -> Option<Topic> {
+        utils::lock(&self.inner).topics.get(chan)
+    }
+
+    pub(crate) fn get_serv_addrs(&self) -> Vec<ServerAddr> {
+        utils::lock(&self.inner).server_info.addrs.clone()
+    }
+
+    pub(crate) fn get_user_info(&self, nick: &str) -> Option<UserInfo> {
+        utils::lock(&self.inner).users.get(nick)
+    }
+
+    pub(crate) fn toggle_ignore_host(&self, host: &str) -> bool {
+        let ignored_hosts = &mut utils::lock(&self.inner).ignored_hosts;
+        if ignored_hosts.remove(host) {
+            false
+        } else {
+            ignored_hosts.insert(host.to_owned());
+            true
+        }
+    }
+
+    pub(crate) fn is_host_ignored(&self, host: &str) -> bool {
+        utils::lock(&self.inner).ignored_hosts.contains(host)
+    }
+
+    pub(crate) fn get_ignored_hosts(&self) -> Vec<String> {
+        utils::lock(&self.inner)
+            .ignored_hosts
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    pub(crate) fn set_raw_events(&self, enabled: bool) {
+        utils::lock(&self.inner).raw_events = enabled;
+    }
+
+    pub(crate) fn raw_events(&self) -> bool {
+        utils::lock(&self.inner).raw_events
+    }
+
+    /// Did a fatal registration error happen since the last call?
+    pub(crate) fn take_fatal_error(&self) -> bool {
+        let inner = &mut *utils::lock(&self.inner);
+        let fatal_error = inner.fatal_error;
+        inner.fatal_error = false;
+        fatal_error
+    }
+
+    /// Was the last message a reply to a request sent by the client itself? These are not
+    /// forwarded as `Event::Msg`.
+    pub(crate) fn take_internal_reply(&self) -> bool {
+        let inner = &mut *utils::lock(&self.inner);
+        let internal_reply = inner.internal_reply;
+        inner.internal_reply = false;
+        internal_reply
+    }
+
+    /// Value of the `sts` capability, if the server advertised it since the last call.
+    pub(crate) fn take_sts(&self) -> Option<String> {
+        utils::lock(&self.inner).sts.take()
+    }
+
+    /// Start a LIST request, returns the message to send.
+    pub(crate) fn list(&self, pattern: Option<&str>, min_users: Option<u32>) -> String {
+        let inner = &mut *utils::lock(&self.inner);
+        let elist = inner.isupport.get("ELIST").map(String::as_str);
+        inner.chan_list.request(pattern, min_users, elist)
     }
 }
 
@@ -106,12 +199,53 @@ struct StateInner {
     /// Do we have a nick yet? Try another nick on ERR_NICKNAMEINUSE (433) until we've got a nick.
     nick_accepted: bool,
 
+    /// Generates CTCP replies, with rate limiting.
+    ctcp_replier: CtcpReplier,
+
+    /// DCC chats and transfers. Offers are received as CTCP messages.
+    dcc: Dcc,
+
+    /// WHOIS and WHOWAS queries in progress.
+    whois: Whois,
+
+    /// RPL_ISUPPORT tokens. Tokens without values are mapped to empty strings.
+    isupport: HashMap<String, String>,
+
+    /// Capabilities in CAP LS replies. Capabilities without values are mapped to empty strings.
+    caps: HashMap<String, String>,
+
+    /// Value of the `sts` capability, taken by the main loop to upgrade the connection to TLS or
+    /// to persist the policy.
+    sts: Option<String>,
+
+    /// Set on a fatal registration error, taken by the main loop to stop the client.
+    fatal_error: bool,
+
+    /// Set when the last message was a reply to a request sent by the client itself (currently
+    /// the WHO requests sent after joining channels), taken by the main loop.
+    internal_reply: bool,
+
+    /// Hosts, user names, accounts etc. of users.
+    users: Users,
+
+    /// LIST request in progress.
+    chan_list: ChanList,
+
+    /// Topics of the joined channels.
+    topics: Topics,
+
+    /// Messages from users with these hosts are ignored by the UI. Not reset on reconnect.
+    ignored_hosts: HashSet<String>,
+
+    /// Send `Event::RawIn` and `Event::RawOut` events. Not reset on reconnect.
+    raw_events: bool,
+
     /// Server information
     server_info: ServerInfo,
 }
 
 impl StateInner {
-    fn new(server_info: ServerInfo) -> StateInner {
+    fn new(server_info: ServerInfo, dcc: Dcc) -> StateInner {
         let current_nick = server_info.nicks[0].to_owned();
         let chans = server_info
             .auto_join
@@ -128,6 +262,19 @@ impl StateInner {
             servername: None,
             usermask: None,
             nick_accepted: false,
+            ctcp_replier: CtcpReplier::new(),
+            dcc,
+            whois: Whois::default(),
+            isupport: HashMap::new(),
+            caps: HashMap::new(),
+            sts: None,
+            fatal_error: false,
+            internal_reply: false,
+            users: Users::default(),
+            chan_list: ChanList::default(),
+            topics: Topics::default(),
+            ignored_hosts: HashSet::new(),
+            raw_events: false,
             server_info,
         }
     }
@@ -143,6 +290,15 @@ impl StateInner {
         }
         self.servername = None;
         self.usermask = None;
+        self.whois.reset();
+        self.isupport.clear();
+        self.caps.clear();
+        self.sts = None;
+        self.fatal_error = false;
+        self.internal_reply = false;
+        self.users.reset();
+        self.chan_list.reset();
+        self.topics.reset();
     }
 
     fn send_ping(&mut self, snd_irc_msg: &mut Sender<String>) {
@@ -163,6 +319,18 @@ impl StateInner {
             .unwrap();
     }
 
+    /// Do we request SASL authentication? Only done when the server advertises it in CAP LS.
+    fn sasl_requested(&self) -> bool {
+        self.server_info.sasl_auth.is_some() && self.caps.contains_key("sasl")
+    }
+
+    /// Remove a user from the users cache if we don't share a channel with them anymore.
+    fn prune_user(&mut self, nick: &str) {
+        if !self.chans.iter().any(|(_, nicks)| nicks.contains(nick)) {
+            self.users.remove(nick);
+        }
+    }
+
     fn get_next_nick(&mut self) -> &str {
         self.current_nick_idx += 1;
         // debug!("current_nick_idx: {}", self.current_nick_idx);
@@ -190,69 +358,138 @@ impl StateInner {
             ref mut cmd,
         } = msg;
 
+        if let wire::Cmd::Reply(reply) = cmd {
+            if let Some(whois_info) = self.whois.update(reply) {
+                snd_ev.try_send(Event::Whois(whois_info)).unwrap();
+            }
+            if let Some(chans) = self.chan_list.update(reply) {
+                snd_ev.try_send(Event::ChanList(chans)).unwrap();
+            }
+        }
+
+        if let Some(Pfx::User { nick, user }) = pfx {
+            self.users.update_from_pfx(nick, user);
+        }
+
+        self.topics.update(pfx, cmd);
+
         use wire::Cmd::*;
         match cmd {
             PING { server } => {
                 snd_irc_msg.try_send(wire::pong(server)).unwrap();
             }
 
+            //
+            // DCC offers, replies to our passive offers, and rejections of our offers
+            //
+            PRIVMSG {
+                targets,
+                msg,
+                ctcp: Some(wire::CTCP::Dcc),
+                ..
+            } => {
+                if let Some(Pfx::User { nick, .. }) = pfx {
+                    // Ignore offers sent to channels
+                    let current_nick = &self.current_nick;
+                    if targets.iter().any(|target| match target {
+                        wire::MsgTarget::User(user) => user == current_nick,
+                        wire::MsgTarget::Chan(_) => false,
+                    }) {
+                        self.dcc.handle_ctcp(nick, msg);
+                    }
+                }
+            }
+
+            //
+            // Reply to CTCP requests
+            //
+            PRIVMSG {
+                msg,
+                is_notice: false,
+                ctcp: Some(ctcp),
+                ..
+            } => {
+                if let Some(Pfx::User { nick, .. }) = pfx {
+                    if self.server_info.ctcp_replies {
+                        if let Some(reply) = self.ctcp_replier.reply(
+                            Instant::now(),
+                            nick,
+                            ctcp,
+                            msg,
+                            &self.server_info.realname,
+                        ) {
+                            snd_irc_msg.try_send(reply).unwrap();
+                        }
+                    }
+                }
+            }
+
             //
             // Setting usermask using JOIN, RPL_USERHOST and 396 (?)
             // Also initialize the channel state on JOIN
             //
-            JOIN { chan } => {
+            JOIN {
+                chans,
+                account,
+                realname,
+                ..
+            } => {
                 if let Some(Pfx::User { nick, user }) = pfx {
+                    self.users.update_from_join(nick, account, realname);
                     if nick == &self.current_nick {
                         // Set usermask
                         let usermask = format!("{}!{}", nick, user);
                         self.usermask = Some(usermask);
 
-                        // Initialize channel state
-                        match utils::find_idx(&self.chans, |(s, _)| s == chan) {
-                            None => {
-                                self.chans.push((chan.to_owned(), HashSet::new()));
-                            }
-                            Some(chan_idx) => {
-                                // This happens because we initialize channel states for channels
-                                // that we will join on connection when the client is first created
-                                self.chans[chan_idx].1.clear();
+                        // Initialize channel states
+                        for chan in chans.iter() {
+                            self.users.joined(chan);
+                            match utils::find_idx(&self.chans, |(s, _)| s == chan) {
+                                None => {
+                                    self.chans.push((chan.to_owned(), HashSet::new()));
+                                }
+                                Some(chan_idx) => {
+                                    // This happens because we initialize channel states for
+                                    // channels that we will join on connection when the client
+                                    // is first created
+                                    self.chans[chan_idx].1.clear();
+                                }
                             }
                         }
                     } else {
-                        match utils::find_idx(&self.chans, |(s, _)| s == chan) {
-                            Some(chan_idx) => {
-                                self.chans[chan_idx]
-                                    .1
-                                    .insert(wire::drop_nick_prefix(nick).to_owned());
-                            }
-                            None => {
-                                debug!("Can't find channel state for JOIN: {:?}", cmd);
+                        for chan in chans.iter() {
+                            match utils::find_idx(&self.chans, |(s, _)| s == chan) {
+                                Some(chan_idx) => {
+                                    self.chans[chan_idx]
+                                        .1
+                                        .insert(wire::drop_nick_prefix(nick).to_owned());
+                                }
+                                None => {
+                                    debug!("Can't find channel state for JOIN: {}", chan);
+                                }
                             }
                         }
                     }
                 }
             }
 
-            Reply { num: 396, params } => {
+            Reply(wire::Reply::HostHidden { host, .. }) => {
                 // :hobana.freenode.net 396 osa1 haskell/developer/osa1
                 // :is now your hidden host (set by services.)
-                if params.len() == 3 {
-                    let usermask =
-                        format!("{}!~{}@{}", self.current_nick, self.nicks[0], params[1]);
-                    self.usermask = Some(usermask);
-                }
+                let usermask = format!("{}!~{}@{}", self.current_nick, self.nicks[0], host);
+                self.usermask = Some(usermask);
             }
 
-            Reply { num: 302, params } => {
+            Reply(wire::Reply::UserHost { reply }) => {
                 // 302 RPL_USERHOST
                 // :ircd.stealth.net 302 yournick :syrk=+syrk@millennium.stealth.net
                 //
                 // We know there will be only one nick because /userhost cmd sends
                 // one parameter (our nick)
                 //
-                // Example args: ["osa1", "osa1=+omer@moz-s8a.9ac.93.91.IP "]
+                // Example reply: "osa1=+omer@moz-s8a.9ac.93.91.IP "
 
-                let param = &params[1];
+                let param = reply;
                 match find_byte(param.as_bytes(), b'=') {
                     None => {
                         // TODO: Log this
@@ -269,37 +506,67 @@ impl StateInner {
                 }
             }
 
+            //
+            // Join channels we're invited to by the users in `join_invites_from`
+            //
+            INVITE { nick, chan } => {
+                if let Some(Pfx::User { nick: from, user }) = pfx {
+                    if nick == &self.current_nick && self.joins_invites_from(from, user) {
+                        snd_irc_msg.try_send(wire::join(&[chan.as_str()])).unwrap();
+                    }
+                }
+            }
+
             //
             // Remove channel state on PART
             //
-            PART { chan, .. } => {
+            PART { chans, .. } => {
                 if let Some(Pfx::User { nick, .. }) = pfx {
-                    if nick == &self.current_nick {
+                    for chan in chans.iter() {
                         match utils::find_idx(&self.chans, |(s, _)| s == chan) {
                             None => {
-                                debug!("Can't find channel state: {}", chan);
-                            }
-                            Some(chan_idx) => {
-                                self.chans.remove(chan_idx);
+                                debug!("Can't find channel state for PART: {}", chan);
                             }
-                        }
-                    } else {
-                        match utils::find_idx(&self.chans, |(s, _)| s == chan) {
                             Some(chan_idx) => {
-                                self.chans[chan_idx].1.remove(wire::drop_nick_prefix(nick));
-                            }
-                            None => {
-                                debug!("Can't find channel state for PART: {:?}", cmd);
+                                if nick == &self.current_nick {
+                                    let (_, nicks) = self.chans.remove(chan_idx);
+                                    self.topics.remove(chan);
+                                    for nick in nicks.iter() {
+                                        self.prune_user(nick);
+                                    }
+                                } else {
+                                    let nick = wire::drop_nick_prefix(nick);
+                                    self.chans[chan_idx].1.remove(nick);
+                                    self.prune_user(nick);
+                                }
                             }
                         }
                     }
                 }
             }
 
+            KICK { chan, nick, .. } => match utils::find_idx(&self.chans, |(s, _)| s == chan) {
+                None => {
+                    debug!("Can't find channel state for KICK: {}", chan);
+                }
+                Some(chan_idx) => {
+                    if nick == &self.current_nick {
+                        let (_, nicks) = self.chans.remove(chan_idx);
+                        self.topics.remove(chan);
+                        for nick in nicks.iter() {
+                            self.prune_user(nick);
+                        }
+                    } else {
+                        self.chans[chan_idx].1.remove(nick);
+                        self.prune_user(nick);
+                    }
+                }
+            },
+
             //
             // RPL_WELCOME, start introduction sequence and NickServ authentication
             //
-            Reply { num: 001, .. } => {
+            Reply(wire::Reply::Welcome { .. }) => {
                 snd_ev.try_send(Event::Connected).unwrap();
                 snd_ev
                     .try_send(Event::NickChange(self.current_nick.clone()))
@@ -315,13 +582,13 @@ impl StateInner {
             //
             // RPL_YOURHOST, set servername
             //
-            Reply { num: 002, params } => {
+            Reply(wire::Reply::YourHost { msg }) => {
                 // 002    RPL_YOURHOST
                 //        "Your host is <servername>, running version <ver>"
 
                 // An example <servername>: cherryh.freenode.net[149.56.134.238/8001]
 
-                match parse_servername(params) {
+                match parse_servername(msg) {
                     None => {
                         // TODO: Log
                     }
@@ -334,7 +601,7 @@ impl StateInner {
             //
             // ERR_NICKNAMEINUSE, try another nick if we don't have a nick yet
             //
-            Reply { num: 433, .. } => {
+            Reply(wire::Reply::NicknameInUse { .. }) => {
                 // ERR_NICKNAMEINUSE. If we don't have a nick already try next nick.
                 if !self.nick_accepted {
                     let new_nick = self.get_next_nick();
@@ -346,6 +613,50 @@ impl StateInner {
                 }
             }
 
+            //
+            // ERR_ERRONEUSNICKNAME, try the next nick if we don't have a nick yet. Unlike
+            // ERR_NICKNAMEINUSE adding underscores won't fix the nick.
+            //
+            Reply(wire::Reply::ErroneousNickname { nick, msg }) => {
+                if !self.nick_accepted {
+                    if self.current_nick_idx + 1 < self.nicks.len() {
+                        let new_nick = self.get_next_nick();
+                        snd_ev
+                            .try_send(Event::NickChange(new_nick.to_owned()))
+                            .unwrap();
+                        snd_irc_msg.try_send(wire::nick(new_nick)).unwrap();
+                    } else {
+                        self.registration_error(
+                            RegistrationError::ErroneousNickname {
+                                nick: nick.to_owned(),
+                                msg: msg.to_owned(),
+                            },
+                            snd_ev,
+                        );
+                    }
+                }
+            }
+
+            //
+            // ERR_PASSWDMISMATCH and ERR_YOUREBANNEDCREEP, reconnecting won't help during
+            // registration. After registration these are normal errors (e.g. 464 for a wrong
+            // OPER password), shown by the UI as `Event::Msg`.
+            //
+            Reply(wire::Reply::PasswdMismatch { msg }) => {
+                if !self.nick_accepted {
+                    self.registration_error(
+                        RegistrationError::PasswdMismatch(msg.to_owned()),
+                        snd_ev,
+                    );
+                }
+            }
+
+            Reply(wire::Reply::YoureBannedCreep { msg }) => {
+                if !self.nick_accepted {
+                    self.registration_error(RegistrationError::Banned(msg.to_owned()), snd_ev);
+                }
+            }
+
             //
             // NICK message sent from the server when our nick change request was successful
             //
@@ -372,6 +683,8 @@ impl StateInner {
                         self.current_nick = new_nick.to_owned();
                     }
 
+                    self.users.rename(old_nick, new_nick);
+
                     // Rename the nick in channel states, also populate the chan list
                     for (chan, nicks) in &mut self.chans {
                         if nicks.remove(old_nick) {
@@ -385,7 +698,7 @@ impl StateInner {
             //
             // RPL_ENDOFMOTD, join channels, set away status (TODO)
             //
-            Reply { num: 376, .. } => {
+            Reply(wire::Reply::EndOfMotd { .. }) => {
                 let chans: Vec<&str> = self.chans.iter().map(|(s, _)| s.as_str()).collect();
                 if !chans.is_empty() {
                     snd_irc_msg.try_send(wire::join(&chans)).unwrap();
@@ -395,25 +708,86 @@ impl StateInner {
             //
             // RPL_NAMREPLY: users in a channel
             //
-            Reply { num: 353, params } => {
-                let chan = &params[2];
+            Reply(wire::Reply::NamReply { chan, nicks }) => {
                 match utils::find_idx(&self.chans, |(s, _)| s == chan) {
                     None => self.chans.push((
                         chan.to_owned(),
-                        params[3]
-                            .split_whitespace()
+                        nicks
+                            .iter()
                             .map(|s| wire::drop_nick_prefix(s).to_owned())
                             .collect(),
                     )),
                     Some(idx) => {
                         let nick_set = &mut self.chans[idx].1;
-                        for nick in params[3].split_whitespace() {
+                        for nick in nicks.iter() {
                             nick_set.insert(wire::drop_nick_prefix(nick).to_owned());
                         }
                     }
                 }
             }
 
+            //
+            // RPL_ENDOFNAMES: WHO the channel if we just joined it
+            //
+            Reply(wire::Reply::EndOfNames { chan }) => {
+                let n_users = match utils::find_idx(&self.chans, |(s, _)| s == chan) {
+                    None => 0,
+                    Some(idx) => self.chans[idx].1.len(),
+                };
+                if let Some(who) = self.users.end_of_names(chan, n_users) {
+                    snd_irc_msg.try_send(who).unwrap();
+                }
+            }
+
+            //
+            // WHO and WHOX replies: update user information
+            //
+            Reply(wire::Reply::WhoReply {
+                chan,
+                nick,
+                user,
+                host,
+                flags,
+                realname,
+                ..
+            }) => {
+                self.internal_reply = self.users.is_who_in_flight(chan);
+                self.users.who_reply(nick, user, host, flags, realname);
+            }
+
+            Reply(wire::Reply::WhoSpcReply { fields }) => {
+                self.internal_reply = self.users.whox_reply(fields);
+            }
+
+            Reply(wire::Reply::EndOfWho { mask }) => {
+                self.internal_reply = self.users.is_who_in_flight(mask);
+                if let Some(who) = self.users.end_of_who(mask) {
+                    snd_irc_msg.try_send(who).unwrap();
+                }
+            }
+
+            //
+            // RPL_ISUPPORT
+            //
+            Reply(wire::Reply::ISupport { tokens }) => {
+                for token in tokens.iter() {
+                    if token.starts_with('-') {
+                        self.isupport.remove(token.trim_start_matches('-'));
+                        continue;
+                    }
+                    match find_byte(token.as_bytes(), b'=') {
+                        None => {
+                            self.isupport.insert(token.to_owned(), String::new());
+                        }
+                        Some(idx) => {
+                            self.isupport
+                                .insert(token[..idx].to_owned(), token[idx + 1..].to_owned());
+                        }
+                    }
+                }
+                self.users.set_whox(self.isupport.contains_key("WHOX"));
+            }
+
             //
             // QUIT: Update the `chans` field for the channels that the user was in
             //
@@ -431,30 +805,78 @@ impl StateInner {
                         nicks.remove(nick);
                     }
                 }
+                self.users.remove(nick);
             }
 
             //
-            // SASL authentication
+            // Capability negotiation: STS and SASL authentication
             //
             CAP {
                 client: _,
                 subcommand,
                 params,
+                more,
             } => {
                 match subcommand.as_ref() {
+                    // Capabilities are requested one at a time. Negotiation ends after the SASL
+                    // authentication when SASL is requested, otherwise after a reply to the
+                    // last request.
                     "ACK" => {
                         if params.iter().any(|cap| cap.as_str() == "sasl") {
                             snd_irc_msg.try_send(wire::authenticate("PLAIN")).unwrap();
+                        } else if !self.sasl_requested() {
+                            snd_irc_msg.try_send(wire::cap_end()).unwrap();
                         }
                     }
                     "NAK" => {
-                        snd_irc_msg.try_send(wire::cap_end()).unwrap();
+                        if params.iter().any(|cap| cap.as_str() == "sasl") || !self.sasl_requested()
+                        {
+                            snd_irc_msg.try_send(wire::cap_end()).unwrap();
+                        }
                     }
                     "LS" => {
-                        self.introduce(snd_irc_msg);
-                        if params.iter().any(|cap| cap == "sasl") {
+                        for cap in params.iter().filter(|cap| !cap.is_empty()) {
+                            match find_byte(cap.as_bytes(), b'=') {
+                                None => {
+                                    self.caps.insert(cap.to_owned(), String::new());
+                                }
+                                Some(idx) => {
+                                    self.caps
+                                        .insert(cap[..idx].to_owned(), cap[idx + 1..].to_owned());
+                                }
+                            }
+                        }
+                        if *more {
+                            // Wait for the rest of the capabilities
+                            return;
+                        }
+                        self.sts = self.caps.get("sts").cloned();
+                        let invite_notify = self.caps.contains_key("invite-notify");
+                        if invite_notify {
+                            snd_irc_msg
+                                .try_send(wire::cap_req(&["invite-notify"]))
+                                .unwrap();
+                        }
+                        if self.server_info.sasl_auth.is_some() {
+                            self.introduce(snd_irc_msg);
+                        }
+                        if self.sasl_requested() {
                             snd_irc_msg.try_send(wire::cap_req(&["sasl"])).unwrap();
                             // Will wait for CAP ... ACK from server before authentication.
+                        } else {
+                            if self.server_info.sasl_auth.is_some() {
+                                self.registration_error(
+                                    RegistrationError::SaslFail(
+                                        "SASL authentication is not supported by the server"
+                                            .to_owned(),
+                                    ),
+                                    snd_ev,
+                                );
+                            }
+                            if !invite_notify {
+                                // Introduced above or in `main_loop`
+                                snd_irc_msg.try_send(wire::cap_end()).unwrap();
+                            }
                         }
                     }
                     _ => {}
@@ -477,8 +899,12 @@ impl StateInner {
                 }
             }
 
-            Reply { num: 903, .. } | Reply { num: 904, .. } => {
-                // 903: RPL_SASLSUCCESS, 904: ERR_SASLFAIL
+            Reply(wire::Reply::SaslSuccess { .. }) => {
+                snd_irc_msg.try_send(wire::cap_end()).unwrap();
+            }
+
+            Reply(wire::Reply::SaslFail { msg }) => {
+                self.registration_error(RegistrationError::SaslFail(msg.to_owned()), snd_ev);
                 snd_irc_msg.try_send(wire::cap_end()).unwrap();
             }
 
@@ -489,6 +915,43 @@ impl StateInner {
         }
     }
 
+    fn registration_error(&mut self, err: RegistrationError, snd_ev: &mut Sender<Event>) {
+        if err.is_fatal() {
+            self.fatal_error = true;
+        }
+        snd_ev.try_send(Event::RegistrationError(err)).unwrap();
+    }
+
+    /// `user` is the `user@host` part of the inviter's prefix. Entries of `join_invites_from`
+    /// with a `!` are matched against `nick!user@host`, others against the inviter's account.
+    fn joins_invites_from(&self, nick: &str, user: &str) -> bool {
+        let mask = format!("{}!{}", nick, user).to_lowercase();
+        // Empty account means the user is not logged in
+        let account = self
+            .users
+            .get(nick)
+            .and_then(|info| info.account)
+            .filter(|account| !account.is_empty());
+        self.server_info.join_invites_from.iter().any(|entry| {
+            if entry.contains('!') {
+                glob_match(entry.to_lowercase().as_bytes(), mask.as_bytes())
+            } else {
+                matches!(account, Some(ref account) if account.eq_ignore_ascii_case(entry))
+            }
+        })
+    }
+
+    /// Max. number of modes with parameters in a MODE message, from the `MODES` ISUPPORT token.
+    /// A token without a value means no limit, but we still use the default to keep messages
+    /// short.
+    fn get_max_modes(&self) -> usize {
+        self.isupport
+            .get("MODES")
+            .and_then(|modes| modes.parse().ok())
+            .filter(|modes| *modes > 0)
+            .unwrap_or(DEFAULT_MAX_MODES)
+    }
+
     fn get_chan_nicks(&self, chan: &str) -> Vec<String> {
         match utils::find_idx(&self.chans, |(s, _)| s == chan) {
             None => vec![], // TODO: Log this, this is probably a bug
@@ -498,9 +961,8 @@ impl StateInner {
 }
 
 /// Try to parse servername in a 002 RPL_YOURHOST reply
-fn parse_servername(params: &[String]) -> Option<String> {
-    let msg = params.get(1).or_else(|| params.get(0))?;
-    let slice1 = &msg[13..];
+fn parse_servername(msg: &str) -> Option<String> {
+    let slice1 = msg.get(13..)?;
     let servername_ends =
         find_byte(slice1.as_bytes(), b'[').or_else(|| find_byte(slice1.as_bytes(), b','))?;
     Some((&slice1[..servername_ends]).to_owned())
@@ -514,27 +976,219 @@ mod tests {
 
     #[test]
     fn test_parse_servername_1() {
-        let args = vec![
-            "tiny_test".to_owned(),
-            "Your host is adams.freenode.net[94.125.182.252/8001], \
-             running version ircd-seven-1.1.4"
-                .to_owned(),
-        ];
-        assert_eq!(
-            parse_servername(&args),
-            Some("adams.freenode.net".to_owned())
-        );
+        let msg = "Your host is adams.freenode.net[94.125.182.252/8001], \
+                   running version ircd-seven-1.1.4";
+        assert_eq!(parse_servername(msg), Some("adams.freenode.net".to_owned()));
     }
 
     #[test]
     fn test_parse_servername_2() {
-        let args = vec![
-            "tiny_test".to_owned(),
-            "Your host is belew.mozilla.org, running version InspIRCd-2.0".to_owned(),
-        ];
+        let msg = "Your host is belew.mozilla.org, running version InspIRCd-2.0";
+        assert_eq!(parse_servername(msg), Some("belew.mozilla.org".to_owned()));
+    }
+
+    #[test]
+    fn test_registration_errors() {
+        let (snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(100);
+        let (mut snd_irc_msg, _rcv_irc_msg) = tokio::sync::mpsc::channel(100);
+        let server_info = ServerInfo {
+            name: "test".to_owned(),
+            addrs: vec![],
+            tls: false,
+            sts_file: None,
+            bind_addr: None,
+            ip_pref: crate::IpPref::Any,
+            pass: None,
+            realname: "tiny".to_owned(),
+            nicks: vec!["tiny".to_owned(), "tiny_bot".to_owned()],
+            auto_join: vec![],
+            join_invites_from: vec![],
+            nickserv_ident: None,
+            sasl_auth: None,
+            ctcp_replies: false,
+            dcc_download_dir: None,
+            record_file: None,
+        };
+        let state = State::new(server_info, Dcc::new(None, snd_ev.clone()));
+        let mut snd_ev = snd_ev;
+        let mut update = |msg: &str| {
+            let mut buf = msg.as_bytes().to_vec();
+            let mut msg = wire::parse_irc_msg(&mut buf).unwrap();
+            state.update(&mut msg, &mut snd_ev, &mut snd_irc_msg);
+            state.take_fatal_error()
+        };
+
+        // SASL failure is not fatal
+        assert!(!update(":server 904 tiny :SASL authentication failed\r\n"));
+        // First erroneous nick moves to the next nick in the list, second one is fatal
+        assert!(!update(":server 432 * tiny :Erroneous Nickname\r\n"));
+        assert_eq!(state.get_nick(), "tiny_bot");
+        assert!(update(":server 432 * tiny_bot :Erroneous Nickname\r\n"));
+        assert!(update(":server 465 * :You are banned from this server\r\n"));
+
+        // After registration these are not registration errors
+        assert!(!update(":server 001 tiny_bot :Welcome\r\n"));
+        assert!(!update(":server 464 tiny_bot :Password incorrect\r\n"));
+        assert!(!update(
+            ":server 465 tiny_bot :You are banned from this server\r\n"
+        ));
+    }
+
+    #[test]
+    fn test_joins_invites_from() {
+        let (snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(100);
+        let (mut snd_irc_msg, _rcv_irc_msg) = tokio::sync::mpsc::channel(100);
+        let server_info = ServerInfo {
+            name: "test".to_owned(),
+            addrs: vec![],
+            tls: false,
+            sts_file: None,
+            bind_addr: None,
+            ip_pref: crate::IpPref::Any,
+            pass: None,
+            realname: "tiny".to_owned(),
+            nicks: vec!["tiny".to_owned()],
+            auto_join: vec![],
+            join_invites_from: vec!["ChanServ!*@services.*".to_owned(), "alice".to_owned()],
+            nickserv_ident: None,
+            sasl_auth: None,
+            ctcp_replies: false,
+            dcc_download_dir: None,
+            record_file: None,
+        };
+        let state = State::new(server_info, Dcc::new(None, snd_ev.clone()));
+        let mut snd_ev = snd_ev;
+
+        assert!(state.joins_invites_from("chanserv", "ChanServ@services.example.org"));
+        assert!(!state.joins_invites_from("ChanServ", "ChanServ@example.org"));
+
+        // Account of alice is not known yet
+        assert!(!state.joins_invites_from("alice", "alice@example.org"));
+        // `extended-join` JOINs have accounts
+        for msg in &[
+            ":alice!alice@example.org JOIN #tiny alice :Alice\r\n",
+            ":mallory!mallory@example.org JOIN #tiny * :Mallory\r\n",
+        ] {
+            let mut buf = msg.as_bytes().to_vec();
+            let mut msg = wire::parse_irc_msg(&mut buf).unwrap();
+            state.update(&mut msg, &mut snd_ev, &mut snd_irc_msg);
+        }
+        assert!(state.joins_invites_from("alice", "alice@example.org"));
+        assert!(!state.joins_invites_from("mallory", "mallory@example.org"));
+
+        // Accounts are forgotten when users leave, and when someone else takes the nick
+        for msg in &[
+            ":alice!alice@example.org PART #tiny\r\n",
+            ":alice!mallory@example.org INVITE tiny #secret\r\n",
+        ] {
+            let mut buf = msg.as_bytes().to_vec();
+            let mut msg = wire::parse_irc_msg(&mut buf).unwrap();
+            state.update(&mut msg, &mut snd_ev, &mut snd_irc_msg);
+        }
+        assert!(!state.joins_invites_from("alice", "mallory@example.org"));
+    }
+
+    #[test]
+    fn test_max_modes() {
+        let (snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(100);
+        let (mut snd_irc_msg, _rcv_irc_msg) = tokio::sync::mpsc::channel(100);
+        let server_info = ServerInfo {
+            name: "test".to_owned(),
+            addrs: vec![],
+            tls: false,
+            sts_file: None,
+            bind_addr: None,
+            ip_pref: crate::IpPref::Any,
+            pass: None,
+            realname: "tiny".to_owned(),
+            nicks: vec!["tiny".to_owned()],
+            auto_join: vec![],
+            join_invites_from: vec![],
+            nickserv_ident: None,
+            sasl_auth: None,
+            ctcp_replies: false,
+            dcc_download_dir: None,
+            record_file: None,
+        };
+        let state = State::new(server_info, Dcc::new(None, snd_ev.clone()));
+        let mut snd_ev = snd_ev;
+        let mut update = |msg: &str| {
+            let mut buf = msg.as_bytes().to_vec();
+            let mut msg = wire::parse_irc_msg(&mut buf).unwrap();
+            state.update(&mut msg, &mut snd_ev, &mut snd_irc_msg);
+            state.get_max_modes()
+        };
+
+        assert_eq!(state.get_max_modes(), DEFAULT_MAX_MODES);
+        assert_eq!(update(":server 005 tiny MODES=4 :are supported\r\n"), 4);
+        assert_eq!(
+            update(":server 005 tiny MODES :are supported\r\n"),
+            DEFAULT_MAX_MODES
+        );
+        assert_eq!(update(":server 005 tiny MODES=6 :are supported\r\n"), 6);
         assert_eq!(
-            parse_servername(&args),
-            Some("belew.mozilla.org".to_owned())
+            update(":server 005 tiny -MODES :are supported\r\n"),
+            DEFAULT_MAX_MODES
         );
     }
+
+    #[test]
+    fn test_who_replies() {
+        let (snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(100);
+        let (mut snd_irc_msg, mut rcv_irc_msg) = tokio::sync::mpsc::channel(100);
+        let server_info = ServerInfo {
+            name: "test".to_owned(),
+            addrs: vec![],
+            tls: false,
+            sts_file: None,
+            bind_addr: None,
+            ip_pref: crate::IpPref::Any,
+            pass: None,
+            realname: "tiny".to_owned(),
+            nicks: vec!["tiny".to_owned()],
+            auto_join: vec![],
+            join_invites_from: vec![],
+            nickserv_ident: None,
+            sasl_auth: None,
+            ctcp_replies: false,
+            dcc_download_dir: None,
+            record_file: None,
+        };
+        let state = State::new(server_info, Dcc::new(None, snd_ev.clone()));
+        let mut snd_ev = snd_ev;
+        let mut update = |msg: &str| {
+            let mut buf = msg.as_bytes().to_vec();
+            let mut msg = wire::parse_irc_msg(&mut buf).unwrap();
+            state.update(&mut msg, &mut snd_ev, &mut snd_irc_msg);
+            state.take_internal_reply()
+        };
+
+        assert!(!update(":tiny!tiny@example.org JOIN #tiny\r\n"));
+        assert!(!update(":server 353 tiny = #tiny :tiny @alice bob\r\n"));
+        assert!(!update(":server 366 tiny #tiny :End of /NAMES list.\r\n"));
+        assert_eq!(rcv_irc_msg.try_recv().unwrap(), "WHO #tiny\r\n");
+
+        // Replies to our WHO are not forwarded, replies to other WHOs are. Channel names are
+        // compared case-insensitively.
+        assert!(update(
+            ":server 352 tiny #Tiny alice example.org server alice H@ :0 Alice\r\n"
+        ));
+        assert!(update(
+            ":server 352 tiny #Tiny bob example.org server bob G :0 Bob\r\n"
+        ));
+        assert!(!update(
+            ":server 352 tiny #other carol example.org server carol H :0 Carol\r\n"
+        ));
+        assert!(!update(":server 315 tiny #other :End of /WHO list.\r\n"));
+        assert!(update(":server 315 tiny #Tiny :End of /WHO list.\r\n"));
+        assert!(!update(":server 315 tiny #tiny :End of /WHO list.\r\n"));
+
+        // Users are removed from the cache when we don't share a channel anymore
+        assert!(state.get_user_info("alice").is_some());
+        update(":alice!alice@example.org PART #tiny\r\n");
+        assert_eq!(state.get_user_info("alice"), None);
+        assert!(state.get_user_info("bob").is_some());
+        update(":tiny!tiny@example.org PART #tiny\r\n");
+        assert_eq!(state.get_user_info("bob"), None);
+    }
 }
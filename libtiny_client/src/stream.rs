@@ -1,3 +1,5 @@
+use crate::ws::{self, WsStream};
+
 use native_tls;
 use std::{
     net::SocketAddr,
@@ -11,9 +13,12 @@ use tokio::{
 use tokio_tls::TlsStream;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub(crate) enum Stream {
     TcpStream(TcpStream),
     TlsStream(TlsStream<TcpStream>),
+    /// WebSocket over TCP or TLS.
+    WsStream(Box<WsStream<Stream>>),
 }
 
 pub(crate) enum StreamError {
@@ -34,17 +39,40 @@ impl From<std::io::Error> for StreamError {
 }
 
 impl Stream {
-    pub(crate) async fn new_tcp(addr: SocketAddr) -> Result<Stream, StreamError> {
-        Ok(Stream::TcpStream(TcpStream::connect(addr).await?))
+    pub(crate) fn new_tcp(tcp_stream: TcpStream) -> Stream {
+        Stream::TcpStream(tcp_stream)
     }
 
-    pub(crate) async fn new_tls(addr: SocketAddr, host_name: &str) -> Result<Stream, StreamError> {
-        let tcp_stream = TcpStream::connect(addr).await?;
+    /// Start a TLS session over an established TCP connection.
+    pub(crate) async fn new_tls(
+        tcp_stream: TcpStream,
+        host_name: &str,
+    ) -> Result<Stream, StreamError> {
         let tls_connector =
             tokio_tls::TlsConnector::from(native_tls::TlsConnector::builder().build()?);
         let tls_stream = tls_connector.connect(host_name, tcp_stream).await?;
         Ok(Stream::TlsStream(tls_stream))
     }
+
+    /// Do the WebSocket handshake over a TCP or TLS stream.
+    pub(crate) async fn new_ws(
+        stream: Stream,
+        host: &str,
+        port: u16,
+        path: &str,
+    ) -> Result<Stream, StreamError> {
+        let ws_stream = ws::connect(stream, host, port, path).await?;
+        Ok(Stream::WsStream(Box::new(ws_stream)))
+    }
+
+    /// Local address of the connection. Used in DCC offers.
+    pub(crate) fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        match self {
+            Stream::TcpStream(tcp_stream) => tcp_stream.local_addr(),
+            Stream::TlsStream(tls_stream) => tls_stream.get_ref().local_addr(),
+            Stream::WsStream(ws_stream) => ws_stream.get_ref().local_addr(),
+        }
+    }
 }
 
 //
@@ -60,6 +88,7 @@ impl AsyncRead for Stream {
         match *self {
             Stream::TcpStream(ref mut tcp_stream) => Pin::new(tcp_stream).poll_read(cx, buf),
             Stream::TlsStream(ref mut tls_stream) => Pin::new(tls_stream).poll_read(cx, buf),
+            Stream::WsStream(ref mut ws_stream) => Pin::new(&mut **ws_stream).poll_read(cx, buf),
         }
     }
 }
@@ -73,6 +102,7 @@ impl AsyncWrite for Stream {
         match *self {
             Stream::TcpStream(ref mut tcp_stream) => Pin::new(tcp_stream).poll_write(cx, buf),
             Stream::TlsStream(ref mut tls_stream) => Pin::new(tls_stream).poll_write(cx, buf),
+            Stream::WsStream(ref mut ws_stream) => Pin::new(&mut **ws_stream).poll_write(cx, buf),
         }
     }
 
@@ -80,6 +110,7 @@ impl AsyncWrite for Stream {
         match *self {
             Stream::TcpStream(ref mut tcp_stream) => Pin::new(tcp_stream).poll_flush(cx),
             Stream::TlsStream(ref mut tls_stream) => Pin::new(tls_stream).poll_flush(cx),
+            Stream::WsStream(ref mut ws_stream) => Pin::new(&mut **ws_stream).poll_flush(cx),
         }
     }
 
@@ -90,6 +121,7 @@ impl AsyncWrite for Stream {
         match *self {
             Stream::TcpStream(ref mut tcp_stream) => Pin::new(tcp_stream).poll_shutdown(cx),
             Stream::TlsStream(ref mut tls_stream) => Pin::new(tls_stream).poll_shutdown(cx),
+            Stream::WsStream(ref mut ws_stream) => Pin::new(&mut **ws_stream).poll_shutdown(cx),
         }
     }
 }
@@ -0,0 +1,223 @@
+//! Strict Transport Security (STS) policies. See https://ircv3.net/specs/extensions/sts.
+//!
+//! A server advertising the `sts` capability over plaintext tells us the TLS port to reconnect
+//! to. Over TLS it tells us how long to keep using TLS for the host, and these policies are
+//! persisted in a file so that later plaintext connections to the host are upgraded without
+//! first connecting over plaintext.
+//!
+//! File format is one policy per line: `<host> <port> <expiry time in Unix seconds>`.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs;
+
+/// Value of an `sts` capability, e.g. `port=6697,duration=2592000`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct StsValue {
+    /// TLS port to connect to. Only used when advertised over plaintext.
+    pub(crate) port: Option<u16>,
+    /// How long (in seconds) to keep the policy. Only used when advertised over TLS.
+    pub(crate) duration: Option<u64>,
+}
+
+pub(crate) fn parse_sts_value(value: &str) -> StsValue {
+    let mut ret = StsValue::default();
+    for key_value in value.split(',') {
+        let mut split = key_value.splitn(2, '=');
+        match (split.next(), split.next()) {
+            (Some("port"), Some(port)) => {
+                ret.port = port.parse().ok();
+            }
+            (Some("duration"), Some(duration)) => {
+                ret.duration = duration.parse().ok();
+            }
+            _ => {}
+        }
+    }
+    ret
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Policy {
+    port: u16,
+    /// Unix seconds. `None` for policies received over plaintext, which are not persisted.
+    expires: Option<u64>,
+}
+
+#[derive(Debug)]
+pub(crate) struct StsPolicies {
+    /// Where policies are persisted. Policies are only kept in memory when `None`.
+    file: Option<PathBuf>,
+    /// Policies of hosts, keys are lowercased host names.
+    policies: HashMap<String, Policy>,
+}
+
+impl StsPolicies {
+    /// Read policies from the file. A missing or invalid file is not an error, we just start
+    /// without any policies.
+    pub(crate) async fn new(file: Option<PathBuf>) -> StsPolicies {
+        let policies = match file {
+            None => HashMap::new(),
+            Some(ref file) => match fs::read_to_string(file).await {
+                Err(_) => HashMap::new(),
+                Ok(contents) => parse_policies(&contents),
+            },
+        };
+        StsPolicies { file, policies }
+    }
+
+    /// TLS port for `host`, if we have a policy for it.
+    pub(crate) fn get_port(&self, host: &str) -> Option<u16> {
+        let now = now();
+        self.policies
+            .get(&host.to_lowercase())
+            .filter(|policy| policy.expires.map(|expires| expires > now).unwrap_or(true))
+            .map(|policy| policy.port)
+    }
+
+    /// Add a policy received over plaintext, used until the client stops.
+    pub(crate) fn upgrade(&mut self, host: &str, port: u16) {
+        self.policies.insert(
+            host.to_lowercase(),
+            Policy {
+                port,
+                expires: None,
+            },
+        );
+    }
+
+    /// Add (or remove, when `duration` is 0) a policy received over TLS, and persist it.
+    pub(crate) async fn update(
+        &mut self,
+        host: &str,
+        port: u16,
+        duration: u64,
+    ) -> std::io::Result<()> {
+        let host = host.to_lowercase();
+        let policy = if duration == 0 {
+            None
+        } else {
+            Some(Policy {
+                port,
+                expires: Some(now() + duration),
+            })
+        };
+        update_policy(&mut self.policies, &host, policy);
+
+        let file = match self.file {
+            None => {
+                return Ok(());
+            }
+            Some(ref file) => file,
+        };
+
+        // Other clients may have updated the file since we read it, so update the policies in
+        // the file instead of writing ours
+        let mut file_policies = match fs::read_to_string(file).await {
+            Err(_) => HashMap::new(),
+            Ok(contents) => parse_policies(&contents),
+        };
+        update_policy(&mut file_policies, &host, policy);
+
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let now = now();
+        let mut contents = String::new();
+        for (host, policy) in &file_policies {
+            if let Some(expires) = policy.expires {
+                if expires > now {
+                    contents.push_str(&format!("{} {} {}\n", host, policy.port, expires));
+                }
+            }
+        }
+        fs::write(file, contents).await
+    }
+}
+
+fn update_policy(policies: &mut HashMap<String, Policy>, host: &str, policy: Option<Policy>) {
+    match policy {
+        None => {
+            policies.remove(host);
+        }
+        Some(policy) => {
+            policies.insert(host.to_owned(), policy);
+        }
+    }
+}
+
+fn parse_policies(contents: &str) -> HashMap<String, Policy> {
+    let mut policies = HashMap::new();
+    for line in contents.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if let [host, port, expires] = words.as_slice() {
+            if let (Ok(port), Ok(expires)) = (port.parse(), expires.parse()) {
+                policies.insert(
+                    (*host).to_owned(),
+                    Policy {
+                        port,
+                        expires: Some(expires),
+                    },
+                );
+            }
+        }
+    }
+    policies
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sts_value() {
+        assert_eq!(
+            parse_sts_value("port=6697,duration=2592000,preload"),
+            StsValue {
+                port: Some(6697),
+                duration: Some(2_592_000),
+            }
+        );
+        assert_eq!(
+            parse_sts_value("duration=0"),
+            StsValue {
+                port: None,
+                duration: Some(0),
+            }
+        );
+    }
+
+    #[test]
+    fn test_policies() {
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut policies = StsPolicies::new(None).await;
+            policies.upgrade("irc.Example.org", 6697);
+            assert_eq!(policies.get_port("IRC.example.org"), Some(6697));
+            policies.update("irc.example.org", 6697, 0).await.unwrap();
+            assert_eq!(policies.get_port("irc.example.org"), None);
+        });
+
+        let policies = parse_policies("irc.example.org 6697 100\nirc.example.com 6697 x\n");
+        assert_eq!(
+            policies.get("irc.example.org"),
+            Some(&Policy {
+                port: 6697,
+                expires: Some(100),
+            })
+        );
+        assert_eq!(policies.len(), 1);
+    }
+}
@@ -0,0 +1,135 @@
+//! Keeping track of channel topics, from RPL_TOPIC, RPL_TOPICWHOTIME and TOPIC messages.
+
+use libtiny_wire::{Cmd, Pfx, Reply};
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Topic of a channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Topic {
+    pub topic: String,
+    /// Nick (or `nick!user@host`, depending on the server) of the user who set the topic. `None`
+    /// until the server sends RPL_TOPICWHOTIME.
+    pub set_by: Option<String>,
+    /// When the topic was set, in seconds since Unix epoch.
+    pub set_at: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Topics {
+    /// Keys are lowercase channel names. Channels without topics are not in the map.
+    topics: HashMap<String, Topic>,
+}
+
+impl Topics {
+    pub(crate) fn reset(&mut self) {
+        self.topics.clear();
+    }
+
+    pub(crate) fn get(&self, chan: &str) -> Option<Topic> {
+        self.topics.get(&chan.to_lowercase()).cloned()
+    }
+
+    /// Forget the topic of a channel, e.g. after leaving the channel.
+    pub(crate) fn remove(&mut self, chan: &str) {
+        self.topics.remove(&chan.to_lowercase());
+    }
+
+    pub(crate) fn update(&mut self, pfx: &Option<Pfx>, cmd: &Cmd) {
+        match cmd {
+            Cmd::TOPIC { chan, topic } => {
+                if topic.is_empty() {
+                    self.remove(chan);
+                    return;
+                }
+                let set_by = match pfx {
+                    Some(Pfx::User { nick, .. }) | Some(Pfx::Server(nick)) => Some(nick.clone()),
+                    None => None,
+                };
+                self.topics.insert(
+                    chan.to_lowercase(),
+                    Topic {
+                        topic: topic.clone(),
+                        set_by,
+                        set_at: Some(now_secs()),
+                    },
+                );
+            }
+            Cmd::Reply(Reply::Topic { chan, topic }) => {
+                self.topics.insert(
+                    chan.to_lowercase(),
+                    Topic {
+                        topic: topic.clone(),
+                        set_by: None,
+                        set_at: None,
+                    },
+                );
+            }
+            Cmd::Reply(Reply::NoTopic { chan }) => {
+                self.remove(chan);
+            }
+            Cmd::Reply(Reply::TopicWhoTime { chan, nick, set_at }) => {
+                if let Some(topic) = self.topics.get_mut(&chan.to_lowercase()) {
+                    topic.set_by = Some(nick.clone());
+                    topic.set_at = Some(*set_at);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libtiny_wire as wire;
+
+    fn parse(msgs: &str) -> Vec<wire::Msg> {
+        let mut buf = msgs.as_bytes().to_vec();
+        let mut ret = vec![];
+        while let Some(msg) = wire::parse_irc_msg(&mut buf) {
+            ret.push(msg);
+        }
+        ret
+    }
+
+    #[test]
+    fn test_topics() {
+        let mut topics = Topics::default();
+        for msg in parse(
+            ":irc.example.org 332 tiny #Tiny :Topic 1\r\n\
+             :irc.example.org 333 tiny #Tiny alice!alice@host 1600000000\r\n",
+        ) {
+            topics.update(&msg.pfx, &msg.cmd);
+        }
+        assert_eq!(
+            topics.get("#tiny"),
+            Some(Topic {
+                topic: "Topic 1".to_owned(),
+                set_by: Some("alice!alice@host".to_owned()),
+                set_at: Some(1_600_000_000),
+            })
+        );
+
+        for msg in parse(":bob!bob@host TOPIC #tiny :Topic 2\r\n") {
+            topics.update(&msg.pfx, &msg.cmd);
+        }
+        let topic = topics.get("#tiny").unwrap();
+        assert_eq!(topic.topic, "Topic 2");
+        assert_eq!(topic.set_by.as_deref(), Some("bob"));
+        assert!(topic.set_at.unwrap() > 1_600_000_000);
+
+        for msg in parse(":bob!bob@host TOPIC #tiny :\r\n") {
+            topics.update(&msg.pfx, &msg.cmd);
+        }
+        assert_eq!(topics.get("#tiny"), None);
+    }
+}
@@ -0,0 +1,309 @@
+//! Information about users in the channels we're in: hosts, accounts, real names, away states.
+//! Collected from message prefixes, `extended-join` JOINs, and WHO (or WHOX, when supported)
+//! replies to the WHO requests we send after joining channels.
+
+use libtiny_wire as wire;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Channels with more users than this are not WHO'd after joining, to avoid flooding the
+/// connection with replies.
+const WHO_MAX_USERS: usize = 500;
+
+/// Token used in WHOX requests, to be able to tell replies to our requests apart from replies to
+/// requests sent by the user.
+const WHOX_TOKEN: &str = "152";
+
+/// Fields requested in WHOX requests: token, channel, nick, user, host, real name, account, flags.
+/// Replies have these in the order specified in the WHOX spec: token, channel, user, host, nick,
+/// flags, account, real name.
+const WHOX_FIELDS: &str = "tcnuhraf";
+
+/// Information about a user. Fields are `None` until we learn about them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserInfo {
+    pub nick: String,
+    pub user: Option<String>,
+    pub host: Option<String>,
+    pub realname: Option<String>,
+    /// Services account name. `Some("")` means the user is known to be not logged in. Only
+    /// available with WHOX or `extended-join`.
+    pub account: Option<String>,
+    pub away: Option<bool>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Users {
+    /// Keys are lowercase nicks.
+    users: HashMap<String, UserInfo>,
+
+    /// Channels we joined and waiting for RPL_ENDOFNAMES to decide whether to WHO the channel.
+    awaiting_names: HashSet<String>,
+
+    /// Channels to WHO. We only have one WHO request in flight at a time.
+    who_queue: VecDeque<String>,
+
+    /// The channel for the WHO request in flight.
+    who_in_flight: Option<String>,
+
+    /// Does the server support WHOX? Set from RPL_ISUPPORT.
+    whox: bool,
+}
+
+impl Users {
+    pub(crate) fn reset(&mut self) {
+        self.users.clear();
+        self.awaiting_names.clear();
+        self.who_queue.clear();
+        self.who_in_flight = None;
+        self.whox = false;
+    }
+
+    pub(crate) fn set_whox(&mut self, whox: bool) {
+        self.whox = whox;
+    }
+
+    pub(crate) fn get(&self, nick: &str) -> Option<UserInfo> {
+        self.users.get(&nick.to_lowercase()).cloned()
+    }
+
+    fn entry(&mut self, nick: &str) -> &mut UserInfo {
+        self.users
+            .entry(nick.to_lowercase())
+            .or_insert_with(|| UserInfo {
+                nick: nick.to_owned(),
+                ..UserInfo::default()
+            })
+    }
+
+    /// Update user and host of a user from a message prefix (`user@host` part of
+    /// `nick!user@host`). When these change the nick is probably used by someone else now, so the
+    /// rest of the information is dropped.
+    pub(crate) fn update_from_pfx(&mut self, nick: &str, user_host: &str) {
+        if let Some((user, host)) = split_user_host(user_host) {
+            let info = self.entry(nick);
+            if info.user.as_deref() != Some(user) || info.host.as_deref() != Some(host) {
+                *info = UserInfo {
+                    nick: info.nick.clone(),
+                    user: Some(user.to_owned()),
+                    host: Some(host.to_owned()),
+                    ..UserInfo::default()
+                };
+            }
+        }
+    }
+
+    /// Update account and real name of a user from an `extended-join` JOIN.
+    pub(crate) fn update_from_join(
+        &mut self,
+        nick: &str,
+        account: &Option<String>,
+        realname: &Option<String>,
+    ) {
+        if let Some(realname) = realname {
+            let info = self.entry(nick);
+            info.realname = Some(realname.clone());
+            // `account` is `None` when the user is not logged in with extended-join
+            info.account = Some(account.clone().unwrap_or_default());
+        }
+    }
+
+    /// NICK. The account is dropped, we don't get notified when it changes so we only trust
+    /// accounts from WHOX replies and JOINs.
+    pub(crate) fn rename(&mut self, old_nick: &str, new_nick: &str) {
+        if let Some(mut info) = self.users.remove(&old_nick.to_lowercase()) {
+            info.nick = new_nick.to_owned();
+            info.account = None;
+            self.users.insert(new_nick.to_lowercase(), info);
+        }
+    }
+
+    pub(crate) fn remove(&mut self, nick: &str) {
+        self.users.remove(&nick.to_lowercase());
+    }
+
+    /// We joined a channel. The channel will be WHO'd after RPL_ENDOFNAMES if it's not too large.
+    pub(crate) fn joined(&mut self, chan: &str) {
+        self.awaiting_names.insert(chan.to_owned());
+    }
+
+    /// RPL_ENDOFNAMES for a channel with `n_users` users. Returns a WHO message to send.
+    pub(crate) fn end_of_names(&mut self, chan: &str, n_users: usize) -> Option<String> {
+        if !self.awaiting_names.remove(chan) {
+            return None;
+        }
+        if n_users > WHO_MAX_USERS {
+            debug!("Not sending WHO for {}: {} users", chan, n_users);
+            return None;
+        }
+        self.who_queue.push_back(chan.to_owned());
+        self.next_who()
+    }
+
+    /// Is `mask` the mask of the WHO request we sent? Used to tell replies to our requests apart
+    /// from replies to requests sent by the user.
+    pub(crate) fn is_who_in_flight(&self, mask: &str) -> bool {
+        match &self.who_in_flight {
+            Some(chan) => wire::irc_eq(chan, mask),
+            None => false,
+        }
+    }
+
+    /// RPL_ENDOFWHO. Returns the next WHO message to send.
+    pub(crate) fn end_of_who(&mut self, mask: &str) -> Option<String> {
+        if self.is_who_in_flight(mask) {
+            self.who_in_flight = None;
+        }
+        self.next_who()
+    }
+
+    fn next_who(&mut self) -> Option<String> {
+        if self.who_in_flight.is_some() {
+            return None;
+        }
+        let chan = self.who_queue.pop_front()?;
+        let msg = if self.whox {
+            wire::whox(&chan, WHOX_FIELDS, WHOX_TOKEN)
+        } else {
+            wire::who(&chan)
+        };
+        self.who_in_flight = Some(chan);
+        Some(msg)
+    }
+
+    /// RPL_WHOREPLY
+    pub(crate) fn who_reply(
+        &mut self,
+        nick: &str,
+        user: &str,
+        host: &str,
+        flags: &str,
+        realname: &str,
+    ) {
+        let info = self.entry(nick);
+        info.user = Some(user.to_owned());
+        info.host = Some(host.to_owned());
+        info.realname = Some(realname.to_owned());
+        info.away = parse_away_flag(flags);
+    }
+
+    /// RPL_WHOSPCRPL. Replies to requests not sent by us are ignored. Returns whether the reply is
+    /// to our request.
+    pub(crate) fn whox_reply(&mut self, fields: &[String]) -> bool {
+        match fields {
+            [token, _chan, user, host, nick, flags, account, realname] if token == WHOX_TOKEN => {
+                let info = self.entry(nick);
+                info.user = Some(user.to_owned());
+                info.host = Some(host.to_owned());
+                info.realname = Some(realname.to_owned());
+                info.away = parse_away_flag(flags);
+                // "0" means not logged in
+                info.account = Some(if account == "0" {
+                    String::new()
+                } else {
+                    account.to_owned()
+                });
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// WHO flags start with `H` (here) or `G` (gone).
+fn parse_away_flag(flags: &str) -> Option<bool> {
+    match flags.as_bytes().first() {
+        Some(b'H') => Some(false),
+        Some(b'G') => Some(true),
+        _ => None,
+    }
+}
+
+fn split_user_host(user_host: &str) -> Option<(&str, &str)> {
+    let idx = wire::find_byte(user_host.as_bytes(), b'@')?;
+    Some((&user_host[..idx], &user_host[idx + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_who_queue() {
+        let mut users = Users::default();
+        users.set_whox(true);
+
+        // Only channels we joined are WHO'd
+        assert_eq!(users.end_of_names("#foo", 10), None);
+
+        users.joined("#a");
+        users.joined("#b");
+        users.joined("#c");
+        assert_eq!(
+            users.end_of_names("#a", 10),
+            Some("WHO #a %tcnuhraf,152\r\n".to_owned())
+        );
+        // One request at a time
+        assert_eq!(users.end_of_names("#b", 10), None);
+        // Too large
+        assert_eq!(users.end_of_names("#c", WHO_MAX_USERS + 1), None);
+        assert!(users.is_who_in_flight("#A"));
+        assert!(!users.is_who_in_flight("#b"));
+        assert_eq!(
+            users.end_of_who("#A"),
+            Some("WHO #b %tcnuhraf,152\r\n".to_owned())
+        );
+        assert_eq!(users.end_of_who("#b"), None);
+    }
+
+    #[test]
+    fn test_user_info() {
+        let mut users = Users::default();
+        users.update_from_pfx("osa1", "~omer@host");
+        assert!(users.whox_reply(&[
+            "152".to_owned(),
+            "#tiny".to_owned(),
+            "~omer".to_owned(),
+            "host".to_owned(),
+            "osa1".to_owned(),
+            "G@".to_owned(),
+            "0".to_owned(),
+            "Ömer".to_owned(),
+        ]));
+        assert_eq!(users.get("osa1").unwrap().account, Some("".to_owned()));
+        users.rename("osa1", "osa2");
+        assert_eq!(users.get("osa1"), None);
+        assert_eq!(
+            users.get("OSA2"),
+            Some(UserInfo {
+                nick: "osa2".to_owned(),
+                user: Some("~omer".to_owned()),
+                host: Some("host".to_owned()),
+                realname: Some("Ömer".to_owned()),
+                account: None,
+                away: Some(true),
+            })
+        );
+
+        // Same user and host, information is kept
+        users.update_from_pfx("osa2", "~omer@host");
+        assert_eq!(users.get("osa2").unwrap().realname, Some("Ömer".to_owned()));
+        // Someone else took the nick
+        users.update_from_pfx("osa2", "~mallory@example.org");
+        assert_eq!(
+            users.get("osa2"),
+            Some(UserInfo {
+                nick: "osa2".to_owned(),
+                user: Some("~mallory".to_owned()),
+                host: Some("example.org".to_owned()),
+                ..UserInfo::default()
+            })
+        );
+
+        users.who_reply("foo", "bar", "baz", "H", "Foo Bar");
+        assert_eq!(users.get("foo").unwrap().away, Some(false));
+        assert_eq!(users.get("foo").unwrap().account, None);
+        users.remove("foo");
+        assert_eq!(users.get("foo"), None);
+    }
+}
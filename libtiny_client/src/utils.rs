@@ -1,3 +1,5 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
 pub(crate) struct SplitIterator<'a> {
     s: Option<&'a str>,
     max: usize,
@@ -63,6 +65,13 @@ impl<'a> Iterator for SplitIterator<'a> {
     }
 }
 
+/// Lock a mutex, ignoring poisoning. The state behind our mutexes is still usable after a panic
+/// in another task, and propagating the panic to every `Client` method would only make things
+/// worse.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
 pub(crate) fn find_idx<A, F: Fn(&A) -> bool>(slice: &[A], f: F) -> Option<usize> {
     for (idx, a) in slice.iter().enumerate() {
         if f(a) {
@@ -0,0 +1,241 @@
+//! Collecting WHOIS and WHOWAS replies into a single `WhoisInfo`.
+
+use libtiny_wire::Reply;
+
+use std::collections::HashMap;
+
+/// Result of a WHOIS or WHOWAS query. Fields are `None` (or empty) when the server didn't send
+/// the corresponding reply. `user` is `None` when the user does not exist (or, for WHOWAS, there
+/// isn't a history for the nick).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WhoisInfo {
+    pub nick: String,
+    /// Whether this is a WHOWAS result.
+    pub whowas: bool,
+    pub user: Option<String>,
+    pub host: Option<String>,
+    pub realname: Option<String>,
+    /// Server the user is (or was) connected to.
+    pub server: Option<String>,
+    /// Server description, or for WHOWAS the time the user was last seen.
+    pub server_info: Option<String>,
+    /// Services account name.
+    pub account: Option<String>,
+    /// Channels, with membership prefixes (`@`, `+` etc.).
+    pub chans: Vec<String>,
+    pub idle_secs: Option<u64>,
+    /// Sign-on time, in seconds since the epoch.
+    pub signon: Option<u64>,
+    /// Away message when the user is away.
+    pub away: Option<String>,
+    /// RPL_WHOISOPERATOR message, when the user is an IRC operator.
+    pub operator: Option<String>,
+    /// RPL_WHOISHOST message, usually the actual host and IP address of the user. Only sent to
+    /// IRC operators and the user itself.
+    pub actual_host: Option<String>,
+    /// Whether the user is connected with TLS.
+    pub secure: bool,
+}
+
+/// Collects WHOIS and WHOWAS replies until the end of the replies (RPL_ENDOFWHOIS and
+/// RPL_ENDOFWHOWAS).
+#[derive(Debug, Default)]
+pub(crate) struct Whois {
+    /// Queries in progress. Keys are lowercase nicks.
+    pending: HashMap<String, WhoisInfo>,
+}
+
+impl Whois {
+    /// Returns the result when `reply` ends a WHOIS or WHOWAS.
+    pub(crate) fn update(&mut self, reply: &Reply) -> Option<WhoisInfo> {
+        use Reply::*;
+        match reply {
+            WhoisUser {
+                nick,
+                user,
+                host,
+                realname,
+            } => {
+                let info = self.entry(nick, false);
+                info.user = Some(user.clone());
+                info.host = Some(host.clone());
+                info.realname = Some(realname.clone());
+            }
+            WhowasUser {
+                nick,
+                user,
+                host,
+                realname,
+            } => {
+                // WHOWAS may return multiple entries for a nick, we only keep the latest one,
+                // which is sent first
+                let info = self.entry(nick, true);
+                if info.user.is_none() {
+                    info.user = Some(user.clone());
+                    info.host = Some(host.clone());
+                    info.realname = Some(realname.clone());
+                }
+            }
+            WhoisServer { nick, server, info } => {
+                let entry = self.entry(nick, false);
+                if entry.server.is_none() {
+                    entry.server = Some(server.clone());
+                    entry.server_info = Some(info.clone());
+                }
+            }
+            WhoisOperator { nick, msg } => {
+                self.entry(nick, false).operator = Some(msg.clone());
+            }
+            WhoisIdle {
+                nick,
+                idle_secs,
+                signon,
+            } => {
+                let info = self.entry(nick, false);
+                info.idle_secs = Some(*idle_secs);
+                info.signon = *signon;
+            }
+            WhoisChannels { nick, chans } => {
+                // Servers split long channel lists into multiple replies
+                self.entry(nick, false).chans.extend(chans.iter().cloned());
+            }
+            WhoisAccount { nick, account } => {
+                let info = self.entry(nick, false);
+                if info.account.is_none() {
+                    info.account = Some(account.clone());
+                }
+            }
+            WhoisHost { nick, msg } => {
+                self.entry(nick, false).actual_host = Some(msg.clone());
+            }
+            WhoisSecure { nick, .. } => {
+                self.entry(nick, false).secure = true;
+            }
+            Away { nick, msg } => {
+                // RPL_AWAY is also sent when we message an away user, only use it when we're
+                // collecting a WHOIS for the nick
+                if let Some(info) = self.pending.get_mut(&nick.to_lowercase()) {
+                    info.away = Some(msg.clone());
+                }
+            }
+            EndOfWhois { nick } => {
+                return Some(self.finish(nick, false));
+            }
+            EndOfWhowas { nick } => {
+                return Some(self.finish(nick, true));
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Get the query in progress for the nick, or start a new one.
+    fn entry(&mut self, nick: &str, whowas: bool) -> &mut WhoisInfo {
+        self.pending
+            .entry(nick.to_lowercase())
+            .or_insert_with(|| WhoisInfo {
+                nick: nick.to_owned(),
+                whowas,
+                ..WhoisInfo::default()
+            })
+    }
+
+    /// Remove the query for the nick and return the result.
+    fn finish(&mut self, nick: &str, whowas: bool) -> WhoisInfo {
+        let mut info = self
+            .pending
+            .remove(&nick.to_lowercase())
+            .unwrap_or_default();
+        info.nick = nick.to_owned();
+        info.whowas = whowas;
+        info
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_whois() {
+        let mut whois = Whois::default();
+        let replies = vec![
+            Reply::WhoisUser {
+                nick: "osa1".to_owned(),
+                user: "~osa1".to_owned(),
+                host: "host".to_owned(),
+                realname: "Ömer".to_owned(),
+            },
+            Reply::WhoisChannels {
+                nick: "osa1".to_owned(),
+                chans: vec!["@#tiny".to_owned()],
+            },
+            Reply::WhoisChannels {
+                nick: "osa1".to_owned(),
+                chans: vec!["#rust".to_owned()],
+            },
+            Reply::Away {
+                nick: "OSA1".to_owned(),
+                msg: "gone".to_owned(),
+            },
+            Reply::WhoisAccount {
+                nick: "osa1".to_owned(),
+                account: "osa1".to_owned(),
+            },
+            Reply::WhoisSecure {
+                nick: "osa1".to_owned(),
+                msg: "is using a secure connection".to_owned(),
+            },
+        ];
+        for reply in &replies {
+            assert_eq!(whois.update(reply), None);
+        }
+
+        // Away without a WHOIS in progress is ignored
+        assert_eq!(
+            whois.update(&Reply::Away {
+                nick: "foo".to_owned(),
+                msg: "gone".to_owned(),
+            }),
+            None
+        );
+
+        assert_eq!(
+            whois.update(&Reply::EndOfWhois {
+                nick: "osa1".to_owned()
+            }),
+            Some(WhoisInfo {
+                nick: "osa1".to_owned(),
+                whowas: false,
+                user: Some("~osa1".to_owned()),
+                host: Some("host".to_owned()),
+                realname: Some("Ömer".to_owned()),
+                chans: vec!["@#tiny".to_owned(), "#rust".to_owned()],
+                away: Some("gone".to_owned()),
+                account: Some("osa1".to_owned()),
+                secure: true,
+                ..WhoisInfo::default()
+            })
+        );
+        assert!(whois.pending.is_empty());
+    }
+
+    #[test]
+    fn test_whowas_no_such_nick() {
+        let mut whois = Whois::default();
+        assert_eq!(
+            whois.update(&Reply::EndOfWhowas {
+                nick: "foo".to_owned()
+            }),
+            Some(WhoisInfo {
+                nick: "foo".to_owned(),
+                whowas: true,
+                ..WhoisInfo::default()
+            })
+        );
+    }
+}
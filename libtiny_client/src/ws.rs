@@ -0,0 +1,556 @@
+//! WebSocket transport, as described in the IRCv3 WebSocket spec
+//! (https://ircv3.net/specs/extensions/websocket).
+//!
+//! Each IRC message is sent in a WebSocket message, without the trailing "\r\n". `WsStream` adds
+//! the "\r\n" back to messages it reads, and splits written bytes into messages at line endings,
+//! so the rest of the client can use it like a TCP stream.
+
+use futures::ready;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const TEXT_PROTOCOL: &str = "text.ircv3.net";
+const BINARY_PROTOCOL: &str = "binary.ircv3.net";
+
+/// Used in Sec-WebSocket-Accept, see RFC 6455 section 1.3.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Max. size of the HTTP response header in the handshake.
+const MAX_HEADER_LEN: usize = 8 * 1024;
+
+/// Max. size of a frame payload, and of a message made of multiple frames. IRC messages are much
+/// smaller than this.
+const MAX_PAYLOAD_LEN: u64 = 1024 * 1024;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+#[derive(Debug)]
+pub(crate) struct WsStream<S> {
+    inner: S,
+
+    /// Was `binary.ircv3.net` subprotocol selected by the server? We send binary frames instead
+    /// of text frames in that case.
+    binary: bool,
+
+    /// Bytes read from the socket, not decoded yet.
+    read_buf: Vec<u8>,
+
+    /// Payloads of frames of a fragmented message.
+    fragments: Vec<u8>,
+
+    /// Decoded IRC messages (with "\r\n" suffixes) not yet returned by `poll_read`.
+    msgs: Vec<u8>,
+
+    /// Written bytes that don't form a complete IRC message yet.
+    line_buf: Vec<u8>,
+
+    /// Encoded frames not yet written to the socket.
+    write_buf: Vec<u8>,
+
+    /// Did the server close the connection?
+    closed: bool,
+}
+
+/// Do the WebSocket opening handshake on `stream`.
+pub(crate) async fn connect<S>(
+    mut stream: S,
+    host: &str,
+    port: u16,
+    path: &str,
+) -> io::Result<WsStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let key = base64::encode(&rand::random::<[u8; 16]>());
+
+    // IPv6 addresses need brackets in the Host header
+    let host_header = if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    };
+
+    let request = format!(
+        "GET {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: {}\r\n\
+         Sec-WebSocket-Version: 13\r\n\
+         Sec-WebSocket-Protocol: {}, {}\r\n\
+         \r\n",
+        path, host_header, key, TEXT_PROTOCOL, BINARY_PROTOCOL
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut buf: Vec<u8> = vec![];
+    let header_end = loop {
+        if let Some(idx) = buf.windows(4).position(|bytes| bytes == b"\r\n\r\n") {
+            break idx + 4;
+        }
+        if buf.len() > MAX_HEADER_LEN {
+            return Err(invalid_data("WebSocket handshake response is too long"));
+        }
+        let mut read_buf = [0u8; 1024];
+        let n_read = stream.read(&mut read_buf).await?;
+        if n_read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection closed during WebSocket handshake",
+            ));
+        }
+        buf.extend_from_slice(&read_buf[..n_read]);
+    };
+
+    let binary = check_response(&String::from_utf8_lossy(&buf[..header_end]), &key)?;
+
+    Ok(WsStream {
+        inner: stream,
+        binary,
+        // Server may send messages right after the handshake
+        read_buf: buf[header_end..].to_vec(),
+        fragments: vec![],
+        msgs: vec![],
+        line_buf: vec![],
+        write_buf: vec![],
+        closed: false,
+    })
+}
+
+/// Check the handshake response. Returns whether the binary subprotocol is selected.
+fn check_response(response: &str, key: &str) -> io::Result<bool> {
+    let mut lines = response.lines();
+    let status_line = lines.next().unwrap_or("");
+    if status_line.split_whitespace().nth(1) != Some("101") {
+        return Err(invalid_data(&format!(
+            "WebSocket handshake failed: {}",
+            status_line
+        )));
+    }
+
+    let mut accept = None;
+    let mut binary = false;
+    for line in lines {
+        let mut split = line.splitn(2, ':');
+        let (name, value) = match (split.next(), split.next()) {
+            (Some(name), Some(value)) => (name.trim().to_lowercase(), value.trim()),
+            _ => {
+                continue;
+            }
+        };
+        if name == "sec-websocket-accept" {
+            accept = Some(value);
+        } else if name == "sec-websocket-protocol" {
+            binary = value == BINARY_PROTOCOL;
+        }
+    }
+
+    if accept != Some(accept_key(key).as_str()) {
+        return Err(invalid_data(
+            "Invalid Sec-WebSocket-Accept in handshake response",
+        ));
+    }
+
+    Ok(binary)
+}
+
+fn accept_key(key: &str) -> String {
+    base64::encode(
+        &sha1::Sha1::from(format!("{}{}", key, ACCEPT_GUID))
+            .digest()
+            .bytes(),
+    )
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl<S> WsStream<S> {
+    pub(crate) fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Decode frames in `read_buf`. Complete messages are moved to `msgs`, replies to control
+    /// frames are added to `write_buf`.
+    fn decode_frames(&mut self) -> io::Result<()> {
+        while let Some((frame, frame_len)) = decode_frame(&self.read_buf)? {
+            self.read_buf.drain(..frame_len);
+            match frame.opcode {
+                OPCODE_CONTINUATION | OPCODE_TEXT | OPCODE_BINARY => {
+                    if (self.fragments.len() + frame.payload.len()) as u64 > MAX_PAYLOAD_LEN {
+                        return Err(invalid_data("WebSocket message is too long"));
+                    }
+                    self.fragments.extend_from_slice(&frame.payload);
+                    if frame.fin {
+                        // Servers shouldn't send line endings, but be lenient
+                        while self.fragments.last() == Some(&b'\n')
+                            || self.fragments.last() == Some(&b'\r')
+                        {
+                            self.fragments.pop();
+                        }
+                        self.msgs.extend_from_slice(&self.fragments);
+                        self.msgs.extend_from_slice(b"\r\n");
+                        self.fragments.clear();
+                    }
+                }
+                OPCODE_PING => {
+                    let mask = rand::random();
+                    encode_frame(OPCODE_PONG, &frame.payload, mask, &mut self.write_buf);
+                }
+                OPCODE_CLOSE if !self.closed => {
+                    // Echo the status code
+                    let mask = rand::random();
+                    let status = &frame.payload[..std::cmp::min(frame.payload.len(), 2)];
+                    encode_frame(OPCODE_CLOSE, status, mask, &mut self.write_buf);
+                    self.closed = true;
+                }
+                _ => {
+                    // Pong, a second close, or an unknown opcode
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Add complete lines in `line_buf` to `write_buf` as frames.
+    fn encode_lines(&mut self) {
+        let opcode = if self.binary {
+            OPCODE_BINARY
+        } else {
+            OPCODE_TEXT
+        };
+        while let Some(idx) = self.line_buf.iter().position(|byte| *byte == b'\n') {
+            let mut line: Vec<u8> = self.line_buf.drain(..=idx).collect();
+            while line.last() == Some(&b'\n') || line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            if !line.is_empty() {
+                let mask = rand::random();
+                encode_frame(opcode, &line, mask, &mut self.write_buf);
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> WsStream<S> {
+    fn poll_write_buf(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        while !self.write_buf.is_empty() {
+            let n_written = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.write_buf))?;
+            if n_written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.write_buf.drain(..n_written);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WsStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            this.decode_frames()?;
+
+            // Send replies to control frames. If the socket is not ready we try again on the next
+            // read or write.
+            if let Poll::Ready(Err(err)) = this.poll_write_buf(cx) {
+                return Poll::Ready(Err(err));
+            }
+
+            if !this.msgs.is_empty() {
+                let n_read = std::cmp::min(buf.len(), this.msgs.len());
+                buf[..n_read].copy_from_slice(&this.msgs[..n_read]);
+                this.msgs.drain(..n_read);
+                return Poll::Ready(Ok(n_read));
+            }
+
+            if this.closed {
+                return Poll::Ready(Ok(0));
+            }
+
+            let mut read_buf = [0u8; 1024];
+            match ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read_buf))? {
+                0 => {
+                    return Poll::Ready(Ok(0));
+                }
+                n_read => {
+                    this.read_buf.extend_from_slice(&read_buf[..n_read]);
+                }
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for WsStream<S> {
+    /// Written bytes are sent in WebSocket messages, line by line. Frames that can't be written
+    /// right away are written on the next write or flush.
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        // Don't buffer more before writing what we have
+        ready!(this.poll_write_buf(cx))?;
+        this.line_buf.extend_from_slice(buf);
+        this.encode_lines();
+        if let Poll::Ready(Err(err)) = this.poll_write_buf(cx) {
+            return Poll::Ready(Err(err));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_buf(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_buf(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+//
+// Frames
+//
+
+#[derive(Debug, PartialEq, Eq)]
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// Encode a final (unfragmented) frame, masked with `mask` as required for client frames.
+fn encode_frame(opcode: u8, payload: &[u8], mask: [u8; 4], buf: &mut Vec<u8>) {
+    buf.push(0x80 | opcode);
+    let len = payload.len();
+    if len < 126 {
+        buf.push(0x80 | len as u8);
+    } else if len <= 0xFFFF {
+        buf.push(0x80 | 126);
+        buf.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        buf.push(0x80 | 127);
+        buf.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+    buf.extend_from_slice(&mask);
+    buf.extend(
+        payload
+            .iter()
+            .enumerate()
+            .map(|(idx, byte)| byte ^ mask[idx % 4]),
+    );
+}
+
+/// Decode a frame at the beginning of `buf`. Returns the frame and its length in `buf`, or
+/// `None` if `buf` doesn't have a complete frame yet.
+fn decode_frame(buf: &[u8]) -> io::Result<Option<(Frame, usize)>> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    let fin = buf[0] & 0x80 != 0;
+    let opcode = buf[0] & 0x0F;
+    let masked = buf[1] & 0x80 != 0;
+
+    let (payload_len, mut idx) = match buf[1] & 0x7F {
+        126 => {
+            if buf.len() < 4 {
+                return Ok(None);
+            }
+            (u64::from(u16::from_be_bytes([buf[2], buf[3]])), 4)
+        }
+        127 => {
+            if buf.len() < 10 {
+                return Ok(None);
+            }
+            let mut len_bytes = [0u8; 8];
+            len_bytes.copy_from_slice(&buf[2..10]);
+            (u64::from_be_bytes(len_bytes), 10)
+        }
+        len => (u64::from(len), 2),
+    };
+    if payload_len > MAX_PAYLOAD_LEN {
+        return Err(invalid_data("WebSocket frame is too large"));
+    }
+
+    let mask = if masked {
+        if buf.len() < idx + 4 {
+            return Ok(None);
+        }
+        let mask = [buf[idx], buf[idx + 1], buf[idx + 2], buf[idx + 3]];
+        idx += 4;
+        Some(mask)
+    } else {
+        None
+    };
+
+    let end = idx + payload_len as usize;
+    if buf.len() < end {
+        return Ok(None);
+    }
+    let mut payload = buf[idx..end].to_vec();
+    if let Some(mask) = mask {
+        for (idx, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[idx % 4];
+        }
+    }
+
+    Ok(Some((
+        Frame {
+            fin,
+            opcode,
+            payload,
+        },
+        end,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_accept_key() {
+        // Example in RFC 6455
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let mut buf = vec![];
+        let payload = vec![b'x'; 300];
+        encode_frame(OPCODE_TEXT, b"PING :tiny", [1, 2, 3, 4], &mut buf);
+        encode_frame(OPCODE_BINARY, &payload, [5, 6, 7, 8], &mut buf);
+
+        let (frame, len) = decode_frame(&buf).unwrap().unwrap();
+        assert_eq!(
+            frame,
+            Frame {
+                fin: true,
+                opcode: OPCODE_TEXT,
+                payload: b"PING :tiny".to_vec(),
+            }
+        );
+        buf.drain(..len);
+
+        // Incomplete frame
+        assert_eq!(decode_frame(&buf[..100]).unwrap(), None);
+
+        let (frame, len) = decode_frame(&buf).unwrap().unwrap();
+        assert_eq!(frame.opcode, OPCODE_BINARY);
+        assert_eq!(frame.payload, payload);
+        assert_eq!(len, buf.len());
+    }
+
+    fn stub_read(stream: &mut std::net::TcpStream, buf: &mut Vec<u8>) {
+        let mut read_buf = [0u8; 1024];
+        let n_read = stream.read(&mut read_buf).unwrap();
+        assert!(n_read != 0);
+        buf.extend_from_slice(&read_buf[..n_read]);
+    }
+
+    fn stub_read_frame(stream: &mut std::net::TcpStream, buf: &mut Vec<u8>) -> Frame {
+        loop {
+            if let Some((frame, len)) = decode_frame(buf).unwrap() {
+                buf.drain(..len);
+                return frame;
+            }
+            stub_read(stream, buf);
+        }
+    }
+
+    /// Connect to a WebSocket IRC stub on a local port. The stub sends a ping before replying to
+    /// our message.
+    #[test]
+    fn test_ws_stream() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf: Vec<u8> = vec![];
+
+            while !buf.windows(4).any(|bytes| bytes == b"\r\n\r\n") {
+                stub_read(&mut stream, &mut buf);
+            }
+            let request = String::from_utf8(buf.clone()).unwrap();
+            assert!(request.starts_with("GET /webirc HTTP/1.1\r\n"));
+            let key = request
+                .lines()
+                .find(|line| line.starts_with("Sec-WebSocket-Key: "))
+                .unwrap()
+                .trim_start_matches("Sec-WebSocket-Key: ");
+            let response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                 Upgrade: websocket\r\n\
+                 Connection: Upgrade\r\n\
+                 Sec-WebSocket-Accept: {}\r\n\
+                 Sec-WebSocket-Protocol: text.ircv3.net\r\n\r\n",
+                accept_key(key)
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+            buf.clear();
+
+            assert_eq!(
+                stub_read_frame(&mut stream, &mut buf),
+                Frame {
+                    fin: true,
+                    opcode: OPCODE_TEXT,
+                    payload: b"PING :tiny".to_vec(),
+                }
+            );
+            stream.write_all(&[0x89, 0x01, b'p']).unwrap();
+            stream.write_all(&[0x81, 0x0A]).unwrap();
+            stream.write_all(b"PONG :tiny").unwrap();
+            assert_eq!(
+                stub_read_frame(&mut stream, &mut buf),
+                Frame {
+                    fin: true,
+                    opcode: OPCODE_PONG,
+                    payload: b"p".to_vec(),
+                }
+            );
+        });
+
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async move {
+            let tcp_stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let mut ws = connect(tcp_stream, "127.0.0.1", addr.port(), "/webirc")
+                .await
+                .unwrap();
+            ws.write_all(b"PING :tiny\r\n").await.unwrap();
+            ws.flush().await.unwrap();
+
+            let mut received: Vec<u8> = vec![];
+            while !received.ends_with(b"\r\n") {
+                let mut read_buf = [0u8; 100];
+                let n_read = ws.read(&mut read_buf).await.unwrap();
+                assert!(n_read != 0);
+                received.extend_from_slice(&read_buf[..n_read]);
+            }
+            assert_eq!(received, b"PONG :tiny\r\n");
+        });
+
+        server.join().unwrap();
+    }
+}
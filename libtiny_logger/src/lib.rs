@@ -5,7 +5,7 @@ use std::fs::{File, OpenOptions};
 use std::io;
 use std::io::Result;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use time;
 use time::Tm;
@@ -42,12 +42,15 @@ impl UI for Logger {
     delegate!(close_server_tab(serv: &str,));
     delegate!(new_chan_tab(serv: &str, chan: &str,));
     delegate!(close_chan_tab(serv: &str, chan: &str,));
+    // User logs are created on the first message
+    fn new_user_tab(&self, _serv: &str, _nick: &str) {}
+
     delegate!(close_user_tab(serv: &str, nick: &str,));
     delegate!(add_client_msg(msg: &str, target: &MsgTarget,));
     delegate!(add_msg(msg: &str, ts: Tm, target: &MsgTarget,));
     delegate!(add_err_msg(msg: &str, ts: Tm, target: &MsgTarget,));
     delegate!(add_client_err_msg(msg: &str, target: &MsgTarget,));
-    delegate!(clear_nicks(serv: &str,));
+    delegate!(clear_nicks(target: &MsgTarget,));
     delegate!(set_nick(serv: &str, nick: &str,));
     delegate!(add_privmsg(
         sender: &str,
@@ -72,10 +75,26 @@ impl UI for Logger {
     fn user_tab_exists(&self, _serv: &str, _nick: &str) -> bool {
         false
     }
+
+    fn show_chan_list(&self, _serv: &str, _chans: &[ChanListEntry]) {}
+
+    fn new_raw_tab(&self, _serv: &str, _filter: &str) {}
+
+    fn close_raw_tab(&self, _serv: &str) {}
+
+    fn raw_tab_exists(&self, _serv: &str) -> bool {
+        false
+    }
+
+    fn add_raw_msg(&self, _serv: &str, _msg: &str, _ts: Tm, _incoming: bool) {}
+
+    fn exit(&self) {}
 }
 
 struct LoggerInner {
-    /// Log file directory
+    /// Log file directory. Server tab logs are in `<log_dir>/<serv>.txt`, channel and user tab logs
+    /// are in `<log_dir>/<serv>/<chan or nick>.txt`. Server logs are not in the server's
+    /// directory as a nick or channel can have the same name as the server.
     log_dir: PathBuf,
 
     /// Maps server names to their fds
@@ -91,6 +110,31 @@ struct ServerLogs {
     users: HashMap<String, File>,
 }
 
+/// Open the log file of a server (when `name` is `None`), or of a channel or user in the server,
+/// creating the server's log directory when necessary. Channel and user log files in the old
+/// layout (`<log_dir>/<serv>_<name>.txt`) are moved to the new location to keep logs in one file.
+fn open_log_file(log_dir: &Path, serv: &str, name: Option<&str>) -> Result<File> {
+    let path = match name {
+        None => log_dir.join(format!("{}.txt", serv)),
+        Some(name) => {
+            let serv_dir = log_dir.join(serv);
+            fs::create_dir_all(&serv_dir)?;
+            let path = serv_dir.join(format!("{}.txt", name));
+            let old_path = log_dir.join(format!("{}_{}.txt", serv, name));
+            if !path.exists() && old_path.exists() {
+                debug!("Moving log file {:?} to {:?}", old_path, path);
+                fs::rename(&old_path, &path)?;
+            }
+            path
+        }
+    };
+
+    debug!("Trying to open log file: {:?}", path);
+    let mut fd = OpenOptions::new().create(true).append(true).open(path)?;
+    print_header(&mut fd)?;
+    Ok(fd)
+}
+
 fn print_header(fd: &mut File) -> Result<()> {
     writeln!(fd)?;
     writeln!(
@@ -133,14 +177,7 @@ impl LoggerInner {
             return;
         }
 
-        let mut path = self.log_dir.clone();
-        path.push(&format!("{}.txt", serv));
-        debug!("Trying to open log file: {:?}", path);
-        let mut fd = report_io_err!(
-            self.report_err,
-            OpenOptions::new().create(true).append(true).open(path)
-        );
-        report_io_err!(self.report_err, print_header(&mut fd));
+        let fd = report_io_err!(self.report_err, open_log_file(&self.log_dir, serv, None));
 
         self.servers.insert(
             serv.to_string(),
@@ -163,14 +200,10 @@ impl LoggerInner {
         }
 
         let server = self.servers.get_mut(serv).unwrap();
-        let mut path = self.log_dir.clone();
-        path.push(&format!("{}_{}.txt", serv, chan));
-        debug!("Trying to open log file: {:?}", path);
-        let mut fd = report_io_err!(
+        let fd = report_io_err!(
             self.report_err,
-            OpenOptions::new().create(true).append(true).open(path)
+            open_log_file(&self.log_dir, serv, Some(chan))
         );
-        report_io_err!(self.report_err, print_header(&mut fd));
         server.chans.insert(chan.to_string(), fd);
     }
 
@@ -225,7 +258,7 @@ impl LoggerInner {
         // Ditto with client error messages
     }
 
-    fn clear_nicks(&self, _serv: &str) {
+    fn clear_nicks(&self, _target: &MsgTarget) {
         // Nothing to do here
     }
 
@@ -332,14 +365,10 @@ impl LoggerInner {
                 if !users.contains_key(nick) {
                     // We don't have a `new_user_tab` trait method so user log files are created
                     // here
-                    let mut path = self.log_dir.clone();
-                    path.push(&format!("{}_{}.txt", serv, nick));
-                    debug!("Trying to open log file: {:?}", path);
-                    let mut fd = report_io_err!(
+                    let fd = report_io_err!(
                         self.report_err,
-                        OpenOptions::new().create(true).append(true).open(path)
+                        open_log_file(&self.log_dir, serv, Some(nick))
                     );
-                    report_io_err!(self.report_err, print_header(&mut fd));
                     users.insert(nick.to_owned(), fd);
                 }
                 let fd = users.get_mut(nick).unwrap();
@@ -378,3 +407,48 @@ fn now() -> String {
 fn strf(tm: &Tm) -> String {
     time::strftime("%H:%M:%S", tm).unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_file_layout() {
+        let log_dir = std::env::temp_dir().join(format!("tiny_logger_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&log_dir);
+        fs::create_dir_all(&log_dir).unwrap();
+        // A user log in the old layout, with the same name as the network
+        fs::write(log_dir.join("oftc_OFTC.txt"), "old log\n").unwrap();
+
+        let logger = Logger::new(log_dir.clone(), Box::new(|err| panic!("{}", err))).unwrap();
+        logger.new_server_tab("oftc");
+        logger.new_chan_tab("oftc", "#tiny");
+        logger.add_msg(
+            "server msg",
+            time::now(),
+            &MsgTarget::Server { serv: "oftc" },
+        );
+        logger.add_privmsg(
+            "OFTC",
+            "user msg",
+            time::now(),
+            &MsgTarget::User {
+                serv: "oftc",
+                nick: "OFTC",
+            },
+            false,
+            false,
+        );
+
+        let serv_log = fs::read_to_string(log_dir.join("oftc.txt")).unwrap();
+        assert!(serv_log.contains("server msg"));
+        assert!(!serv_log.contains("user msg"));
+        assert!(log_dir.join("oftc").join("#tiny.txt").is_file());
+        let user_log = fs::read_to_string(log_dir.join("oftc").join("OFTC.txt")).unwrap();
+        assert!(user_log.starts_with("old log\n"));
+        assert!(user_log.contains("user msg"));
+        assert!(!log_dir.join("oftc_OFTC.txt").exists());
+
+        fs::remove_dir_all(&log_dir).unwrap();
+    }
+}
@@ -9,7 +9,7 @@ use libtiny_tui::{Colors, TUI};
 use libtiny_ui::*;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use tokio::runtime::current_thread::Runtime;
+use tokio::task::LocalSet;
 
 fn main() {
     let args = std::env::args().collect::<Vec<_>>();
@@ -18,15 +18,18 @@ fn main() {
     let file_buffered = BufReader::new(file);
     let lines = file_buffered.lines().map(Result::unwrap).collect();
 
-    let mut executor = Runtime::new().unwrap();
-    let (tui, _) = TUI::run(Colors::default(), &mut executor);
+    let mut runtime = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .unwrap();
+    let local = LocalSet::new();
+    let (tui, _) = TUI::run(Colors::default(), &local);
 
     tui.new_server_tab("test");
     tui.draw();
 
-    executor.block_on(bench_task(tui, lines));
-
-    // executor.run();
+    local.block_on(&mut runtime, bench_task(tui, lines));
 }
 
 async fn bench_task(tui: TUI, lines: Vec<String>) {
@@ -0,0 +1,278 @@
+//! A channel list, shown in a tab of its own. Typing filters the list, enter joins the selected
+//! channel.
+
+use std::cmp::{max, min};
+
+use term_input::{Arrow, Key};
+use termbox_simple::Termbox;
+
+use crate::{config::Colors, termbox::print_chars, widget::WidgetRet, ChanListEntry};
+
+/// Name of channel list tabs, shown in the tab bar.
+pub(crate) const CHAN_LIST_TAB_NAME: &str = "channels";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortBy {
+    /// Channels with more users first.
+    Users,
+    Name,
+}
+
+pub(crate) struct ChanList {
+    chans: Vec<ChanListEntry>,
+
+    /// Indices of channels in `chans` matching the filter, sorted.
+    shown: Vec<usize>,
+
+    /// Shown channels have this string in their names or topics (case-insensitive).
+    filter: String,
+
+    sort_by: SortBy,
+
+    /// Index of the selected channel in `shown`.
+    selected: usize,
+
+    /// Index of the first channel drawn in `shown`.
+    scroll: usize,
+
+    width: i32,
+    height: i32,
+}
+
+impl ChanList {
+    pub(crate) fn new(width: i32, height: i32, chans: Vec<ChanListEntry>) -> ChanList {
+        let mut chan_list = ChanList {
+            chans,
+            shown: vec![],
+            filter: String::new(),
+            sort_by: SortBy::Users,
+            selected: 0,
+            scroll: 0,
+            width,
+            height,
+        };
+        chan_list.update_shown();
+        chan_list
+    }
+
+    pub(crate) fn set_chans(&mut self, chans: Vec<ChanListEntry>) {
+        self.chans = chans;
+        self.update_shown();
+    }
+
+    pub(crate) fn resize(&mut self, width: i32, height: i32) {
+        self.width = width;
+        self.height = height;
+        self.select(self.selected);
+    }
+
+    pub(crate) fn selected_chan(&self) -> Option<&str> {
+        self.shown
+            .get(self.selected)
+            .map(|idx| self.chans[*idx].chan.as_str())
+    }
+
+    /// Filter and sort channels, reset selection.
+    fn update_shown(&mut self) {
+        let filter = self.filter.to_lowercase();
+        let chans = &self.chans;
+        let mut shown: Vec<usize> = (0..chans.len())
+            .filter(|idx| {
+                let chan = &chans[*idx];
+                filter.is_empty()
+                    || chan.chan.to_lowercase().contains(&filter)
+                    || chan.topic.to_lowercase().contains(&filter)
+            })
+            .collect();
+        match self.sort_by {
+            SortBy::Users => shown.sort_by(|a, b| {
+                chans[*b]
+                    .n_users
+                    .cmp(&chans[*a].n_users)
+                    .then_with(|| chans[*a].chan.cmp(&chans[*b].chan))
+            }),
+            SortBy::Name => shown.sort_by_key(|idx| chans[*idx].chan.to_lowercase()),
+        }
+        self.shown = shown;
+        self.selected = 0;
+        self.scroll = 0;
+    }
+
+    /// Number of channels that fit into the widget. First line is used for the header.
+    fn n_rows(&self) -> usize {
+        max(self.height - 1, 1) as usize
+    }
+
+    /// Select the channel at the given index in `shown`, scroll to make it visible.
+    fn select(&mut self, idx: usize) {
+        if self.shown.is_empty() {
+            return;
+        }
+        self.selected = min(idx, self.shown.len() - 1);
+        let n_rows = self.n_rows();
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + n_rows {
+            self.scroll = self.selected + 1 - n_rows;
+        }
+    }
+
+    pub(crate) fn keypressed(&mut self, key: Key) -> WidgetRet {
+        match key {
+            Key::Arrow(Arrow::Up) => {
+                self.select(self.selected.saturating_sub(1));
+                WidgetRet::KeyHandled
+            }
+            Key::Arrow(Arrow::Down) => {
+                self.select(self.selected + 1);
+                WidgetRet::KeyHandled
+            }
+            Key::PageUp => {
+                self.select(self.selected.saturating_sub(self.n_rows()));
+                WidgetRet::KeyHandled
+            }
+            Key::PageDown => {
+                self.select(self.selected + self.n_rows());
+                WidgetRet::KeyHandled
+            }
+            Key::Home => {
+                self.select(0);
+                WidgetRet::KeyHandled
+            }
+            Key::End => {
+                self.select(self.shown.len());
+                WidgetRet::KeyHandled
+            }
+            Key::Tab => {
+                self.sort_by = match self.sort_by {
+                    SortBy::Users => SortBy::Name,
+                    SortBy::Name => SortBy::Users,
+                };
+                self.update_shown();
+                WidgetRet::KeyHandled
+            }
+            Key::Char('\r') => match self.selected_chan() {
+                None => WidgetRet::KeyHandled,
+                Some(chan) => WidgetRet::Input(format!("/join {}", chan).chars().collect()),
+            },
+            Key::Char(c) => {
+                self.filter.push(c);
+                self.update_shown();
+                WidgetRet::KeyHandled
+            }
+            Key::Backspace => {
+                self.filter.pop();
+                self.update_shown();
+                WidgetRet::KeyHandled
+            }
+            Key::Esc => WidgetRet::Remove,
+            _ => WidgetRet::KeyIgnored,
+        }
+    }
+
+    pub(crate) fn draw(&self, tb: &mut Termbox, colors: &Colors, pos_x: i32, pos_y: i32) {
+        tb.hide_cursor();
+
+        let width = max(self.width, 0) as usize;
+
+        let header = format!(
+            "{} of {} channels, sorted by {} (enter: join, tab: sort, esc: close) filter: {}",
+            self.shown.len(),
+            self.chans.len(),
+            match self.sort_by {
+                SortBy::Users => "users",
+                SortBy::Name => "name",
+            },
+            self.filter
+        );
+        print_chars(tb, pos_x, pos_y, colors.topic, header.chars().take(width));
+
+        let rows = self.shown.iter().skip(self.scroll).take(self.n_rows());
+        let chan_width = min(
+            rows.clone()
+                .map(|idx| self.chans[*idx].chan.chars().count())
+                .max()
+                .unwrap_or(0),
+            width / 3,
+        );
+
+        for (row, idx) in rows.enumerate() {
+            let chan = &self.chans[*idx];
+            let style = if self.scroll + row == self.selected {
+                colors.completion
+            } else {
+                colors.user_msg
+            };
+            let line = format!(
+                "{:chan_width$} {:>5} {}",
+                chan.chan,
+                chan.n_users,
+                chan.topic,
+                chan_width = chan_width
+            );
+            print_chars(
+                tb,
+                pos_x,
+                pos_y + 1 + row as i32,
+                style,
+                // Topics may have IRC formatting characters
+                line.chars().filter(|c| !c.is_control()).take(width),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chan(chan: &str, n_users: u32, topic: &str) -> ChanListEntry {
+        ChanListEntry {
+            chan: chan.to_owned(),
+            n_users,
+            topic: topic.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_chan_list_keys() {
+        let mut chan_list = ChanList::new(
+            80,
+            3,
+            vec![
+                chan("#haskell", 800, "Haskell"),
+                chan("#rust-beginners", 300, "Rust"),
+                chan("#rust", 1000, "Rust"),
+            ],
+        );
+        assert_eq!(chan_list.selected_chan(), Some("#rust"));
+
+        // Sort by name
+        chan_list.keypressed(Key::Tab);
+        assert_eq!(chan_list.selected_chan(), Some("#haskell"));
+        chan_list.keypressed(Key::End);
+        assert_eq!(chan_list.selected_chan(), Some("#rust-beginners"));
+        // Two rows fit, the first one should be scrolled out
+        assert_eq!(chan_list.scroll, 1);
+
+        // Filter
+        chan_list.keypressed(Key::Char('r'));
+        chan_list.keypressed(Key::Char('U'));
+        chan_list.keypressed(Key::Arrow(Arrow::Down));
+        assert_eq!(chan_list.selected_chan(), Some("#rust-beginners"));
+        match chan_list.keypressed(Key::Char('\r')) {
+            WidgetRet::Input(input) => {
+                assert_eq!(
+                    input.into_iter().collect::<String>(),
+                    "/join #rust-beginners"
+                )
+            }
+            _ => panic!("Expected input"),
+        }
+
+        chan_list.keypressed(Key::Char('x'));
+        assert_eq!(chan_list.selected_chan(), None);
+        chan_list.keypressed(Key::Backspace);
+        assert_eq!(chan_list.selected_chan(), Some("#rust"));
+    }
+}
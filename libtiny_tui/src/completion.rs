@@ -0,0 +1,121 @@
+//! Completion of command names and command arguments in the input field. Nicks in messages are
+//! completed by `MessagingUI`.
+
+use crate::trie::Trie;
+
+/// What to complete in the first argument of a command. See `TUI::set_cmds`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgCompletion {
+    /// Nicks in the tabs of the current server
+    Nick,
+    /// Channels of the current server
+    Chan,
+}
+
+/// What the word at the cursor is, when the input is a command.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum CompletionTarget {
+    /// Command name, after the `/`
+    CmdName,
+    /// First argument of the command with the given name
+    CmdArg(String),
+}
+
+/// Find out what to complete at the cursor. Returns `None` when the line is not a command, or the
+/// cursor is after the first argument.
+pub(crate) fn completion_target(line: &[char], cursor: usize) -> Option<CompletionTarget> {
+    if line.first() != Some(&'/') {
+        return None;
+    }
+    let cursor = std::cmp::min(cursor, line.len());
+
+    let name_end = line
+        .iter()
+        .position(|c| c.is_whitespace())
+        .unwrap_or(line.len());
+    if cursor <= name_end {
+        return Some(CompletionTarget::CmdName);
+    }
+
+    let arg_start = line[name_end..]
+        .iter()
+        .position(|c| !c.is_whitespace())
+        .map(|idx| name_end + idx)
+        .unwrap_or(line.len());
+    if line[std::cmp::min(arg_start, cursor)..cursor]
+        .iter()
+        .any(|c| c.is_whitespace())
+    {
+        return None;
+    }
+
+    Some(CompletionTarget::CmdArg(line[1..name_end].iter().collect()))
+}
+
+/// Resolve a possibly abbreviated command name to the full name, the same way commands are parsed:
+/// an exact match, or the only command starting with `name`.
+pub(crate) fn resolve_cmd_name(cmd_names: &Trie, name: &str) -> Option<String> {
+    if cmd_names.contains(name) {
+        return Some(name.to_owned());
+    }
+    match cmd_names.drop_pfx(&mut name.chars()).as_slice() {
+        [suffix] => Some(format!("{}{}", name, suffix)),
+        _ => None,
+    }
+}
+
+/// Characters of channel names, for finding the beginning of the channel name being completed.
+pub(crate) fn is_chan_char(c: char) -> bool {
+    !c.is_whitespace() && c != ','
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(line: &str, cursor: usize) -> Option<CompletionTarget> {
+        let line: Vec<char> = line.chars().collect();
+        completion_target(&line, cursor)
+    }
+
+    #[test]
+    fn test_completion_target() {
+        assert_eq!(target("hello", 5), None);
+        assert_eq!(target("/jo", 3), Some(CompletionTarget::CmdName));
+        assert_eq!(target("/jo #tiny", 2), Some(CompletionTarget::CmdName));
+        assert_eq!(
+            target("/join #ti", 9),
+            Some(CompletionTarget::CmdArg("join".to_owned()))
+        );
+        assert_eq!(
+            target("/msg ", 5),
+            Some(CompletionTarget::CmdArg("msg".to_owned()))
+        );
+        assert_eq!(target("/msg bob hi", 11), None);
+        // Cursor after the end of the line, e.g. in the middle of a completion
+        assert_eq!(target("/jo", 5), Some(CompletionTarget::CmdName));
+    }
+
+    #[test]
+    fn test_resolve_cmd_name() {
+        let mut cmd_names = Trie::new();
+        for name in &["join", "me", "mode", "msg", "deop", "devoice"] {
+            cmd_names.insert(name);
+        }
+
+        // Abbreviated command in `/jo #ti<Tab>`
+        let cmd = match target("/jo #ti", 7) {
+            Some(CompletionTarget::CmdArg(cmd)) => cmd,
+            other => panic!("Unexpected completion target: {:?}", other),
+        };
+        assert_eq!(resolve_cmd_name(&cmd_names, &cmd), Some("join".to_owned()));
+
+        assert_eq!(resolve_cmd_name(&cmd_names, "msg"), Some("msg".to_owned()));
+        // Exact matches are preferred
+        assert_eq!(resolve_cmd_name(&cmd_names, "me"), Some("me".to_owned()));
+        assert_eq!(resolve_cmd_name(&cmd_names, "ms"), Some("msg".to_owned()));
+        // Ambiguous
+        assert_eq!(resolve_cmd_name(&cmd_names, "de"), None);
+        assert_eq!(resolve_cmd_name(&cmd_names, "xyz"), None);
+    }
+}
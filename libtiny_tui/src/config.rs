@@ -37,6 +37,8 @@ pub struct Colors {
     pub statusline_normal: Style,
     pub statusline_left: Style,
     pub statusline_right: Style,
+    pub raw_in: Style,
+    pub raw_out: Style,
 }
 
 impl Default for Colors {
@@ -102,6 +104,14 @@ impl Default for Colors {
             statusline_normal: Style { fg: 15, bg: 8 },
             statusline_left: Style { fg: 10, bg: 8 },
             statusline_right: Style { fg: 7, bg: 8 },
+            raw_in: Style {
+                fg: 6,
+                bg: TB_DEFAULT,
+            },
+            raw_out: Style {
+                fg: 5,
+                bg: TB_DEFAULT,
+            },
         }
     }
 }
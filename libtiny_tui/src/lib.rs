@@ -2,6 +2,8 @@
 #![allow(clippy::too_many_arguments)]
 #![allow(clippy::cognitive_complexity)]
 
+mod chan_list;
+mod completion;
 mod config;
 mod exit_dialogue;
 mod messaging;
@@ -9,6 +11,7 @@ mod messaging;
 #[doc(hidden)]
 pub mod msg_area;
 mod notifier;
+mod raw;
 mod statusline;
 mod tab;
 mod termbox;
@@ -18,19 +21,20 @@ mod tui;
 mod utils;
 mod widget;
 
+pub use crate::completion::ArgCompletion;
 pub use crate::config::Colors;
 pub use crate::tab::TabStyle;
 pub use libtiny_ui::*;
 
 use futures::select;
-use futures_util::stream::StreamExt;
+use futures::stream::StreamExt;
 use std::cell::RefCell;
 use std::rc::{Rc, Weak};
 use term_input::Input;
 use time::Tm;
-use tokio::runtime::current_thread::Runtime;
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::mpsc;
+use tokio::task::LocalSet;
 
 #[macro_use]
 extern crate log;
@@ -38,10 +42,14 @@ extern crate log;
 #[derive(Clone)]
 pub struct TUI {
     inner: Weak<RefCell<tui::TUI>>,
+    /// Stops the input handler, see `UI::exit`
+    snd_exit: mpsc::Sender<()>,
 }
 
 impl TUI {
-    pub fn run(colors: Colors, runtime: &mut Runtime) -> (TUI, mpsc::Receiver<Event>) {
+    /// Create the TUI and spawn its tasks on `local`. TUI is not `Send`, so its tasks run in a
+    /// `LocalSet`.
+    pub fn run(colors: Colors, local: &LocalSet) -> (TUI, mpsc::Receiver<Event>) {
         let tui = Rc::new(RefCell::new(tui::TUI::new(colors)));
         let inner = Rc::downgrade(&tui);
 
@@ -51,12 +59,15 @@ impl TUI {
         let (snd_abort, rcv_abort) = mpsc::channel::<()>(1);
 
         // Spawn SIGWINCH handler
-        runtime.spawn(sigwinch_handler(inner.clone(), rcv_abort));
+        local.spawn_local(sigwinch_handler(inner.clone(), rcv_abort));
+
+        // For stopping the input handler
+        let (snd_exit, rcv_exit) = mpsc::channel::<()>(1);
 
         // Spawn input handler task
-        runtime.spawn(input_handler(tui, snd_ev, snd_abort));
+        local.spawn_local(input_handler(tui, snd_ev, snd_abort, rcv_exit));
 
-        (TUI { inner }, rcv_ev)
+        (TUI { inner, snd_exit }, rcv_ev)
     }
 }
 
@@ -95,9 +106,28 @@ async fn input_handler(
     tui: Rc<RefCell<tui::TUI>>,
     mut snd_ev: mpsc::Sender<Event>,
     mut snd_abort: mpsc::Sender<()>,
+    rcv_exit: mpsc::Receiver<()>,
 ) {
-    let mut input = Input::new();
-    while let Some(mb_ev) = input.next().await {
+    let mut input = Input::new().fuse();
+    let mut rcv_exit_fused = rcv_exit.fuse();
+    loop {
+        let mb_ev = select! {
+            mb_ev = input.next() => match mb_ev {
+                None => {
+                    return;
+                }
+                Some(mb_ev) => mb_ev,
+            },
+            exit = rcv_exit_fused.next() => {
+                if exit.is_some() {
+                    snd_ev.try_send(Event::Abort).unwrap();
+                    let _ = snd_abort.try_send(());
+                    return;
+                }
+                // All `TUI` handles are dropped, keep handling input
+                continue;
+            }
+        };
         use tui::TUIRet::*;
         match mb_ev {
             Err(io_err) => {
@@ -172,11 +202,27 @@ impl UI for TUI {
     delegate!(new_chan_tab(serv_name: &str, chan: &str,));
     delegate!(close_chan_tab(serv_name: &str, chan: &str,));
     delegate!(close_user_tab(serv_name: &str, nick: &str,));
+
+    fn new_user_tab(&self, serv_name: &str, nick: &str) {
+        if let Some(tui) = self.inner.upgrade() {
+            let mut tui = tui.borrow_mut();
+            let tab_idx = tui
+                .new_user_tab(serv_name, nick)
+                .or_else(|| tui.find_user_tab_idx(serv_name, nick));
+            if let Some(tab_idx) = tab_idx {
+                tui.select_tab(tab_idx);
+            }
+        }
+    }
+
+    fn exit(&self) {
+        let _ = self.snd_exit.clone().try_send(());
+    }
     delegate!(add_client_msg(msg: &str, target: &MsgTarget,));
     delegate!(add_msg(msg: &str, ts: Tm, target: &MsgTarget,));
     delegate!(add_err_msg(msg: &str, ts: Tm, target: &MsgTarget,));
     delegate!(add_client_err_msg(msg: &str, target: &MsgTarget,));
-    delegate!(clear_nicks(serv_name: &str,));
+    delegate!(clear_nicks(target: &MsgTarget,));
     delegate!(set_nick(serv_name: &str, new_nick: &str,));
     delegate!(add_privmsg(
         sender: &str,
@@ -201,6 +247,10 @@ impl UI for TUI {
         chan_name: &str,
     ));
     delegate!(set_tab_style(style: TabStyle, target: &MsgTarget,));
+    delegate!(show_chan_list(serv_name: &str, chans: &[ChanListEntry],));
+    delegate!(new_raw_tab(serv_name: &str, filter: &str,));
+    delegate!(close_raw_tab(serv_name: &str,));
+    delegate!(add_raw_msg(serv_name: &str, msg: &str, ts: Tm, incoming: bool,));
 
     fn user_tab_exists(&self, serv_name: &str, nick: &str) -> bool {
         match self.inner.upgrade() {
@@ -208,8 +258,16 @@ impl UI for TUI {
             None => false,
         }
     }
+
+    fn raw_tab_exists(&self, serv_name: &str) -> bool {
+        match self.inner.upgrade() {
+            Some(tui) => tui.borrow().raw_tab_exists(serv_name),
+            None => false,
+        }
+    }
 }
 
 impl TUI {
     delegate_pub!(set_colors(colors: Colors,));
+    delegate_pub!(set_cmds(cmds: Vec<(String, Option<ArgCompletion>)>,));
 }
@@ -15,6 +15,7 @@ use crate::{
     termbox,
     text_field::TextField,
     trie::Trie,
+    utils,
     widget::WidgetRet,
 };
 
@@ -44,6 +45,9 @@ pub(crate) struct MessagingUI {
 
     last_activity_line: Option<ActivityLine>,
     last_activity_ts: Option<Timestamp>,
+
+    // Channel topic, shown in the status line
+    topic: Option<String>,
 }
 
 /// Like `time::Tm`, but we only care about hour and minute parts.
@@ -91,6 +95,7 @@ impl MessagingUI {
             show_current_nick: true,
             last_activity_line: None,
             last_activity_ts: None,
+            topic: None,
         }
     }
 
@@ -106,6 +111,28 @@ impl MessagingUI {
         self.current_nick.as_ref().map(String::as_str)
     }
 
+    pub(crate) fn get_nicks(&self) -> &Trie {
+        &self.nicks
+    }
+
+    /// Contents of the input field and the cursor position, for completing commands. `None` when
+    /// the exit dialogue is shown.
+    pub(crate) fn input_field_line(&self) -> Option<(&[char], usize)> {
+        if self.exit_dialogue.is_some() {
+            None
+        } else {
+            Some(self.input_field.line())
+        }
+    }
+
+    pub(crate) fn autocomplete(&mut self, dict: &Trie, is_word_char: fn(char) -> bool) {
+        self.input_field.autocomplete(dict, is_word_char);
+    }
+
+    pub(crate) fn get_topic(&self) -> Option<&str> {
+        self.topic.as_deref()
+    }
+
     fn draw_input_field(&self, tb: &mut Termbox, colors: &Colors, pos_x: i32, pos_y: i32) {
         match self.exit_dialogue {
             Some(ref exit_dialogue) => exit_dialogue.draw(tb, colors, pos_x, pos_y),
@@ -174,7 +201,8 @@ impl MessagingUI {
 
             Key::Tab => {
                 if self.exit_dialogue.is_none() {
-                    self.input_field.autocomplete(&self.nicks);
+                    self.input_field
+                        .autocomplete(&self.nicks, utils::is_nick_char);
                 }
                 WidgetRet::KeyHandled
             }
@@ -274,7 +302,14 @@ impl MessagingUI {
         self.last_activity_ts = Some(ts);
     }
 
+    /// Set the topic and show it in the message area. An empty topic means the topic is cleared.
     pub(crate) fn show_topic(&mut self, topic: &str, ts: Timestamp) {
+        if topic.is_empty() {
+            self.topic = None;
+            return;
+        }
+        self.topic = Some(topic.to_owned());
+
         self.add_timestamp(ts);
 
         self.msg_area
@@ -381,6 +416,23 @@ impl MessagingUI {
         self.msg_area.flush_line();
     }
 
+    /// Add a line of a raw protocol tab. Incoming and outgoing lines have different colors and
+    /// "<-" and "->" prefixes.
+    pub(crate) fn add_raw_msg(&mut self, msg: &str, ts: Timestamp, incoming: bool) {
+        self.reset_activity_line();
+
+        self.add_timestamp(ts);
+        let (style, dir) = if incoming {
+            (SchemeStyle::RawIn, "<- ")
+        } else {
+            (SchemeStyle::RawOut, "-> ")
+        };
+        self.msg_area.set_style(SegStyle::SchemeStyle(style));
+        self.msg_area.add_text(dir);
+        self.msg_area.add_text(msg);
+        self.msg_area.flush_line();
+    }
+
     pub(crate) fn clear(&mut self) {
         self.msg_area.clear();
     }
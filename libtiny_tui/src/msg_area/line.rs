@@ -57,6 +57,8 @@ pub(crate) enum SchemeStyle {
     Faded,
     Highlight,
     Timestamp,
+    RawIn,
+    RawOut,
 }
 
 impl Seg {
@@ -79,6 +81,8 @@ impl Seg {
                     Faded => colors.faded,
                     Highlight => colors.highlight,
                     Timestamp => colors.timestamp,
+                    RawIn => colors.raw_in,
+                    RawOut => colors.raw_out,
                 }
             }
         }
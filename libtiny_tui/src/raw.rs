@@ -0,0 +1,87 @@
+//! Raw protocol tabs show lines sent to and read from a server, for debugging. Lines can be
+//! filtered by command or numeric.
+
+/// Name of raw protocol tabs, shown in the tab bar.
+pub(crate) const RAW_TAB_NAME: &str = "raw";
+
+/// Filter of a raw protocol tab. Parsed from a list of commands and numerics separated by spaces
+/// or commas, e.g. "PRIVMSG 433". Commands prefixed with `-` are hidden, e.g. "-PING,-PONG" shows
+/// everything except pings. An empty filter shows all lines.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct RawFilter {
+    /// Commands to show, in uppercase. All commands are shown when empty.
+    show: Vec<String>,
+    /// Commands to hide, in uppercase.
+    hide: Vec<String>,
+}
+
+impl RawFilter {
+    pub(crate) fn new(expr: &str) -> RawFilter {
+        let mut filter = RawFilter::default();
+        for word in expr
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|word| !word.is_empty())
+        {
+            match word.strip_prefix('-') {
+                Some("") => {}
+                Some(cmd) => filter.hide.push(cmd.to_ascii_uppercase()),
+                None => filter.show.push(word.to_ascii_uppercase()),
+            }
+        }
+        filter
+    }
+
+    pub(crate) fn matches(&self, line: &str) -> bool {
+        let cmd = match line_cmd(line) {
+            None => return self.show.is_empty(),
+            Some(cmd) => cmd.to_ascii_uppercase(),
+        };
+        (self.show.is_empty() || self.show.contains(&cmd)) && !self.hide.contains(&cmd)
+    }
+}
+
+/// Command (or numeric) of an IRC message, after the tags and the prefix.
+fn line_cmd(line: &str) -> Option<&str> {
+    let mut words = line.split(' ').filter(|word| !word.is_empty());
+    let mut word = words.next()?;
+    if word.starts_with('@') {
+        word = words.next()?;
+    }
+    if word.starts_with(':') {
+        word = words.next()?;
+    }
+    Some(word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_cmd() {
+        assert_eq!(line_cmd("PING :irc.example.org"), Some("PING"));
+        assert_eq!(line_cmd(":irc.example.org 001 tiny :Welcome"), Some("001"));
+        assert_eq!(
+            line_cmd("@time=2020-01-01T00:00:00.000Z :nick!user@host PRIVMSG #tiny :hi"),
+            Some("PRIVMSG")
+        );
+        assert_eq!(line_cmd(":irc.example.org"), None);
+    }
+
+    #[test]
+    fn test_filter() {
+        let all = RawFilter::new("");
+        assert!(all.matches("PING :x"));
+        assert!(all.matches(""));
+
+        let filter = RawFilter::new("privmsg, 433");
+        assert!(filter.matches(":nick!user@host PRIVMSG #tiny :hi"));
+        assert!(filter.matches(":irc.example.org 433 * tiny :Nickname is already in use"));
+        assert!(!filter.matches("PING :x"));
+
+        let filter = RawFilter::new("-PING -pong");
+        assert!(!filter.matches("PING :x"));
+        assert!(!filter.matches(":irc.example.org PONG irc.example.org :x"));
+        assert!(filter.matches(":nick!user@host PRIVMSG #tiny :hi"));
+    }
+}
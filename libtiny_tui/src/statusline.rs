@@ -15,6 +15,7 @@ pub(crate) fn draw_statusline(
     visible_name: &str,
     notifier: Notifier,
     ignore_mode: bool,
+    topic: Option<&str>,
 ) {
     let notify_state = match notifier {
         Notifier::Off => "Off",
@@ -34,29 +35,36 @@ pub(crate) fn draw_statusline(
             " ".repeat(width as usize).chars(),
         );
         print_chars(tb, 0, 0, colors.statusline_left, left_pane.chars());
-        if width >= left_pane.chars().count() as i32 + 35 {
-            let right_pane = format!(" Notify: {} | Ignore: {} ", notify_state, ignore_state);
-            let spacing_length =
-                width - (right_pane.chars().count() as i32) - (left_pane.chars().count() as i32);
-            print_chars(
-                tb,
-                spacing_length + left_pane.chars().count() as i32,
-                0,
-                colors.statusline_right,
-                right_pane.chars(),
-            );
+        let right_pane = if width >= left_pane.chars().count() as i32 + 35 {
+            format!(" Notify: {} | Ignore: {} ", notify_state, ignore_state)
         } else {
             let notify_state_mini: String = notify_state.chars().take(3).collect();
-            let right_pane = format!("N:{} | I:{}", notify_state_mini, ignore_state);
-            let spacing_length =
-                width - (right_pane.chars().count() as i32) - (left_pane.chars().count() as i32);
-            print_chars(
-                tb,
-                spacing_length + left_pane.chars().count() as i32,
-                0,
-                colors.statusline_right,
-                right_pane.chars(),
-            );
+            format!("N:{} | I:{}", notify_state_mini, ignore_state)
+        };
+        let spacing_length =
+            width - (right_pane.chars().count() as i32) - (left_pane.chars().count() as i32);
+        print_chars(
+            tb,
+            spacing_length + left_pane.chars().count() as i32,
+            0,
+            colors.statusline_right,
+            right_pane.chars(),
+        );
+        // Topic goes between the panes, truncated to the space available
+        if let Some(topic) = topic {
+            let topic_width = spacing_length - 2;
+            if topic_width > 0 {
+                print_chars(
+                    tb,
+                    left_pane.chars().count() as i32 + 1,
+                    0,
+                    colors.statusline_normal,
+                    topic
+                        .chars()
+                        .filter(|c| !c.is_control())
+                        .take(topic_width as usize),
+                );
+            }
         }
     } else if width > 15 {
         print_chars(
@@ -2,9 +2,11 @@ pub use libtiny_ui::TabStyle;
 use termbox_simple::{Termbox, TB_UNDERLINE};
 
 use crate::{
+    chan_list::{ChanList, CHAN_LIST_TAB_NAME},
     config::{Colors, Style},
     messaging::MessagingUI,
     notifier::Notifier,
+    raw::{RawFilter, RAW_TAB_NAME},
     MsgSource,
 };
 
@@ -15,6 +17,10 @@ pub(crate) struct Tab {
     /// Alt-character to use to switch to this tab.
     pub(crate) switch: Option<char>,
     pub(crate) notifier: Notifier,
+    /// Channel list to show instead of `widget`. Channel list tabs have the server tab's source.
+    pub(crate) chan_list: Option<ChanList>,
+    /// Filter of a raw protocol tab. Raw tabs have the server tab's source.
+    pub(crate) raw: Option<RawFilter>,
 }
 
 fn tab_style(style: TabStyle, colors: &Colors) -> Style {
@@ -27,7 +33,27 @@ fn tab_style(style: TabStyle, colors: &Colors) -> Style {
 
 impl Tab {
     pub(crate) fn visible_name(&self) -> &str {
-        self.src.visible_name()
+        if self.chan_list.is_some() {
+            CHAN_LIST_TAB_NAME
+        } else if self.raw.is_some() {
+            RAW_TAB_NAME
+        } else {
+            self.src.visible_name()
+        }
+    }
+
+    pub(crate) fn is_chan_list(&self) -> bool {
+        self.chan_list.is_some()
+    }
+
+    pub(crate) fn is_raw(&self) -> bool {
+        self.raw.is_some()
+    }
+
+    /// Is this a channel list or a raw tab? These have the server tab's source, but they're not
+    /// server tabs.
+    pub(crate) fn is_aux(&self) -> bool {
+        self.is_chan_list() || self.is_raw()
     }
 
     pub(crate) fn set_style(&mut self, style: TabStyle) {
@@ -601,7 +601,15 @@ fn draw_line(
 }
 
 impl TextField {
-    pub(crate) fn autocomplete(&mut self, dict: &Trie) {
+    /// The line being edited and the cursor position. The line doesn't include the current
+    /// completion.
+    pub(crate) fn line(&self) -> (&[char], usize) {
+        (self.shown_line(), self.cursor as usize)
+    }
+
+    /// Complete the word at the cursor using the words in `dict`, or switch to the next completion
+    /// when already completing. `is_word_char` is used to find the beginning of the word.
+    pub(crate) fn autocomplete(&mut self, dict: &Trie, is_word_char: fn(char) -> bool) {
         if self.in_autocomplete() {
             // AWFUL CODE YO
             self.keypressed(Key::Arrow(Arrow::Up));
@@ -617,7 +625,7 @@ impl TextField {
             while cursor_left >= 0
                 && line
                     .get(cursor_left as usize)
-                    .map(|c| utils::is_nick_char(*c))
+                    .map(|c| is_word_char(*c))
                     .unwrap_or(false)
             {
                 cursor_left -= 1;
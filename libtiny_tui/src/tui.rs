@@ -2,20 +2,28 @@
 #![allow(clippy::new_without_default)]
 #![allow(clippy::too_many_arguments)]
 
+use std::collections::HashMap;
 use std::str;
 use std::str::SplitWhitespace;
 use time::Tm;
 
+use crate::chan_list::{ChanList, CHAN_LIST_TAB_NAME};
+use crate::completion::{
+    completion_target, is_chan_char, resolve_cmd_name, ArgCompletion, CompletionTarget,
+};
 use crate::config::Colors;
 use crate::notifier::Notifier;
+use crate::raw::{RawFilter, RAW_TAB_NAME};
 use crate::tab::TabStyle;
 
 use crate::config::Style;
 use crate::messaging::{MessagingUI, Timestamp};
 use crate::statusline::{draw_statusline, statusline_visible};
 use crate::tab::Tab;
+use crate::trie::Trie;
+use crate::utils;
 use crate::widget::WidgetRet;
-use crate::{MsgSource, MsgTarget};
+use crate::{ChanListEntry, MsgSource, MsgTarget};
 use term_input::{Arrow, Event, Key};
 use termbox_simple::Termbox;
 
@@ -41,6 +49,9 @@ pub(crate) enum TUIRet {
     },
 }
 
+/// Commands handled by the TUI, see `try_handle_cmd`. Used for completing command names.
+const TUI_CMDS: [&str; 5] = ["clear", "ignore", "notify", "statusline", "switch"];
+
 const LEFT_ARROW: char = '<';
 const RIGHT_ARROW: char = '>';
 
@@ -61,6 +72,12 @@ pub(crate) struct TUI {
     show_statusline: bool,
     /// Is there room for statusline?
     statusline_visible: bool,
+
+    /// Command names, for completion. Has the TUI commands and the commands set with `set_cmds`.
+    cmd_names: Trie,
+
+    /// What to complete in the first arguments of commands
+    cmd_args: HashMap<String, ArgCompletion>,
 }
 
 impl TUI {
@@ -81,6 +98,86 @@ impl TUI {
             h_scroll: 0,
             show_statusline: false,
             statusline_visible: statusline_visible(width, height),
+            cmd_names: tui_cmd_names(),
+            cmd_args: HashMap::new(),
+        }
+    }
+
+    /// Set commands to complete in the input field, in addition to the TUI commands. Replaces the
+    /// commands set before.
+    pub(crate) fn set_cmds(&mut self, cmds: Vec<(String, Option<ArgCompletion>)>) {
+        self.cmd_names = tui_cmd_names();
+        self.cmd_args.clear();
+        for (name, arg) in cmds {
+            self.cmd_names.insert(&name);
+            // Later entries override earlier ones, e.g. aliases shadow commands
+            match arg {
+                Some(arg) => {
+                    self.cmd_args.insert(name, arg);
+                }
+                None => {
+                    self.cmd_args.remove(&name);
+                }
+            }
+        }
+    }
+
+    /// Complete a command name or the first argument of a command in the input field. Returns
+    /// `false` when the input is not a command or the argument is completed as a nick in the
+    /// current tab, which is done by the tab's widget.
+    fn complete_cmd(&mut self) -> bool {
+        let tab = &self.tabs[self.active_idx];
+        let target = match tab.widget.input_field_line() {
+            None => {
+                return false;
+            }
+            Some((line, cursor)) => completion_target(line, cursor),
+        };
+        let serv = tab.src.serv_name();
+
+        match target {
+            None => false,
+            Some(CompletionTarget::CmdName) => {
+                self.tabs[self.active_idx]
+                    .widget
+                    .autocomplete(&self.cmd_names, utils::is_nick_char);
+                true
+            }
+            Some(CompletionTarget::CmdArg(cmd)) => match resolve_cmd_name(&self.cmd_names, &cmd)
+                .and_then(|cmd| self.cmd_args.get(&cmd).copied())
+            {
+                None => false,
+                Some(ArgCompletion::Nick) => {
+                    let mut nicks = Trie::new();
+                    for tab in self.tabs.iter().filter(|tab| tab.src.serv_name() == serv) {
+                        for nick in tab.widget.get_nicks().to_strings("") {
+                            nicks.insert(&nick);
+                        }
+                    }
+                    self.tabs[self.active_idx]
+                        .widget
+                        .autocomplete(&nicks, utils::is_nick_char);
+                    true
+                }
+                Some(ArgCompletion::Chan) => {
+                    let mut chans = Trie::new();
+                    for tab in &self.tabs {
+                        if let MsgSource::Chan {
+                            serv: ref serv_,
+                            ref chan,
+                        } = tab.src
+                        {
+                            if serv_ == serv {
+                                chans.insert(chan);
+                            }
+                        }
+                    }
+                    self.tabs[self.active_idx]
+                        .widget
+                        .autocomplete(&chans, is_chan_char);
+                    true
+                }
+            },
         }
     }
 
@@ -154,7 +251,17 @@ impl TUI {
 
     pub(crate) fn try_handle_cmd(&mut self, cmd: &str, src: &MsgSource) -> bool {
         let mut words = cmd.split_whitespace();
+        let in_raw_tab = self.tabs[self.active_idx].is_raw();
         match words.next() {
+            // `src` of a raw tab is the server, handle commands that would act on the server tab
+            Some("clear") if in_raw_tab => {
+                self.clear(&MsgTarget::CurrentTab);
+                true
+            }
+            Some("close") if in_raw_tab => {
+                self.close_tab(self.active_idx);
+                true
+            }
             Some("clear") => {
                 self.clear(&src.to_target());
                 true
@@ -191,7 +298,17 @@ impl TUI {
         self.colors = colors;
     }
 
-    fn new_tab(&mut self, idx: usize, src: MsgSource, status: bool, notifier: Notifier) {
+    /// Create a new tab. A channel list tab is created when `chan_list` is available, a raw tab
+    /// is created when `raw` is available.
+    fn new_tab(
+        &mut self,
+        idx: usize,
+        src: MsgSource,
+        status: bool,
+        notifier: Notifier,
+        chan_list: Option<Vec<ChanListEntry>>,
+        raw: Option<RawFilter>,
+    ) {
         use std::collections::HashMap;
 
         let mut switch_keys: HashMap<char, i8> = HashMap::with_capacity(self.tabs.len());
@@ -204,7 +321,14 @@ impl TUI {
         let switch = {
             let mut ret = None;
             let mut n = 0;
-            for ch in src.visible_name().chars() {
+            let name = if chan_list.is_some() {
+                CHAN_LIST_TAB_NAME
+            } else if raw.is_some() {
+                RAW_TAB_NAME
+            } else {
+                src.visible_name()
+            };
+            for ch in name.chars() {
                 if !ch.is_alphabetic() {
                     continue;
                 }
@@ -229,14 +353,17 @@ impl TUI {
         } else {
             0
         };
+        let height = self.height - 1 - statusline_height;
         self.tabs.insert(
             idx,
             Tab {
-                widget: MessagingUI::new(self.width, self.height - 1 - statusline_height, status),
+                widget: MessagingUI::new(self.width, height, status),
                 src,
                 style: TabStyle::Normal,
                 switch,
                 notifier,
+                chan_list: chan_list.map(|chans| ChanList::new(self.width, height, chans)),
+                raw,
             },
         );
     }
@@ -253,6 +380,8 @@ impl TUI {
                     },
                     true,
                     Notifier::Mentions,
+                    None,
+                    None,
                 );
                 Some(tab_idx)
             }
@@ -299,6 +428,8 @@ impl TUI {
                         },
                         status_val,
                         notifier,
+                        None,
+                        None,
                     );
                     if self.active_idx >= tab_idx {
                         self.next_tab();
@@ -340,6 +471,8 @@ impl TUI {
                         },
                         true,
                         Notifier::Messages,
+                        None,
+                        None,
                     );
                     if let Some(nick) = self.tabs[tab_idx].widget.get_nick().map(str::to_owned) {
                         self.tabs[tab_idx + 1].widget.set_nick(nick);
@@ -361,6 +494,108 @@ impl TUI {
         }
     }
 
+    /// Show the channel list in the server's channel list tab, creating the tab if it doesn't
+    /// exist. Switches to the tab.
+    pub(crate) fn show_chan_list(&mut self, serv: &str, chans: &[ChanListEntry]) {
+        if let Some(tab_idx) = self.find_chan_list_tab_idx(serv) {
+            if let Some(ref mut chan_list) = self.tabs[tab_idx].chan_list {
+                chan_list.set_chans(chans.to_vec());
+            }
+            self.select_tab(tab_idx);
+            return;
+        }
+
+        let tab_idx = match self.find_last_serv_tab_idx(serv) {
+            None => {
+                self.new_server_tab(serv);
+                self.tabs.len()
+            }
+            Some(last_tab_idx) => last_tab_idx + 1,
+        };
+        self.new_tab(
+            tab_idx,
+            MsgSource::Serv {
+                serv: serv.to_owned(),
+            },
+            true,
+            Notifier::Off,
+            Some(chans.to_vec()),
+            None,
+        );
+        if self.active_idx >= tab_idx {
+            self.next_tab();
+        }
+        self.select_tab(tab_idx);
+    }
+
+    /// Create the server's raw tab, or update its filter if it exists. Switches to the tab.
+    pub(crate) fn new_raw_tab(&mut self, serv: &str, filter: &str) {
+        let msg = if filter.is_empty() {
+            "Showing all messages".to_owned()
+        } else {
+            format!("Showing messages matching filter: {}", filter)
+        };
+
+        if let Some(tab_idx) = self.find_raw_tab_idx(serv) {
+            self.tabs[tab_idx].raw = Some(RawFilter::new(filter));
+            self.tabs[tab_idx].widget.add_client_notify_msg(&msg);
+            self.select_tab(tab_idx);
+            return;
+        }
+
+        let tab_idx = match self.find_last_serv_tab_idx(serv) {
+            None => {
+                self.new_server_tab(serv);
+                self.tabs.len()
+            }
+            Some(last_tab_idx) => last_tab_idx + 1,
+        };
+        self.new_tab(
+            tab_idx,
+            MsgSource::Serv {
+                serv: serv.to_owned(),
+            },
+            true,
+            Notifier::Off,
+            None,
+            Some(RawFilter::new(filter)),
+        );
+        self.tabs[tab_idx].widget.add_client_notify_msg(&msg);
+        if self.active_idx >= tab_idx {
+            self.next_tab();
+        }
+        self.select_tab(tab_idx);
+    }
+
+    pub(crate) fn close_raw_tab(&mut self, serv: &str) {
+        if let Some(tab_idx) = self.find_raw_tab_idx(serv) {
+            self.close_tab(tab_idx);
+        }
+    }
+
+    pub(crate) fn raw_tab_exists(&self, serv: &str) -> bool {
+        self.find_raw_tab_idx(serv).is_some()
+    }
+
+    /// Add a line to the server's raw tab if the line matches the tab's filter.
+    pub(crate) fn add_raw_msg(&mut self, serv: &str, msg: &str, ts: Tm, incoming: bool) {
+        if let Some(tab_idx) = self.find_raw_tab_idx(serv) {
+            let tab = &mut self.tabs[tab_idx];
+            if let Some(ref filter) = tab.raw {
+                if filter.matches(msg) {
+                    tab.widget.add_raw_msg(msg, Timestamp::from(ts), incoming);
+                }
+            }
+        }
+    }
+
+    fn close_tab(&mut self, tab_idx: usize) {
+        self.tabs.remove(tab_idx);
+        if self.active_idx == tab_idx {
+            self.select_tab(if tab_idx == 0 { 0 } else { tab_idx - 1 });
+        }
+    }
+
     pub(crate) fn handle_input_event(&mut self, ev: Event) -> TUIRet {
         match ev {
             Event::Key(key) => self.keypressed(key),
@@ -435,14 +670,28 @@ impl TUI {
     }
 
     fn keypressed(&mut self, key: Key) -> TUIRet {
-        match self.tabs[self.active_idx].widget.keypressed(key) {
+        if key == Key::Tab && self.tabs[self.active_idx].chan_list.is_none() && self.complete_cmd()
+        {
+            return TUIRet::KeyHandled;
+        }
+
+        let tab = &mut self.tabs[self.active_idx];
+        let ret = match tab.chan_list {
+            Some(ref mut chan_list) => chan_list.keypressed(key),
+            None => tab.widget.keypressed(key),
+        };
+        match ret {
             WidgetRet::KeyHandled => TUIRet::KeyHandled,
             WidgetRet::KeyIgnored => self.handle_keypress(key),
             WidgetRet::Input(input) => TUIRet::Input {
                 msg: input,
                 from: self.tabs[self.active_idx].src.clone(),
             },
-            WidgetRet::Remove => unimplemented!(),
+            WidgetRet::Remove => {
+                // Only channel lists are removed this way
+                self.close_tab(self.active_idx);
+                TUIRet::KeyHandled
+            }
             WidgetRet::Abort => TUIRet::Abort,
         }
     }
@@ -530,6 +779,9 @@ impl TUI {
         for tab in &mut self.tabs {
             tab.widget
                 .resize(self.width, self.height - 1 - statusline_height);
+            if let Some(ref mut chan_list) = tab.chan_list {
+                chan_list.resize(self.width, self.height - 1 - statusline_height);
+            }
         }
         // scroll the tab bar so that currently active tab is still visible
         let (mut tab_left, mut tab_right) = self.rendered_tabs();
@@ -684,12 +936,20 @@ impl TUI {
                 &self.tabs[self.active_idx].visible_name(),
                 self.tabs[self.active_idx].notifier,
                 self.tabs[self.active_idx].widget.get_ignore_state(),
+                self.tabs[self.active_idx].widget.get_topic(),
             );
         }
 
-        self.tabs[self.active_idx]
-            .widget
-            .draw(&mut self.tb, &self.colors, 0, statusline_height);
+        let tab = &self.tabs[self.active_idx];
+        match tab.chan_list {
+            Some(ref chan_list) => {
+                chan_list.draw(&mut self.tb, &self.colors, 0, statusline_height);
+            }
+            None => {
+                tab.widget
+                    .draw(&mut self.tb, &self.colors, 0, statusline_height);
+            }
+        }
 
         // decide whether we need to draw left/right arrows in tab bar
         let left_arr = self.draw_left_arrow();
@@ -733,7 +993,7 @@ impl TUI {
     ////////////////////////////////////////////////////////////////////////////
     // Moving between tabs, horizontal scroll updates
 
-    fn select_tab(&mut self, tab_idx: usize) {
+    pub(crate) fn select_tab(&mut self, tab_idx: usize) {
         if tab_idx < self.active_idx {
             while tab_idx < self.active_idx {
                 self.prev_tab_();
@@ -894,7 +1154,7 @@ impl TUI {
             MsgTarget::Server { serv } => {
                 for (tab_idx, tab) in self.tabs.iter().enumerate() {
                     if let MsgSource::Serv { serv: ref serv_ } = tab.src {
-                        if serv == serv_ {
+                        if serv == serv_ && !tab.is_aux() {
                             target_idxs.push(tab_idx);
                             break;
                         }
@@ -934,7 +1194,7 @@ impl TUI {
 
             MsgTarget::AllServTabs { serv } => {
                 for (tab_idx, tab) in self.tabs.iter().enumerate() {
-                    if tab.src.serv_name() == serv {
+                    if tab.src.serv_name() == serv && !tab.is_aux() {
                         target_idxs.push(tab_idx);
                     }
                 }
@@ -1048,9 +1308,8 @@ impl TUI {
         });
     }
 
-    pub(crate) fn clear_nicks(&mut self, serv: &str) {
-        let target = MsgTarget::AllServTabs { serv };
-        self.apply_to_target(&target, &|tab: &mut Tab, _| {
+    pub(crate) fn clear_nicks(&mut self, target: &MsgTarget) {
+        self.apply_to_target(target, &|tab: &mut Tab, _| {
             tab.widget.clear_nicks();
         });
     }
@@ -1158,7 +1417,29 @@ impl TUI {
     fn find_serv_tab_idx(&self, serv_: &str) -> Option<usize> {
         for (tab_idx, tab) in self.tabs.iter().enumerate() {
             if let MsgSource::Serv { ref serv } = tab.src {
-                if serv_ == serv {
+                if serv_ == serv && !tab.is_aux() {
+                    return Some(tab_idx);
+                }
+            }
+        }
+        None
+    }
+
+    fn find_chan_list_tab_idx(&self, serv_: &str) -> Option<usize> {
+        for (tab_idx, tab) in self.tabs.iter().enumerate() {
+            if let MsgSource::Serv { ref serv } = tab.src {
+                if serv_ == serv && tab.is_chan_list() {
+                    return Some(tab_idx);
+                }
+            }
+        }
+        None
+    }
+
+    fn find_raw_tab_idx(&self, serv_: &str) -> Option<usize> {
+        for (tab_idx, tab) in self.tabs.iter().enumerate() {
+            if let MsgSource::Serv { ref serv } = tab.src {
+                if serv_ == serv && tab.is_raw() {
                     return Some(tab_idx);
                 }
             }
@@ -1177,7 +1458,7 @@ impl TUI {
         None
     }
 
-    fn find_user_tab_idx(&self, serv_: &str, nick_: &str) -> Option<usize> {
+    pub(crate) fn find_user_tab_idx(&self, serv_: &str, nick_: &str) -> Option<usize> {
         for (tab_idx, tab) in self.tabs.iter().enumerate() {
             if let MsgSource::User { ref serv, ref nick } = tab.src {
                 if serv_ == serv && nick_ == nick {
@@ -1200,7 +1481,7 @@ impl TUI {
 
     fn is_server_tab(&self, idx: usize) -> bool {
         match self.tabs[idx].src {
-            MsgSource::Serv { .. } => true,
+            MsgSource::Serv { .. } => !self.tabs[idx].is_aux(),
             MsgSource::Chan { .. } | MsgSource::User { .. } => false,
         }
     }
@@ -1300,3 +1581,11 @@ fn paste_lines(tb: &mut Termbox, tf: String, str: &str) -> Result<Vec<String>, P
 
     Ok(filtered_lines)
 }
+
+fn tui_cmd_names() -> Trie {
+    let mut trie = Trie::new();
+    for cmd in &TUI_CMDS {
+        trie.insert(cmd);
+    }
+    trie
+}
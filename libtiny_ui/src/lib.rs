@@ -2,6 +2,9 @@ pub use objekt::clone_box;
 use time::Tm;
 
 /// Target of a message to be shown in a UI.
+///
+/// `serv` fields are network names (not server addresses) of connections. A network name uniquely
+/// identifies a connection, and is used as the server tab name.
 pub enum MsgTarget<'a> {
     /// Show the message in the server tab.
     Server { serv: &'a str },
@@ -19,7 +22,7 @@ pub enum MsgTarget<'a> {
     CurrentTab,
 }
 
-/// Source of a message from the user.
+/// Source of a message from the user. `serv` fields are network names, see `MsgTarget`.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MsgSource {
     /// Message sent in a server tab.
@@ -69,6 +72,15 @@ pub enum TabStyle {
     Highlight,
 }
 
+/// A channel in a channel list, see `UI::show_chan_list`. Also used by libtiny_client for LIST
+/// results.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChanListEntry {
+    pub chan: String,
+    pub n_users: u32,
+    pub topic: String,
+}
+
 /// UI events
 #[derive(Debug)]
 pub enum Event {
@@ -102,6 +114,10 @@ pub trait UI: objekt::Clone {
     /// Close a channel tab.
     fn close_chan_tab(&self, serv: &str, chan: &str);
 
+    /// Create a user tab in the given server and switch to it. Switches to the tab if it already
+    /// exists.
+    fn new_user_tab(&self, serv: &str, nick: &str);
+
     /// Close a user tab.
     fn close_user_tab(&self, serv: &str, nick: &str);
 
@@ -119,8 +135,8 @@ pub trait UI: objekt::Clone {
     /// error, e.g. "unknown command".
     fn add_client_err_msg(&self, msg: &str, target: &MsgTarget);
 
-    /// Clear all nicks in a server from the UI's cache. Does not show anything.
-    fn clear_nicks(&self, serv: &str);
+    /// Clear all nicks in the given tabs from the UI's cache. Does not show anything.
+    fn clear_nicks(&self, target: &MsgTarget);
 
     /// Set the client's nick in the given server.
     fn set_nick(&self, serv: &str, nick: &str);
@@ -163,6 +179,26 @@ pub trait UI: objekt::Clone {
     /// coming from server; e.g. messages from services sometimes shown in their own tabs,
     /// sometimes in the server tab.
     fn user_tab_exists(&self, serv: &str, nick: &str) -> bool;
+
+    /// Show a channel list (usually the result of a LIST request) for the given server.
+    fn show_chan_list(&self, serv: &str, chans: &[ChanListEntry]);
+
+    /// Create the raw protocol tab of the given server, or update the filter of the tab if it
+    /// exists. The tab shows lines sent to and read from the server that match the filter.
+    fn new_raw_tab(&self, serv: &str, filter: &str);
+
+    /// Close the raw protocol tab of the given server.
+    fn close_raw_tab(&self, serv: &str);
+
+    /// Do we have a raw protocol tab for the given server?
+    fn raw_tab_exists(&self, serv: &str) -> bool;
+
+    /// Add a line to the raw protocol tab of the given server. `incoming` is true for lines read
+    /// from the server. Does nothing when the server doesn't have a raw tab.
+    fn add_raw_msg(&self, serv: &str, msg: &str, ts: Tm, incoming: bool);
+
+    /// Stop the UI, e.g. after `/quit`. The UI sends an `Event::Abort` and stops sending events.
+    fn exit(&self);
 }
 
 #[derive(Clone)]
@@ -197,6 +233,11 @@ impl<UI1: UI + Clone, UI2: UI + Clone> UI for CombinedUIs<UI1, UI2> {
         self.ui2.close_chan_tab(serv, chan);
     }
 
+    fn new_user_tab(&self, serv: &str, nick: &str) {
+        self.ui1.new_user_tab(serv, nick);
+        self.ui2.new_user_tab(serv, nick);
+    }
+
     fn close_user_tab(&self, serv: &str, nick: &str) {
         self.ui1.close_user_tab(serv, nick);
         self.ui2.close_user_tab(serv, nick);
@@ -222,9 +263,9 @@ impl<UI1: UI + Clone, UI2: UI + Clone> UI for CombinedUIs<UI1, UI2> {
         self.ui2.add_client_err_msg(msg, target);
     }
 
-    fn clear_nicks(&self, serv: &str) {
-        self.ui1.clear_nicks(serv);
-        self.ui2.clear_nicks(serv);
+    fn clear_nicks(&self, target: &MsgTarget) {
+        self.ui1.clear_nicks(target);
+        self.ui2.clear_nicks(target);
     }
 
     fn set_nick(&self, serv: &str, nick: &str) {
@@ -276,6 +317,35 @@ impl<UI1: UI + Clone, UI2: UI + Clone> UI for CombinedUIs<UI1, UI2> {
         // TODO weird
         self.ui1.user_tab_exists(serv, nick)
     }
+
+    fn show_chan_list(&self, serv: &str, chans: &[ChanListEntry]) {
+        self.ui1.show_chan_list(serv, chans);
+        self.ui2.show_chan_list(serv, chans);
+    }
+
+    fn new_raw_tab(&self, serv: &str, filter: &str) {
+        self.ui1.new_raw_tab(serv, filter);
+        self.ui2.new_raw_tab(serv, filter);
+    }
+
+    fn close_raw_tab(&self, serv: &str) {
+        self.ui1.close_raw_tab(serv);
+        self.ui2.close_raw_tab(serv);
+    }
+
+    fn raw_tab_exists(&self, serv: &str) -> bool {
+        self.ui1.raw_tab_exists(serv) || self.ui2.raw_tab_exists(serv)
+    }
+
+    fn add_raw_msg(&self, serv: &str, msg: &str, ts: Tm, incoming: bool) {
+        self.ui1.add_raw_msg(serv, msg, ts, incoming);
+        self.ui2.add_raw_msg(serv, msg, ts, incoming);
+    }
+
+    fn exit(&self) {
+        self.ui1.exit();
+        self.ui2.exit();
+    }
 }
 
 pub fn combine<UI1: UI, UI2: UI>(ui1: UI1, ui2: UI2) -> CombinedUIs<UI1, UI2> {
@@ -36,8 +36,41 @@ pub fn join(chans: &[&str]) -> String {
     format!("JOIN {}\r\n", chans.join(","))
 }
 
-pub fn part(channel: &str) -> String {
-    format!("PART {}\r\n", channel)
+pub fn part(channel: &str, reason: Option<&str>) -> String {
+    match reason {
+        None => format!("PART {}\r\n", channel),
+        Some(reason) => format!("PART {} :{}\r\n", channel, reason),
+    }
+}
+
+/// A TOPIC message. Sets the topic when `topic` is available, requests the current topic
+/// otherwise. An empty `topic` clears the topic.
+pub fn topic(chan: &str, topic: Option<&str>) -> String {
+    match topic {
+        None => format!("TOPIC {}\r\n", chan),
+        Some(topic) => format!("TOPIC {} :{}\r\n", chan, topic),
+    }
+}
+
+pub fn invite(nick: &str, chan: &str) -> String {
+    format!("INVITE {} {}\r\n", nick, chan)
+}
+
+pub fn kick(chan: &str, nick: &str, reason: Option<&str>) -> String {
+    match reason {
+        None => format!("KICK {} {}\r\n", chan, nick),
+        Some(reason) => format!("KICK {} {} :{}\r\n", chan, nick, reason),
+    }
+}
+
+/// A MODE message. `params` are the mode string and its arguments, e.g. `["+o", "nick"]`. Sends a
+/// mode query when `params` is empty.
+pub fn mode(target: &str, params: &[&str]) -> String {
+    if params.is_empty() {
+        format!("MODE {}\r\n", target)
+    } else {
+        format!("MODE {} {}\r\n", target, params.join(" "))
+    }
 }
 
 pub fn privmsg(msgtarget: &str, msg: &str) -> String {
@@ -48,11 +81,60 @@ pub fn privmsg(msgtarget: &str, msg: &str) -> String {
     format!("PRIVMSG {} :{}\r\n", msgtarget, msg)
 }
 
+pub fn notice(msgtarget: &str, msg: &str) -> String {
+    assert!(msgtarget.len() + msg.len() + 11 <= 512); // See comments in `privmsg`
+    format!("NOTICE {} :{}\r\n", msgtarget, msg)
+}
+
 pub fn action(msgtarget: &str, msg: &str) -> String {
     assert!(msgtarget.len() + msg.len() + 21 <= 512); // See comments in `privmsg`
     format!("PRIVMSG {} :\x01ACTION {}\x01\r\n", msgtarget, msg)
 }
 
+pub fn who(mask: &str) -> String {
+    format!("WHO {}\r\n", mask)
+}
+
+/// A WHOX request, see https://ircv3.net/specs/extensions/whox. Replies are sent as
+/// `Reply::WhoSpcReply`.
+pub fn whox(mask: &str, fields: &str, token: &str) -> String {
+    format!("WHO {} %{},{}\r\n", mask, fields, token)
+}
+
+pub fn whois(nick: &str) -> String {
+    format!("WHOIS {}\r\n", nick)
+}
+
+pub fn whowas(nick: &str) -> String {
+    format!("WHOWAS {}\r\n", nick)
+}
+
+/// A LIST request. `args` can be channel names, or with the ELIST extension masks and search
+/// conditions like `>10`, separated by commas.
+pub fn list(args: Option<&str>) -> String {
+    match args {
+        None => "LIST\r\n".to_string(),
+        Some(args) => format!("LIST {}\r\n", args),
+    }
+}
+
+/// A CTCP request, e.g. `ctcp_request("nick", "PING", Some("123"))`.
+pub fn ctcp_request(msgtarget: &str, ctcp: &str, args: Option<&str>) -> String {
+    match args {
+        None => format!("PRIVMSG {} :\x01{}\x01\r\n", msgtarget, ctcp),
+        Some(args) => format!("PRIVMSG {} :\x01{} {}\x01\r\n", msgtarget, ctcp, args),
+    }
+}
+
+/// A CTCP reply. Replies are sent as NOTICEs so that clients don't reply to replies.
+pub fn ctcp_reply(msgtarget: &str, ctcp: &str, args: &str) -> String {
+    if args.is_empty() {
+        format!("NOTICE {} :\x01{}\x01\r\n", msgtarget, ctcp)
+    } else {
+        format!("NOTICE {} :\x01{} {}\x01\r\n", msgtarget, ctcp, args)
+    }
+}
+
 pub fn away(msg: Option<&str>) -> String {
     match msg {
         None => "AWAY\r\n".to_string(),
@@ -61,7 +143,7 @@ pub fn away(msg: Option<&str>) -> String {
 }
 
 pub fn cap_ls() -> String {
-    "CAP LS\r\n".to_string()
+    "CAP LS 302\r\n".to_string()
 }
 
 pub fn cap_req(cap_identifiers: &[&str]) -> String {
@@ -115,6 +197,13 @@ pub struct Msg {
 pub enum CTCP {
     Version,
     Action,
+    Ping,
+    Time,
+    ClientInfo,
+    Source,
+    UserInfo,
+    /// DCC offers. See https://modern.ircdocs.horse/dcc.html
+    Dcc,
     Other(String),
 }
 
@@ -123,9 +212,30 @@ impl CTCP {
         match s {
             "VERSION" => CTCP::Version,
             "ACTION" => CTCP::Action,
+            "PING" => CTCP::Ping,
+            "TIME" => CTCP::Time,
+            "CLIENTINFO" => CTCP::ClientInfo,
+            "SOURCE" => CTCP::Source,
+            "USERINFO" => CTCP::UserInfo,
+            "DCC" => CTCP::Dcc,
             _ => CTCP::Other(s.to_owned()),
         }
     }
+
+    /// Name of the CTCP message, as sent on the wire.
+    pub fn name(&self) -> &str {
+        match self {
+            CTCP::Version => "VERSION",
+            CTCP::Action => "ACTION",
+            CTCP::Ping => "PING",
+            CTCP::Time => "TIME",
+            CTCP::ClientInfo => "CLIENTINFO",
+            CTCP::Source => "SOURCE",
+            CTCP::UserInfo => "USERINFO",
+            CTCP::Dcc => "DCC",
+            CTCP::Other(s) => s,
+        }
+    }
 }
 
 /// An IRC command or reply
@@ -133,23 +243,29 @@ impl CTCP {
 pub enum Cmd {
     /// A PRIVMSG or NOTICE. Check `is_notice` field.
     PRIVMSG {
-        // TODO: In theory this should be a list of targets, but in practice I've never
-        // encountered that case.
-        target: MsgTarget,
+        /// Comma-separated targets of the message. Usually there's only one target.
+        targets: Vec<MsgTarget>,
         msg: String,
         is_notice: bool,
         ctcp: Option<CTCP>,
     },
 
     JOIN {
-        // TODO: Same as above, this should be a list ...
-        chan: String, // TODO: key field might be useful when joining restricted channels. In practice I've never
-                      // needed it.
+        /// Channels joined. Usually there's only one channel.
+        chans: Vec<String>,
+        /// Channel keys. Servers don't send these, but bouncers may relay the JOIN messages sent
+        /// by other clients.
+        keys: Vec<String>,
+        /// Account name of the user, when `extended-join` capability is enabled and the user is
+        /// logged in.
+        account: Option<String>,
+        /// Real name of the user, when `extended-join` capability is enabled.
+        realname: Option<String>,
     },
 
     PART {
-        // TODO: List of channels
-        chan: String,
+        /// Channels left. Usually there's only one channel.
+        chans: Vec<String>,
         msg: Option<String>,
     },
 
@@ -182,10 +298,34 @@ pub enum Cmd {
         topic: String,
     },
 
+    /// `nick` is invited to `chan`. `nick` is our nick, or with the `invite-notify` capability,
+    /// another user invited to a channel we're in.
+    INVITE {
+        nick: String,
+        chan: String,
+    },
+
+    /// `nick` is kicked from `chan`.
+    KICK {
+        chan: String,
+        nick: String,
+        msg: Option<String>,
+    },
+
+    /// A channel or user mode change.
+    MODE {
+        /// A channel, or our nick for user modes.
+        target: String,
+        /// The mode string and its arguments, e.g. `["+ov", "nick1", "nick2"]`.
+        params: Vec<String>,
+    },
+
     CAP {
         client: String,
         subcommand: String,
         params: Vec<String>,
+        /// More messages with the same subcommand follow. Set in multi-line CAP LS 302 replies.
+        more: bool,
     },
 
     AUTHENTICATE {
@@ -198,12 +338,501 @@ pub enum Cmd {
         params: Vec<String>,
     },
 
-    /// Numeric replies are kept generic as there are just too many replies and we probably only
-    /// need to handle a small subset of them.
-    Reply {
-        num: u16,
-        params: Vec<String>,
+    /// A numeric reply. Common replies are parsed into typed variants, see `Reply`.
+    Reply(Reply),
+}
+
+/// Numeric replies. Only replies that we (or users of this library) handle are parsed into their
+/// own variants; the rest are returned as `Reply::Unknown`. A reply with an unexpected number of
+/// parameters is also returned as `Reply::Unknown`, so servers that send weird replies don't cause
+/// panics.
+///
+/// The first parameter of every numeric reply is the target (i.e. our nick, or `*` when we don't
+/// have a nick yet). This parameter is dropped in the typed variants unless it's useful.
+///
+/// See https://modern.ircdocs.horse/#numerics and RFC 2812 section 5 for the definitions.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Reply {
+    /// 001 RPL_WELCOME. `nick` is the nick accepted by the server, empty when the server only sends
+    /// the message.
+    Welcome { nick: String, msg: String },
+
+    /// 002 RPL_YOURHOST
+    YourHost { msg: String },
+
+    /// 003 RPL_CREATED
+    Created { msg: String },
+
+    /// 004 RPL_MYINFO
+    MyInfo { params: Vec<String> },
+
+    /// 005 RPL_ISUPPORT. `tokens` are the `KEY` or `KEY=VALUE` tokens, without the trailing
+    /// "are supported by this server" message.
+    ISupport { tokens: Vec<String> },
+
+    /// 301 RPL_AWAY
+    Away { nick: String, msg: String },
+
+    /// 302 RPL_USERHOST. `reply` is the space-separated `nick=+user@host` list.
+    UserHost { reply: String },
+
+    /// 305 RPL_UNAWAY
+    UnAway { msg: String },
+
+    /// 306 RPL_NOWAWAY
+    NowAway { msg: String },
+
+    /// 311 RPL_WHOISUSER
+    WhoisUser {
+        nick: String,
+        user: String,
+        host: String,
+        realname: String,
+    },
+
+    /// 312 RPL_WHOISSERVER
+    WhoisServer {
+        nick: String,
+        server: String,
+        info: String,
+    },
+
+    /// 313 RPL_WHOISOPERATOR
+    WhoisOperator { nick: String, msg: String },
+
+    /// 314 RPL_WHOWASUSER
+    WhowasUser {
+        nick: String,
+        user: String,
+        host: String,
+        realname: String,
+    },
+
+    /// 315 RPL_ENDOFWHO. `mask` is the mask (usually a channel) in the WHO request.
+    EndOfWho { mask: String },
+
+    /// 317 RPL_WHOISIDLE. `signon` is not in RFC 2812 but sent by most servers.
+    WhoisIdle {
+        nick: String,
+        idle_secs: u64,
+        signon: Option<u64>,
+    },
+
+    /// 318 RPL_ENDOFWHOIS
+    EndOfWhois { nick: String },
+
+    /// 319 RPL_WHOISCHANNELS. Channel names may have membership prefixes (`@`, `+` etc.).
+    WhoisChannels { nick: String, chans: Vec<String> },
+
+    /// 321 RPL_LISTSTART
+    ListStart,
+
+    /// 322 RPL_LIST
+    List {
+        chan: String,
+        n_users: u32,
+        topic: String,
+    },
+
+    /// 323 RPL_LISTEND
+    ListEnd,
+
+    /// 330 RPL_WHOISACCOUNT
+    WhoisAccount { nick: String, account: String },
+
+    /// 331 RPL_NOTOPIC
+    NoTopic { chan: String },
+
+    /// 332 RPL_TOPIC
+    Topic { chan: String, topic: String },
+
+    /// 333 RPL_TOPICWHOTIME (not in RFC 2812). `nick` is the nick or the `nick!user@host` of the
+    /// user who set the topic, `set_at` is in seconds since Unix epoch.
+    TopicWhoTime {
+        chan: String,
+        nick: String,
+        set_at: u64,
+    },
+
+    /// 341 RPL_INVITING: `nick` is invited to `chan`, sent in reply to INVITE.
+    Inviting { nick: String, chan: String },
+
+    /// 352 RPL_WHOREPLY
+    WhoReply {
+        chan: String,
+        user: String,
+        host: String,
+        server: String,
+        nick: String,
+        /// `H` (here) or `G` (gone), followed by optional `*` and membership prefixes.
+        flags: String,
+        hops: u32,
+        realname: String,
     },
+
+    /// 353 RPL_NAMREPLY. Nicks may have membership prefixes, see `drop_nick_prefix`.
+    NamReply { chan: String, nicks: Vec<String> },
+
+    /// 354 RPL_WHOSPCRPL, a reply to a WHOX request. Fields depend on the request.
+    WhoSpcReply { fields: Vec<String> },
+
+    /// 366 RPL_ENDOFNAMES
+    EndOfNames { chan: String },
+
+    /// 369 RPL_ENDOFWHOWAS
+    EndOfWhowas { nick: String },
+
+    /// 372 RPL_MOTD
+    Motd { msg: String },
+
+    /// 375 RPL_MOTDSTART
+    MotdStart { msg: String },
+
+    /// 376 RPL_ENDOFMOTD
+    EndOfMotd { msg: String },
+
+    /// 378 RPL_WHOISHOST (not in RFC 2812). `msg` is usually "is connecting from <host> <ip>".
+    WhoisHost { nick: String, msg: String },
+
+    /// 396 RPL_HOSTHIDDEN (not in RFC 2812)
+    HostHidden { host: String, msg: String },
+
+    /// 401 ERR_NOSUCHNICK
+    NoSuchNick { nick: String, msg: String },
+
+    /// 403 ERR_NOSUCHCHANNEL
+    NoSuchChannel { chan: String, msg: String },
+
+    /// 404 ERR_CANNOTSENDTOCHAN
+    CannotSendToChan { chan: String, msg: String },
+
+    /// 406 ERR_WASNOSUCHNICK
+    WasNoSuchNick { nick: String, msg: String },
+
+    /// 422 ERR_NOMOTD
+    NoMotd { msg: String },
+
+    /// 432 ERR_ERRONEUSNICKNAME
+    ErroneousNickname { nick: String, msg: String },
+
+    /// 433 ERR_NICKNAMEINUSE
+    NicknameInUse { nick: String, msg: String },
+
+    /// 442 ERR_NOTONCHANNEL
+    NotOnChannel { chan: String, msg: String },
+
+    /// 443 ERR_USERONCHANNEL
+    UserOnChannel {
+        nick: String,
+        chan: String,
+        msg: String,
+    },
+
+    /// 464 ERR_PASSWDMISMATCH
+    PasswdMismatch { msg: String },
+
+    /// 465 ERR_YOUREBANNEDCREEP
+    YoureBannedCreep { msg: String },
+
+    /// 471 ERR_CHANNELISFULL, 473 ERR_INVITEONLYCHAN, 474 ERR_BANNEDFROMCHAN, 475
+    /// ERR_BADCHANNELKEY: reasons for a failed JOIN.
+    CannotJoin { num: u16, chan: String, msg: String },
+
+    /// 482 ERR_CHANOPRIVSNEEDED
+    ChanOpPrivsNeeded { chan: String, msg: String },
+
+    /// 671 RPL_WHOISSECURE (not in RFC 2812): the user is connected with TLS.
+    WhoisSecure { nick: String, msg: String },
+
+    /// 903 RPL_SASLSUCCESS
+    SaslSuccess { msg: String },
+
+    /// 904 ERR_SASLFAIL
+    SaslFail { msg: String },
+
+    /// A reply that's not handled by this library, or a known reply with unexpected parameters.
+    Unknown { num: u16, params: Vec<String> },
+}
+
+impl Reply {
+    /// Numeric code of the reply.
+    pub fn num(&self) -> u16 {
+        use Reply::*;
+        match *self {
+            Welcome { .. } => 1,
+            YourHost { .. } => 2,
+            Created { .. } => 3,
+            MyInfo { .. } => 4,
+            ISupport { .. } => 5,
+            Away { .. } => 301,
+            UserHost { .. } => 302,
+            UnAway { .. } => 305,
+            NowAway { .. } => 306,
+            WhoisUser { .. } => 311,
+            WhoisServer { .. } => 312,
+            WhoisOperator { .. } => 313,
+            WhowasUser { .. } => 314,
+            EndOfWho { .. } => 315,
+            WhoisIdle { .. } => 317,
+            EndOfWhois { .. } => 318,
+            WhoisChannels { .. } => 319,
+            ListStart => 321,
+            List { .. } => 322,
+            ListEnd => 323,
+            WhoisAccount { .. } => 330,
+            NoTopic { .. } => 331,
+            Topic { .. } => 332,
+            TopicWhoTime { .. } => 333,
+            Inviting { .. } => 341,
+            WhoReply { .. } => 352,
+            NamReply { .. } => 353,
+            WhoSpcReply { .. } => 354,
+            EndOfNames { .. } => 366,
+            EndOfWhowas { .. } => 369,
+            Motd { .. } => 372,
+            MotdStart { .. } => 375,
+            EndOfMotd { .. } => 376,
+            WhoisHost { .. } => 378,
+            HostHidden { .. } => 396,
+            NoSuchNick { .. } => 401,
+            NoSuchChannel { .. } => 403,
+            CannotSendToChan { .. } => 404,
+            WasNoSuchNick { .. } => 406,
+            NoMotd { .. } => 422,
+            ErroneousNickname { .. } => 432,
+            NicknameInUse { .. } => 433,
+            NotOnChannel { .. } => 442,
+            UserOnChannel { .. } => 443,
+            PasswdMismatch { .. } => 464,
+            YoureBannedCreep { .. } => 465,
+            CannotJoin { num, .. } => num,
+            ChanOpPrivsNeeded { .. } => 482,
+            WhoisSecure { .. } => 671,
+            SaslSuccess { .. } => 903,
+            SaslFail { .. } => 904,
+            Unknown { num, .. } => num,
+        }
+    }
+
+    fn parse(num: u16, params: Vec<&str>) -> Reply {
+        use Reply::*;
+
+        let n_params = params.len();
+        // Last parameter, usually the human-readable message
+        let last = || params[n_params - 1].to_owned();
+        // `idx`th parameter
+        let param = |idx: usize| params[idx].to_owned();
+
+        match num {
+            // Target of the message is the nick, when sent
+            1 if n_params >= 2 => Welcome {
+                nick: param(0),
+                msg: last(),
+            },
+            1 if n_params == 1 => Welcome {
+                nick: String::new(),
+                msg: last(),
+            },
+            2 if n_params >= 1 => YourHost { msg: last() },
+            3 if n_params >= 1 => Created { msg: last() },
+            4 if n_params >= 1 => MyInfo {
+                params: params[1..].iter().map(|s| (*s).to_owned()).collect(),
+            },
+            5 if n_params >= 2 => ISupport {
+                // Drop the target and trailing "are supported by this server"
+                tokens: params[1..n_params - 1]
+                    .iter()
+                    .map(|s| (*s).to_owned())
+                    .collect(),
+            },
+            301 if n_params == 3 => Away {
+                nick: param(1),
+                msg: param(2),
+            },
+            302 if n_params == 2 => UserHost { reply: param(1) },
+            305 if n_params == 2 => UnAway { msg: param(1) },
+            306 if n_params == 2 => NowAway { msg: param(1) },
+            311 if n_params == 6 => WhoisUser {
+                nick: param(1),
+                user: param(2),
+                host: param(3),
+                realname: param(5),
+            },
+            312 if n_params == 4 => WhoisServer {
+                nick: param(1),
+                server: param(2),
+                info: param(3),
+            },
+            313 if n_params == 3 => WhoisOperator {
+                nick: param(1),
+                msg: param(2),
+            },
+            314 if n_params == 6 => WhowasUser {
+                nick: param(1),
+                user: param(2),
+                host: param(3),
+                realname: param(5),
+            },
+            315 if n_params == 3 => EndOfWho { mask: param(1) },
+            317 if n_params == 4 || n_params == 5 => match params[2].parse::<u64>() {
+                Err(_) => Unknown {
+                    num,
+                    params: params.into_iter().map(|s| s.to_owned()).collect(),
+                },
+                Ok(idle_secs) => WhoisIdle {
+                    nick: param(1),
+                    idle_secs,
+                    signon: if n_params == 5 {
+                        params[3].parse::<u64>().ok()
+                    } else {
+                        None
+                    },
+                },
+            },
+            318 if n_params == 3 => EndOfWhois { nick: param(1) },
+            319 if n_params == 3 => WhoisChannels {
+                nick: param(1),
+                chans: params[2].split_whitespace().map(str::to_owned).collect(),
+            },
+            321 => ListStart,
+            322 if n_params == 4 => match params[2].parse::<u32>() {
+                Err(_) => Unknown {
+                    num,
+                    params: params.into_iter().map(|s| s.to_owned()).collect(),
+                },
+                Ok(n_users) => List {
+                    chan: param(1),
+                    n_users,
+                    topic: param(3),
+                },
+            },
+            323 => ListEnd,
+            330 if n_params == 4 => WhoisAccount {
+                nick: param(1),
+                account: param(2),
+            },
+            331 if n_params == 3 => NoTopic { chan: param(1) },
+            // RFC 2812 says this will have 2 arguments, but servers send 3 (the extra one being
+            // our nick).
+            332 if n_params == 2 || n_params == 3 => Topic {
+                chan: param(n_params - 2),
+                topic: last(),
+            },
+            333 if n_params == 4 => match params[3].parse::<u64>() {
+                Err(_) => Unknown {
+                    num,
+                    params: params.into_iter().map(|s| s.to_owned()).collect(),
+                },
+                Ok(set_at) => TopicWhoTime {
+                    chan: param(1),
+                    nick: param(2),
+                    set_at,
+                },
+            },
+            341 if n_params == 3 => Inviting {
+                nick: param(1),
+                chan: param(2),
+            },
+            352 if n_params == 8 => {
+                // Last parameter is "<hopcount> <real name>"
+                let hops_realname = params[7];
+                let (hops, realname) = match find_byte(hops_realname.as_bytes(), b' ') {
+                    None => (hops_realname, ""),
+                    Some(idx) => (&hops_realname[..idx], &hops_realname[idx + 1..]),
+                };
+                WhoReply {
+                    chan: param(1),
+                    user: param(2),
+                    host: param(3),
+                    server: param(4),
+                    nick: param(5),
+                    flags: param(6),
+                    hops: hops.parse().unwrap_or(0),
+                    realname: realname.to_owned(),
+                }
+            }
+            // <client> <symbol> <channel> :[prefix]<nick>{ [prefix]<nick>}
+            // Some servers don't send <symbol>
+            353 if n_params == 3 || n_params == 4 => NamReply {
+                chan: param(n_params - 2),
+                nicks: params[n_params - 1]
+                    .split_whitespace()
+                    .map(str::to_owned)
+                    .collect(),
+            },
+            354 if n_params >= 1 => WhoSpcReply {
+                fields: params[1..].iter().map(|s| (*s).to_owned()).collect(),
+            },
+            366 if n_params == 3 => EndOfNames { chan: param(1) },
+            369 if n_params == 3 => EndOfWhowas { nick: param(1) },
+            372 if n_params == 2 => Motd { msg: param(1) },
+            375 if n_params == 2 => MotdStart { msg: param(1) },
+            376 if n_params == 2 => EndOfMotd { msg: param(1) },
+            378 if n_params == 3 => WhoisHost {
+                nick: param(1),
+                msg: param(2),
+            },
+            396 if n_params == 3 => HostHidden {
+                host: param(1),
+                msg: param(2),
+            },
+            401 if n_params == 3 => NoSuchNick {
+                nick: param(1),
+                msg: param(2),
+            },
+            403 if n_params == 3 => NoSuchChannel {
+                chan: param(1),
+                msg: param(2),
+            },
+            404 if n_params == 3 => CannotSendToChan {
+                chan: param(1),
+                msg: param(2),
+            },
+            406 if n_params == 3 => WasNoSuchNick {
+                nick: param(1),
+                msg: param(2),
+            },
+            422 if n_params == 2 => NoMotd { msg: param(1) },
+            432 if n_params == 3 => ErroneousNickname {
+                nick: param(1),
+                msg: param(2),
+            },
+            433 if n_params == 3 => NicknameInUse {
+                nick: param(1),
+                msg: param(2),
+            },
+            442 if n_params == 3 => NotOnChannel {
+                chan: param(1),
+                msg: param(2),
+            },
+            443 if n_params == 4 => UserOnChannel {
+                nick: param(1),
+                chan: param(2),
+                msg: param(3),
+            },
+            464 if n_params >= 1 => PasswdMismatch { msg: last() },
+            465 if n_params >= 1 => YoureBannedCreep { msg: last() },
+            471 | 473 | 474 | 475 if n_params == 3 => CannotJoin {
+                num,
+                chan: param(1),
+                msg: param(2),
+            },
+            482 if n_params == 3 => ChanOpPrivsNeeded {
+                chan: param(1),
+                msg: param(2),
+            },
+            671 if n_params == 3 => WhoisSecure {
+                nick: param(1),
+                msg: param(2),
+            },
+            903 if n_params >= 1 => SaslSuccess { msg: last() },
+            904 if n_params >= 1 => SaslFail { msg: last() },
+            _ => Unknown {
+                num,
+                params: params.into_iter().map(|s| s.to_owned()).collect(),
+            },
+        }
+    }
 }
 
 /// An intermediate type used during parsing.
@@ -265,13 +894,16 @@ pub fn parse_irc_msg(buf: &mut Vec<u8>) -> Option<Msg> {
                 } else {
                     false
                 };
-                let target = params[0];
+                let targets = split_list(params[0])
+                    .map(|target| {
+                        if target.starts_with('#') {
+                            MsgTarget::Chan(target.to_owned())
+                        } else {
+                            MsgTarget::User(target.to_owned())
+                        }
+                    })
+                    .collect();
                 let mut msg = params[1];
-                let target = if target.chars().nth(0) == Some('#') {
-                    MsgTarget::Chan(target.to_owned())
-                } else {
-                    MsgTarget::User(target.to_owned())
-                };
 
                 let mut ctcp: Option<CTCP> = None;
                 if !msg.is_empty() && msg.as_bytes()[0] == 0x01 {
@@ -297,16 +929,35 @@ pub fn parse_irc_msg(buf: &mut Vec<u8>) -> Option<Msg> {
                 }
 
                 Cmd::PRIVMSG {
-                    target,
+                    targets,
                     msg: msg.to_owned(),
                     is_notice,
                     ctcp,
                 }
             }
-            MsgType::Cmd("JOIN") if params.len() == 1 => {
-                let chan = params[0];
-                Cmd::JOIN {
-                    chan: chan.to_owned(),
+            // JOIN <channel>{,<channel>} [<key>{,<key>}]
+            // With extended-join: JOIN <channel> <account> :<realname>
+            MsgType::Cmd("JOIN") if !params.is_empty() && params.len() <= 3 => {
+                let chans = split_list(params[0]).map(str::to_owned).collect();
+                match params.len() {
+                    3 => Cmd::JOIN {
+                        chans,
+                        keys: vec![],
+                        // "*" means the user is not logged in
+                        account: Some(params[1])
+                            .filter(|account| *account != "*")
+                            .map(str::to_owned),
+                        realname: Some(params[2].to_owned()),
+                    },
+                    _ => Cmd::JOIN {
+                        chans,
+                        keys: params
+                            .get(1)
+                            .map(|keys| split_list(keys).map(str::to_owned).collect())
+                            .unwrap_or_default(),
+                        account: None,
+                        realname: None,
+                    },
                 }
             }
             MsgType::Cmd("PART") if params.len() == 1 || params.len() == 2 => {
@@ -316,7 +967,7 @@ pub fn parse_irc_msg(buf: &mut Vec<u8>) -> Option<Msg> {
                     None
                 };
                 Cmd::PART {
-                    chan: params[0].to_owned(),
+                    chans: split_list(params[0]).map(str::to_owned).collect(),
                     msg: mb_msg,
                 }
             }
@@ -348,18 +999,35 @@ pub fn parse_irc_msg(buf: &mut Vec<u8>) -> Option<Msg> {
                 chan: params[0].to_owned(),
                 topic: params[1].to_owned(),
             },
+            MsgType::Cmd("INVITE") if params.len() == 2 => Cmd::INVITE {
+                nick: params[0].to_owned(),
+                chan: params[1].to_owned(),
+            },
+            MsgType::Cmd("KICK") if params.len() == 2 || params.len() == 3 => Cmd::KICK {
+                chan: params[0].to_owned(),
+                nick: params[1].to_owned(),
+                msg: params.get(2).map(|s| (*s).to_owned()),
+            },
+            MsgType::Cmd("MODE") if params.len() >= 2 => Cmd::MODE {
+                target: params[0].to_owned(),
+                params: params[1..].iter().map(|s| (*s).to_owned()).collect(),
+            },
             MsgType::Cmd("CAP") if params.len() == 3 => Cmd::CAP {
                 client: params[0].to_owned(),
                 subcommand: params[1].to_owned(),
                 params: params[2].split(' ').map(|s| s.to_owned()).collect(),
+                more: false,
+            },
+            MsgType::Cmd("CAP") if params.len() == 4 && params[2] == "*" => Cmd::CAP {
+                client: params[0].to_owned(),
+                subcommand: params[1].to_owned(),
+                params: params[3].split(' ').map(|s| s.to_owned()).collect(),
+                more: true,
             },
             MsgType::Cmd("AUTHENTICATE") if params.len() == 1 => Cmd::AUTHENTICATE {
                 param: params[0].to_owned(),
             },
-            MsgType::Num(n) => Cmd::Reply {
-                num: n,
-                params: params.into_iter().map(|s| s.to_owned()).collect(),
-            },
+            MsgType::Num(n) => Cmd::Reply(Reply::parse(n, params)),
             MsgType::Cmd(cmd) => Cmd::Other {
                 cmd: cmd.to_owned(),
                 params: params.into_iter().map(|s| s.to_owned()).collect(),
@@ -433,6 +1101,30 @@ pub fn find_byte(buf: &[u8], byte0: u8) -> Option<usize> {
     None
 }
 
+/// Split a comma-separated list of channels, targets or keys, skipping empty items.
+fn split_list(s: &str) -> impl Iterator<Item = &str> {
+    s.split(',').filter(|s| !s.is_empty())
+}
+
+/// Compare two nicks or channel names case-insensitively, using the `rfc1459` casemapping:
+/// ASCII letters, and `[]\~` as uppercase of `{}|^`.
+pub fn irc_eq(s1: &str, s2: &str) -> bool {
+    fn lower(b: u8) -> u8 {
+        match b {
+            b'[' => b'{',
+            b']' => b'}',
+            b'\\' => b'|',
+            b'~' => b'^',
+            _ => b.to_ascii_lowercase(),
+        }
+    }
+    s1.len() == s2.len()
+        && s1
+            .bytes()
+            .zip(s2.bytes())
+            .all(|(b1, b2)| lower(b1) == lower(b2))
+}
+
 /// Nicks may have prefixes, indicating it is a operator, founder, or something else.
 ///
 /// Channel Membership Prefixes: http://modern.ircdocs.horse/#channel-membership-prefixes
@@ -478,7 +1170,7 @@ mod tests {
                     user: "~nick@unaffiliated/nick".to_owned(),
                 }),
                 cmd: Cmd::PRIVMSG {
-                    target: MsgTarget::User("tiny".to_owned()),
+                    targets: vec![MsgTarget::User("tiny".to_owned())],
                     msg: "a b c".to_owned(),
                     is_notice: false,
                     ctcp: None,
@@ -501,7 +1193,7 @@ mod tests {
             Some(Msg {
                 pfx: Some(Pfx::Server("barjavel.freenode.net".to_owned())),
                 cmd: Cmd::PRIVMSG {
-                    target: MsgTarget::User("*".to_owned()),
+                    targets: vec![MsgTarget::User("*".to_owned())],
                     msg: "*** Looking up your hostname...".to_owned(),
                     is_notice: true,
                     ctcp: None,
@@ -558,7 +1250,7 @@ mod tests {
                     user: "~tiny@123.123.123.123".to_owned(),
                 }),
                 cmd: Cmd::PART {
-                    chan: "#haskell".to_owned(),
+                    chans: vec!["#haskell".to_owned()],
                     msg: None,
                 },
             })
@@ -577,7 +1269,10 @@ mod tests {
                     user: "~tiny@192.168.0.1".to_owned(),
                 }),
                 cmd: Cmd::JOIN {
-                    chan: "#haskell".to_owned(),
+                    chans: vec!["#haskell".to_owned()],
+                    keys: vec![],
+                    account: None,
+                    realname: None,
                 },
             })
         );
@@ -601,7 +1296,7 @@ mod tests {
                     user: "u@localhost".to_owned(),
                 }),
                 cmd: Cmd::PRIVMSG {
-                    target: MsgTarget::Chan("#ircv3".to_owned()),
+                    targets: vec![MsgTarget::Chan("#ircv3".to_owned())],
                     msg: "writes some specs!".to_owned(),
                     is_notice: false,
                     ctcp: Some(CTCP::Action),
@@ -627,7 +1322,7 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().cmd,
             Cmd::PRIVMSG {
-                target: MsgTarget::User("target".to_owned()),
+                targets: vec![MsgTarget::User("target".to_owned())],
                 msg: "msg contents".to_owned(),
                 is_notice: false,
                 ctcp: Some(CTCP::Action),
@@ -640,7 +1335,7 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().cmd,
             Cmd::PRIVMSG {
-                target: MsgTarget::User("target".to_owned()),
+                targets: vec![MsgTarget::User("target".to_owned())],
                 msg: "".to_owned(),
                 is_notice: false,
                 ctcp: Some(CTCP::Action),
@@ -655,7 +1350,7 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().cmd,
             Cmd::PRIVMSG {
-                target: MsgTarget::User("target".to_owned()),
+                targets: vec![MsgTarget::User("target".to_owned())],
                 msg: "’’’’’’’".to_owned(),
                 is_notice: false,
                 ctcp: None,
@@ -671,7 +1366,7 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().cmd,
             Cmd::PRIVMSG {
-                target: MsgTarget::User("target".to_owned()),
+                targets: vec![MsgTarget::User("target".to_owned())],
                 msg: "".to_owned(),
                 is_notice: false,
                 ctcp: Some(CTCP::Version),
@@ -683,7 +1378,7 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().cmd,
             Cmd::PRIVMSG {
-                target: MsgTarget::User("target".to_owned()),
+                targets: vec![MsgTarget::User("target".to_owned())],
                 msg: "".to_owned(),
                 is_notice: false,
                 ctcp: Some(CTCP::Version),
@@ -698,7 +1393,7 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().cmd,
             Cmd::PRIVMSG {
-                target: MsgTarget::User("target".to_owned()),
+                targets: vec![MsgTarget::User("target".to_owned())],
                 msg: "blah ".to_owned(),
                 is_notice: false,
                 ctcp: Some(CTCP::Other("blah".to_owned())),
@@ -710,7 +1405,7 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().cmd,
             Cmd::PRIVMSG {
-                target: MsgTarget::User("target".to_owned()),
+                targets: vec![MsgTarget::User("target".to_owned())],
                 msg: "blah ".to_owned(),
                 is_notice: false,
                 ctcp: Some(CTCP::Other("blah".to_owned())),
@@ -736,4 +1431,414 @@ mod tests {
             }),
         );
     }
+
+    #[test]
+    fn test_kick_mode_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            ":alice!~alice@host KICK #tiny bob :spam\r\n\
+             :alice!~alice@host KICK #tiny bob\r\n\
+             :alice!~alice@host MODE #tiny +ov bob carol\r\n\
+             :tiny MODE tiny :+i\r\n"
+        )
+        .unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::KICK {
+                chan: "#tiny".to_owned(),
+                nick: "bob".to_owned(),
+                msg: Some("spam".to_owned()),
+            }
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::KICK {
+                chan: "#tiny".to_owned(),
+                nick: "bob".to_owned(),
+                msg: None,
+            }
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::MODE {
+                target: "#tiny".to_owned(),
+                params: vec!["+ov".to_owned(), "bob".to_owned(), "carol".to_owned()],
+            }
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::MODE {
+                target: "tiny".to_owned(),
+                params: vec!["+i".to_owned()],
+            }
+        );
+        assert_eq!(parse_irc_msg(&mut buf), None);
+    }
+
+    #[test]
+    fn test_invite_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            ":alice!~alice@host INVITE tiny :#tiny\r\n\
+             :card.freenode.net 341 tiny bob #tiny\r\n\
+             :card.freenode.net 443 tiny bob #tiny :is already on channel\r\n"
+        )
+        .unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf),
+            Some(Msg {
+                pfx: Some(Pfx::User {
+                    nick: "alice".to_owned(),
+                    user: "~alice@host".to_owned(),
+                }),
+                cmd: Cmd::INVITE {
+                    nick: "tiny".to_owned(),
+                    chan: "#tiny".to_owned(),
+                },
+            }),
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::Reply(Reply::Inviting {
+                nick: "bob".to_owned(),
+                chan: "#tiny".to_owned(),
+            })
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::Reply(Reply::UserOnChannel {
+                nick: "bob".to_owned(),
+                chan: "#tiny".to_owned(),
+                msg: "is already on channel".to_owned(),
+            })
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn test_reply_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            ":card.freenode.net 332 tiny_test #haskell :Haskell topic\r\n\
+             :card.freenode.net 332 #haskell :Haskell topic\r\n\
+             :card.freenode.net 333 tiny_test #haskell osa1!~osa1@host 1573000000\r\n\
+             :card.freenode.net 353 tiny_test = #haskell :@op +voiced nick\r\n\
+             :card.freenode.net 317 tiny_test osa1 42 1573000000 :seconds idle, signon time\r\n\
+             :card.freenode.net 314 tiny_test osa1 ~osa1 host * :Ömer\r\n\
+             :card.freenode.net 671 tiny_test osa1 :is using a secure connection\r\n\
+             :card.freenode.net 354 tiny_test 152 #tiny ~omer host osa1 H 0 :Ömer\r\n\
+             :card.freenode.net 001 tiny_test extra :Welcome\r\n\
+             :card.freenode.net 002 :Your host is card.freenode.net\r\n\
+             :card.freenode.net 001 :Welcome to the network\r\n"
+        )
+        .unwrap();
+
+        let topic = Cmd::Reply(Reply::Topic {
+            chan: "#haskell".to_owned(),
+            topic: "Haskell topic".to_owned(),
+        });
+        assert_eq!(parse_irc_msg(&mut buf).unwrap().cmd, topic);
+        assert_eq!(parse_irc_msg(&mut buf).unwrap().cmd, topic);
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::Reply(Reply::TopicWhoTime {
+                chan: "#haskell".to_owned(),
+                nick: "osa1!~osa1@host".to_owned(),
+                set_at: 1_573_000_000,
+            })
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::Reply(Reply::NamReply {
+                chan: "#haskell".to_owned(),
+                nicks: vec!["@op".to_owned(), "+voiced".to_owned(), "nick".to_owned()],
+            })
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::Reply(Reply::WhoisIdle {
+                nick: "osa1".to_owned(),
+                idle_secs: 42,
+                signon: Some(1_573_000_000),
+            })
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::Reply(Reply::WhowasUser {
+                nick: "osa1".to_owned(),
+                user: "~osa1".to_owned(),
+                host: "host".to_owned(),
+                realname: "Ömer".to_owned(),
+            })
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::Reply(Reply::WhoisSecure {
+                nick: "osa1".to_owned(),
+                msg: "is using a secure connection".to_owned(),
+            })
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::Reply(Reply::WhoSpcReply {
+                fields: vec!["152", "#tiny", "~omer", "host", "osa1", "H", "0", "Ömer"]
+                    .into_iter()
+                    .map(str::to_owned)
+                    .collect(),
+            })
+        );
+        // Welcome messages with unusual number of params are still parsed
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::Reply(Reply::Welcome {
+                nick: "tiny_test".to_owned(),
+                msg: "Welcome".to_owned(),
+            })
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::Reply(Reply::YourHost {
+                msg: "Your host is card.freenode.net".to_owned(),
+            })
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::Reply(Reply::Welcome {
+                nick: "".to_owned(),
+                msg: "Welcome to the network".to_owned(),
+            })
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn test_reply_unknown() {
+        let mut buf = vec![];
+        // Unknown numeric
+        write!(&mut buf, ":serv 999 tiny_test a b :c d\r\n").unwrap();
+        let msg = parse_irc_msg(&mut buf).unwrap();
+        let reply = match msg.cmd {
+            Cmd::Reply(reply) => reply,
+            other => panic!("Unexpected cmd: {:?}", other),
+        };
+        assert_eq!(reply.num(), 999);
+        assert_eq!(
+            reply,
+            Reply::Unknown {
+                num: 999,
+                params: vec![
+                    "tiny_test".to_owned(),
+                    "a".to_owned(),
+                    "b".to_owned(),
+                    "c d".to_owned()
+                ],
+            }
+        );
+
+        // Known numeric with unexpected parameters should not panic
+        write!(&mut buf, ":serv 332 :topic\r\n").unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::Reply(Reply::Unknown {
+                num: 332,
+                params: vec!["topic".to_owned()],
+            })
+        );
+
+        write!(&mut buf, ":serv 322 tiny_test #chan lots :topic\r\n").unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::Reply(Reply::Unknown {
+                num: 322,
+                params: vec![
+                    "tiny_test".to_owned(),
+                    "#chan".to_owned(),
+                    "lots".to_owned(),
+                    "topic".to_owned()
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn test_isupport_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            ":serv 005 tiny_test CHANTYPES=# EXCEPTS NETWORK=freenode :are supported by this server\r\n"
+        )
+        .unwrap();
+        let reply = Reply::ISupport {
+            tokens: vec![
+                "CHANTYPES=#".to_owned(),
+                "EXCEPTS".to_owned(),
+                "NETWORK=freenode".to_owned(),
+            ],
+        };
+        assert_eq!(reply.num(), 5);
+        assert_eq!(parse_irc_msg(&mut buf).unwrap().cmd, Cmd::Reply(reply));
+    }
+
+    #[test]
+    fn test_cap_ls_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            ":serv CAP * LS * :multi-prefix sasl=PLAIN\r\n\
+             :serv CAP * LS :sts=port=6697\r\n"
+        )
+        .unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::CAP {
+                client: "*".to_owned(),
+                subcommand: "LS".to_owned(),
+                params: vec!["multi-prefix".to_owned(), "sasl=PLAIN".to_owned()],
+                more: true,
+            }
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::CAP {
+                client: "*".to_owned(),
+                subcommand: "LS".to_owned(),
+                params: vec!["sts=port=6697".to_owned()],
+                more: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_multi_target_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            ":a!b@c JOIN #a,#b\r\n\
+             :a!b@c JOIN #a,#b key1,key2\r\n\
+             :a!b@c JOIN #a acc :Real Name\r\n\
+             :a!b@c JOIN #a * :Real Name\r\n\
+             :a!b@c PART #a,#b :bye\r\n\
+             :a!b@c PRIVMSG #a,nick :hi\r\n"
+        )
+        .unwrap();
+
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::JOIN {
+                chans: vec!["#a".to_owned(), "#b".to_owned()],
+                keys: vec![],
+                account: None,
+                realname: None,
+            }
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::JOIN {
+                chans: vec!["#a".to_owned(), "#b".to_owned()],
+                keys: vec!["key1".to_owned(), "key2".to_owned()],
+                account: None,
+                realname: None,
+            }
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::JOIN {
+                chans: vec!["#a".to_owned()],
+                keys: vec![],
+                account: Some("acc".to_owned()),
+                realname: Some("Real Name".to_owned()),
+            }
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::JOIN {
+                chans: vec!["#a".to_owned()],
+                keys: vec![],
+                account: None,
+                realname: Some("Real Name".to_owned()),
+            }
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::PART {
+                chans: vec!["#a".to_owned(), "#b".to_owned()],
+                msg: Some("bye".to_owned()),
+            }
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::PRIVMSG {
+                targets: vec![
+                    MsgTarget::Chan("#a".to_owned()),
+                    MsgTarget::User("nick".to_owned())
+                ],
+                msg: "hi".to_owned(),
+                is_notice: false,
+                ctcp: None,
+            }
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn test_ctcp_roundtrip() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            ":a!b@c {}",
+            ctcp_request("target", "PING", Some("1234"))
+        )
+        .unwrap();
+        write!(&mut buf, ":a!b@c {}", ctcp_reply("target", "TIME", "now")).unwrap();
+        assert_eq!(
+            ctcp_reply("target", "CLIENTINFO", ""),
+            "NOTICE target :\x01CLIENTINFO\x01\r\n"
+        );
+        write!(
+            &mut buf,
+            ":a!b@c {}",
+            ctcp_request("target", "CLIENTINFO", None)
+        )
+        .unwrap();
+
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::PRIVMSG {
+                targets: vec![MsgTarget::User("target".to_owned())],
+                msg: "1234".to_owned(),
+                is_notice: false,
+                ctcp: Some(CTCP::Ping),
+            }
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::PRIVMSG {
+                targets: vec![MsgTarget::User("target".to_owned())],
+                msg: "now".to_owned(),
+                is_notice: true,
+                ctcp: Some(CTCP::Time),
+            }
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().cmd,
+            Cmd::PRIVMSG {
+                targets: vec![MsgTarget::User("target".to_owned())],
+                msg: "".to_owned(),
+                is_notice: false,
+                ctcp: Some(CTCP::ClientInfo),
+            }
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn test_irc_eq() {
+        assert!(irc_eq("#Tiny", "#tiny"));
+        assert!(irc_eq("nick[a]\\~", "NICK{A}|^"));
+        assert!(!irc_eq("#tiny", "#tiny2"));
+        assert!(!irc_eq("#tíny", "#tiny"));
+    }
 }
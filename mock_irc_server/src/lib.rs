@@ -0,0 +1,317 @@
+//! A scriptable IRC server for testing IRC clients.
+//!
+//! A `MockServer` listens on a local port and runs a `Script` in a thread of its own: a list of
+//! steps like waiting for the client to connect, expecting a line from the client, sending a
+//! line, and closing the connection. A step that fails (e.g. the client doesn't send the expected
+//! line in time) panics the server thread, and the panic is propagated by `MockServer::finish`.
+//!
+//! ```no_run
+//! use mock_irc_server::{MockServer, Script};
+//!
+//! let server = MockServer::start(
+//!     Script::new()
+//!         .accept()
+//!         .expect("NICK tiny")
+//!         .send(":irc.example.org 001 tiny :Welcome")
+//!         .expect("JOIN #tiny"),
+//! );
+//! // Connect a client to `server.port()` ...
+//! let lines = server.finish();
+//! ```
+
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// Wait for a client to connect. The current connection, if there is one, is closed.
+    Accept,
+    /// Read lines from the client until a line matches the pattern. Patterns are matched against
+    /// lines without the "\r\n" suffix. A pattern ending with `*` matches lines starting with the
+    /// rest of the pattern, other patterns need to match the whole line.
+    Expect(String),
+    /// Send a line to the client. "\r\n" suffix is added.
+    Send(String),
+    /// Wait before the next step, e.g. to delay a reply.
+    Delay(Duration),
+    /// Close the connection.
+    Disconnect,
+    /// Read lines until the client closes the connection.
+    ExpectClosed,
+}
+
+/// Steps of a `MockServer`. Methods add steps, see `Step` for what the steps do.
+#[derive(Debug, Clone)]
+pub struct Script {
+    steps: Vec<Step>,
+    /// How long to wait for the client in `Accept`, `Expect` and `ExpectClosed` steps.
+    timeout: Duration,
+}
+
+impl Script {
+    pub fn new() -> Script {
+        Script {
+            steps: vec![],
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Set how long to wait for the client before failing. The default is 10 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Script {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn step(mut self, step: Step) -> Script {
+        self.steps.push(step);
+        self
+    }
+
+    pub fn accept(self) -> Script {
+        self.step(Step::Accept)
+    }
+
+    pub fn expect(self, pattern: &str) -> Script {
+        self.step(Step::Expect(pattern.to_owned()))
+    }
+
+    pub fn send(self, line: &str) -> Script {
+        self.step(Step::Send(line.to_owned()))
+    }
+
+    pub fn delay(self, delay: Duration) -> Script {
+        self.step(Step::Delay(delay))
+    }
+
+    pub fn disconnect(self) -> Script {
+        self.step(Step::Disconnect)
+    }
+
+    pub fn expect_closed(self) -> Script {
+        self.step(Step::ExpectClosed)
+    }
+}
+
+impl Default for Script {
+    fn default() -> Script {
+        Script::new()
+    }
+}
+
+pub struct MockServer {
+    addr: SocketAddr,
+    thread: thread::JoinHandle<Vec<String>>,
+}
+
+impl MockServer {
+    /// Listen on a local port and start running the script.
+    pub fn start(script: Script) -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let thread = thread::spawn(move || run(listener, script));
+        MockServer { addr, thread }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// Wait until the script is done. Returns all lines read from the client, without the "\r\n"
+    /// suffixes. Panics if a step failed.
+    pub fn finish(self) -> Vec<String> {
+        match self.thread.join() {
+            Ok(lines) => lines,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+struct Conn {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Conn {
+    /// Returns `None` when the connection is closed.
+    fn read_line(&mut self, waiting_for: &Step) -> Option<String> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => {
+                while line.ends_with('\n') || line.ends_with('\r') {
+                    line.pop();
+                }
+                Some(line)
+            }
+            Err(err)
+                if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut =>
+            {
+                panic!("Timed out in step {:?}", waiting_for)
+            }
+            // E.g. connection reset by the client
+            Err(_) => None,
+        }
+    }
+}
+
+impl Drop for Conn {
+    fn drop(&mut self) {
+        let _ = self.writer.shutdown(Shutdown::Both);
+    }
+}
+
+fn run(listener: TcpListener, script: Script) -> Vec<String> {
+    let Script { steps, timeout } = script;
+    let mut lines = vec![];
+    let mut conn: Option<Conn> = None;
+
+    for step in steps {
+        match step {
+            Step::Accept => {
+                // Close the current connection before accepting a new one
+                drop(conn.take());
+                conn = Some(accept(&listener, timeout));
+            }
+            Step::Expect(ref pattern) => {
+                let conn = conn_of(&mut conn, &step);
+                loop {
+                    match conn.read_line(&step) {
+                        None => panic!("Connection closed in step {:?}", step),
+                        Some(line) => {
+                            let matches = line_matches(pattern, &line);
+                            lines.push(line);
+                            if matches {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            Step::Send(ref line) => {
+                let conn = conn_of(&mut conn, &step);
+                // Client may have closed the connection, let the next steps fail in that case
+                let _ = conn.writer.write_all(format!("{}\r\n", line).as_bytes());
+            }
+            Step::Delay(delay) => {
+                thread::sleep(delay);
+            }
+            Step::Disconnect => {
+                conn = None;
+            }
+            Step::ExpectClosed => {
+                let conn_ = conn_of(&mut conn, &step);
+                while let Some(line) = conn_.read_line(&step) {
+                    lines.push(line);
+                }
+                conn = None;
+            }
+        }
+    }
+
+    lines
+}
+
+fn conn_of<'a>(conn: &'a mut Option<Conn>, step: &Step) -> &'a mut Conn {
+    match conn {
+        None => panic!("Step {:?} needs a connection, add an `Accept` step", step),
+        Some(conn) => conn,
+    }
+}
+
+fn accept(listener: &TcpListener, timeout: Duration) -> Conn {
+    listener.set_nonblocking(true).unwrap();
+    let started = Instant::now();
+    let stream = loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                break stream;
+            }
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock => {
+                if started.elapsed() > timeout {
+                    panic!("Timed out in step {:?}", Step::Accept);
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(err) => {
+                panic!("Can't accept connection: {:?}", err);
+            }
+        }
+    };
+    stream.set_nonblocking(false).unwrap();
+    stream.set_read_timeout(Some(timeout)).unwrap();
+    let writer = stream.try_clone().unwrap();
+    Conn {
+        reader: BufReader::new(stream),
+        writer,
+    }
+}
+
+fn line_matches(pattern: &str, line: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => line.starts_with(prefix),
+        None => pattern == line,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_line_matches() {
+        assert!(line_matches("NICK tiny", "NICK tiny"));
+        assert!(!line_matches("NICK tiny", "NICK tiny_"));
+        assert!(line_matches("PING *", "PING irc.example.org"));
+        assert!(line_matches("*", "QUIT"));
+    }
+
+    #[test]
+    fn test_script() {
+        let server = MockServer::start(
+            Script::new()
+                .accept()
+                .expect("USER *")
+                .send("PING :x")
+                .disconnect()
+                .accept()
+                .expect_closed(),
+        );
+
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream
+            .write_all(b"NICK tiny\r\nUSER tiny 8 * :tiny\r\n")
+            .unwrap();
+        let mut contents = String::new();
+        stream.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "PING :x\r\n");
+
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream.write_all(b"QUIT\r\n").unwrap();
+        drop(stream);
+
+        assert_eq!(
+            server.finish(),
+            vec!["NICK tiny", "USER tiny 8 * :tiny", "QUIT"]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Timed out")]
+    fn test_timeout() {
+        let server = MockServer::start(
+            Script::new()
+                .timeout(Duration::from_millis(100))
+                .accept()
+                .expect("NICK tiny"),
+        );
+        let _stream = TcpStream::connect(server.addr()).unwrap();
+        server.finish();
+    }
+}
@@ -10,7 +10,8 @@ use std::collections::VecDeque;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
-use tokio::prelude::*;
+use futures::stream::Stream;
+use tokio::io::PollEvented;
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 // Public types
@@ -168,16 +169,16 @@ pub struct Input {
     /// Used when reading from stdin.
     buf: Vec<u8>,
 
-    stdin: tokio::net::util::PollEvented<mio::unix::EventedFd<'static>>,
+    stdin: PollEvented<mio::unix::EventedFd<'static>>,
 }
 
 impl Input {
+    /// Needs to be called in the context of a tokio runtime with the IO driver enabled.
     pub fn new() -> Input {
         Input {
             evs: VecDeque::new(),
             buf: Vec::with_capacity(100),
-            stdin: tokio::net::util::PollEvented::new(mio::unix::EventedFd(&libc::STDIN_FILENO))
-                .unwrap(),
+            stdin: PollEvented::new(mio::unix::EventedFd(&libc::STDIN_FILENO)).unwrap(),
         }
     }
 }
@@ -0,0 +1,123 @@
+//! User-defined command aliases, from the `aliases` section of the config file.
+//!
+//! An alias maps a command name to a template like `/msg deploybot deploy $1`. Templates can have
+//! multiple commands separated with `;`. Variables in templates:
+//!
+//! - `$1`, `$2`, ...: Arguments of the alias. Missing arguments are replaced with empty strings.
+//! - `$1-`, `$2-`, ...: Arguments starting from the given one, with the original spacing.
+//! - `$nick`, `$chan`, `$serv`: Current nick, channel of the current tab (empty when the current
+//!   tab is not a channel), and server of the current tab.
+//! - `$$`: A literal `$`.
+
+use crate::utils;
+
+/// Values of the `$nick`, `$chan` and `$serv` variables.
+pub(crate) struct AliasVars<'a> {
+    pub(crate) nick: &'a str,
+    pub(crate) chan: &'a str,
+    pub(crate) serv: &'a str,
+}
+
+/// Expand an alias template with the given arguments. Returns the lines the alias expands to, one
+/// for each `;`-separated part of the template. Empty parts are skipped.
+///
+/// The template is split before the substitution, so arguments with `;` don't add new commands.
+pub(crate) fn expand(template: &str, args: &str, vars: &AliasVars) -> Vec<String> {
+    template
+        .split(';')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(|part| substitute(part, args, vars))
+        .collect()
+}
+
+fn substitute(part: &str, args: &str, vars: &AliasVars) -> String {
+    let mut ret = String::with_capacity(part.len());
+    let mut rest = part;
+    while let Some(dollar_idx) = rest.find('$') {
+        ret.push_str(&rest[..dollar_idx]);
+        rest = &rest[dollar_idx + 1..];
+
+        if let Some(rest_) = rest.strip_prefix('$') {
+            ret.push('$');
+            rest = rest_;
+            continue;
+        }
+
+        let n_digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if n_digits != 0 {
+            // Argument numbers start from 1, `$0` is the same as `$1`
+            let arg_idx = rest[..n_digits].parse::<usize>().unwrap_or(0).max(1) - 1;
+            rest = &rest[n_digits..];
+            match rest.strip_prefix('-') {
+                Some(rest_) => {
+                    ret.push_str(args_from(args, arg_idx));
+                    rest = rest_;
+                }
+                None => ret.push_str(args.split_whitespace().nth(arg_idx).unwrap_or("")),
+            }
+            continue;
+        }
+
+        let var_len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        match &rest[..var_len] {
+            "nick" => ret.push_str(vars.nick),
+            "chan" => ret.push_str(vars.chan),
+            "serv" => ret.push_str(vars.serv),
+            // Not a variable, keep as it is
+            _ => {
+                ret.push('$');
+                continue;
+            }
+        }
+        rest = &rest[var_len..];
+    }
+    ret.push_str(rest);
+    ret
+}
+
+/// Arguments starting from the given (0-based) argument.
+fn args_from(args: &str, arg_idx: usize) -> &str {
+    match utils::split_whitespace_indices(args).nth(arg_idx) {
+        None => "",
+        Some(idx) => args[idx..].trim_end(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VARS: AliasVars = AliasVars {
+        nick: "tiny",
+        chan: "#tiny",
+        serv: "freenode",
+    };
+
+    #[test]
+    fn test_expand() {
+        assert_eq!(
+            expand("/msg deploybot deploy $1", "prod now", &VARS),
+            vec!["/msg deploybot deploy prod"]
+        );
+        assert_eq!(
+            expand("/msg $1 $2-", "bob  hi  there ", &VARS),
+            vec!["/msg bob hi  there"]
+        );
+        assert_eq!(
+            expand("/join $1; /msg $1 hi from $nick@$serv", "#rust", &VARS),
+            vec!["/join #rust", "/msg #rust hi from tiny@freenode"]
+        );
+        assert_eq!(
+            expand("/me costs $$5 in $chan", "", &VARS),
+            vec!["/me costs $5 in #tiny"]
+        );
+        assert_eq!(expand("/msg $1 $3", "bob", &VARS), vec!["/msg bob "]);
+        assert_eq!(expand("$foo $", "", &VARS), vec!["$foo $"]);
+        assert_eq!(expand(";/away $1-;", "a;b", &VARS), vec!["/away a;b"]);
+    }
+}
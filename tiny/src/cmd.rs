@@ -2,15 +2,19 @@
 #![allow(clippy::borrowed_box)]
 
 use crate::config;
+use crate::ui::Settings;
 use crate::utils;
-use libtiny_client::{Client, ServerInfo};
+use libtiny_client::{Client, DccId, ServerAddr, ServerInfo, UserInfo};
+use libtiny_tui::{ArgCompletion, TUI};
 use libtiny_ui::{MsgSource, MsgTarget, UI};
+use std::collections::HashMap;
 use std::path::Path;
 
 pub(crate) struct CmdArgs<'a> {
     pub args: &'a str,
-    pub config_path: &'a Path,
-    pub defaults: &'a config::Defaults,
+    pub settings: &'a mut Settings,
+    /// For updating TUI settings that are not in `UI`, e.g. colors
+    pub tui: &'a TUI,
     pub ui: &'a Box<dyn UI>,
     pub clients: &'a mut Vec<Client>,
     pub src: MsgSource,
@@ -38,13 +42,25 @@ pub(crate) enum ParseCmdResult<'a> {
         rest: &'a str,
     },
 
-    // Command name is ambiguous, here are possible values
-    // Ambiguous(Vec<&'static str>),
+    /// Command is a user-defined alias, see `alias::expand`
+    Alias {
+        template: String,
+
+        /// Rest of the command after extracting alias name
+        rest: &'a str,
+    },
+
+    /// Command name is ambiguous, here are possible values
+    Ambiguous(Vec<String>),
+
     /// Unknown command
     Unknown,
 }
 
-pub(crate) fn parse_cmd(cmd: &str) -> ParseCmdResult {
+/// Parse a command. `aliases` map alias names to their templates, and are checked before the
+/// built-in commands. Command names can be abbreviated to unique prefixes, e.g. `/jo` is `/join`.
+/// An exact match is preferred over prefix matches, so `/me` is not ambiguous with `/mode`.
+pub(crate) fn parse_cmd<'a>(cmd: &'a str, aliases: &HashMap<String, String>) -> ParseCmdResult<'a> {
     match cmd.split_whitespace().next() {
         None => ParseCmdResult::Unknown,
         Some(cmd_name) => {
@@ -56,26 +72,71 @@ pub(crate) fn parse_cmd(cmd: &str) -> ParseCmdResult {
                     Some(rest_idx) => &cmd[rest_idx..],
                 }
             };
-            // let mut possibilities: Vec<&'static Cmd> = vec![];
+            if let Some(template) = aliases.get(cmd_name) {
+                return ParseCmdResult::Alias {
+                    template: template.clone(),
+                    rest,
+                };
+            }
             for cmd in &CMDS {
                 if cmd_name == cmd.name {
                     // exact match, return
                     return ParseCmdResult::Ok { cmd, rest };
                 }
             }
-            ParseCmdResult::Unknown
-            // match possibilities.len() {
-            //     0 =>
-            //         ParseCmdResult::Unknown,
-            //     1 =>
-            //         ParseCmdResult::Ok {
-            //             cmd: possibilities[0],
-            //             rest,
-            //         },
-            //     _ =>
-            //         ParseCmdResult::Ambiguous(possibilities.into_iter().map(|cmd| cmd.name).collect()),
-            // }
+
+            // Aliases shadow built-in commands with the same name
+            let alias_matches = aliases
+                .iter()
+                .filter(|(name, _)| name.starts_with(cmd_name));
+            let cmd_matches = CMDS
+                .iter()
+                .filter(|cmd| cmd.name.starts_with(cmd_name) && !aliases.contains_key(cmd.name));
+            let mut possibilities: Vec<String> = alias_matches
+                .clone()
+                .map(|(name, _)| name.clone())
+                .chain(cmd_matches.clone().map(|cmd| cmd.name.to_owned()))
+                .collect();
+
+            match possibilities.len() {
+                0 => ParseCmdResult::Unknown,
+                1 => match alias_matches.map(|(_, template)| template).next() {
+                    Some(template) => ParseCmdResult::Alias {
+                        template: template.clone(),
+                        rest,
+                    },
+                    None => ParseCmdResult::Ok {
+                        cmd: cmd_matches.copied().next().unwrap(),
+                        rest,
+                    },
+                },
+                _ => {
+                    possibilities.sort_unstable();
+                    ParseCmdResult::Ambiguous(possibilities)
+                }
+            }
+        }
+    }
+}
+
+/// Command and alias names, with what to complete in their first arguments, for the TUI.
+pub(crate) fn cmd_completions(
+    aliases: &HashMap<String, String>,
+) -> Vec<(String, Option<ArgCompletion>)> {
+    CMDS.iter()
+        .map(|cmd| (cmd.name.to_owned(), arg_completion(cmd.name)))
+        .chain(aliases.keys().map(|alias| (alias.clone(), None)))
+        .collect()
+}
+
+fn arg_completion(cmd_name: &str) -> Option<ArgCompletion> {
+    match cmd_name {
+        "ban" | "ctcp" | "deop" | "devoice" | "ignorehost" | "invite" | "kick" | "kickban"
+        | "msg" | "names" | "op" | "query" | "quiet" | "unban" | "voice" | "whois" | "whowas" => {
+            Some(ArgCompletion::Nick)
         }
+        "join" | "part" => Some(ArgCompletion::Chan),
+        _ => None,
     }
 }
 
@@ -95,19 +156,55 @@ fn find_client<'a>(clients: &'a mut Vec<Client>, serv_name: &str) -> Option<&'a
     }
 }
 
+/// Split command arguments to the first word and the rest, e.g. a nick and a kick reason.
+fn split_first_word(args: &str) -> Option<(&str, Option<&str>)> {
+    let args = args.trim();
+    if args.is_empty() {
+        return None;
+    }
+    match args.find(char::is_whitespace) {
+        None => Some((args, None)),
+        Some(ws_idx) => Some((&args[..ws_idx], Some(args[ws_idx..].trim_start()))),
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
-static CMDS: [&Cmd; 9] = [
+static CMDS: [&Cmd; 34] = [
     &HELP_CMD,
     &AWAY_CMD,
+    &BAN_CMD,
     &CLOSE_CMD,
     &CONNECT_CMD,
+    &CTCP_CMD,
+    &DCC_CMD,
+    &DEOP_CMD,
+    &DEVOICE_CMD,
+    &IGNOREHOST_CMD,
+    &INVITE_CMD,
     &JOIN_CMD,
+    &KICK_CMD,
+    &KICKBAN_CMD,
+    &LIST_CMD,
     &ME_CMD,
+    &MODE_CMD,
     &MSG_CMD,
     &NAMES_CMD,
     &NICK_CMD,
-    // &RELOAD_CMD,
+    &NOTICE_CMD,
+    &OP_CMD,
+    &PART_CMD,
+    &QUERY_CMD,
+    &QUIET_CMD,
+    &QUIT_CMD,
+    &RAW_CMD,
+    &RAWLOG_CMD,
+    &RELOAD_CMD,
+    &TOPIC_CMD,
+    &UNBAN_CMD,
+    &VOICE_CMD,
+    &WHOIS_CMD,
+    &WHOWAS_CMD,
 ];
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
@@ -171,7 +268,7 @@ fn close(args: CmdArgs) {
         MsgSource::Chan { serv, chan } => {
             ui.close_chan_tab(&serv, &chan);
             let client_idx = find_client_idx(&clients, &serv).unwrap();
-            clients[client_idx].part(&chan);
+            clients[client_idx].part(&chan, None);
         }
         MsgSource::User { serv, nick } => {
             ui.close_user_tab(&serv, &nick);
@@ -190,13 +287,14 @@ static CONNECT_CMD: Cmd = Cmd {
 fn connect(args: CmdArgs) {
     let CmdArgs {
         args,
-        defaults,
+        settings,
         ui,
         clients,
         src,
         ..
     } = args;
     let words: Vec<&str> = args.split_whitespace().collect();
+    let defaults = &settings.defaults;
 
     match words.len() {
         0 => reconnect(ui, clients, src),
@@ -252,13 +350,32 @@ fn connect_(
         }
     };
 
-    // if we already connected to this server reconnect using new port
-    if let Some(client) = find_client(clients, serv_name) {
-        ui.add_client_msg("Connecting...", &MsgTarget::AllServTabs { serv: serv_name });
+    // if we already connected to this server reconnect using new port. The server may be in the
+    // config file with a different network name, so also look for the address.
+    let client_idx = find_client_idx(clients, serv_name).or_else(|| {
+        clients.iter().position(|client| {
+            client
+                .get_serv_addrs()
+                .iter()
+                .any(|addr| addr.host.eq_ignore_ascii_case(serv_name))
+        })
+    });
+    if let Some(client_idx) = client_idx {
+        let client = &mut clients[client_idx];
+        ui.add_client_msg(
+            "Connecting...",
+            &MsgTarget::AllServTabs {
+                serv: client.get_serv_name(),
+            },
+        );
         client.reconnect(Some(serv_port));
         return;
     }
 
+    if let Err(err) = config::validate_network_name(serv_name) {
+        return ui.add_client_err_msg(&format!("connect: {}", err), &MsgTarget::CurrentTab);
+    }
+
     // otherwise create a new connection
     // can't move the rest to an else branch because of borrowchk
 
@@ -267,38 +384,55 @@ fn connect_(
     let msg_target = MsgTarget::Server { serv: serv_name };
     ui.add_client_msg("Connecting...", &msg_target);
 
-    let (client, rcv_ev) = Client::new(
+    new_client(
         ServerInfo {
-            addr: serv_name.to_owned(),
-            port: serv_port,
+            name: serv_name.to_owned(),
+            addrs: vec![ServerAddr {
+                host: serv_name.to_owned(),
+                port: serv_port,
+                ws_path: None,
+            }],
             tls: defaults.tls,
+            sts_file: config::get_sts_file_path(),
+            bind_addr: defaults.bind_addr,
+            ip_pref: config::IpVersion::to_ip_pref(defaults.ip_version),
             realname: defaults.realname.clone(),
             pass: pass.map(str::to_owned),
             nicks: defaults.nicks.clone(),
             auto_join: defaults.join.clone(),
+            join_invites_from: defaults.join_invites_from.clone(),
             nickserv_ident: None,
             sasl_auth: None,
+            ctcp_replies: defaults.ctcp_replies,
+            dcc_download_dir: defaults.dcc_download_dir.clone(),
+            record_file: None,
         },
-        None, // tokio executor
+        ui,
+        clients,
     );
+}
+
+/// Create a client and spawn a task to handle its events.
+fn new_client(server_info: ServerInfo, ui: &Box<dyn UI>, clients: &mut Vec<Client>) {
+    let (client, rcv_ev) = Client::new(server_info);
 
     // Spawn UI task
     let ui_clone = libtiny_ui::clone_box(&**ui);
     let client_clone = client.clone();
-    tokio::runtime::current_thread::spawn(crate::conn::task(rcv_ev, ui_clone, client_clone));
+    tokio::task::spawn_local(crate::conn::task(rcv_ev, ui_clone, client_clone));
 
     clients.push(client);
 }
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
-static JOIN_CMD: Cmd = Cmd {
-    name: "join",
-    cmd_fn: join,
-    description: "Join a channel.\n\tUsage: /join CH1[,CH2,...]",
+static CTCP_CMD: Cmd = Cmd {
+    name: "ctcp",
+    cmd_fn: ctcp,
+    description: "Send a CTCP request.\n\tUsage: /ctcp NICK VERSION|PING|TIME|CLIENTINFO|SOURCE|USERINFO [ARGS]",
 };
 
-fn join(args: CmdArgs) {
+fn ctcp(args: CmdArgs) {
     let CmdArgs {
         args,
         ui,
@@ -306,69 +440,87 @@ fn join(args: CmdArgs) {
         src,
         ..
     } = args;
-    let words = args.split_whitespace().collect::<Vec<_>>();
-    if words.is_empty() {
-        return ui.add_client_err_msg(&JOIN_CMD.description, &MsgTarget::CurrentTab);
-    }
 
-    match find_client(clients, src.serv_name()) {
-        Some(client) => client.join(&words),
-        None => ui.add_client_err_msg(
-            &format!("Can't JOIN: Not connected to server {}", src.serv_name()),
-            &MsgTarget::CurrentTab,
-        ),
-    }
-}
+    let (target, ctcp, ctcp_args) = match parse_ctcp_args(args) {
+        None => {
+            return ui.add_client_err_msg(CTCP_CMD.description, &MsgTarget::CurrentTab);
+        }
+        Some(ret) => ret,
+    };
 
-////////////////////////////////////////////////////////////////////////////////////////////////////
+    let client = match find_client(clients, src.serv_name()) {
+        None => {
+            return ui.add_client_err_msg(
+                &format!(
+                    "Can't send CTCP: Not connected to server {}",
+                    src.serv_name()
+                ),
+                &MsgTarget::CurrentTab,
+            );
+        }
+        Some(client) => client,
+    };
 
-static ME_CMD: Cmd = Cmd {
-    name: "me",
-    cmd_fn: me,
-    description: "Invoke an action.\n\tUsage: /me ACTION",
-};
+    // Send current time in PING requests to be able to show round-trip time when we get the
+    // reply
+    let ping_arg;
+    let ctcp_args = if ctcp == "PING" && ctcp_args.is_none() {
+        ping_arg = utils::now_millis().to_string();
+        Some(ping_arg.as_str())
+    } else {
+        ctcp_args
+    };
 
-fn me(args: CmdArgs) {
-    let CmdArgs {
-        args,
-        ui,
-        clients,
-        src,
-        ..
-    } = args;
-    if args.is_empty() {
-        return ui.add_client_err_msg(&ME_CMD.description, &MsgTarget::CurrentTab);
-    }
-    crate::ui::send_msg(&**ui, clients, &src, args.to_string(), true);
+    client.ctcp(target, &ctcp, ctcp_args);
+    ui.add_client_msg(
+        &format!("Sent CTCP {} request to {}", ctcp, target),
+        &MsgTarget::CurrentTab,
+    );
+}
+
+/// Split `/ctcp` arguments into target, CTCP command (in upper case), and optional arguments.
+fn parse_ctcp_args(args: &str) -> Option<(&str, String, Option<&str>)> {
+    let mut ws_idxs = utils::split_whitespace_indices(args);
+    let target_idx = ws_idxs.next()?;
+    let ctcp_idx = ws_idxs.next()?;
+    let args_idx = ws_idxs.next();
+
+    let target = args[target_idx..ctcp_idx].trim_end();
+    let ctcp = match args_idx {
+        None => args[ctcp_idx..].trim_end(),
+        Some(args_idx) => args[ctcp_idx..args_idx].trim_end(),
+    };
+    let ctcp_args = args_idx.map(|idx| args[idx..].trim_end());
+
+    Some((target, ctcp.to_uppercase(), ctcp_args))
 }
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
-static MSG_CMD: Cmd = Cmd {
-    name: "msg",
-    cmd_fn: msg,
-    description: "Send a message to a user in a private tab.\n\tUsage: /msg NICK MESSAGE",
+static DCC_CMD: Cmd = Cmd {
+    name: "dcc",
+    cmd_fn: dcc,
+    description: "DCC chats and file transfers. -p sends a passive offer (for when you're behind a NAT).\n\tUsage: /dcc list|accept ID|reject ID|cancel ID|send [-p] NICK PATH|chat [-p] NICK",
 };
 
-fn split_msg_args(args: &str) -> Option<(&str, &str)> {
-    // Apparently we can't break with a val in a for loop yet so using mut var
-    let mut target_msg: Option<(&str, &str)> = None;
-    for (i, c) in args.char_indices() {
-        if !utils::is_nick_char(c) {
-            // This is where we split the message into target and actual message, however if the
-            // current char is a whitespace then we don't include it in the message, otherwise most
-            // messages would start with a whitespace. See `test_msg_args` below for some examples.
-            let target = &args[0..i];
-            let i = if c.is_whitespace() { i + 1 } else { i };
-            let msg = &args[i..];
-            target_msg = Some((target, msg));
-            break;
-        }
-    }
-    target_msg
+#[derive(Debug, PartialEq, Eq)]
+enum DccCmd<'a> {
+    List,
+    Accept(DccId),
+    Reject(DccId),
+    Cancel(DccId),
+    Send {
+        nick: &'a str,
+        path: &'a str,
+        passive: bool,
+    },
+    Chat {
+        nick: &'a str,
+        passive: bool,
+    },
 }
 
-fn msg(args: CmdArgs) {
+fn dcc(args: CmdArgs) {
     let CmdArgs {
         args,
         ui,
@@ -376,94 +528,166 @@ fn msg(args: CmdArgs) {
         src,
         ..
     } = args;
-    let fail = || {
-        ui.add_client_err_msg(&MSG_CMD.description, &MsgTarget::CurrentTab);
-    };
 
-    let (target, msg) = match split_msg_args(args) {
-        None => return fail(),
-        Some((target, msg)) => {
-            if msg.is_empty() {
-                return fail();
-            } else {
-                (target, msg)
-            }
+    let dcc_cmd = match parse_dcc_args(args) {
+        None => {
+            return ui.add_client_err_msg(DCC_CMD.description, &MsgTarget::CurrentTab);
         }
+        Some(dcc_cmd) => dcc_cmd,
     };
 
-    let src = if clients
-        .iter()
-        .any(|client| client.get_serv_name() == target)
-    {
-        MsgSource::Serv {
-            serv: target.to_owned(),
+    let client = match find_client(clients, src.serv_name()) {
+        None => {
+            return ui.add_client_err_msg(
+                &format!("Can't DCC: Not connected to server {}", src.serv_name()),
+                &MsgTarget::CurrentTab,
+            );
         }
-    } else {
-        let serv = src.serv_name();
-        MsgSource::User {
-            serv: serv.to_owned(),
-            nick: target.to_owned(),
+        Some(client) => client,
+    };
+
+    let ret = match dcc_cmd {
+        DccCmd::List => {
+            let dccs = client.dcc_list();
+            if dccs.is_empty() {
+                ui.add_client_msg("No DCC offers or transfers", &MsgTarget::CurrentTab);
+            }
+            for info in dccs {
+                ui.add_client_msg(
+                    &format!(
+                        "{}: {:?} {} with {}: {:?}, {} bytes",
+                        info.id,
+                        info.dir,
+                        crate::conn::dcc_desc(&info.kind),
+                        info.nick,
+                        info.status,
+                        info.bytes
+                    ),
+                    &MsgTarget::CurrentTab,
+                );
+            }
+            Ok(())
         }
+        DccCmd::Accept(id) => client.dcc_accept(id),
+        DccCmd::Reject(id) => client.dcc_reject(id),
+        DccCmd::Cancel(id) => client.dcc_cancel(id),
+        DccCmd::Send {
+            nick,
+            path,
+            passive,
+        } => client.dcc_send(nick, Path::new(path), passive).map(|id| {
+            ui.add_client_msg(
+                &format!("Sent DCC offer {} to {}", id, nick),
+                &MsgTarget::CurrentTab,
+            )
+        }),
+        DccCmd::Chat { nick, passive } => client.dcc_chat(nick, passive).map(|id| {
+            ui.add_client_msg(
+                &format!("Sent DCC chat offer {} to {}", id, nick),
+                &MsgTarget::CurrentTab,
+            )
+        }),
     };
 
-    crate::ui::send_msg(&**ui, clients, &src, msg.to_owned(), false);
+    if let Err(err) = ret {
+        ui.add_client_err_msg(&format!("DCC error: {}", err), &MsgTarget::CurrentTab);
+    }
+}
+
+fn parse_dcc_args(args: &str) -> Option<DccCmd<'_>> {
+    let words: Vec<&str> = args.split_whitespace().collect();
+    match words.as_slice() {
+        ["list"] => Some(DccCmd::List),
+        ["accept", id] => Some(DccCmd::Accept(id.parse().ok()?)),
+        ["reject", id] => Some(DccCmd::Reject(id.parse().ok()?)),
+        ["cancel", id] => Some(DccCmd::Cancel(id.parse().ok()?)),
+        ["chat", nick] => Some(DccCmd::Chat {
+            nick,
+            passive: false,
+        }),
+        ["chat", "-p", nick] => Some(DccCmd::Chat {
+            nick,
+            passive: true,
+        }),
+        ["send", ..] => {
+            let passive = words.get(1) == Some(&"-p");
+            let nick_word = if passive { 2 } else { 1 };
+            let nick = words.get(nick_word)?;
+            // Rest of the line is the path, which may have spaces
+            let path_idx = utils::split_whitespace_indices(args).nth(nick_word + 1)?;
+            Some(DccCmd::Send {
+                nick,
+                path: args[path_idx..].trim_end(),
+                passive,
+            })
+        }
+        _ => None,
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
-static NAMES_CMD: Cmd = Cmd {
-    name: "names",
-    cmd_fn: names,
-    description: "Lookup a nick or list all nicks in the current channel.\n\tUsage: /names [NICK]",
+static IGNOREHOST_CMD: Cmd = Cmd {
+    name: "ignorehost",
+    cmd_fn: ignorehost,
+    description: "Ignore messages from a host, or from the host of a nick. Run again to stop ignoring. Lists ignored hosts when run without arguments.\n\tUsage: /ignorehost [NICK|HOST]",
 };
 
-fn names(args: CmdArgs) {
+fn ignorehost(args: CmdArgs) {
     let CmdArgs {
         args,
         ui,
-        src,
         clients,
+        src,
         ..
     } = args;
     let words: Vec<&str> = args.split_whitespace().collect();
 
     let client = match find_client(clients, src.serv_name()) {
         None => {
-            return;
+            return ui.add_client_err_msg(
+                &format!("Can't ignore: Not connected to server {}", src.serv_name()),
+                &MsgTarget::CurrentTab,
+            );
         }
         Some(client) => client,
     };
 
-    if let MsgSource::Chan { ref serv, ref chan } = src {
-        let nicks_vec = client.get_chan_nicks(chan);
-        let target = MsgTarget::Chan { serv, chan };
-        if words.is_empty() {
-            ui.add_client_msg(
-                &format!("{} users: {}", nicks_vec.len(), nicks_vec.join(", ")),
-                &target,
-            );
-        } else {
-            let nick = words[0];
-            if nicks_vec.iter().any(|v| v == nick) {
-                ui.add_client_msg(&format!("{} is online", nick), &target);
+    match words.as_slice() {
+        [] => {
+            let hosts = client.get_ignored_hosts();
+            let msg = if hosts.is_empty() {
+                "No ignored hosts".to_owned()
             } else {
-                ui.add_client_msg(&format!("{} is not in the channel", nick), &target);
-            }
+                format!("Ignored hosts: {}", hosts.join(", "))
+            };
+            ui.add_client_msg(&msg, &MsgTarget::CurrentTab);
         }
-    } else {
-        ui.add_client_err_msg("/names only supported in chan tabs", &MsgTarget::CurrentTab);
+        [nick_or_host] => {
+            let host = client
+                .get_user_info(nick_or_host)
+                .and_then(|info| info.host)
+                .unwrap_or_else(|| (*nick_or_host).to_owned());
+            let msg = if client.toggle_ignore_host(&host) {
+                format!("Ignoring messages from {}", host)
+            } else {
+                format!("No longer ignoring messages from {}", host)
+            };
+            ui.add_client_msg(&msg, &MsgTarget::CurrentTab);
+        }
+        _ => ui.add_client_err_msg(IGNOREHOST_CMD.description, &MsgTarget::CurrentTab),
     }
 }
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
-static NICK_CMD: Cmd = Cmd {
-    name: "nick",
-    cmd_fn: nick,
-    description: "Change nick.\n\tUsage: /nick NICK",
+static INVITE_CMD: Cmd = Cmd {
+    name: "invite",
+    cmd_fn: invite,
+    description: "Invite a user to a channel. Uses the channel of the current tab when CHAN is not given.\n\tUsage: /invite NICK [CHAN]",
 };
 
-fn nick(args: CmdArgs) {
+fn invite(args: CmdArgs) {
     let CmdArgs {
         args,
         ui,
@@ -471,70 +695,1295 @@ fn nick(args: CmdArgs) {
         src,
         ..
     } = args;
+
     let words: Vec<&str> = args.split_whitespace().collect();
-    if words.len() == 1 {
-        if let Some(client) = find_client(clients, src.serv_name()) {
-            let new_nick = words[0];
-            client.nick(new_nick);
+    let (nick, chan) = match (words.as_slice(), &src) {
+        ([nick, chan], _) => (*nick, *chan),
+        ([nick], MsgSource::Chan { chan, .. }) => (*nick, chan.as_str()),
+        _ => {
+            return ui.add_client_err_msg(INVITE_CMD.description, &MsgTarget::CurrentTab);
         }
-    } else {
-        ui.add_client_err_msg(&NICK_CMD.description, &MsgTarget::CurrentTab);
+    };
+
+    match find_client(clients, src.serv_name()) {
+        Some(client) => client.invite(nick, chan),
+        None => ui.add_client_err_msg(
+            &format!("Can't INVITE: Not connected to server {}", src.serv_name()),
+            &MsgTarget::CurrentTab,
+        ),
     }
 }
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
-/*
-static RELOAD_CMD: Cmd = Cmd {
-    name: "reload",
-    cmd_fn: reload,
+static JOIN_CMD: Cmd = Cmd {
+    name: "join",
+    cmd_fn: join,
+    description: "Join a channel.\n\tUsage: /join CH1[,CH2,...]",
 };
 
-fn reload(args: CmdArgs) {
+fn join(args: CmdArgs) {
     let CmdArgs {
-        config_path, ui, ..
+        args,
+        ui,
+        clients,
+        src,
+        ..
     } = args;
-    match config::parse_config(config_path) {
-        Ok(config::Config { colors, .. }) => ui.set_colors(colors),
-        Err(err) => {
-            ui.add_client_err_msg("Can't parse config file:", &MsgTarget::CurrentTab);
-            for line in err.description().lines() {
-                ui.add_client_err_msg(line, &MsgTarget::CurrentTab);
-            }
-        }
+    let words = args.split_whitespace().collect::<Vec<_>>();
+    if words.is_empty() {
+        return ui.add_client_err_msg(&JOIN_CMD.description, &MsgTarget::CurrentTab);
+    }
+
+    match find_client(clients, src.serv_name()) {
+        Some(client) => client.join(&words),
+        None => ui.add_client_err_msg(
+            &format!("Can't JOIN: Not connected to server {}", src.serv_name()),
+            &MsgTarget::CurrentTab,
+        ),
     }
 }
-*/
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
+// Channel operator commands
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+static KICK_CMD: Cmd = Cmd {
+    name: "kick",
+    cmd_fn: kick,
+    description: "Kick a user from the current channel.\n\tUsage: /kick NICK [REASON]",
+};
 
-    #[test]
-    fn test_parse_cmd() {
-        let ret = parse_cmd("msg NickServ identify notMyPassword");
-        match ret {
-            ParseCmdResult::Ok { cmd, rest } => {
-                assert_eq!(cmd.name, "msg");
-                assert_eq!(rest, "NickServ identify notMyPassword");
-            }
-            _ => {
-                panic!("Can't parse cmd");
-            }
+fn kick(args: CmdArgs) {
+    kick_(args, &KICK_CMD, false)
+}
+
+static KICKBAN_CMD: Cmd = Cmd {
+    name: "kickban",
+    cmd_fn: kickban,
+    description: "Ban a user from the current channel and kick them. The ban mask is generated from the user's host when known.\n\tUsage: /kickban NICK [REASON]",
+};
+
+fn kickban(args: CmdArgs) {
+    kick_(args, &KICKBAN_CMD, true)
+}
+
+fn kick_(args: CmdArgs, cmd: &Cmd, ban: bool) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+
+    // Masks are only accepted by /ban
+    let (nick, reason) = match split_first_word(args) {
+        Some((nick, reason)) if !nick.contains(&['!', '@'][..]) => (nick, reason),
+        _ => {
+            return ui.add_client_err_msg(cmd.description, &MsgTarget::CurrentTab);
         }
+    };
 
-        let ret = parse_cmd("join #foo");
-        match ret {
-            ParseCmdResult::Ok { cmd, rest } => {
-                assert_eq!(cmd.name, "join");
-                assert_eq!(rest, "#foo");
-            }
-            _ => {
-                panic!("Can't parse cmd");
-            }
+    if let Some((client, chan)) = find_op_cmd_client(cmd, ui, clients, &src) {
+        if ban {
+            let host = client.get_user_info(nick).and_then(|info| info.host);
+            let mask = ban_mask(nick, host.as_deref());
+            client.mode(chan, &["+b", &mask]);
         }
+        client.kick(chan, nick, reason);
+    }
+}
+
+static BAN_CMD: Cmd = Cmd {
+    name: "ban",
+    cmd_fn: ban,
+    description: "Ban a user or a mask from the current channel. The ban mask of a nick is generated from the user's host when known.\n\tUsage: /ban NICK|MASK",
+};
+
+fn ban(args: CmdArgs) {
+    mask_mode(args, &BAN_CMD, "+b")
+}
+
+static UNBAN_CMD: Cmd = Cmd {
+    name: "unban",
+    cmd_fn: unban,
+    description: "Remove a ban from the current channel.\n\tUsage: /unban NICK|MASK",
+};
+
+fn unban(args: CmdArgs) {
+    mask_mode(args, &UNBAN_CMD, "-b")
+}
+
+static QUIET_CMD: Cmd = Cmd {
+    name: "quiet",
+    cmd_fn: quiet,
+    description: "Quiet a user or a mask in the current channel (mode +q, not supported by all servers).\n\tUsage: /quiet NICK|MASK",
+};
+
+fn quiet(args: CmdArgs) {
+    mask_mode(args, &QUIET_CMD, "+q")
+}
+
+/// Set a list mode (ban, quiet) with a mask generated from a nick, or given by the user.
+fn mask_mode(args: CmdArgs, cmd: &Cmd, mode: &str) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+
+    let words: Vec<&str> = args.split_whitespace().collect();
+    let nick_or_mask = match words.as_slice() {
+        [nick_or_mask] => *nick_or_mask,
+        _ => {
+            return ui.add_client_err_msg(cmd.description, &MsgTarget::CurrentTab);
+        }
+    };
+
+    if let Some((client, chan)) = find_op_cmd_client(cmd, ui, clients, &src) {
+        let host = client
+            .get_user_info(nick_or_mask)
+            .and_then(|info| info.host);
+        let mask = ban_mask(nick_or_mask, host.as_deref());
+        client.mode(chan, &[mode, &mask]);
+    }
+}
+
+static OP_CMD: Cmd = Cmd {
+    name: "op",
+    cmd_fn: op,
+    description: "Give channel operator status to users in the current channel.\n\tUsage: /op NICK [NICK ...]",
+};
+
+fn op(args: CmdArgs) {
+    nick_mode(args, &OP_CMD, '+', 'o')
+}
+
+static DEOP_CMD: Cmd = Cmd {
+    name: "deop",
+    cmd_fn: deop,
+    description: "Take channel operator status from users in the current channel.\n\tUsage: /deop NICK [NICK ...]",
+};
+
+fn deop(args: CmdArgs) {
+    nick_mode(args, &DEOP_CMD, '-', 'o')
+}
+
+static VOICE_CMD: Cmd = Cmd {
+    name: "voice",
+    cmd_fn: voice,
+    description: "Give voice to users in the current channel.\n\tUsage: /voice NICK [NICK ...]",
+};
+
+fn voice(args: CmdArgs) {
+    nick_mode(args, &VOICE_CMD, '+', 'v')
+}
+
+static DEVOICE_CMD: Cmd = Cmd {
+    name: "devoice",
+    cmd_fn: devoice,
+    description: "Take voice from users in the current channel.\n\tUsage: /devoice NICK [NICK ...]",
+};
+
+fn devoice(args: CmdArgs) {
+    nick_mode(args, &DEVOICE_CMD, '-', 'v')
+}
+
+/// Set or unset a nick mode (op, voice) of the given nicks.
+fn nick_mode(args: CmdArgs, cmd: &Cmd, sign: char, mode: char) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+
+    let nicks: Vec<&str> = args.split_whitespace().collect();
+    if nicks.is_empty() {
+        return ui.add_client_err_msg(cmd.description, &MsgTarget::CurrentTab);
+    }
+
+    if let Some((client, chan)) = find_op_cmd_client(cmd, ui, clients, &src) {
+        for nicks in nicks.chunks(client.get_max_modes()) {
+            let modes = format!("{}{}", sign, mode.to_string().repeat(nicks.len()));
+            let mut params = vec![modes.as_str()];
+            params.extend_from_slice(nicks);
+            client.mode(chan, &params);
+        }
+    }
+}
+
+static MODE_CMD: Cmd = Cmd {
+    name: "mode",
+    cmd_fn: mode,
+    description: "Show or change modes of the current channel, or of the given channel or nick.\n\tUsage: /mode [TARGET] [MODES [ARGS]]",
+};
+
+fn mode(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+
+    let words: Vec<&str> = args.split_whitespace().collect();
+    let (target, params) = match (words.split_first(), &src) {
+        (Some((target, params)), _) if !target.starts_with(&['+', '-'][..]) => (*target, params),
+        (_, MsgSource::Chan { chan, .. }) => (chan.as_str(), words.as_slice()),
+        _ => {
+            return ui.add_client_err_msg(MODE_CMD.description, &MsgTarget::CurrentTab);
+        }
+    };
+
+    match find_client(clients, src.serv_name()) {
+        Some(client) => client.mode(target, params),
+        None => ui.add_client_err_msg(
+            &format!("Can't MODE: Not connected to server {}", src.serv_name()),
+            &MsgTarget::CurrentTab,
+        ),
+    }
+}
+
+/// Find the client and the channel of an operator command. Shows an error and returns `None` when
+/// the command is not run in a channel tab or the server is not connected.
+fn find_op_cmd_client<'a>(
+    cmd: &Cmd,
+    ui: &Box<dyn UI>,
+    clients: &'a mut Vec<Client>,
+    src: &'a MsgSource,
+) -> Option<(&'a mut Client, &'a str)> {
+    let (serv, chan) = match src {
+        MsgSource::Chan { serv, chan } => (serv, chan),
+        _ => {
+            ui.add_client_err_msg(
+                &format!("/{} only supported in chan tabs", cmd.name),
+                &MsgTarget::CurrentTab,
+            );
+            return None;
+        }
+    };
+    match find_client(clients, serv) {
+        Some(client) => Some((client, chan)),
+        None => {
+            ui.add_client_err_msg(
+                &format!(
+                    "Can't {}: Not connected to server {}",
+                    cmd.name.to_uppercase(),
+                    serv
+                ),
+                &MsgTarget::CurrentTab,
+            );
+            None
+        }
+    }
+}
+
+/// Ban mask for a nick: `*!*@host` when the host of the user is known, `nick!*@*` otherwise.
+/// Arguments that already look like masks are returned as they are.
+fn ban_mask(nick_or_mask: &str, host: Option<&str>) -> String {
+    if nick_or_mask.contains(&['!', '@'][..]) {
+        return nick_or_mask.to_owned();
+    }
+    match host {
+        Some(host) => format!("*!*@{}", host),
+        None => format!("{}!*@*", nick_or_mask),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static LIST_CMD: Cmd = Cmd {
+    name: "list",
+    cmd_fn: list,
+    description: "List channels with names matching PATTERN (\"rust\", \"#rust*\") and at least MIN_USERS users. Channels are shown in a new tab, press enter to join the selected channel.\n\tUsage: /list [PATTERN] [MIN_USERS]",
+};
+
+fn list(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+
+    let (pattern, min_users) = match parse_list_args(args) {
+        None => {
+            return ui.add_client_err_msg(LIST_CMD.description, &MsgTarget::CurrentTab);
+        }
+        Some(args) => args,
+    };
+
+    match find_client(clients, src.serv_name()) {
+        Some(client) => {
+            client.list(pattern, min_users);
+            ui.add_client_msg(
+                "Requesting channel list...",
+                &MsgTarget::Server {
+                    serv: src.serv_name(),
+                },
+            );
+        }
+        None => ui.add_client_err_msg(
+            &format!("Can't LIST: Not connected to server {}", src.serv_name()),
+            &MsgTarget::CurrentTab,
+        ),
+    }
+}
+
+/// Parse `/list` arguments into a pattern and minimum number of users.
+fn parse_list_args(args: &str) -> Option<(Option<&str>, Option<u32>)> {
+    let words: Vec<&str> = args.split_whitespace().collect();
+    match words.as_slice() {
+        [] => Some((None, None)),
+        [arg] => match arg.parse::<u32>() {
+            Ok(min_users) => Some((None, Some(min_users))),
+            Err(_) => Some((Some(arg), None)),
+        },
+        [pattern, min_users] => min_users
+            .parse::<u32>()
+            .ok()
+            .map(|min_users| (Some(*pattern), Some(min_users))),
+        _ => None,
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static ME_CMD: Cmd = Cmd {
+    name: "me",
+    cmd_fn: me,
+    description: "Invoke an action.\n\tUsage: /me ACTION",
+};
+
+fn me(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    if args.is_empty() {
+        return ui.add_client_err_msg(&ME_CMD.description, &MsgTarget::CurrentTab);
+    }
+    crate::ui::send_msg(&**ui, clients, &src, args.to_string(), true);
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static MSG_CMD: Cmd = Cmd {
+    name: "msg",
+    cmd_fn: msg,
+    description: "Send a message to a user in a private tab.\n\tUsage: /msg NICK MESSAGE",
+};
+
+fn split_msg_args(args: &str) -> Option<(&str, &str)> {
+    // Apparently we can't break with a val in a for loop yet so using mut var
+    let mut target_msg: Option<(&str, &str)> = None;
+    for (i, c) in args.char_indices() {
+        if !utils::is_nick_char(c) {
+            // This is where we split the message into target and actual message, however if the
+            // current char is a whitespace then we don't include it in the message, otherwise most
+            // messages would start with a whitespace. See `test_msg_args` below for some examples.
+            let target = &args[0..i];
+            let i = if c.is_whitespace() { i + 1 } else { i };
+            let msg = &args[i..];
+            target_msg = Some((target, msg));
+            break;
+        }
+    }
+    target_msg
+}
+
+fn msg(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let fail = || {
+        ui.add_client_err_msg(&MSG_CMD.description, &MsgTarget::CurrentTab);
+    };
+
+    let (target, msg) = match split_msg_args(args) {
+        None => return fail(),
+        Some((target, msg)) => {
+            if msg.is_empty() {
+                return fail();
+            } else {
+                (target, msg)
+            }
+        }
+    };
+
+    let src = if clients
+        .iter()
+        .any(|client| client.get_serv_name() == target)
+    {
+        MsgSource::Serv {
+            serv: target.to_owned(),
+        }
+    } else {
+        let serv = src.serv_name();
+        MsgSource::User {
+            serv: serv.to_owned(),
+            nick: target.to_owned(),
+        }
+    };
+
+    crate::ui::send_msg(&**ui, clients, &src, msg.to_owned(), false);
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static NAMES_CMD: Cmd = Cmd {
+    name: "names",
+    cmd_fn: names,
+    description: "Lookup a nick or list all nicks in the current channel.\n\tUsage: /names [NICK]",
+};
+
+fn names(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        src,
+        clients,
+        ..
+    } = args;
+    let words: Vec<&str> = args.split_whitespace().collect();
+
+    let client = match find_client(clients, src.serv_name()) {
+        None => {
+            return;
+        }
+        Some(client) => client,
+    };
+
+    if let MsgSource::Chan { ref serv, ref chan } = src {
+        let nicks_vec = client.get_chan_nicks(chan);
+        let target = MsgTarget::Chan { serv, chan };
+        if words.is_empty() {
+            ui.add_client_msg(
+                &format!("{} users: {}", nicks_vec.len(), nicks_vec.join(", ")),
+                &target,
+            );
+        } else {
+            let nick = words[0];
+            if nicks_vec.iter().any(|v| v == nick) {
+                let msg = match client.get_user_info(nick) {
+                    None => format!("{} is online", nick),
+                    Some(info) => format!("{} is online: {}", nick, format_user_info(&info)),
+                };
+                ui.add_client_msg(&msg, &target);
+            } else {
+                ui.add_client_msg(&format!("{} is not in the channel", nick), &target);
+            }
+        }
+    } else {
+        ui.add_client_err_msg("/names only supported in chan tabs", &MsgTarget::CurrentTab);
+    }
+}
+
+/// Format user information for `/names NICK`, e.g. "~user@host (account: foo, away): Real Name"
+fn format_user_info(info: &UserInfo) -> String {
+    let mut ret = match (&info.user, &info.host) {
+        (Some(user), Some(host)) => format!("{}@{}", user, host),
+        _ => "unknown host".to_owned(),
+    };
+    let mut extras = vec![];
+    match info.account {
+        Some(ref account) if !account.is_empty() => extras.push(format!("account: {}", account)),
+        _ => {}
+    }
+    if info.away == Some(true) {
+        extras.push("away".to_owned());
+    }
+    if !extras.is_empty() {
+        ret.push_str(&format!(" ({})", extras.join(", ")));
+    }
+    if let Some(ref realname) = info.realname {
+        ret.push_str(&format!(": {}", realname));
+    }
+    ret
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static NICK_CMD: Cmd = Cmd {
+    name: "nick",
+    cmd_fn: nick,
+    description: "Change nick.\n\tUsage: /nick NICK",
+};
+
+fn nick(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let words: Vec<&str> = args.split_whitespace().collect();
+    if words.len() == 1 {
+        if let Some(client) = find_client(clients, src.serv_name()) {
+            let new_nick = words[0];
+            client.nick(new_nick);
+        }
+    } else {
+        ui.add_client_err_msg(&NICK_CMD.description, &MsgTarget::CurrentTab);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static NOTICE_CMD: Cmd = Cmd {
+    name: "notice",
+    cmd_fn: notice,
+    description: "Send a notice to a user or a channel.\n\tUsage: /notice TARGET MESSAGE",
+};
+
+fn notice(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+
+    let (target, msg) = match split_first_word(args) {
+        Some((target, Some(msg))) => (target, msg),
+        _ => {
+            return ui.add_client_err_msg(NOTICE_CMD.description, &MsgTarget::CurrentTab);
+        }
+    };
+
+    let client = match find_client(clients, src.serv_name()) {
+        None => {
+            return ui.add_client_err_msg(
+                &format!("Can't NOTICE: Not connected to server {}", src.serv_name()),
+                &MsgTarget::CurrentTab,
+            );
+        }
+        Some(client) => client,
+    };
+
+    // Show the notice as a message when sent to the current tab's channel or user, as a client
+    // message in the current tab otherwise
+    let in_target_tab = match src {
+        MsgSource::Chan { ref chan, .. } => chan.eq_ignore_ascii_case(target),
+        MsgSource::User { ref nick, .. } => nick.eq_ignore_ascii_case(target),
+        MsgSource::Serv { .. } => false,
+    };
+    let ts = time::now();
+    for msg in client.split_privmsg(target.len(), msg) {
+        client.notice(target, msg);
+        if in_target_tab {
+            ui.add_privmsg(&client.get_nick(), msg, ts, &src.to_target(), false, false);
+        } else {
+            ui.add_client_msg(
+                &format!("Notice to {}: {}", target, msg),
+                &MsgTarget::CurrentTab,
+            );
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static PART_CMD: Cmd = Cmd {
+    name: "part",
+    cmd_fn: part,
+    description: "Leave a channel and close its tab. CHAN defaults to the channel of the current tab.\n\tUsage: /part [CHAN] [REASON]",
+};
+
+fn part(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+
+    let current_chan = match src {
+        MsgSource::Chan { ref chan, .. } => Some(chan.as_str()),
+        _ => None,
+    };
+    let (chan, reason) = match parse_part_args(args, current_chan) {
+        Some(chan_reason) => chan_reason,
+        None => {
+            return ui.add_client_err_msg(PART_CMD.description, &MsgTarget::CurrentTab);
+        }
+    };
+
+    let serv = src.serv_name();
+    match find_client(clients, serv) {
+        Some(client) => {
+            client.part(chan, reason);
+            ui.close_chan_tab(serv, chan);
+        }
+        None => ui.add_client_err_msg(
+            &format!("Can't PART: Not connected to server {}", serv),
+            &MsgTarget::CurrentTab,
+        ),
+    }
+}
+
+/// Parse `/part` arguments to a channel and an optional reason. The channel can be omitted when
+/// the command is run in a channel tab (`current_chan`).
+fn parse_part_args<'a>(
+    args: &'a str,
+    current_chan: Option<&'a str>,
+) -> Option<(&'a str, Option<&'a str>)> {
+    match (split_first_word(args), current_chan) {
+        (Some((chan, reason)), _) if chan.starts_with(&['#', '&'][..]) => Some((chan, reason)),
+        (Some(_), Some(current_chan)) => Some((current_chan, Some(args.trim()))),
+        (None, Some(current_chan)) => Some((current_chan, None)),
+        (_, None) => None,
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static QUERY_CMD: Cmd = Cmd {
+    name: "query",
+    cmd_fn: query,
+    description: "Open a private tab with a user, optionally sending a message.\n\tUsage: /query NICK [MESSAGE]",
+};
+
+fn query(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+
+    let (nick, msg) = match split_first_word(args) {
+        Some((nick, msg)) if nick.chars().all(utils::is_nick_char) => (nick, msg),
+        _ => {
+            return ui.add_client_err_msg(QUERY_CMD.description, &MsgTarget::CurrentTab);
+        }
+    };
+
+    let serv = src.serv_name();
+    if find_client_idx(clients, serv).is_none() {
+        return ui.add_client_err_msg(
+            &format!("Can't open a private tab: Not connected to server {}", serv),
+            &MsgTarget::CurrentTab,
+        );
+    }
+
+    ui.new_user_tab(serv, nick);
+    if let Some(msg) = msg {
+        let src = MsgSource::User {
+            serv: serv.to_owned(),
+            nick: nick.to_owned(),
+        };
+        crate::ui::send_msg(&**ui, clients, &src, msg.to_owned(), false);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static QUIT_CMD: Cmd = Cmd {
+    name: "quit",
+    cmd_fn: quit,
+    description: "Disconnect from all servers and exit tiny.\n\tUsage: /quit [REASON]",
+};
+
+fn quit(args: CmdArgs) {
+    let CmdArgs {
+        args, ui, clients, ..
+    } = args;
+
+    let reason = Some(args.trim())
+        .filter(|reason| !reason.is_empty())
+        .map(str::to_owned);
+    for mut client in clients.drain(..) {
+        client.quit(reason.clone());
+    }
+    ui.exit();
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static RAW_CMD: Cmd = Cmd {
+    name: "raw",
+    cmd_fn: raw,
+    description: "Send a raw IRC message to the server of the current tab.\n\tUsage: /raw MESSAGE",
+};
+
+fn raw(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+
+    let msg = args.trim();
+    if msg.is_empty() {
+        return ui.add_client_err_msg(RAW_CMD.description, &MsgTarget::CurrentTab);
+    }
+
+    match find_client(clients, src.serv_name()) {
+        Some(client) => client.raw_msg(msg),
+        None => ui.add_client_err_msg(
+            &format!(
+                "Can't send raw message: Not connected to server {}",
+                src.serv_name()
+            ),
+            &MsgTarget::CurrentTab,
+        ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static RAWLOG_CMD: Cmd = Cmd {
+    name: "rawlog",
+    cmd_fn: rawlog,
+    description: "Show messages sent to and read from the server in a \"raw\" tab, or close the tab when it's open. FILTER is a list of commands and numerics to show (\"PRIVMSG 433\") or hide (\"-PING -PONG\"), running the command with a filter updates the filter of an open tab.\n\tUsage: /rawlog [FILTER]",
+};
+
+fn rawlog(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+
+    let serv = src.serv_name();
+    let client = match find_client(clients, serv) {
+        None => {
+            return ui.add_client_err_msg(
+                &format!("Can't show raw messages: Not connected to server {}", serv),
+                &MsgTarget::CurrentTab,
+            );
+        }
+        Some(client) => client,
+    };
+
+    let filter = args.trim();
+    if filter.is_empty() && ui.raw_tab_exists(serv) {
+        ui.close_raw_tab(serv);
+        client.set_raw_events(false);
+    } else {
+        ui.new_raw_tab(serv, filter);
+        client.set_raw_events(true);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static RELOAD_CMD: Cmd = Cmd {
+    name: "reload",
+    cmd_fn: reload,
+    description: "Reload the config file. Updates colors, defaults and aliases, and connects to new servers. Servers removed from the config file stay connected, use /close in their tabs to disconnect.\n\tUsage: /reload",
+};
+
+fn reload(args: CmdArgs) {
+    let CmdArgs {
+        settings,
+        tui,
+        ui,
+        clients,
+        ..
+    } = args;
+
+    let config = match read_config(&settings.config_path) {
+        Ok(config) => config,
+        Err(lines) => {
+            for line in lines {
+                ui.add_client_err_msg(&line, &MsgTarget::CurrentTab);
+            }
+            return;
+        }
+    };
+    let config::Config {
+        servers,
+        defaults,
+        colors,
+        aliases,
+        // Logger can't be changed without restarting
+        log_dir: _,
+    } = config;
+
+    tui.set_colors(colors);
+    tui.set_cmds(cmd_completions(&aliases));
+    settings.defaults = defaults;
+    settings.aliases = aliases;
+
+    let (new_servers, removed) = diff_servers(&mut settings.servers, servers, |serv| {
+        find_client_idx(clients, serv).is_some()
+    });
+
+    for server in new_servers {
+        let serv_name = server.name();
+        ui.new_server_tab(&serv_name);
+        ui.add_client_msg("Connecting...", &MsgTarget::Server { serv: &serv_name });
+        new_client(server.into_server_info(&settings.defaults), ui, clients);
+    }
+
+    ui.add_client_msg("Config file reloaded.", &MsgTarget::CurrentTab);
+
+    if !removed.is_empty() {
+        ui.add_client_msg(
+            &format!(
+                "Servers removed from the config file: {}. Use `/close` in their tabs to disconnect.",
+                removed.join(", ")
+            ),
+            &MsgTarget::CurrentTab,
+        );
+    }
+}
+
+/// Parse and check the config file. On errors returns the lines of the error message.
+fn read_config(config_path: &Path) -> Result<config::Config, Vec<String>> {
+    let config = config::parse_config(config_path).map_err(|err| {
+        std::iter::once("Can't parse config file:".to_owned())
+            .chain(err.to_string().lines().map(str::to_owned))
+            .collect::<Vec<_>>()
+    })?;
+    config::validate_servers(&config.servers).map_err(|err| {
+        std::iter::once("Invalid server in config file, config not reloaded:".to_owned())
+            .chain(err.lines().map(str::to_owned))
+            .collect::<Vec<_>>()
+    })?;
+    Ok(config)
+}
+
+/// Update `old_servers` (names of the servers in the config file before `/reload`) with the
+/// servers in the reloaded config file. Returns the servers to connect and names of the connected
+/// servers that are not in the config file anymore.
+///
+/// Servers that were in the config file before are not connected again as they may be closed by
+/// the user.
+fn diff_servers(
+    old_servers: &mut Vec<String>,
+    servers: Vec<config::Server>,
+    is_connected: impl Fn(&str) -> bool,
+) -> (Vec<config::Server>, Vec<String>) {
+    let new_names: Vec<String> = servers.iter().map(config::Server::name).collect();
+    let new_servers = servers
+        .into_iter()
+        .filter(|server| {
+            let name = server.name();
+            !old_servers.contains(&name) && !is_connected(&name)
+        })
+        .collect();
+    let removed = std::mem::replace(old_servers, new_names)
+        .into_iter()
+        .filter(|serv| !old_servers.contains(serv) && is_connected(serv))
+        .collect();
+    (new_servers, removed)
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static TOPIC_CMD: Cmd = Cmd {
+    name: "topic",
+    cmd_fn: topic,
+    description: "Show or set the topic of the current channel.\n\tUsage: /topic [TOPIC]",
+};
+
+fn topic(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+
+    let (serv, chan) = match src {
+        MsgSource::Chan { ref serv, ref chan } => (serv, chan),
+        _ => {
+            return ui.add_client_err_msg(TOPIC_CMD.description, &MsgTarget::CurrentTab);
+        }
+    };
+
+    let client = match find_client(clients, serv) {
+        None => {
+            return ui.add_client_err_msg(
+                &format!("Can't set topic: Not connected to server {}", serv),
+                &MsgTarget::CurrentTab,
+            );
+        }
+        Some(client) => client,
+    };
+
+    let new_topic = args.trim();
+    if !new_topic.is_empty() {
+        client.topic(chan, Some(new_topic));
+        return;
+    }
+
+    // Show the topic we know of, or ask the server when we don't know the topic
+    match client.get_topic(chan) {
+        None => client.topic(chan, None),
+        Some(topic) => {
+            let target = MsgTarget::Chan { serv, chan };
+            ui.add_client_msg(&format!("Topic: {}", topic.topic), &target);
+            if let (Some(set_by), Some(set_at)) = (topic.set_by, topic.set_at) {
+                let nick = set_by.split('!').next().unwrap_or(&set_by);
+                let set_at = time::at(time::Timespec::new(set_at as i64, 0));
+                ui.add_client_msg(
+                    &format!("Topic set by {} on {}", nick, set_at.ctime()),
+                    &target,
+                );
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static WHOIS_CMD: Cmd = Cmd {
+    name: "whois",
+    cmd_fn: whois,
+    description: "Show information about a user. NICK defaults to the user of the current tab.\n\tUsage: /whois [NICK]",
+};
+
+fn whois(args: CmdArgs) {
+    whois_(args, &WHOIS_CMD, Client::whois)
+}
+
+static WHOWAS_CMD: Cmd = Cmd {
+    name: "whowas",
+    cmd_fn: whowas,
+    description: "Show information about a user who is no longer online. NICK defaults to the user of the current tab.\n\tUsage: /whowas [NICK]",
+};
+
+fn whowas(args: CmdArgs) {
+    whois_(args, &WHOWAS_CMD, Client::whowas)
+}
+
+fn whois_(args: CmdArgs, cmd: &Cmd, send: fn(&mut Client, &str)) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let words: Vec<&str> = args.split_whitespace().collect();
+    let nick = match (words.as_slice(), &src) {
+        ([nick], _) => *nick,
+        ([], MsgSource::User { nick, .. }) => nick,
+        _ => {
+            return ui.add_client_err_msg(cmd.description, &MsgTarget::CurrentTab);
+        }
+    };
+
+    match find_client(clients, src.serv_name()) {
+        Some(client) => send(client, nick),
+        None => ui.add_client_err_msg(
+            &format!(
+                "Can't {}: Not connected to server {}",
+                cmd.name.to_uppercase(),
+                src.serv_name()
+            ),
+            &MsgTarget::CurrentTab,
+        ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cmd() {
+        let aliases = HashMap::new();
+        let ret = parse_cmd("msg NickServ identify notMyPassword", &aliases);
+        match ret {
+            ParseCmdResult::Ok { cmd, rest } => {
+                assert_eq!(cmd.name, "msg");
+                assert_eq!(rest, "NickServ identify notMyPassword");
+            }
+            _ => {
+                panic!("Can't parse cmd");
+            }
+        }
+
+        let ret = parse_cmd("join #foo", &aliases);
+        match ret {
+            ParseCmdResult::Ok { cmd, rest } => {
+                assert_eq!(cmd.name, "join");
+                assert_eq!(rest, "#foo");
+            }
+            _ => {
+                panic!("Can't parse cmd");
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_cmd_prefix() {
+        let mut aliases = HashMap::new();
+        aliases.insert("deploy".to_owned(), "/msg deploybot deploy $1".to_owned());
+        aliases.insert("nick".to_owned(), "/nick $1; /names".to_owned());
+
+        match parse_cmd("jo #tiny", &aliases) {
+            ParseCmdResult::Ok { cmd, rest } => {
+                assert_eq!(cmd.name, "join");
+                assert_eq!(rest, "#tiny");
+            }
+            _ => panic!("Can't parse cmd prefix"),
+        }
+
+        // Exact matches are preferred
+        match parse_cmd("me hi", &aliases) {
+            ParseCmdResult::Ok { cmd, .. } => assert_eq!(cmd.name, "me"),
+            _ => panic!("Can't parse cmd"),
+        }
+
+        match parse_cmd("dep prod", &aliases) {
+            ParseCmdResult::Alias { template, rest } => {
+                assert_eq!(template, "/msg deploybot deploy $1");
+                assert_eq!(rest, "prod");
+            }
+            _ => panic!("Can't parse alias prefix"),
+        }
+
+        match parse_cmd("de", &aliases) {
+            ParseCmdResult::Ambiguous(names) => {
+                assert_eq!(names, vec!["deop", "deploy", "devoice"]);
+            }
+            _ => panic!("Ambiguous prefix not detected"),
+        }
+
+        // The alias shadows the built-in `/nick`
+        match parse_cmd("nic", &aliases) {
+            ParseCmdResult::Alias { template, .. } => assert_eq!(template, "/nick $1; /names"),
+            _ => panic!("Can't parse alias prefix"),
+        }
+
+        match parse_cmd("xyz", &aliases) {
+            ParseCmdResult::Unknown => {}
+            _ => panic!("Unknown command parsed"),
+        }
+    }
+
+    #[test]
+    fn test_parse_cmd_ambiguous() {
+        let mut aliases = HashMap::new();
+        aliases.insert("joinall".to_owned(), "/join #tiny,#rust".to_owned());
+        aliases.insert("msgbot".to_owned(), "/msg bot $1-".to_owned());
+
+        // A prefix of both an alias and a built-in command
+        match parse_cmd("joi #tiny", &aliases) {
+            ParseCmdResult::Ambiguous(names) => assert_eq!(names, vec!["join", "joinall"]),
+            _ => panic!("Ambiguous prefix not detected"),
+        }
+        match parse_cmd("ms hi", &aliases) {
+            ParseCmdResult::Ambiguous(names) => assert_eq!(names, vec!["msg", "msgbot"]),
+            _ => panic!("Ambiguous prefix not detected"),
+        }
+
+        // Exact matches are not ambiguous
+        match parse_cmd("join #tiny", &aliases) {
+            ParseCmdResult::Ok { cmd, .. } => assert_eq!(cmd.name, "join"),
+            _ => panic!("Can't parse cmd"),
+        }
+        match parse_cmd("msgbot hi", &aliases) {
+            ParseCmdResult::Alias { template, .. } => assert_eq!(template, "/msg bot $1-"),
+            _ => panic!("Can't parse alias"),
+        }
+    }
+
+    #[test]
+    fn test_parse_alias() {
+        let mut aliases = HashMap::new();
+        aliases.insert("deploy".to_owned(), "/msg deploybot deploy $1".to_owned());
+        aliases.insert("join".to_owned(), "/join $1-; /names".to_owned());
+
+        match parse_cmd("deploy prod", &aliases) {
+            ParseCmdResult::Alias { template, rest } => {
+                assert_eq!(template, "/msg deploybot deploy $1");
+                assert_eq!(rest, "prod");
+            }
+            _ => panic!("Can't parse alias"),
+        }
+
+        // Aliases are checked before built-in commands
+        match parse_cmd("join #tiny", &aliases) {
+            ParseCmdResult::Alias { template, .. } => assert_eq!(template, "/join $1-; /names"),
+            _ => panic!("Can't parse alias"),
+        }
+    }
+
+    fn parse_servers(yaml: &str) -> Vec<config::Server> {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_reload_servers() {
+        let servers = parse_servers(
+            "
+- addr: irc.libera.chat
+  port: 6697
+  realname: tiny
+  nicks: [tiny]
+- addr: irc.oftc.net
+  port: 6697
+  realname: tiny
+  nicks: [tiny]
+- name: local
+  addr: localhost
+  port: 6667
+  realname: tiny
+  nicks: [tiny]
+",
+        );
+        let mut old_servers = vec![
+            "irc.libera.chat".to_owned(),
+            "irc.gimp.org".to_owned(),
+            "closed.example.org".to_owned(),
+        ];
+        let connected = ["irc.libera.chat", "irc.gimp.org", "local"];
+        let (new_servers, removed) =
+            diff_servers(&mut old_servers, servers, |serv| connected.contains(&serv));
+
+        // Servers already in the config file or connected with `/connect` are not connected again
+        assert_eq!(
+            new_servers
+                .iter()
+                .map(config::Server::name)
+                .collect::<Vec<_>>(),
+            vec!["irc.oftc.net"]
+        );
+        // Only connected servers are reported as removed
+        assert_eq!(removed, vec!["irc.gimp.org"]);
+        assert_eq!(
+            old_servers,
+            vec!["irc.libera.chat", "irc.oftc.net", "local"]
+        );
+    }
+
+    #[test]
+    fn test_reload_config_errs() {
+        let path = std::env::temp_dir().join(format!("tiny_reload_test_{}", std::process::id()));
+
+        std::fs::write(&path, "servers: [").unwrap();
+        let lines = read_config(&path).err().unwrap();
+        assert_eq!(lines[0], "Can't parse config file:");
+        assert!(lines.len() > 1);
+
+        std::fs::write(
+            &path,
+            "
+servers:
+  - addr: irc.libera.chat
+    port: 6697
+    realname: tiny
+    nicks: [tiny]
+  - addr: irc.libera.chat
+    port: 6667
+    realname: tiny
+    nicks: [tiny]
+defaults:
+  nicks: [tiny]
+  realname: tiny
+",
+        )
+        .unwrap();
+        let lines = read_config(&path).err().unwrap();
+        assert_eq!(
+            lines,
+            vec![
+                "Invalid server in config file, config not reloaded:".to_owned(),
+                "Multiple servers with network name irc.libera.chat, use `name` fields to give \
+                 them different names"
+                    .to_owned()
+            ]
+        );
+
+        std::fs::write(
+            &path,
+            "
+servers:
+  - addr: irc.libera.chat
+    port: 6697
+    realname: tiny
+    nicks: [tiny]
+defaults:
+  nicks: [tiny]
+  realname: tiny
+",
+        )
+        .unwrap();
+        assert_eq!(read_config(&path).ok().unwrap().servers.len(), 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_split_first_word() {
+        assert_eq!(split_first_word(""), None);
+        assert_eq!(split_first_word(" bob "), Some(("bob", None)));
+        assert_eq!(
+            split_first_word("bob  stop spamming"),
+            Some(("bob", Some("stop spamming")))
+        );
+    }
+
+    #[test]
+    fn test_part_args() {
+        assert_eq!(parse_part_args("", Some("#tiny")), Some(("#tiny", None)));
+        assert_eq!(
+            parse_part_args("bye all", Some("#tiny")),
+            Some(("#tiny", Some("bye all")))
+        );
+        assert_eq!(
+            parse_part_args("#rust bye", Some("#tiny")),
+            Some(("#rust", Some("bye")))
+        );
+        assert_eq!(parse_part_args("#rust", None), Some(("#rust", None)));
+        assert_eq!(parse_part_args("", None), None);
+        assert_eq!(parse_part_args("bye", None), None);
+    }
+
+    #[test]
+    fn test_parse_new_cmds() {
+        for name in &["notice", "part", "query", "quit", "raw"] {
+            match parse_cmd(&format!("{} foo bar", name), &HashMap::new()) {
+                ParseCmdResult::Ok { cmd, rest } => {
+                    assert_eq!(cmd.name, *name);
+                    assert_eq!(rest, "foo bar");
+                }
+                _ => panic!("Can't parse cmd: {}", name),
+            }
+        }
+    }
+
+    #[test]
+    fn test_ban_mask() {
+        assert_eq!(ban_mask("bob", Some("example.org")), "*!*@example.org");
+        assert_eq!(ban_mask("bob", None), "bob!*@*");
+        assert_eq!(
+            ban_mask("*!*@spam.org", Some("example.org")),
+            "*!*@spam.org"
+        );
     }
 
     #[test]
@@ -544,4 +1993,55 @@ mod tests {
         assert_eq!(split_msg_args("foo, bar"), Some(("foo", ", bar")));
         assert_eq!(split_msg_args("foo ,bar"), Some(("foo", ",bar")));
     }
+
+    #[test]
+    fn test_ctcp_args() {
+        assert_eq!(parse_ctcp_args(""), None);
+        assert_eq!(parse_ctcp_args("nick"), None);
+        assert_eq!(
+            parse_ctcp_args("nick version"),
+            Some(("nick", "VERSION".to_owned(), None))
+        );
+        assert_eq!(
+            parse_ctcp_args(" nick  PING 123 456 "),
+            Some(("nick", "PING".to_owned(), Some("123 456")))
+        );
+    }
+
+    #[test]
+    fn test_list_args() {
+        assert_eq!(parse_list_args(""), Some((None, None)));
+        assert_eq!(parse_list_args("rust"), Some((Some("rust"), None)));
+        assert_eq!(parse_list_args(" 10 "), Some((None, Some(10))));
+        assert_eq!(
+            parse_list_args("#rust* 10"),
+            Some((Some("#rust*"), Some(10)))
+        );
+        assert_eq!(parse_list_args("rust ten"), None);
+        assert_eq!(parse_list_args("rust 10 20"), None);
+    }
+
+    #[test]
+    fn test_dcc_args() {
+        assert_eq!(parse_dcc_args(""), None);
+        assert_eq!(parse_dcc_args("list"), Some(DccCmd::List));
+        assert_eq!(parse_dcc_args("accept 3"), Some(DccCmd::Accept(3)));
+        assert_eq!(parse_dcc_args("reject x"), None);
+        assert_eq!(
+            parse_dcc_args("send nick /tmp/my file.txt "),
+            Some(DccCmd::Send {
+                nick: "nick",
+                path: "/tmp/my file.txt",
+                passive: false,
+            })
+        );
+        assert_eq!(parse_dcc_args("send -p nick"), None);
+        assert_eq!(
+            parse_dcc_args("chat -p nick"),
+            Some(DccCmd::Chat {
+                nick: "nick",
+                passive: true,
+            })
+        );
+    }
 }
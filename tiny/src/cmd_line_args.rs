@@ -6,12 +6,16 @@ pub(crate) struct CmdLineArgs {
 
     /// Path to config file
     pub(crate) config_path: Option<PathBuf>,
+
+    /// Recording to replay instead of connecting to servers
+    pub(crate) replay: Option<PathBuf>,
 }
 
 pub(crate) fn parse_cmd_line_args(mut args: Args) -> CmdLineArgs {
     let mut parsed_args = CmdLineArgs {
         servers: Vec::new(),
         config_path: None,
+        replay: None,
     };
 
     args.next(); // skip program name
@@ -21,6 +25,10 @@ pub(crate) fn parse_cmd_line_args(mut args: Args) -> CmdLineArgs {
             if let Some(config_path) = args.next() {
                 parsed_args.config_path = Some(PathBuf::from(config_path));
             }
+        } else if arg == "--replay" {
+            if let Some(replay) = args.next() {
+                parsed_args.replay = Some(PathBuf::from(replay));
+            }
         } else {
             parsed_args.servers.push(arg);
         }
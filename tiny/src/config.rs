@@ -1,10 +1,13 @@
 //! To see how color numbers map to actual colors in your terminal run
 //! `cargo run --example colors`. Use tab to swap fg/bg colors.
+use libtiny_client::{IpPref, SASLAuth as ClientSASLAuth, ServerAddr, ServerInfo};
 use serde::Deserialize;
 use serde_yaml;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::fs::File;
 use std::io::{Read, Write};
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 
 #[derive(Clone, Deserialize, Debug, PartialEq, Eq)]
@@ -15,16 +18,40 @@ pub(crate) struct SASLAuth {
 
 #[derive(Clone, Deserialize)]
 pub(crate) struct Server {
-    /// Address of the server
-    pub(crate) addr: String,
+    /// Network name, used in tab names and log file names. Defaults to the first address of the
+    /// server.
+    #[serde(default)]
+    pub(crate) name: Option<String>,
+
+    /// Address of the server. Either this and `port`, or `addrs` should be given.
+    #[serde(default)]
+    pub(crate) addr: Option<String>,
 
     /// Port of the server
-    pub(crate) port: u16,
+    #[serde(default)]
+    pub(crate) port: Option<u16>,
+
+    /// Addresses of the server, in `host:port` form. Tried in order when connecting, after
+    /// `addr`.
+    #[serde(default)]
+    pub(crate) addrs: Vec<String>,
 
-    /// Use tls
+    /// WebSocket URL of the server (`ws://` or `wss://`), instead of `addr` and `addrs`.
+    #[serde(default)]
+    pub(crate) url: Option<String>,
+
+    /// Use tls. Implied by `wss://` URLs.
     #[serde(default)]
     pub(crate) tls: bool,
 
+    /// Local address to connect from. Falls back to `bind_addr` in defaults.
+    #[serde(default)]
+    pub(crate) bind_addr: Option<IpAddr>,
+
+    /// IP versions to use. Falls back to `ip_version` in defaults.
+    #[serde(default)]
+    pub(crate) ip_version: Option<IpVersion>,
+
     /// Server password (optional)
     #[serde(default)]
     pub(crate) pass: Option<String>,
@@ -40,12 +67,29 @@ pub(crate) struct Server {
     #[serde(default)]
     pub(crate) join: Vec<String>,
 
+    /// Join channels automatically when invited by these nicks.
+    #[serde(default)]
+    pub(crate) join_invites_from: Vec<String>,
+
     /// NickServ identification password. Used on connecting to the server and nick change.
     pub(crate) nickserv_ident: Option<String>,
 
     /// Authenication method
     #[serde(rename = "sasl")]
     pub(crate) sasl_auth: Option<SASLAuth>,
+
+    /// Reply to CTCP requests (VERSION, PING, TIME etc.)
+    #[serde(default = "default_ctcp_replies")]
+    pub(crate) ctcp_replies: bool,
+
+    /// Where to save files received via DCC. Falls back to `dcc_download_dir` in defaults.
+    #[serde(default)]
+    pub(crate) dcc_download_dir: Option<PathBuf>,
+
+    /// Record raw IRC messages to this file, for debugging. Recordings can be replayed with
+    /// `--replay`.
+    #[serde(default)]
+    pub(crate) record_file: Option<PathBuf>,
 }
 
 /// Similar to `Server`, but used when connecting via the `/connect` command.
@@ -56,7 +100,230 @@ pub(crate) struct Defaults {
     #[serde(default)]
     pub(crate) join: Vec<String>,
     #[serde(default)]
+    pub(crate) join_invites_from: Vec<String>,
+    #[serde(default)]
     pub(crate) tls: bool,
+    #[serde(default)]
+    pub(crate) bind_addr: Option<IpAddr>,
+    #[serde(default)]
+    pub(crate) ip_version: Option<IpVersion>,
+    #[serde(default = "default_ctcp_replies")]
+    pub(crate) ctcp_replies: bool,
+    #[serde(default)]
+    pub(crate) dcc_download_dir: Option<PathBuf>,
+}
+
+/// Which IP versions to use when connecting. Both versions are used when not specified, starting
+/// with the version of the first address of the server.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum IpVersion {
+    PreferIpv4,
+    PreferIpv6,
+    Ipv4,
+    Ipv6,
+}
+
+impl IpVersion {
+    pub(crate) fn to_ip_pref(version: Option<IpVersion>) -> IpPref {
+        match version {
+            None => IpPref::Any,
+            Some(IpVersion::PreferIpv4) => IpPref::PreferV4,
+            Some(IpVersion::PreferIpv6) => IpPref::PreferV6,
+            Some(IpVersion::Ipv4) => IpPref::OnlyV4,
+            Some(IpVersion::Ipv6) => IpPref::OnlyV6,
+        }
+    }
+}
+
+fn default_ctcp_replies() -> bool {
+    true
+}
+
+impl Server {
+    /// Server addresses, in order: `addr` and `port` first, then `addrs`. Or the address in
+    /// `url`.
+    pub(crate) fn addrs(&self) -> Result<Vec<ServerAddr>, String> {
+        if let Some(ref url) = self.url {
+            if self.addr.is_some() || self.port.is_some() || !self.addrs.is_empty() {
+                return Err(format!(
+                    "Server {} has both `url` and `addr` or `addrs`, use only one",
+                    url
+                ));
+            }
+            return match parse_ws_url(url) {
+                Some((addr, _)) => Ok(vec![addr]),
+                None => Err(format!(
+                    "Can't parse server URL {:?}, expected ws://<host>[:<port>]/<path> or wss://...",
+                    url
+                )),
+            };
+        }
+
+        let mut addrs = vec![];
+        match (&self.addr, self.port) {
+            (Some(addr), Some(port)) => addrs.push(ServerAddr {
+                host: addr.clone(),
+                port,
+                ws_path: None,
+            }),
+            (Some(addr), None) => return Err(format!("Server {} doesn't have a port", addr)),
+            (None, Some(_)) => return Err("Server with a port but no address".to_owned()),
+            (None, None) => {}
+        }
+        for addr in &self.addrs {
+            match parse_addr(addr) {
+                Some(addr) => addrs.push(addr),
+                None => {
+                    return Err(format!(
+                        "Can't parse server address {:?}, expected <host>:<port>",
+                        addr
+                    ))
+                }
+            }
+        }
+        if addrs.is_empty() {
+            return Err("Server without an address, use `addr` and `port`, or `addrs`".to_owned());
+        }
+        Ok(addrs)
+    }
+
+    /// Use TLS? `tls`, or a `wss://` URL.
+    pub(crate) fn tls(&self) -> bool {
+        match self.url.as_ref().and_then(|url| parse_ws_url(url)) {
+            Some((_, tls)) => tls,
+            None => self.tls,
+        }
+    }
+
+    /// Client settings of the server, using `defaults` for fields not set in the server. Addresses
+    /// should be checked with `validate_servers` first.
+    pub(crate) fn into_server_info(self, defaults: &Defaults) -> ServerInfo {
+        ServerInfo {
+            name: self.name(),
+            addrs: self.addrs().unwrap(),
+            tls: self.tls(),
+            sts_file: get_sts_file_path(),
+            bind_addr: self.bind_addr.or(defaults.bind_addr),
+            ip_pref: IpVersion::to_ip_pref(self.ip_version.or(defaults.ip_version)),
+            pass: self.pass,
+            realname: self.realname,
+            nicks: self.nicks,
+            auto_join: self.join,
+            join_invites_from: self.join_invites_from,
+            nickserv_ident: self.nickserv_ident,
+            sasl_auth: self.sasl_auth.map(|auth| ClientSASLAuth {
+                username: auth.username,
+                password: auth.password,
+            }),
+            ctcp_replies: self.ctcp_replies,
+            dcc_download_dir: self
+                .dcc_download_dir
+                .or_else(|| defaults.dcc_download_dir.clone()),
+            record_file: self.record_file,
+        }
+    }
+
+    /// Network name of the server: `name` when available, otherwise the first address.
+    pub(crate) fn name(&self) -> String {
+        match (&self.name, &self.url, &self.addr, self.addrs.first()) {
+            (Some(name), _, _, _) => name.clone(),
+            (None, Some(url), _, _) => parse_ws_url(url)
+                .map(|(addr, _)| addr.host)
+                .unwrap_or_else(|| url.clone()),
+            (None, None, Some(addr), _) => addr.clone(),
+            (None, None, None, Some(addr)) => parse_addr(addr)
+                .map(|addr| addr.host)
+                .unwrap_or_else(|| addr.clone()),
+            (None, None, None, None) => String::new(),
+        }
+    }
+}
+
+/// Check server addresses and names. Network names should be unique as they're used to identify
+/// connections, tabs, and log directories.
+pub(crate) fn validate_servers(servers: &[Server]) -> Result<(), String> {
+    let mut names: HashSet<String> = HashSet::new();
+    for server in servers {
+        server.addrs()?;
+        let name = server.name();
+        validate_network_name(&name)?;
+        if !names.insert(name.clone()) {
+            return Err(format!(
+                "Multiple servers with network name {}, use `name` fields to give them different names",
+                name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Check a network name, of a server in the config file or of a server connected with
+/// `/connect`. Names are used in log file paths, so can't have path separators or be `..`.
+pub(crate) fn validate_network_name(name: &str) -> Result<(), String> {
+    if name == "mentions" {
+        return Err("Network name \"mentions\" is reserved for the mentions tab".to_owned());
+    }
+    if name.is_empty() || name == "." || name == ".." || name.contains(std::path::is_separator) {
+        return Err(format!(
+            "Invalid network name {:?}, network names are used in log file paths",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Parse a `host:port` string. IPv6 addresses should be in brackets: `[::1]:6667`.
+fn parse_addr(addr: &str) -> Option<ServerAddr> {
+    let split = addr.rfind(':')?;
+    let port = addr[split + 1..].parse::<u16>().ok()?;
+    let host = addr[..split].trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return None;
+    }
+    Some(ServerAddr {
+        host: host.to_owned(),
+        port,
+        ws_path: None,
+    })
+}
+
+/// Parse a `ws://` or `wss://` URL. Returns the address and whether to use TLS. Port defaults to
+/// 80 for `ws://` and 443 for `wss://`.
+fn parse_ws_url(url: &str) -> Option<(ServerAddr, bool)> {
+    let mut split = url.splitn(2, "://");
+    let (tls, rest) = match (split.next(), split.next()) {
+        (Some("wss"), Some(rest)) => (true, rest),
+        (Some("ws"), Some(rest)) => (false, rest),
+        _ => {
+            return None;
+        }
+    };
+    let (authority, path) = match rest.find('/') {
+        None => (rest, "/"),
+        Some(idx) => (&rest[..idx], &rest[idx..]),
+    };
+    let addr = match parse_addr(authority) {
+        Some(addr) => addr,
+        None => {
+            let host = authority.trim_start_matches('[').trim_end_matches(']');
+            if host.is_empty() || host.contains(']') {
+                return None;
+            }
+            ServerAddr {
+                host: host.to_owned(),
+                port: if tls { 443 } else { 80 },
+                ws_path: None,
+            }
+        }
+    };
+    Some((
+        ServerAddr {
+            ws_path: Some(path.to_owned()),
+            ..addr
+        },
+        tls,
+    ))
 }
 
 #[derive(Deserialize)]
@@ -66,6 +333,9 @@ pub(crate) struct Config {
     #[serde(default)]
     pub(crate) colors: libtiny_tui::Colors,
     pub(crate) log_dir: Option<PathBuf>,
+    /// Command aliases. Maps alias names to command templates, see `alias` module.
+    #[serde(default)]
+    pub(crate) aliases: HashMap<String, String>,
 }
 
 /// Returns tiny config file path. File may or may not exist.
@@ -134,6 +404,17 @@ You may want to edit {0:?} before re-running tiny.",
     );
 }
 
+/// Returns the file to persist STS policies in: $XDG_DATA_HOME/tiny/sts (or the platform
+/// equivalent). `None` when the data directory can't be found, in which case policies are not
+/// persisted.
+pub(crate) fn get_sts_file_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|mut data_dir| {
+        data_dir.push("tiny");
+        data_dir.push("sts");
+        data_dir
+    })
+}
+
 fn get_default_config_yaml() -> String {
     let mut log_dir = dirs::home_dir().unwrap();
     log_dir.push("tiny_logs");
@@ -155,12 +436,15 @@ mod tests {
                 println!("{}", yaml_err);
                 panic!();
             }
-            Ok(Config { servers, .. }) => {
+            Ok(Config {
+                servers, aliases, ..
+            }) => {
+                assert_eq!(aliases.get("j").map(String::as_str), Some("/join $1-"));
                 assert_eq!(
                     servers[0].join,
                     vec!["#tiny".to_owned(), "#rust".to_owned()]
                 );
-                assert_eq!(servers[0].tls, true);
+                assert!(servers[0].tls);
                 assert_eq!(servers[0].pass, Some("hunter2".to_owned()));
                 assert_eq!(
                     servers[0].sasl_auth,
@@ -170,7 +454,101 @@ mod tests {
                     })
                 );
                 assert_eq!(servers[0].nickserv_ident, Some("hunter2".to_owned()));
+                assert!(servers[0].ctcp_replies);
+                assert_eq!(servers[0].name(), "mozilla");
+                assert_eq!(
+                    servers[0].addrs(),
+                    Ok(vec![
+                        ServerAddr {
+                            host: "irc.mozilla.org".to_owned(),
+                            port: 6697,
+                            ws_path: None,
+                        },
+                        ServerAddr {
+                            host: "irc2.mozilla.org".to_owned(),
+                            port: 6697,
+                            ws_path: None,
+                        },
+                    ])
+                );
             }
         }
     }
+
+    #[test]
+    fn test_validate_servers() {
+        let config = parse_config_str(&get_default_config_yaml()).unwrap();
+        let mut servers = config.servers;
+        assert_eq!(validate_servers(&servers), Ok(()));
+
+        // Same address, no names
+        let mut server = servers[0].clone();
+        server.name = None;
+        servers[0].name = None;
+        servers.push(server);
+        assert!(validate_servers(&servers).is_err());
+
+        servers[1].name = Some("mozilla2".to_owned());
+        assert_eq!(validate_servers(&servers), Ok(()));
+
+        servers[1].name = Some("../mozilla".to_owned());
+        assert!(validate_servers(&servers).is_err());
+        servers[1].name = Some("..".to_owned());
+        assert!(validate_servers(&servers).is_err());
+        servers[1].name = Some("mentions".to_owned());
+        assert!(validate_servers(&servers).is_err());
+
+        servers[1].name = Some("mozilla2".to_owned());
+        servers[1].port = None;
+        assert!(validate_servers(&servers).is_err());
+    }
+
+    #[test]
+    fn test_parse_addr() {
+        assert_eq!(
+            parse_addr("chat.freenode.net:6697"),
+            Some(ServerAddr {
+                host: "chat.freenode.net".to_owned(),
+                port: 6697,
+                ws_path: None,
+            })
+        );
+        assert_eq!(
+            parse_addr("[::1]:6667"),
+            Some(ServerAddr {
+                host: "::1".to_owned(),
+                port: 6667,
+                ws_path: None,
+            })
+        );
+        assert_eq!(parse_addr("chat.freenode.net"), None);
+        assert_eq!(parse_addr(":6667"), None);
+    }
+
+    #[test]
+    fn test_parse_ws_url() {
+        assert_eq!(
+            parse_ws_url("wss://irc.example.org/webirc"),
+            Some((
+                ServerAddr {
+                    host: "irc.example.org".to_owned(),
+                    port: 443,
+                    ws_path: Some("/webirc".to_owned()),
+                },
+                true
+            ))
+        );
+        assert_eq!(
+            parse_ws_url("ws://[::1]:8080"),
+            Some((
+                ServerAddr {
+                    host: "::1".to_owned(),
+                    port: 8080,
+                    ws_path: Some("/".to_owned()),
+                },
+                false
+            ))
+        );
+        assert_eq!(parse_ws_url("irc.example.org:6667"), None);
+    }
 }
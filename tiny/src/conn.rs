@@ -3,8 +3,8 @@
 
 //! IRC event handling
 
-use futures_util::stream::StreamExt;
-use libtiny_client::Client;
+use futures::stream::StreamExt;
+use libtiny_client::{Client, DccKind};
 use libtiny_ui::{MsgTarget, TabStyle, UI};
 use libtiny_wire as wire;
 use std::error::Error;
@@ -15,20 +15,38 @@ pub(crate) async fn task(
     ui: Box<dyn UI>,
     client: Client,
 ) {
+    // Set after a fatal registration error, until the client starts connecting again
+    let mut stopped = false;
     while let Some(ev) = rcv_ev.next().await {
-        if handle_conn_ev(&*ui, &client, ev) {
+        if handle_conn_ev(&*ui, &client, &mut stopped, ev) {
             return;
         }
         ui.draw();
     }
 }
 
-fn handle_conn_ev(ui: &dyn UI, client: &Client, ev: libtiny_client::Event) -> bool {
+fn add_raw_msg(ui: &dyn UI, client: &Client, msg: &str, incoming: bool) {
+    let serv = client.get_serv_name();
+    if ui.raw_tab_exists(serv) {
+        ui.add_raw_msg(serv, msg, time::now(), incoming);
+    } else {
+        // Raw tab closed with /close, stop raw events
+        client.set_raw_events(false);
+    }
+}
+
+fn handle_conn_ev(
+    ui: &dyn UI,
+    client: &Client,
+    stopped: &mut bool,
+    ev: libtiny_client::Event,
+) -> bool {
     use libtiny_client::Event::*;
     match ev {
-        ResolvingHost => {
+        ResolvingHost(host) => {
+            *stopped = false;
             ui.add_client_msg(
-                "Resolving host...",
+                &format!("Resolving {}...", host),
                 &MsgTarget::AllServTabs {
                     serv: client.get_serv_name(),
                 },
@@ -42,6 +60,56 @@ fn handle_conn_ev(ui: &dyn UI, client: &Client, ev: libtiny_client::Event) -> bo
                 },
             );
         }
+        StsUpgrade(port) => {
+            ui.add_client_msg(
+                &format!(
+                    "Server requires TLS (STS policy), reconnecting to port {}",
+                    port
+                ),
+                &MsgTarget::AllServTabs {
+                    serv: client.get_serv_name(),
+                },
+            );
+        }
+        RegistrationTimeout => {
+            ui.add_err_msg(
+                &format!(
+                    "Server didn't complete registration in {} seconds.",
+                    libtiny_client::REGISTRATION_TIMEOUT_SECS
+                ),
+                time::now(),
+                &MsgTarget::AllServTabs {
+                    serv: client.get_serv_name(),
+                },
+            );
+        }
+        RegistrationError(err) => {
+            use libtiny_client::RegistrationError::*;
+            let serv = client.get_serv_name();
+            let reason = match err {
+                PasswdMismatch(_) => "wrong server password".to_owned(),
+                Banned(_) => "banned from the server".to_owned(),
+                ErroneousNickname { nick, .. } => format!("server rejected nick {}", nick),
+                SaslFail(_) => {
+                    ui.add_err_msg(
+                        "SASL authentication failed, continuing without authentication.",
+                        time::now(),
+                        &MsgTarget::Server { serv },
+                    );
+                    return false;
+                }
+            };
+            ui.add_err_msg(
+                &format!(
+                    "Registration failed: {}. Not reconnecting, use /connect to reconnect.",
+                    reason
+                ),
+                time::now(),
+                &MsgTarget::AllServTabs { serv },
+            );
+            ui.clear_nicks(&MsgTarget::AllServTabs { serv });
+            *stopped = true;
+        }
         Connected => {
             ui.add_msg(
                 "Connected.",
@@ -53,6 +121,11 @@ fn handle_conn_ev(ui: &dyn UI, client: &Client, ev: libtiny_client::Event) -> bo
         }
         Disconnected => {
             let serv = client.get_serv_name();
+            ui.clear_nicks(&MsgTarget::AllServTabs { serv });
+            if *stopped {
+                // Client won't reconnect, the reason is already shown
+                return false;
+            }
             ui.add_err_msg(
                 &format!(
                     "Disconnected. Will try to reconnect in {} seconds.",
@@ -61,7 +134,6 @@ fn handle_conn_ev(ui: &dyn UI, client: &Client, ev: libtiny_client::Event) -> bo
                 time::now(),
                 &MsgTarget::AllServTabs { serv },
             );
-            ui.clear_nicks(serv);
         }
         IoErr(err) => {
             ui.add_err_msg(
@@ -105,6 +177,26 @@ fn handle_conn_ev(ui: &dyn UI, client: &Client, ev: libtiny_client::Event) -> bo
         Msg(msg) => {
             handle_irc_msg(ui, client, msg);
         }
+        RawIn(msg) => {
+            add_raw_msg(ui, client, &msg, true);
+        }
+        RawOut(msg) => {
+            add_raw_msg(ui, client, &msg, false);
+        }
+        Whois(info) => {
+            show_whois(ui, client.get_serv_name(), &info);
+        }
+        ChanList(chans) => {
+            ui.show_chan_list(client.get_serv_name(), &chans);
+        }
+        DccOffer(_)
+        | DccConnected(_)
+        | DccProgress { .. }
+        | DccChatMsg { .. }
+        | DccDone { .. }
+        | DccFailed { .. } => {
+            handle_dcc_ev(ui, client, ev);
+        }
         Closed => {
             return true;
         }
@@ -121,7 +213,7 @@ fn handle_irc_msg(ui: &dyn UI, client: &Client, msg: wire::Msg) {
     let serv = client.get_serv_name();
     match cmd {
         PRIVMSG {
-            target,
+            targets,
             msg,
             is_notice,
             ctcp,
@@ -140,67 +232,84 @@ fn handle_irc_msg(ui: &dyn UI, client: &Client, msg: wire::Msg) {
                 User { ref nick, .. } => nick,
             };
 
-            if ctcp == Some(wire::CTCP::Version) {
-                let msg_target = if ui.user_tab_exists(serv, origin) {
-                    MsgTarget::User { serv, nick: origin }
-                } else {
-                    MsgTarget::Server { serv }
-                };
-                ui.add_client_msg(
-                    &format!("Received version request from {}", origin),
-                    &msg_target,
-                );
-                return;
+            // Ignore messages from hosts ignored with `/ignorehost`
+            if let User { ref user, .. } = pfx {
+                if let Some(host) = user.split('@').nth(1) {
+                    if client.is_host_ignored(host) {
+                        return;
+                    }
+                }
+            }
+
+            match ctcp {
+                None | Some(wire::CTCP::Action) => {}
+                // DCC messages are handled by libtiny_client, see `handle_dcc_ev`
+                Some(wire::CTCP::Dcc) => {
+                    return;
+                }
+                Some(ref ctcp) => {
+                    return handle_ctcp(ui, serv, origin, ctcp, &msg, is_notice);
+                }
             }
 
             let is_action = ctcp == Some(wire::CTCP::Action);
 
-            match target {
-                wire::MsgTarget::Chan(chan) => {
-                    let ui_msg_target = MsgTarget::Chan { serv, chan: &chan };
-                    // highlight the message if it mentions us
-                    if msg.find(&client.get_nick()).is_some() {
-                        ui.add_privmsg(origin, &msg, ts, &ui_msg_target, true, is_action);
-                        ui.set_tab_style(TabStyle::Highlight, &ui_msg_target);
-                        let mentions_target = MsgTarget::Server { serv: "mentions" };
-                        ui.add_msg(
-                            &format!("{} in {}:{}: {}", origin, serv, chan, msg),
-                            ts,
-                            &mentions_target,
-                        );
-                        ui.set_tab_style(TabStyle::Highlight, &mentions_target);
-                    } else {
-                        ui.add_privmsg(origin, &msg, ts, &ui_msg_target, false, is_action);
-                        ui.set_tab_style(TabStyle::NewMsg, &ui_msg_target);
+            // Messages sent to multiple users (e.g. us and someone else) are shown in the
+            // sender's tab only once
+            let mut shown_in_user_tab = false;
+
+            for target in targets {
+                match target {
+                    wire::MsgTarget::Chan(chan) => {
+                        let ui_msg_target = MsgTarget::Chan { serv, chan: &chan };
+                        // highlight the message if it mentions us
+                        if msg.find(&client.get_nick()).is_some() {
+                            ui.add_privmsg(origin, &msg, ts, &ui_msg_target, true, is_action);
+                            ui.set_tab_style(TabStyle::Highlight, &ui_msg_target);
+                            let mentions_target = MsgTarget::Server { serv: "mentions" };
+                            ui.add_msg(
+                                &format!("{} in {}:{}: {}", origin, serv, chan, msg),
+                                ts,
+                                &mentions_target,
+                            );
+                            ui.set_tab_style(TabStyle::Highlight, &mentions_target);
+                        } else {
+                            ui.add_privmsg(origin, &msg, ts, &ui_msg_target, false, is_action);
+                            ui.set_tab_style(TabStyle::NewMsg, &ui_msg_target);
+                        }
                     }
-                }
-                wire::MsgTarget::User(target) => {
-                    let msg_target = {
-                        match pfx {
-                            Server(_) => MsgTarget::Server { serv },
-                            User { ref nick, .. } => {
-                                // show NOTICE messages in server tabs if we don't have a tab
-                                // for the sender already (see #21)
-                                if is_notice && !ui.user_tab_exists(serv, nick) {
-                                    MsgTarget::Server { serv }
-                                } else {
-                                    MsgTarget::User { serv, nick }
+                    wire::MsgTarget::User(target) => {
+                        if shown_in_user_tab {
+                            continue;
+                        }
+                        shown_in_user_tab = true;
+                        let msg_target = {
+                            match pfx {
+                                Server(_) => MsgTarget::Server { serv },
+                                User { ref nick, .. } => {
+                                    // show NOTICE messages in server tabs if we don't have a tab
+                                    // for the sender already (see #21)
+                                    if is_notice && !ui.user_tab_exists(serv, nick) {
+                                        MsgTarget::Server { serv }
+                                    } else {
+                                        MsgTarget::User { serv, nick }
+                                    }
                                 }
                             }
+                        };
+                        ui.add_privmsg(origin, &msg, ts, &msg_target, false, is_action);
+                        if target == client.get_nick() {
+                            ui.set_tab_style(TabStyle::Highlight, &msg_target);
+                        } else {
+                            // not sure if this case can happen
+                            ui.set_tab_style(TabStyle::NewMsg, &msg_target);
                         }
-                    };
-                    ui.add_privmsg(origin, &msg, ts, &msg_target, false, is_action);
-                    if target == client.get_nick() {
-                        ui.set_tab_style(TabStyle::Highlight, &msg_target);
-                    } else {
-                        // not sure if this case can happen
-                        ui.set_tab_style(TabStyle::NewMsg, &msg_target);
                     }
                 }
             }
         }
 
-        JOIN { chan } => {
+        JOIN { chans, .. } => {
             let nick = match pfx {
                 Some(User { nick, .. }) => nick,
                 _ => {
@@ -210,11 +319,15 @@ fn handle_irc_msg(ui: &dyn UI, client: &Client, msg: wire::Msg) {
             };
 
             if nick == client.get_nick() {
-                ui.new_chan_tab(serv, &chan);
+                for chan in &chans {
+                    ui.new_chan_tab(serv, chan);
+                }
             } else {
                 let nick = wire::drop_nick_prefix(&nick);
                 let ts = Some(time::now());
-                ui.add_nick(nick, ts, &MsgTarget::Chan { serv, chan: &chan });
+                for chan in &chans {
+                    ui.add_nick(nick, ts, &MsgTarget::Chan { serv, chan });
+                }
                 // Also update the private message tab if it exists
                 // Nothing will be shown if the user already known to be online by the tab
                 if ui.user_tab_exists(serv, nick) {
@@ -223,7 +336,7 @@ fn handle_irc_msg(ui: &dyn UI, client: &Client, msg: wire::Msg) {
             }
         }
 
-        PART { chan, .. } => {
+        PART { chans, .. } => {
             let nick = match pfx {
                 Some(User { nick, .. }) => nick,
                 _ => {
@@ -232,11 +345,9 @@ fn handle_irc_msg(ui: &dyn UI, client: &Client, msg: wire::Msg) {
                 }
             };
             if nick != client.get_nick() {
-                ui.remove_nick(
-                    &nick,
-                    Some(time::now()),
-                    &MsgTarget::Chan { serv, chan: &chan },
-                );
+                for chan in &chans {
+                    ui.remove_nick(&nick, Some(time::now()), &MsgTarget::Chan { serv, chan });
+                }
             }
         }
 
@@ -287,18 +398,6 @@ fn handle_irc_msg(ui: &dyn UI, client: &Client, msg: wire::Msg) {
             }
         }
 
-        Reply { num: 433, .. } => {
-            // ERR_NICKNAMEINUSE
-            if client.is_nick_accepted() {
-                // Nick change request from user failed. Just show an error message.
-                ui.add_err_msg(
-                    "Nickname is already in use",
-                    time::now(),
-                    &MsgTarget::AllServTabs { serv },
-                );
-            }
-        }
-
         PING { .. } | PONG { .. } => {
             // Ignore
         }
@@ -309,12 +408,98 @@ fn handle_irc_msg(ui: &dyn UI, client: &Client, msg: wire::Msg) {
 
         TOPIC { chan, topic } => {
             ui.set_topic(&topic, time::now(), serv, &chan);
+            if let Some(User { nick, .. }) = pfx {
+                let msg = if topic.is_empty() {
+                    format!("{} cleared the topic", nick)
+                } else {
+                    format!("{} changed the topic", nick)
+                };
+                ui.add_client_msg(&msg, &MsgTarget::Chan { serv, chan: &chan });
+            }
+        }
+
+        KICK { chan, nick, msg } => {
+            let kicker = match pfx {
+                Some(User { nick, .. }) | Some(Server(nick)) => nick,
+                None => {
+                    return;
+                }
+            };
+            let reason = match msg {
+                Some(ref msg) if !msg.is_empty() && *msg != nick => format!(" ({})", msg),
+                _ => String::new(),
+            };
+            let chan_target = MsgTarget::Chan { serv, chan: &chan };
+            if nick == client.get_nick() {
+                // The tab stays open, clear the nicks as we're not in the channel anymore
+                ui.clear_nicks(&chan_target);
+                ui.add_err_msg(
+                    &format!("{} kicked you from {}{}", kicker, chan, reason),
+                    time::now(),
+                    &chan_target,
+                );
+                ui.set_tab_style(TabStyle::Highlight, &chan_target);
+            } else {
+                ui.remove_nick(&nick, Some(time::now()), &chan_target);
+                ui.add_client_msg(
+                    &format!("{} kicked {}{}", kicker, nick, reason),
+                    &chan_target,
+                );
+            }
+        }
+
+        MODE { target, params } => {
+            let setter = match pfx {
+                Some(User { nick, .. }) | Some(Server(nick)) => nick,
+                None => {
+                    return;
+                }
+            };
+            let msg = format!("{} sets mode {}", setter, params.join(" "));
+            if target == client.get_nick() {
+                ui.add_client_msg(&msg, &MsgTarget::Server { serv });
+            } else {
+                ui.add_client_msg(
+                    &msg,
+                    &MsgTarget::Chan {
+                        serv,
+                        chan: &target,
+                    },
+                );
+            }
+        }
+
+        INVITE { nick, chan } => {
+            let (from, from_user) = match pfx {
+                Some(User { nick, user }) => (nick, user),
+                Some(Server(nick)) => (nick, String::new()),
+                None => {
+                    return;
+                }
+            };
+            if nick == client.get_nick() {
+                // Client joins the channel when `from` is in `join_invites_from`
+                let msg = if client.joins_invites_from(&from, &from_user) {
+                    format!("{} invites you to {}, joining", from, chan)
+                } else {
+                    format!("{} invites you to {}, /join to accept", from, chan)
+                };
+                ui.add_msg(&msg, ts, &MsgTarget::CurrentTab);
+            } else {
+                // `invite-notify`: someone in the channel invited another user
+                ui.add_msg(
+                    &format!("{} invited {} to the channel", from, nick),
+                    ts,
+                    &MsgTarget::Chan { serv, chan: &chan },
+                );
+            }
         }
 
         CAP {
             client: _,
             subcommand,
             params,
+            more: _,
         } => {
             match subcommand.as_ref() {
                 "NAK" => {
@@ -327,17 +512,10 @@ fn handle_irc_msg(ui: &dyn UI, client: &Client, msg: wire::Msg) {
                         );
                     }
                 }
-                "LS" => {
-                    if !params.iter().any(|cap| cap.as_str() == "sasl") {
-                        let msg_target = MsgTarget::Server { serv };
-                        ui.add_err_msg(
-                            "Server does not support SASL authenication",
-                            time::now(),
-                            &msg_target,
-                        );
-                    }
-                }
-                "ACK" => {}
+                // SASL is requested even when the server doesn't advertise it in LS, the NAK above
+                // is reported in that case. LS is also sent without SASL to learn about STS and
+                // `invite-notify`.
+                "LS" | "ACK" => {}
                 _cmd => {
                     // self.logger
                     //     .get_debug_logs()
@@ -350,93 +528,7 @@ fn handle_irc_msg(ui: &dyn UI, client: &Client, msg: wire::Msg) {
             // Ignore
         }
 
-        Reply { num: n, params } => {
-            if n <= 003 /* RPL_WELCOME, RPL_YOURHOST, RPL_CREATED */
-                    || n == 251 /* RPL_LUSERCLIENT */
-                    || n == 255 /* RPL_LUSERME */
-                    || n == 372 /* RPL_MOTD */
-                    || n == 375 /* RPL_MOTDSTART */
-                    || n == 376
-            /* RPL_ENDOFMOTD */
-            {
-                debug_assert_eq!(params.len(), 2);
-                let msg = &params[1];
-                ui.add_msg(msg, time::now(), &MsgTarget::Server { serv });
-            } else if n == 4 // RPL_MYINFO
-                    || n == 5 // RPL_BOUNCE
-                    || (n >= 252 && n <= 254)
-            /* RPL_LUSEROP, RPL_LUSERUNKNOWN, */
-            /* RPL_LUSERCHANNELS */
-            {
-                let msg = params.into_iter().collect::<Vec<String>>().join(" ");
-                ui.add_msg(&msg, time::now(), &MsgTarget::Server { serv });
-            } else if n == 265 || n == 266 || n == 250 {
-                let msg = &params[params.len() - 1];
-                ui.add_msg(msg, time::now(), &MsgTarget::Server { serv });
-            }
-            // RPL_TOPIC
-            else if n == 332 {
-                // FIXME: RFC 2812 says this will have 2 arguments, but freenode
-                // sends 3 arguments (extra one being our nick).
-                assert!(params.len() == 3 || params.len() == 2);
-                let chan = &params[params.len() - 2];
-                let topic = &params[params.len() - 1];
-                ui.set_topic(topic, time::now(), serv, chan);
-            }
-            // RPL_NAMREPLY: List of users in a channel
-            else if n == 353 {
-                let chan = &params[2];
-                let chan_target = MsgTarget::Chan { serv, chan };
-
-                for nick in params[3].split_whitespace() {
-                    ui.add_nick(wire::drop_nick_prefix(nick), None, &chan_target);
-                }
-            }
-            // RPL_ENDOFNAMES: End of NAMES list
-            else if n == 366 {
-            }
-            // RPL_UNAWAY or RPL_NOWAWAY
-            else if n == 305 || n == 306 {
-                let msg = &params[1];
-                ui.add_client_msg(msg, &MsgTarget::AllServTabs { serv });
-            }
-            // ERR_NOSUCHNICK
-            else if n == 401 {
-                let nick = &params[1];
-                let msg = &params[2];
-                ui.add_client_msg(msg, &MsgTarget::User { serv, nick });
-            // RPL_AWAY
-            } else if n == 301 {
-                let nick = &params[1];
-                let msg = &params[2];
-                ui.add_client_msg(
-                    &format!("{} is away: {}", nick, msg),
-                    &MsgTarget::User { serv, nick },
-                );
-            } else {
-                match pfx {
-                    Some(Server(msg_serv)) => {
-                        let msg_target = MsgTarget::Server { serv };
-                        ui.add_privmsg(
-                            &msg_serv,
-                            &params.join(" "),
-                            time::now(),
-                            &msg_target,
-                            false,
-                            false,
-                        );
-                        ui.set_tab_style(TabStyle::NewMsg, &msg_target);
-                    }
-                    _pfx => {
-                        // add everything else to debug file
-                        // self.logger.get_debug_logs().write_line(format_args!(
-                        //     "Ignoring numeric reply msg:\nPfx: {:?}, num: {:?}, args: {:?}",
-                        //     pfx, n, params
-                        // ));
-                    }
-                }
-            }
-        }
+        Reply(reply) => handle_reply(ui, client, pfx, reply),
 
         Other { cmd: _, params } => match pfx {
             Some(Server(msg_serv)) => {
@@ -462,3 +554,467 @@ fn handle_irc_msg(ui: &dyn UI, client: &Client, msg: wire::Msg) {
         },
     }
 }
+
+/// Show DCC events in the server tab. DCC chats are shown in "=nick" user tabs.
+fn handle_dcc_ev(ui: &dyn UI, client: &Client, ev: libtiny_client::Event) {
+    use libtiny_client::Event::*;
+
+    let serv = client.get_serv_name();
+    let serv_target = MsgTarget::Server { serv };
+    let find_info = |id| client.dcc_list().into_iter().find(|info| info.id == id);
+
+    match ev {
+        DccOffer(info) => {
+            ui.add_client_msg(
+                &format!(
+                    "{} offers DCC {} (id {}). Use `/dcc accept {}` or `/dcc reject {}`.",
+                    info.nick,
+                    dcc_desc(&info.kind),
+                    info.id,
+                    info.id,
+                    info.id
+                ),
+                &serv_target,
+            );
+            ui.set_tab_style(TabStyle::Highlight, &serv_target);
+        }
+        DccConnected(id) => {
+            if let Some(info) = find_info(id) {
+                match info.kind {
+                    DccKind::Chat => {
+                        let nick = format!("={}", info.nick);
+                        ui.add_client_msg(
+                            &format!("DCC chat with {} started", info.nick),
+                            &MsgTarget::User { serv, nick: &nick },
+                        );
+                    }
+                    DccKind::Send { .. } => {
+                        ui.add_client_msg(
+                            &format!("DCC {}: connected to {}", id, info.nick),
+                            &serv_target,
+                        );
+                    }
+                }
+            }
+        }
+        DccProgress { id, bytes, total } => {
+            let msg = match total {
+                Some(total) if total > 0 => format!(
+                    "DCC {}: {}/{} bytes ({}%)",
+                    id,
+                    bytes,
+                    total,
+                    bytes * 100 / total
+                ),
+                _ => format!("DCC {}: {} bytes", id, bytes),
+            };
+            ui.add_client_msg(&msg, &serv_target);
+        }
+        DccChatMsg { nick, msg, .. } => {
+            let tab_nick = format!("={}", nick);
+            let msg_target = MsgTarget::User {
+                serv,
+                nick: &tab_nick,
+            };
+            let mut words = msg.splitn(2, ' ');
+            let (msg, is_action) = match (words.next(), words.next()) {
+                (Some("\x01ACTION"), Some(action)) => (action.trim_end_matches('\x01'), true),
+                _ => (&msg[..], false),
+            };
+            ui.add_privmsg(&nick, msg, time::now(), &msg_target, false, is_action);
+            ui.set_tab_style(TabStyle::Highlight, &msg_target);
+        }
+        DccDone { id, path } => {
+            let info = find_info(id);
+            match (info, path) {
+                (_, Some(path)) => {
+                    ui.add_client_msg(
+                        &format!("DCC {}: file saved to {}", id, path.to_string_lossy()),
+                        &serv_target,
+                    );
+                }
+                (
+                    Some(libtiny_client::DccInfo {
+                        kind: DccKind::Chat,
+                        nick,
+                        ..
+                    }),
+                    None,
+                ) => {
+                    let tab_nick = format!("={}", nick);
+                    ui.add_client_msg(
+                        "DCC chat ended",
+                        &MsgTarget::User {
+                            serv,
+                            nick: &tab_nick,
+                        },
+                    );
+                }
+                _ => {
+                    ui.add_client_msg(&format!("DCC {}: done", id), &serv_target);
+                }
+            }
+        }
+        DccFailed { id, reason } => {
+            ui.add_client_err_msg(&format!("DCC {} failed: {}", id, reason), &serv_target);
+        }
+        _ => {}
+    }
+}
+
+/// Describe a DCC offer, e.g. "SEND foo.txt (1024 bytes)".
+pub(crate) fn dcc_desc(kind: &DccKind) -> String {
+    match kind {
+        DccKind::Chat => "CHAT".to_owned(),
+        DccKind::Send {
+            file_name,
+            size: Some(size),
+        } => format!("SEND {} ({} bytes)", file_name, size),
+        DccKind::Send {
+            file_name,
+            size: None,
+        } => format!("SEND {}", file_name),
+    }
+}
+
+/// Show a CTCP request or reply (sent as NOTICE) in the user tab of the sender, or in the server
+/// tab if we don't have a tab for the sender. Replies to requests are sent by libtiny_client.
+fn handle_ctcp(
+    ui: &dyn UI,
+    serv: &str,
+    origin: &str,
+    ctcp: &wire::CTCP,
+    msg: &str,
+    is_notice: bool,
+) {
+    let msg_target = if ui.user_tab_exists(serv, origin) {
+        MsgTarget::User { serv, nick: origin }
+    } else {
+        MsgTarget::Server { serv }
+    };
+
+    if !is_notice {
+        ui.add_client_msg(
+            &format!("Received CTCP {} request from {}", ctcp.name(), origin),
+            &msg_target,
+        );
+        return;
+    }
+
+    let reply = match (ctcp, msg.parse::<u64>()) {
+        // `/ctcp nick ping` sends current time in milliseconds, show round-trip time
+        (wire::CTCP::Ping, Ok(sent)) => {
+            format!("{} ms", crate::utils::now_millis().saturating_sub(sent))
+        }
+        _ => msg.to_owned(),
+    };
+    ui.add_client_msg(
+        &format!("CTCP {} reply from {}: {}", ctcp.name(), origin, reply),
+        &msg_target,
+    );
+}
+
+fn handle_reply(ui: &dyn UI, client: &Client, pfx: Option<wire::Pfx>, reply: wire::Reply) {
+    use wire::Reply::*;
+
+    let serv = client.get_serv_name();
+    match reply {
+        Welcome { msg, .. }
+        | YourHost { msg }
+        | Created { msg }
+        | MotdStart { msg }
+        | Motd { msg }
+        | EndOfMotd { msg }
+        | NoMotd { msg } => {
+            ui.add_msg(&msg, time::now(), &MsgTarget::Server { serv });
+        }
+
+        MyInfo { params } => {
+            ui.add_msg(&params.join(" "), time::now(), &MsgTarget::Server { serv });
+        }
+
+        ISupport { tokens } => {
+            ui.add_msg(&tokens.join(" "), time::now(), &MsgTarget::Server { serv });
+        }
+
+        Topic { chan, topic } => {
+            ui.set_topic(&topic, time::now(), serv, &chan);
+        }
+
+        TopicWhoTime { chan, nick, set_at } => {
+            // Some servers send the full `nick!user@host` of the setter
+            let nick = nick.split('!').next().unwrap_or(&nick);
+            let set_at = time::at(time::Timespec::new(set_at as i64, 0));
+            ui.add_client_msg(
+                &format!("Topic set by {} on {}", nick, set_at.ctime()),
+                &MsgTarget::Chan { serv, chan: &chan },
+            );
+        }
+
+        NoTopic { chan } => {
+            ui.set_topic("", time::now(), serv, &chan);
+            ui.add_client_msg("No topic is set", &MsgTarget::Chan { serv, chan: &chan });
+        }
+
+        NamReply { chan, nicks } => {
+            let chan_target = MsgTarget::Chan { serv, chan: &chan };
+            for nick in &nicks {
+                ui.add_nick(wire::drop_nick_prefix(nick), None, &chan_target);
+            }
+        }
+
+        EndOfNames { .. } => {}
+
+        UnAway { msg } | NowAway { msg } => {
+            ui.add_client_msg(&msg, &MsgTarget::AllServTabs { serv });
+        }
+
+        // These are replies to messages and commands like /msg, /invite and /kick, and also sent
+        // as a part of WHOIS replies. Shown in the user tab when we have one, to avoid creating
+        // tabs on WHOIS, otherwise in the current tab.
+        NoSuchNick { nick, msg } => {
+            let msg = format!("{}: {}", nick, msg);
+            if ui.user_tab_exists(serv, &nick) {
+                ui.add_client_msg(&msg, &MsgTarget::User { serv, nick: &nick });
+            } else {
+                ui.add_client_msg(&msg, &MsgTarget::CurrentTab);
+            }
+        }
+
+        Away { nick, msg } => {
+            let msg = format!("{} is away: {}", nick, msg);
+            if ui.user_tab_exists(serv, &nick) {
+                ui.add_client_msg(&msg, &MsgTarget::User { serv, nick: &nick });
+            } else {
+                ui.add_client_msg(&msg, &MsgTarget::CurrentTab);
+            }
+        }
+
+        NicknameInUse { .. } => {
+            if client.is_nick_accepted() {
+                // Nick change request from user failed. Just show an error message.
+                ui.add_err_msg(
+                    "Nickname is already in use",
+                    time::now(),
+                    &MsgTarget::AllServTabs { serv },
+                );
+            }
+        }
+
+        Inviting { nick, chan } => {
+            ui.add_client_msg(
+                &format!("Invited {} to {}", nick, chan),
+                &MsgTarget::CurrentTab,
+            );
+        }
+
+        UserOnChannel { nick, chan, msg } => {
+            ui.add_client_err_msg(
+                &format!("{}: {} {}", chan, nick, msg),
+                &MsgTarget::CurrentTab,
+            );
+        }
+
+        NoSuchChannel { chan, msg }
+        | CannotSendToChan { chan, msg }
+        | NotOnChannel { chan, msg }
+        | CannotJoin { chan, msg, .. } => {
+            ui.add_err_msg(
+                &format!("{}: {}", chan, msg),
+                time::now(),
+                &MsgTarget::Server { serv },
+            );
+        }
+
+        // Sent in response to operator commands like /kick and /op, so show it where the command
+        // was run
+        ChanOpPrivsNeeded { chan, msg } => {
+            ui.add_err_msg(
+                &format!("{}: {}", chan, msg),
+                time::now(),
+                &MsgTarget::CurrentTab,
+            );
+        }
+
+        ErroneousNickname { nick, msg } => {
+            ui.add_err_msg(
+                &format!("{}: {}", nick, msg),
+                time::now(),
+                &MsgTarget::AllServTabs { serv },
+            );
+        }
+
+        PasswdMismatch { msg } | YoureBannedCreep { msg } => {
+            ui.add_err_msg(&msg, time::now(), &MsgTarget::AllServTabs { serv });
+        }
+
+        Unknown { num, params } => {
+            if num == 251 /* RPL_LUSERCLIENT */
+                || num == 255 /* RPL_LUSERME */
+                || num == 250 /* RPL_STATSCONN */
+                || num == 265 /* RPL_LOCALUSERS */
+                || num == 266
+            /* RPL_GLOBALUSERS */
+            {
+                if let Some(msg) = params.last() {
+                    ui.add_msg(msg, time::now(), &MsgTarget::Server { serv });
+                }
+            } else if (252..=254).contains(&num) {
+                // RPL_LUSEROP, RPL_LUSERUNKNOWN, RPL_LUSERCHANNELS
+                ui.add_msg(&params.join(" "), time::now(), &MsgTarget::Server { serv });
+            } else {
+                show_server_msg(ui, serv, pfx, &params.join(" "));
+            }
+        }
+
+        // Collected by libtiny_client and shown on `Event::Whois`
+        WhoisUser { .. }
+        | WhowasUser { .. }
+        | WhoisServer { .. }
+        | WhoisOperator { .. }
+        | WhoisAccount { .. }
+        | WhoisIdle { .. }
+        | WhoisChannels { .. }
+        | WhoisHost { .. }
+        | WhoisSecure { .. }
+        | EndOfWhois { .. }
+        | EndOfWhowas { .. }
+        | WasNoSuchNick { .. } => {}
+
+        // Replies that we don't have special handling for, show them in the server tab
+        HostHidden { host, msg } => {
+            show_server_msg(ui, serv, pfx, &format!("{} {}", host, msg));
+        }
+        // Replies to WHO requests sent by libtiny_client to populate user information (see
+        // `Client::get_user_info`) are not sent as events, these are replies to the user's WHOs
+        WhoReply {
+            chan,
+            user,
+            host,
+            nick,
+            flags,
+            realname,
+            ..
+        } => {
+            show_server_msg(
+                ui,
+                serv,
+                pfx,
+                &format!(
+                    "{} {} {} {}@{} ({})",
+                    chan, nick, flags, user, host, realname
+                ),
+            );
+        }
+        WhoSpcReply { fields } => {
+            show_server_msg(ui, serv, pfx, &fields.join(" "));
+        }
+        EndOfWho { mask } => {
+            show_server_msg(ui, serv, pfx, &format!("End of WHO list for {}", mask));
+        }
+
+        // Collected by libtiny_client and shown as `Event::ChanList`
+        ListStart | List { .. } | ListEnd => {}
+
+        UserHost { .. } | SaslSuccess { .. } => {}
+
+        SaslFail { msg } => {
+            ui.add_err_msg(&msg, time::now(), &MsgTarget::Server { serv });
+        }
+    }
+}
+
+/// Show a WHOIS or WHOWAS result in the user's tab if we have one, in the current tab otherwise.
+fn show_whois(ui: &dyn UI, serv: &str, info: &libtiny_client::WhoisInfo) {
+    let nick = &info.nick;
+    let msg_target = if ui.user_tab_exists(serv, nick) {
+        MsgTarget::User { serv, nick }
+    } else {
+        MsgTarget::CurrentTab
+    };
+
+    let user = match (&info.user, &info.host) {
+        (Some(user), Some(host)) => format!("{}@{}", user, host),
+        _ => {
+            let msg = if info.whowas {
+                format!("{}: There was no such nick", nick)
+            } else {
+                format!("{}: No such nick", nick)
+            };
+            ui.add_client_err_msg(&msg, &msg_target);
+            return;
+        }
+    };
+
+    let mut lines = vec![format!(
+        "{} {} ({}): {}",
+        if info.whowas { "WHOWAS" } else { "WHOIS" },
+        nick,
+        user,
+        info.realname.clone().unwrap_or_default()
+    )];
+    if let Some(ref account) = info.account {
+        lines.push(format!("  account: {}", account));
+    }
+    if !info.chans.is_empty() {
+        lines.push(format!("  channels: {}", info.chans.join(" ")));
+    }
+    if let Some(ref server) = info.server {
+        match info.server_info {
+            Some(ref server_info) if info.whowas => {
+                lines.push(format!("  server: {}, last seen {}", server, server_info))
+            }
+            Some(ref server_info) => lines.push(format!("  server: {} ({})", server, server_info)),
+            None => lines.push(format!("  server: {}", server)),
+        }
+    }
+    if let Some(ref actual_host) = info.actual_host {
+        lines.push(format!("  {}", actual_host));
+    }
+    if let Some(idle_secs) = info.idle_secs {
+        let mut line = format!("  idle: {}", format_duration(idle_secs));
+        if let Some(signon) = info.signon {
+            let signon = time::at(time::Timespec::new(signon as i64, 0));
+            line.push_str(&format!(", signed on: {}", signon.ctime()));
+        }
+        lines.push(line);
+    }
+    if let Some(ref away) = info.away {
+        lines.push(format!("  away: {}", away));
+    }
+    if let Some(ref operator) = info.operator {
+        lines.push(format!("  {}", operator));
+    }
+    if info.secure {
+        lines.push("  using a secure connection".to_owned());
+    }
+
+    for line in lines {
+        ui.add_client_msg(&line, &msg_target);
+    }
+}
+
+/// Format seconds as e.g. "1d 2h 3m 4s".
+fn format_duration(secs: u64) -> String {
+    let (days, secs) = (secs / 86400, secs % 86400);
+    let (hours, secs) = (secs / 3600, secs % 3600);
+    let (mins, secs) = (secs / 60, secs % 60);
+    let mut ret = String::new();
+    for (n, unit) in &[(days, "d"), (hours, "h"), (mins, "m")] {
+        if *n != 0 || !ret.is_empty() {
+            ret.push_str(&format!("{}{} ", n, unit));
+        }
+    }
+    ret.push_str(&format!("{}s", secs));
+    ret
+}
+
+/// Show a message from the server in the server tab. Messages without a server prefix are
+/// ignored.
+fn show_server_msg(ui: &dyn UI, serv: &str, pfx: Option<wire::Pfx>, msg: &str) {
+    if let Some(wire::Pfx::Server(msg_serv)) = pfx {
+        let msg_target = MsgTarget::Server { serv };
+        ui.add_privmsg(&msg_serv, msg, time::now(), &msg_target, false, false);
+        ui.set_tab_style(TabStyle::NewMsg, &msg_target);
+    }
+}
@@ -3,6 +3,7 @@
 #![feature(ptr_offset_from)]
 #![allow(clippy::zero_prefixed_literal)]
 
+mod alias;
 mod cmd;
 mod cmd_line_args;
 mod config;
@@ -11,7 +12,7 @@ mod ui;
 mod utils;
 
 use cmd_line_args::{parse_cmd_line_args, CmdLineArgs};
-use libtiny_client::{Client, ServerInfo};
+use libtiny_client::{Client, IpPref, ServerInfo};
 use libtiny_logger::Logger;
 use libtiny_tui::{Colors, MsgTarget, TUI};
 use libtiny_ui::UI;
@@ -21,7 +22,15 @@ fn main() {
     let CmdLineArgs {
         servers: server_args,
         config_path,
+        replay,
     } = parse_cmd_line_args(std::env::args());
+    let recording = replay.map(|path| match std::fs::read_to_string(&path) {
+        Err(err) => {
+            println!("Can't read recording {}: {}", path.display(), err);
+            ::std::process::exit(1);
+        }
+        Ok(recording) => recording,
+    });
     let config_path = config_path.unwrap_or_else(config::get_config_path);
     if config_path.is_dir() {
         println!("The config path is a directory.");
@@ -40,15 +49,30 @@ fn main() {
                 defaults,
                 colors,
                 log_dir,
+                aliases,
             }) => {
+                if let Err(err) = config::validate_servers(&servers) {
+                    println!("Can't parse config file:");
+                    println!("{}", err);
+                    ::std::process::exit(1);
+                }
+                let settings = ui::Settings {
+                    config_path,
+                    defaults,
+                    aliases,
+                    servers: servers.iter().map(config::Server::name).collect(),
+                };
                 let servers = if !server_args.is_empty() {
                     // connect only to servers that match at least one of
                     // the given patterns
                     servers
                         .into_iter()
                         .filter(|s| {
+                            let addrs = s.addrs().unwrap();
                             for server in &server_args {
-                                if s.addr.contains(server) {
+                                if s.name().contains(server)
+                                    || addrs.iter().any(|addr| addr.host.contains(server))
+                                {
                                     return true;
                                 }
                             }
@@ -58,7 +82,11 @@ fn main() {
                 } else {
                     servers
                 };
-                run(servers, defaults, colors, config_path, log_dir)
+                match recording {
+                    // Don't log replayed messages
+                    Some(recording) => run(vec![], settings, colors, None, Some(recording)),
+                    None => run(servers, settings, colors, log_dir, None),
+                }
             }
         }
     }
@@ -66,10 +94,10 @@ fn main() {
 
 fn run(
     servers: Vec<config::Server>,
-    defaults: config::Defaults,
+    settings: ui::Settings,
     colors: Colors,
-    config_path: PathBuf,
     log_dir: Option<PathBuf>,
+    recording: Option<String>,
 ) {
     env_logger::builder()
         .target(env_logger::Target::Stderr)
@@ -77,10 +105,18 @@ fn run(
 
     // One task for each client to handle IRC events
     // One task for TUI events
-    let mut executor = tokio::runtime::current_thread::Runtime::new().unwrap();
+    let mut runtime = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .unwrap();
+    // TUI and the event handler tasks are not `Send`, they run in a `LocalSet`. Clients spawn
+    // their own tasks on the runtime.
+    let local = tokio::task::LocalSet::new();
 
     // Create TUI task
-    let (tui, rcv_tui_ev) = TUI::run(colors, &mut executor);
+    let (tui, rcv_tui_ev) = TUI::run(colors, &local);
+    tui.set_cmds(cmd::cmd_completions(&settings.aliases));
 
     // Init "mentions" tab. This needs to happen before initializing the logger as otherwise we
     // won't have a tab to show errors when something goes wrong during initialization.
@@ -119,6 +155,9 @@ fn run(
             }
         });
 
+    // For `/reload`, to update TUI settings
+    let tui_handle = tui.clone();
+
     let tui: Box<dyn UI> = match logger {
         None => Box::new(tui) as Box<dyn UI>,
         Some(logger) => Box::new(libtiny_ui::combine(tui, logger)) as Box<dyn UI>,
@@ -126,38 +165,57 @@ fn run(
 
     let mut clients: Vec<Client> = Vec::with_capacity(servers.len());
 
-    for server in servers.iter().cloned() {
-        tui.new_server_tab(&server.addr);
+    if let Some(recording) = recording {
+        let serv_name = "replay".to_owned();
+        tui.new_server_tab(&serv_name);
 
         let server_info = ServerInfo {
-            addr: server.addr,
-            port: server.port,
-            tls: server.tls,
-            pass: server.pass,
-            realname: server.realname,
-            nicks: server.nicks,
-            auto_join: server.join,
-            nickserv_ident: server.nickserv_ident,
-            sasl_auth: server.sasl_auth.map(|auth| libtiny_client::SASLAuth {
-                username: auth.username,
-                password: auth.password,
-            }),
+            name: serv_name,
+            addrs: vec![],
+            tls: false,
+            sts_file: None,
+            bind_addr: None,
+            ip_pref: IpPref::Any,
+            pass: None,
+            realname: settings.defaults.realname.clone(),
+            nicks: settings.defaults.nicks.clone(),
+            auto_join: vec![],
+            join_invites_from: vec![],
+            nickserv_ident: None,
+            sasl_auth: None,
+            ctcp_replies: false,
+            dcc_download_dir: None,
+            record_file: None,
         };
 
-        let (client, rcv_conn_ev) = Client::new(server_info, Some(&mut executor));
+        let (client, rcv_conn_ev) = runtime.enter(|| Client::replay(server_info, recording));
+        let tui_clone = libtiny_ui::clone_box(&*tui);
+        local.spawn_local(conn::task(rcv_conn_ev, tui_clone, client.clone()));
+        clients.push(client);
+    }
+
+    for server in servers {
+        tui.new_server_tab(&server.name());
+
+        // Addresses are checked in `main`
+        let server_info = server.into_server_info(&settings.defaults);
+
+        let (client, rcv_conn_ev) = runtime.enter(|| Client::new(server_info));
         // TODO: Somehow it's quite hard to expose this objekt call with a different name and less
         // polymorphic type in libtiny_ui ...
         let tui_clone = libtiny_ui::clone_box(&*tui);
         let client_clone = client.clone();
 
         // Spawn a task to handle connection events
-        executor.spawn(conn::task(rcv_conn_ev, tui_clone, client_clone));
+        local.spawn_local(conn::task(rcv_conn_ev, tui_clone, client_clone));
 
         clients.push(client);
     }
 
     // Spawn a task to handle TUI events
-    executor.spawn(ui::task(config_path, defaults, tui, clients, rcv_tui_ev));
+    local.spawn_local(ui::task(settings, tui_handle, tui, clients, rcv_tui_ev));
 
-    executor.run().unwrap(); // unwraps RunError
+    // Runs until all tasks in the `LocalSet` return. Event handler tasks return after their
+    // clients are closed, so this also waits for the clients to send QUIT.
+    runtime.block_on(local);
 }
@@ -3,23 +3,35 @@
 
 //! UI event handling
 
+use crate::alias::{self, AliasVars};
 use crate::cmd::{parse_cmd, CmdArgs, ParseCmdResult};
 use crate::config;
-use futures_util::stream::StreamExt;
+use futures::stream::StreamExt;
 use libtiny_client::Client;
+use libtiny_tui::TUI;
 use libtiny_ui::{MsgSource, MsgTarget, UI};
-use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::path::PathBuf;
 use tokio::sync::mpsc;
 
+/// Config file settings used by the commands. Updated by `/reload`.
+pub(crate) struct Settings {
+    pub(crate) config_path: PathBuf,
+    pub(crate) defaults: config::Defaults,
+    pub(crate) aliases: HashMap<String, String>,
+    /// Names of the servers in the config file. `/reload` connects to servers not in this list.
+    pub(crate) servers: Vec<String>,
+}
+
 pub(crate) async fn task(
-    config_path: PathBuf,
-    defaults: config::Defaults,
+    mut settings: Settings,
+    tui: TUI,
     ui: Box<dyn UI>,
     mut clients: Vec<Client>,
     mut rcv_ev: mpsc::Receiver<libtiny_ui::Event>,
 ) {
     while let Some(ev) = rcv_ev.next().await {
-        if handle_input_ev(&config_path, &defaults, &ui, &mut clients, ev) {
+        if handle_input_ev(&mut settings, &tui, &ui, &mut clients, ev) {
             return;
         }
         ui.draw();
@@ -27,8 +39,8 @@ pub(crate) async fn task(
 }
 
 fn handle_input_ev(
-    config_path: &Path,
-    defaults: &config::Defaults,
+    settings: &mut Settings,
+    tui: &TUI,
     ui: &Box<dyn UI>,
     clients: &mut Vec<Client>,
     ev: libtiny_ui::Event,
@@ -49,42 +61,76 @@ fn handle_input_ev(
                 send_msg(&**ui, clients, &source, line, false)
             }
         }
-        Cmd { cmd, source } => handle_cmd(config_path, defaults, ui, clients, source, &cmd),
+        Cmd { cmd, source } => handle_cmd(settings, tui, ui, clients, source, &cmd, true),
     }
 
     false // continue
 }
 
+/// Run a command. Aliases are expanded when `expand_aliases` is set.
 fn handle_cmd(
-    config_path: &Path,
-    defaults: &config::Defaults,
+    settings: &mut Settings,
+    tui: &TUI,
     ui: &Box<dyn UI>,
     clients: &mut Vec<Client>,
     src: MsgSource,
     cmd: &str,
+    expand_aliases: bool,
 ) {
-    match parse_cmd(cmd) {
+    let no_aliases = HashMap::new();
+    let aliases = if expand_aliases {
+        &settings.aliases
+    } else {
+        &no_aliases
+    };
+    match parse_cmd(cmd, aliases) {
         ParseCmdResult::Ok { cmd, rest } => {
             let cmd_args = CmdArgs {
                 args: rest,
-                config_path,
-                defaults,
+                settings,
+                tui,
                 ui,
                 clients,
                 src,
             };
             (cmd.cmd_fn)(cmd_args);
         }
-        // ParseCmdResult::Ambiguous(vec) => {
-        //     self.ui.add_client_err_msg(
-        //         &format!("Unsupported command: \"/{}\"", msg),
-        //         &MsgTarget::CurrentTab,
-        //     );
-        //     self.ui.add_client_err_msg(
-        //         &format!("Did you mean one of {:?} ?", vec),
-        //         &MsgTarget::CurrentTab,
-        //     );
-        // },
+        ParseCmdResult::Alias { template, rest } => {
+            let nick = clients
+                .iter()
+                .find(|client| client.get_serv_name() == src.serv_name())
+                .map(Client::get_nick)
+                .unwrap_or_default();
+            let chan = match src {
+                MsgSource::Chan { ref chan, .. } => chan.as_str(),
+                _ => "",
+            };
+            let vars = AliasVars {
+                nick: &nick,
+                chan,
+                serv: src.serv_name(),
+            };
+            // Expanded commands are not checked for aliases, to avoid loops. Lines that are not
+            // commands are sent as messages to the current tab.
+            for line in alias::expand(&template, rest, &vars) {
+                match line.strip_prefix('/') {
+                    Some(cmd) => handle_cmd(settings, tui, ui, clients, src.clone(), cmd, false),
+                    None => send_msg(&**ui, clients, &src, line, false),
+                }
+            }
+        }
+        ParseCmdResult::Ambiguous(names) => ui.add_client_err_msg(
+            &format!(
+                "Ambiguous command: \"/{}\", did you mean one of {}?",
+                cmd.split_whitespace().next().unwrap_or(""),
+                names
+                    .iter()
+                    .map(|name| format!("/{}", name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            &MsgTarget::CurrentTab,
+        ),
         ParseCmdResult::Unknown => ui.add_client_err_msg(
             &format!("Unsupported command: \"/{}\"", cmd),
             &MsgTarget::CurrentTab,
@@ -132,6 +178,36 @@ pub(crate) fn send_msg(
 
             MsgSource::Chan { ref serv, ref chan } => (MsgTarget::Chan { serv, chan }, chan),
 
+            MsgSource::User { ref serv, ref nick } if nick.starts_with('=') => {
+                // DCC chat tab
+                let ui_target = MsgTarget::User { serv, nick };
+                let ret = match client.dcc_find_chat(&nick[1..]) {
+                    None => Err("Not in a DCC chat with this user".to_owned()),
+                    Some(id) => {
+                        let line = if is_action {
+                            format!("\x01ACTION {}\x01", msg)
+                        } else {
+                            msg.clone()
+                        };
+                        client
+                            .dcc_chat_msg(id, &line)
+                            .map_err(|err| err.to_string())
+                    }
+                };
+                match ret {
+                    Ok(()) => ui.add_privmsg(
+                        &client.get_nick(),
+                        &msg,
+                        time::now(),
+                        &ui_target,
+                        false,
+                        is_action,
+                    ),
+                    Err(err) => ui.add_client_err_msg(&err, &ui_target),
+                }
+                return;
+            }
+
             MsgSource::User { ref serv, ref nick } => {
                 let msg_target = if nick.eq_ignore_ascii_case("nickserv")
                     || nick.eq_ignore_ascii_case("chanserv")
@@ -25,6 +25,15 @@ pub(crate) fn split_whitespace_indices(str: &str) -> SplitWhitespaceIndices {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Milliseconds since the epoch. Used as CTCP PING argument to be able to calculate round-trip
+/// time when we get the reply back.
+pub(crate) fn now_millis() -> u64 {
+    let now = time::get_time();
+    now.sec as u64 * 1000 + now.nsec as u64 / 1_000_000
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 pub(crate) fn is_nick_char(c: char) -> bool {
     // from RFC 2812:
     //